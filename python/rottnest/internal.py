@@ -16,7 +16,7 @@ import time
 
 from .nlp import query_expansion_keyword, query_expansion_llm
 from .utils import get_daft_io_config_from_file_path, get_fs_from_file_path, get_physical_layout, get_virtual_layout, read_columns, read_metadata_file,\
-    get_metadata_and_populate_cache, get_result_from_index_result, return_full_result
+    get_metadata_and_populate_cache, get_result_from_index_result, return_full_result, read_indexed_blocks
 
 
 def index_files_logcloud(file_paths: List[str], column_name: str, name = uuid.uuid4().hex, remote = None, 
@@ -43,11 +43,12 @@ def index_files_logcloud(file_paths: List[str], column_name: str, name = uuid.uu
     polars.concat([polars.read_parquet(f"{i}.maui") for i in range(num_groups)]).write_parquet(f"{name}.maui")
     rottnest.index_logcloud(name, num_groups, wavelet_tree = wavelet)
 
-def index_files_bm25(file_paths: list[str], column_name: str, name = uuid.uuid4().hex, index_mode = "physical", tokenizer_file = None):
+def index_files_bm25(file_paths: list[str], column_name: str, name = uuid.uuid4().hex, index_mode = "physical", tokenizer_file = None, positions = False):
 
     arr, uid, file_data = get_physical_layout(file_paths, column_name) if index_mode == "physical" else get_virtual_layout(file_paths, column_name, "uid")
 
-    cache_ranges = rottnest.build_lava_bm25(f"{name}.lava", arr, uid, tokenizer_file)
+    # positions make the index bigger but allow phrase queries with phrase_slop
+    cache_ranges = rottnest.build_lava_bm25(f"{name}.lava", arr, uid, tokenizer_file, positions = positions)
 
     # do not attempt to manually edit the metadata. It is Parquet, but it is Varsity Parquet to ensure performance.
     file_data = file_data.to_arrow()
@@ -466,8 +467,7 @@ def search_index_bm25(indices: List[str], query: str, K: int, query_expansion =
     assert len(metadata["column_name"].unique()) == 1, "index is not allowed to span multiple column names"
     column_name = metadata["column_name"].unique()[0]
 
-    result = pyarrow.chunked_array(rottnest.read_indexed_pages(column_name, metadata["file_path"].to_list(), metadata["row_groups"].to_list(),
-                                     metadata["data_page_offsets"].to_list(), metadata["data_page_sizes"].to_list(), metadata["dictionary_page_sizes"].to_list()))
+    result = pyarrow.chunked_array(read_indexed_blocks(column_name, metadata, reader_type = reader_type))
     result = pyarrow.table([result], names = ["text"])
     result = result.append_column('row_nr', pyarrow.array(np.arange(len(result)), pyarrow.int64()))
 
@@ -10,6 +10,7 @@ from pyarrow.fs import S3FileSystem, LocalFileSystem
 import json
 import daft
 from concurrent.futures import ThreadPoolExecutor
+from types import SimpleNamespace
 
 def get_fs_from_file_path(filepath):
 
@@ -68,6 +69,52 @@ def read_row_groups(file_paths: list, row_groups: list, row_ranges: list, column
 
     return results
 
+# Reads the values and page layout of a column of a parquet file row group by row group, casting the
+# values of each as they come in, so the uncast values of at most a few row groups are held at once.
+def stream_physical_layout(file_path: str, column_name: str, type = "str"):
+
+    stream = rottnest.stream_parquet_layout(column_name, file_path)
+    layout = {"num_row_groups": stream.num_row_groups, "metadata_bytes": stream.metadata_bytes, "data_page_num_rows": [],
+              "data_page_offsets": [], "data_page_sizes": [], "dictionary_page_sizes": [], "row_group_data_pages": []}
+    arrs = []
+    for row_group_arrs, row_group_layout in stream:
+        arrs.extend([i.cast(pyarrow.large_string() if type == 'str' else pyarrow.large_binary()) for i in row_group_arrs])
+        for key in ["data_page_num_rows", "data_page_offsets", "data_page_sizes", "dictionary_page_sizes"]:
+            layout[key].extend(getattr(row_group_layout, key))
+        layout["row_group_data_pages"].append(len(row_group_layout.data_page_num_rows))
+
+    return pyarrow.concat_arrays(arrs), SimpleNamespace(**layout)
+
+IPC_EXTENSIONS = (".arrow", ".feather", ".ipc")
+NDJSON_EXTENSIONS = (".json", ".jsonl", ".ndjson")
+TEXT_EXTENSIONS = (".txt", ".log")
+
+# The format of a data file by its extension, parquet unless it is an Arrow IPC or a text file.
+def get_file_format(file_path: str):
+
+    if file_path.endswith(IPC_EXTENSIONS):
+        return "ipc"
+    elif file_path.endswith(NDJSON_EXTENSIONS):
+        return "ndjson"
+    elif file_path.endswith(TEXT_EXTENSIONS):
+        return "text"
+    return "parquet"
+
+# The layout of an Arrow IPC or text file in the shape of a parquet one, every record batch or block
+# of lines is a row group of one page, so the same metadata points at them.
+def get_block_layout(file_path: str, column_name: str, file_format: str, type = "str"):
+
+    if file_format == "ipc":
+        arrs, block_layout = rottnest.get_ipc_layout(column_name, file_path)
+    else:
+        arrs, block_layout = rottnest.get_text_layout(file_path, column_name if file_format == "ndjson" else None)
+    arr = pyarrow.concat_arrays([i.cast(pyarrow.large_string() if type == 'str' else pyarrow.large_binary()) for i in arrs])
+    num_blocks = len(block_layout.block_offsets)
+    return arr, SimpleNamespace(num_row_groups = num_blocks, metadata_bytes = block_layout.metadata_bytes,
+                                data_page_num_rows = block_layout.block_num_rows, data_page_offsets = block_layout.block_offsets,
+                                data_page_sizes = block_layout.block_sizes, dictionary_page_sizes = [0] * num_blocks,
+                                row_group_data_pages = [1] * num_blocks)
+
 def get_physical_layout(file_paths: list, column_name: str, type = "str", remote = None):
 
     assert type in {"str", "binary"}
@@ -76,8 +123,11 @@ def get_physical_layout(file_paths: list, column_name: str, type = "str", remote
     all_arrs = []
     all_uids = []
     for file_path in file_paths:
-        arrs, layout = rottnest.get_parquet_layout(column_name, file_path)
-        arr = pyarrow.concat_arrays([i.cast(pyarrow.large_string() if type == 'str' else pyarrow.large_binary()) for i in arrs])
+        file_format = get_file_format(file_path)
+        if file_format == "parquet":
+            arr, layout = stream_physical_layout(file_path, column_name, type)
+        else:
+            arr, layout = get_block_layout(file_path, column_name, file_format, type)
         data_page_num_rows = np.array(layout.data_page_num_rows)
         uid = np.repeat(np.arange(len(data_page_num_rows)), data_page_num_rows) + 1
 
@@ -138,7 +188,7 @@ def get_metadata_and_populate_cache(indices: List[str], suffix = "meta"):
     metadatas = daft.table.read_parquet_into_pyarrow_bulk([f"{index_name}.{suffix}" for index_name in indices], io_config = get_daft_io_config_from_file_path(indices[0]))
     
    
-    if os.getenv("CACHE_ENABLE") and os.getenv("CACHE_ENABLE").lower() == "true":
+    if rottnest.range_cache_backend() is not None:
         metadatas = [(polars.from_arrow(i), json.loads(i.schema.metadata[b'cache_ranges'].decode())) for i in metadatas]
         metadata = polars.concat([f[0].with_columns(polars.lit(i).alias("file_id").cast(polars.Int64)) for i, f in enumerate(metadatas)])
         cache_ranges = {f"{indices[i]}.lava": f[1] for i, f in enumerate(metadatas) if len(f[1]) > 0}
@@ -151,6 +201,28 @@ def get_metadata_and_populate_cache(indices: List[str], suffix = "meta"):
 
     return metadata
 
+# Reads the pages, record batches or blocks of lines the rows of the metadata point at, with the reader
+# of the format of each file, and returns their values in the order of the rows.
+def read_indexed_blocks(column_name: str, metadata: polars.DataFrame, file_metadatas: dict = {}, reader_type = "aws"):
+
+    columns = {c: metadata[c].to_list() for c in ["file_path", "row_groups", "data_page_offsets", "data_page_sizes", "dictionary_page_sizes"]}
+    file_formats = [get_file_format(file_path) for file_path in columns["file_path"]]
+    result = [None] * len(file_formats)
+    for file_format in set(file_formats):
+        rows = [i for i, f in enumerate(file_formats) if f == file_format]
+        paths, row_groups, offsets, sizes, dict_sizes = [[columns[c][i] for i in rows] for c in columns]
+        footers = {k: v for k, v in file_metadatas.items() if k in set(paths)}
+        if file_format == "parquet":
+            arrs = rottnest.read_indexed_pages(column_name, paths, row_groups, offsets, sizes, dict_sizes, reader_type, footers)
+        elif file_format == "ipc":
+            arrs = rottnest.read_indexed_batches(column_name, paths, offsets, sizes, reader_type, footers)
+        else:
+            arrs = rottnest.read_indexed_lines(paths, offsets, sizes, column_name if file_format == "ndjson" else None, reader_type)
+        for i, arr in zip(rows, arrs):
+            result[i] = arr
+
+    return result
+
 def get_result_from_index_result(metadata: polars.DataFrame, index_search_results: list):
     
     uids = polars.from_dict({"file_id": [i[0] for i in index_search_results], "uid": [i[1] for i in index_search_results]})
@@ -162,9 +234,7 @@ def get_result_from_index_result(metadata: polars.DataFrame, index_search_result
 
     file_metadatas = {d["file_path"]: d["metadata_bytes"] for d in file_metadatas.to_dicts()}
 
-    result = rottnest.read_indexed_pages(column_name, metadata["file_path"].to_list(), metadata["row_groups"].to_list(),
-                                     metadata["data_page_offsets"].to_list(), metadata["data_page_sizes"].to_list(), metadata["dictionary_page_sizes"].to_list(),
-                                     "aws", file_metadatas)
+    result = read_indexed_blocks(column_name, metadata, file_metadatas)
     
     # magic number 2044 for vetors
     # result = read_row_groups(metadata["file_path"].to_list(), metadata["row_groups"].to_list(), [(i, i + 2044) for i in metadata['page_row_offset_in_row_group'].to_list()], column_name)
@@ -180,6 +250,7 @@ def get_result_from_index_result(metadata: polars.DataFrame, index_search_result
 
 def return_full_result(result: polars.DataFrame, metadata: polars.DataFrame, column_name: str, columns: List[str]):
     if columns != []:
+        assert all(get_file_format(f) == "parquet" for f in metadata["file_path"].unique().to_list()), "other columns can only be read from parquet files"
         result = result.join(metadata.select(["__metadata_key__", "file_path", "row_groups"]), on = "__metadata_key__", how = "left")
         grouped = result.group_by(["file_path", "row_groups"]).agg([polars.col('__metadata_key__'), polars.col('__row_group_rownr__')])
         collected_results = polars.from_arrow(read_columns(grouped["file_path"].to_list(), grouped["row_groups"].to_list(), grouped["__row_group_rownr__"].to_list()))
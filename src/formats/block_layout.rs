@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+
+use crate::lava::error::LavaError;
+
+/// The blocks of a row-oriented file an index can point at, the record batches of an Arrow IPC
+/// file or runs of lines of a text file, like the data pages of a `ParquetLayout`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockLayout {
+    // what decoding the blocks needs besides the blocks, the footer of an IPC file, empty for text
+    pub metadata_bytes: Bytes,
+    pub block_offsets: Vec<usize>,
+    pub block_sizes: Vec<usize>,
+    pub block_num_rows: Vec<usize>,
+}
+
+// file -> (index among the requests, offset, size) of the blocks requested of it
+pub(crate) type FileBlocks = BTreeMap<String, Vec<(usize, u64, usize)>>;
+
+/// The requested blocks grouped by file, so that the blocks of a file are fetched with one
+/// coalesced `read_ranges` call.
+pub(crate) fn group_blocks(
+    file_paths: Vec<String>,
+    block_offsets: Vec<u64>,
+    block_sizes: Vec<usize>,
+) -> Result<FileBlocks, LavaError> {
+    if file_paths.len() != block_offsets.len() || file_paths.len() != block_sizes.len() {
+        return Err(LavaError::Parse(
+            "file_paths, block_offsets and block_sizes must have the same length".to_string(),
+        ));
+    }
+    let mut file_blocks = FileBlocks::new();
+    for (idx, ((file_path, offset), size)) in file_paths
+        .into_iter()
+        .zip(block_offsets)
+        .zip(block_sizes)
+        .enumerate()
+    {
+        file_blocks
+            .entry(file_path)
+            .or_default()
+            .push((idx, offset, size));
+    }
+    Ok(file_blocks)
+}
@@ -1,34 +1,60 @@
 use crate::{
-    formats::readers::{get_file_size_and_reader, get_reader, AsyncReader, ReaderType},
+    formats::readers::{get_file_size_and_reader, get_reader, AsyncReader, StorageConfig},
     lava::error::LavaError,
 };
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
 
-use super::redis_client::get_redis_connection;
-
+/// Reads the blocks covering `ranges` of every file into the range cache of `storage_config`,
+/// the blocks readers look up. Blocks already cached for the current version of a file are
+/// skipped.
 #[tokio::main]
 pub async fn populate_cache(
     ranges: BTreeMap<String, Vec<(usize, usize)>>,
-    reader_type: ReaderType
+    storage_config: StorageConfig
 ) -> Result<(), LavaError> {
 
-    let mut conn = get_redis_connection().await?;
+    let Some(range_cache) = storage_config.range_cache() else {
+        return Err(LavaError::Unsupported(
+            "populate_cache needs a range cache, none is configured".to_string(),
+        ));
+    };
 
+    let block_size = range_cache.block_size();
     for (file_path, ranges) in &ranges {
-        let (_, mut reader) = get_file_size_and_reader(file_path.to_string(), reader_type.clone()).await?;
-        let cached_ranges = conn.get_ranges(&file_path).await?;
-        // check if exists
-        if cached_ranges.len() == 0 {
-            println!("writing to cache: {}", file_path);
-            for (from, to) in ranges {
-                let data = reader.read_range(*from as u64, *to as u64).await?;
-                conn.set_data(&file_path, *from as u64, *to as u64, data.to_vec()).await?;
-            }
+        let (file_size, mut reader) = get_file_size_and_reader(file_path.to_string(), storage_config.clone()).await?;
+        let Some(version) = reader.version() else {
+            log::warn!("not caching {}, its version is unknown", file_path);
+            continue;
+        };
+        // we put the blocks ourselves, and warming up is not an access of the queries
+        reader.set_range_cache(None);
+        reader.access_recorder = None;
 
-            conn.set_ranges(&file_path, ranges).await?;
+        let file_size = file_size as u64;
+        let blocks: BTreeSet<u64> = ranges
+            .iter()
+            .filter(|(from, to)| from < to)
+            .flat_map(|(from, to)| {
+                *from as u64 / block_size..=(*to as u64).min(file_size).saturating_sub(1) / block_size
+            })
+            .collect();
+        let mut missing = vec![];
+        for block in blocks {
+            let start = block * block_size;
+            let end = (start + block_size).min(file_size);
+            if start < end && !range_cache.contains_range(file_path, &version, start, end).await? {
+                missing.push((start, end));
+            }
+        }
+        if missing.is_empty() {
+            continue;
+        }
+        // putting a block of a rewritten file drops the blocks of its old version
+        let data = reader.read_ranges(&missing).await?;
+        for ((start, _), data) in missing.into_iter().zip(data) {
+            range_cache.put(file_path, &version, start, data).await?;
         }
-        
     }
     Ok(())
 
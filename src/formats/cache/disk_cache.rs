@@ -0,0 +1,538 @@
+use std::{
+    env, fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use super::range_cache::RangeCache;
+use crate::lava::error::LavaError;
+
+pub const DEFAULT_DISK_CACHE_CAPACITY: u64 = 10 * 1024 * 1024 * 1024;
+pub const DEFAULT_DISK_CACHE_BLOCK_SIZE: u64 = 1024 * 1024;
+
+const BLOCK_EXTENSION: &str = "blk";
+// temp files older than this were left behind by a crashed writer
+const STALE_TMP_AGE: Duration = Duration::from_secs(3600);
+
+/// A size-bounded block cache on local disk. Objects are split into fixed size blocks and every
+/// block is stored as its own file, named after a hash of (object key, block offset). The
+/// object key is stored in the block file and checked on every read, so hash collisions are
+/// misses rather than wrong data.
+///
+/// Blocks are written to a temp file and renamed into place, and evicted least recently used
+/// first based on their mtime, which is bumped on every hit. This keeps the cache valid across
+/// restarts and lets several processes on the same host share one directory.
+///
+/// As a `RangeCache`, every range is stored as one block named after the file, its version and
+/// the start of the range. There is no index to keep in sync, so concurrent puts of any number of
+/// processes never lose each other. Ranges are looked up by their start only: readers going
+/// through the cache put and get whole blocks, and a range inside a block that starts elsewhere
+/// is a miss. Clones share the same directory and accounting.
+#[derive(Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    capacity: u64,
+    block_size: u64,
+    // approximate, other processes writing to the same directory are only seen on eviction
+    used: Arc<AtomicU64>,
+    evict_lock: Arc<Mutex<()>>,
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl DiskCache {
+    pub fn new<P: AsRef<Path>>(dir: P, capacity: u64, block_size: u64) -> Result<Self, LavaError> {
+        if block_size == 0 {
+            return Err(LavaError::Parse(
+                "disk cache block size must be positive".to_string(),
+            ));
+        }
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let cache = Self {
+            dir,
+            capacity,
+            block_size,
+            used: Arc::new(AtomicU64::new(0)),
+            evict_lock: Arc::new(Mutex::new(())),
+        };
+        let used = cache.scan()?.iter().map(|(_, size, _)| size).sum();
+        cache.used.store(used, Ordering::SeqCst);
+        Ok(cache)
+    }
+
+    /// Reads ROTTNEST_DISK_CACHE_DIR, ROTTNEST_DISK_CACHE_CAPACITY_MB and
+    /// ROTTNEST_DISK_CACHE_BLOCK_SIZE_KB. Returns None if no directory is configured.
+    pub fn from_env() -> Result<Option<Self>, LavaError> {
+        let dir = match env::var("ROTTNEST_DISK_CACHE_DIR") {
+            Ok(dir) if !dir.is_empty() => dir,
+            _ => return Ok(None),
+        };
+        let capacity = env::var("ROTTNEST_DISK_CACHE_CAPACITY_MB")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(|value| value * 1024 * 1024)
+            .unwrap_or(DEFAULT_DISK_CACHE_CAPACITY);
+        let block_size = env::var("ROTTNEST_DISK_CACHE_BLOCK_SIZE_KB")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(|value| value * 1024)
+            .unwrap_or(DEFAULT_DISK_CACHE_BLOCK_SIZE);
+        Ok(Some(Self::new(dir, capacity, block_size)?))
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    fn block_path(&self, key: &str, offset: u64) -> PathBuf {
+        let hash = fnv1a(key.as_bytes());
+        self.dir
+            .join(format!("{:016x}-{}.{}", hash, offset, BLOCK_EXTENSION))
+    }
+
+    /// Returns the block of `key` starting at `offset`, if it is cached.
+    pub fn get(&self, key: &str, offset: u64) -> Option<Bytes> {
+        self.read_block(&self.block_path(key, offset), key)
+    }
+
+    /// Stores the block of `key` starting at `offset`, evicting old blocks if the cache is full.
+    pub fn put(&self, key: &str, offset: u64, data: &[u8]) -> Result<(), LavaError> {
+        self.write_block(&self.block_path(key, offset), key, data)
+    }
+
+    /// Removes the block of `key` starting at `offset`, if it is cached.
+    pub fn remove(&self, key: &str, offset: u64) {
+        self.remove_block(&self.block_path(key, offset));
+    }
+
+    fn read_block(&self, path: &Path, key: &str) -> Option<Bytes> {
+        let contents = fs::read(path).ok()?;
+        if contents.len() < 4 {
+            return None;
+        }
+        let key_len = u32::from_le_bytes(contents[..4].try_into().unwrap()) as usize;
+        if contents.len() < 4 + key_len || &contents[4..4 + key_len] != key.as_bytes() {
+            return None;
+        }
+        // bump the mtime so the block counts as recently used, failing to do so is harmless
+        if let Ok(file) = fs::File::options().write(true).open(path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(Bytes::from(contents).slice(4 + key_len..))
+    }
+
+    fn write_block(&self, path: &Path, key: &str, data: &[u8]) -> Result<(), LavaError> {
+        let tmp_path = path.with_extension(format!(
+            "tmp-{}-{:x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+
+        // a block that is replaced no longer counts
+        let replaced = fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let write = || -> std::io::Result<()> {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(&(key.len() as u32).to_le_bytes())?;
+            file.write_all(key.as_bytes())?;
+            file.write_all(data)?;
+            fs::rename(&tmp_path, path)
+        };
+        if let Err(e) = write() {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+
+        let size = (4 + key.len() + data.len()) as u64;
+        let _ = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                Some(used.saturating_sub(replaced))
+            });
+        if self.used.fetch_add(size, Ordering::SeqCst) + size > self.capacity {
+            self.evict()?;
+        }
+        Ok(())
+    }
+
+    fn remove_block(&self, path: &Path) {
+        if let Ok(metadata) = fs::metadata(path) {
+            if fs::remove_file(path).is_ok() {
+                let _ = self
+                    .used
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                        Some(used.saturating_sub(metadata.len()))
+                    });
+            }
+        }
+    }
+
+    /// Lists the (mtime, size, path) of all blocks, removing stale temp files on the way.
+    fn scan(&self) -> Result<Vec<(SystemTime, u64, PathBuf)>, LavaError> {
+        let now = SystemTime::now();
+        let mut blocks = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            // other processes may remove files while we are scanning
+            let Ok(entry) = entry else { continue };
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let path = entry.path();
+            let modified = metadata.modified().unwrap_or(now);
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(BLOCK_EXTENSION) => blocks.push((modified, metadata.len(), path)),
+                Some(e)
+                    if e.starts_with("tmp-")
+                        && now.duration_since(modified).unwrap_or_default() > STALE_TMP_AGE =>
+                {
+                    let _ = fs::remove_file(path);
+                }
+                _ => {}
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Removes the least recently used blocks until the cache is at 90% of its capacity.
+    fn evict(&self) -> Result<(), LavaError> {
+        let _guard = self.evict_lock.lock().unwrap();
+        let mut blocks = self.scan()?;
+        let mut used: u64 = blocks.iter().map(|(_, size, _)| size).sum();
+        let target = self.capacity / 10 * 9;
+        if used > self.capacity {
+            blocks.sort_unstable();
+            for (_, size, path) in blocks {
+                if used <= target {
+                    break;
+                }
+                if fs::remove_file(path).is_ok() {
+                    used -= size;
+                }
+            }
+        }
+        self.used.store(used, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn range_key(filename: &str, version: &str) -> String {
+    format!("range:{}:{}", filename, version)
+}
+
+fn version_key(filename: &str) -> String {
+    format!("version:{}", filename)
+}
+
+impl DiskCache {
+    fn range_prefix(filename: &str) -> String {
+        format!("{:016x}-", fnv1a(filename.as_bytes()))
+    }
+
+    fn range_path(&self, filename: &str, version: &str, from: u64) -> PathBuf {
+        self.dir.join(format!(
+            "{}{:016x}-{}.{}",
+            Self::range_prefix(filename),
+            fnv1a(version.as_bytes()),
+            from,
+            BLOCK_EXTENSION
+        ))
+    }
+
+    /// The length of the range cached at `path`, without reading its data or bumping its mtime.
+    fn range_len(path: &Path, key: &str) -> Option<u64> {
+        let len = fs::metadata(path).ok()?.len();
+        let header_len = (4 + key.len()) as u64;
+        (len >= header_len && block_key(path)? == key).then(|| len - header_len)
+    }
+
+    /// Removes the cached ranges of all versions of `filename` but `keep`.
+    fn remove_ranges(&self, filename: &str, keep: Option<&str>) -> Result<(), LavaError> {
+        let prefix = Self::range_prefix(filename);
+        let keep = keep.map(|version| format!("{}{:016x}-", prefix, fnv1a(version.as_bytes())));
+        for entry in fs::read_dir(&self.dir)? {
+            let Ok(entry) = entry else { continue };
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else { continue };
+            if !name.starts_with(&prefix)
+                || !name.ends_with(BLOCK_EXTENSION)
+                || keep.as_ref().is_some_and(|keep| name.starts_with(keep))
+            {
+                continue;
+            }
+            // blocks of other files whose name hashes the same are left alone
+            let path = entry.path();
+            if block_key(&path).is_some_and(|key| key.starts_with(&range_key(filename, ""))) {
+                self.remove_block(&path);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_range(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Option<Bytes>, LavaError> {
+        let path = self.range_path(filename, version, from);
+        Ok(self
+            .read_block(&path, &range_key(filename, version))
+            .filter(|data| data.len() as u64 >= to - from)
+            .map(|data| data.slice(..(to - from) as usize)))
+    }
+
+    fn put_range(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        data: &[u8],
+    ) -> Result<(), LavaError> {
+        // the ranges of older versions are dropped when a new version is seen, a lost race with
+        // another process only leaves blocks that are never read again and age out
+        let current = self.get(&version_key(filename), 0);
+        if current.as_deref() != Some(version.as_bytes()) {
+            self.remove_ranges(filename, Some(version))?;
+            self.put(&version_key(filename), 0, version.as_bytes())?;
+        }
+        let path = self.range_path(filename, version, from);
+        self.write_block(&path, &range_key(filename, version), data)
+    }
+
+    /// Runs `f` on the blocking thread pool, so that the file system calls of the cache do not
+    /// stall the runtime.
+    async fn blocking<T, F>(&self, f: F) -> Result<T, LavaError>
+    where
+        T: Send + 'static,
+        F: FnOnce(DiskCache) -> Result<T, LavaError> + Send + 'static,
+    {
+        let cache = self.clone();
+        tokio::task::spawn_blocking(move || f(cache))
+            .await
+            .map_err(|e| LavaError::Io(std::io::Error::other(e)))?
+    }
+}
+
+/// The key stored in the block at `path`, if it can be read.
+fn block_key(path: &Path) -> Option<String> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut len = [0u8; 4];
+    file.read_exact(&mut len).ok()?;
+    let mut key = vec![0u8; u32::from_le_bytes(len) as usize];
+    file.read_exact(&mut key).ok()?;
+    String::from_utf8(key).ok()
+}
+
+#[async_trait]
+impl RangeCache for DiskCache {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    async fn get(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Option<Bytes>, LavaError> {
+        let (filename, version) = (filename.to_string(), version.to_string());
+        self.blocking(move |cache| cache.get_range(&filename, &version, from, to))
+            .await
+    }
+
+    async fn put(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        data: Bytes,
+    ) -> Result<(), LavaError> {
+        let (filename, version) = (filename.to_string(), version.to_string());
+        self.blocking(move |cache| cache.put_range(&filename, &version, from, &data))
+            .await
+    }
+
+    async fn contains_range(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, LavaError> {
+        let (filename, version) = (filename.to_string(), version.to_string());
+        self.blocking(move |cache| {
+            let path = cache.range_path(&filename, &version, from);
+            Ok(Self::range_len(&path, &range_key(&filename, &version))
+                .is_some_and(|len| len >= to - from))
+        })
+        .await
+    }
+
+    async fn evict(&self, filename: &str) -> Result<(), LavaError> {
+        let filename = filename.to_string();
+        self.blocking(move |cache| {
+            cache.remove_ranges(&filename, None)?;
+            cache.remove(&version_key(&filename), 0);
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::DiskCache;
+    use crate::formats::cache::RangeCache;
+
+    #[test]
+    fn test_disk_cache_eviction() {
+        let dir = std::env::temp_dir().join(format!("rottnest_disk_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // every block takes 4 + 3 + 100 bytes
+        let cache = DiskCache::new(&dir, 1000, 100).unwrap();
+        for i in 0..9u64 {
+            cache.put("s3://a", i * 100, &[i as u8; 100]).unwrap();
+        }
+        assert_eq!(cache.get("s3://a", 300).unwrap(), vec![3u8; 100]);
+        assert!(cache.get("s3://b", 300).is_none());
+
+        // goes over capacity, evicts the oldest blocks
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        cache.get("s3://a", 0).unwrap();
+        cache.put("s3://a", 900, &[9u8; 100]).unwrap();
+        assert!(cache.used() <= 900);
+        assert!(cache.get("s3://a", 0).is_some());
+        assert!(cache.get("s3://a", 900).is_some());
+
+        // survives a restart
+        let used = cache.used();
+        drop(cache);
+        let cache = DiskCache::new(&dir, 1000, 100).unwrap();
+        assert_eq!(cache.used(), used);
+        assert_eq!(cache.get("s3://a", 900).unwrap(), vec![9u8; 100]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disk_range_cache() {
+        let dir = std::env::temp_dir().join(format!("rottnest_disk_range_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let cache = DiskCache::new(&dir, 1 << 20, 100).unwrap();
+        let data = Bytes::from((0..200u8).collect::<Vec<u8>>());
+        // the get, put and evict of the block cache shadow the ones of the trait
+        RangeCache::put(&cache, "s3://a", "v1", 1000, data.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            RangeCache::get(&cache, "s3://a", "v1", 1000, 1020)
+                .await
+                .unwrap()
+                .unwrap(),
+            data.slice(..20)
+        );
+        // ranges are looked up by their start
+        assert!(RangeCache::get(&cache, "s3://a", "v1", 1010, 1020)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(!cache
+            .contains_range("s3://a", "v1", 1100, 1300)
+            .await
+            .unwrap());
+
+        // a new version of the file replaces the old one
+        RangeCache::put(&cache, "s3://a", "v2", 0, data.slice(..10))
+            .await
+            .unwrap();
+        assert!(!cache
+            .contains_range("s3://a", "v1", 1000, 1020)
+            .await
+            .unwrap());
+        assert!(cache.contains_range("s3://a", "v2", 0, 10).await.unwrap());
+        RangeCache::evict(&cache, "s3://a").await.unwrap();
+        assert!(RangeCache::get(&cache, "s3://a", "v2", 0, 10)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(cache.used(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_disk_range_cache_concurrent_puts() {
+        let dir = std::env::temp_dir().join(format!("rottnest_disk_puts_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // two caches on one directory stand in for two processes
+        let caches = [
+            DiskCache::new(&dir, 1 << 20, 100).unwrap(),
+            DiskCache::new(&dir, 1 << 20, 100).unwrap(),
+        ];
+        let puts = (0..64u64).map(|i| {
+            let cache = caches[i as usize % 2].clone();
+            tokio::spawn(async move {
+                RangeCache::put(
+                    &cache,
+                    "s3://a",
+                    "v1",
+                    i * 100,
+                    Bytes::from(vec![i as u8; 100]),
+                )
+                .await
+            })
+        });
+        for put in futures::future::join_all(puts).await {
+            put.unwrap().unwrap();
+        }
+        for cache in &caches {
+            for i in 0..64u64 {
+                assert!(cache
+                    .contains_range("s3://a", "v1", i * 100, i * 100 + 100)
+                    .await
+                    .unwrap());
+            }
+            assert_eq!(
+                RangeCache::get(cache, "s3://a", "v1", 4200, 4210)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+                vec![42u8; 10]
+            );
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
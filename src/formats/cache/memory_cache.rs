@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use super::range_cache::RangeCache;
+use crate::lava::error::LavaError;
+
+pub const DEFAULT_MEMORY_CACHE_CAPACITY: u64 = 1024 * 1024 * 1024;
+
+struct MemoryEntry {
+    version: String,
+    // start -> (data, tick of the last use)
+    ranges: BTreeMap<u64, (Bytes, u64)>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    files: HashMap<String, MemoryEntry>,
+    // tick of the last use -> (filename, start), the first entry is the least recently used
+    lru: BTreeMap<u64, (String, u64)>,
+    tick: u64,
+    used: u64,
+}
+
+impl MemoryState {
+    fn find(&self, filename: &str, version: &str, from: u64, to: u64) -> Option<(u64, u64)> {
+        let entry = self.files.get(filename)?;
+        if entry.version != version {
+            return None;
+        }
+        // ranges may overlap, so the closest start before `from` is not necessarily the one
+        entry
+            .ranges
+            .range(..=from)
+            .rev()
+            .find(|(start, (data, _))| **start + data.len() as u64 >= to)
+            .map(|(start, (_, tick))| (*start, *tick))
+    }
+
+    fn remove_file(&mut self, filename: &str) {
+        if let Some(entry) = self.files.remove(filename) {
+            for (data, tick) in entry.ranges.values() {
+                self.lru.remove(tick);
+                self.used -= data.len() as u64;
+            }
+        }
+    }
+
+    fn remove_range(&mut self, filename: &str, start: u64) {
+        let Some(entry) = self.files.get_mut(filename) else {
+            return;
+        };
+        if let Some((data, tick)) = entry.ranges.remove(&start) {
+            self.lru.remove(&tick);
+            self.used -= data.len() as u64;
+        }
+        if entry.ranges.is_empty() {
+            self.files.remove(filename);
+        }
+    }
+}
+
+/// A size-bounded range cache in the memory of this process, evicting the least recently used
+/// ranges first.
+pub struct MemoryRangeCache {
+    capacity: u64,
+    block_size: u64,
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryRangeCache {
+    pub fn new(capacity: u64, block_size: u64) -> Self {
+        Self {
+            capacity,
+            block_size,
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.state.lock().unwrap().used
+    }
+}
+
+#[async_trait]
+impl RangeCache for MemoryRangeCache {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    async fn get(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Option<Bytes>, LavaError> {
+        let mut state = self.state.lock().unwrap();
+        let Some((start, tick)) = state.find(filename, version, from, to) else {
+            return Ok(None);
+        };
+        state.tick += 1;
+        let new_tick = state.tick;
+        state.lru.remove(&tick);
+        state.lru.insert(new_tick, (filename.to_string(), start));
+        let (data, tick) = state
+            .files
+            .get_mut(filename)
+            .and_then(|entry| entry.ranges.get_mut(&start))
+            .unwrap();
+        *tick = new_tick;
+        Ok(Some(data.slice((from - start) as usize..(to - start) as usize)))
+    }
+
+    async fn put(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        data: Bytes,
+    ) -> Result<(), LavaError> {
+        if data.len() as u64 > self.capacity {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        if state
+            .files
+            .get(filename)
+            .is_some_and(|entry| entry.version != version)
+        {
+            state.remove_file(filename);
+        }
+        state.remove_range(filename, from);
+
+        state.tick += 1;
+        let tick = state.tick;
+        state.used += data.len() as u64;
+        state.lru.insert(tick, (filename.to_string(), from));
+        state
+            .files
+            .entry(filename.to_string())
+            .or_insert_with(|| MemoryEntry {
+                version: version.to_string(),
+                ranges: BTreeMap::new(),
+            })
+            .ranges
+            .insert(from, (data, tick));
+
+        while state.used > self.capacity {
+            let Some((_, (filename, start))) = state.lru.pop_first() else {
+                break;
+            };
+            state.remove_range(&filename, start);
+        }
+        Ok(())
+    }
+
+    async fn contains_range(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, LavaError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.find(filename, version, from, to).is_some())
+    }
+
+    async fn evict(&self, filename: &str) -> Result<(), LavaError> {
+        self.state.lock().unwrap().remove_file(filename);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::MemoryRangeCache;
+    use crate::formats::cache::RangeCache;
+
+    #[tokio::test]
+    async fn test_memory_range_cache() {
+        let cache = MemoryRangeCache::new(250, 100);
+        let data = Bytes::from((0..100u8).collect::<Vec<u8>>());
+        cache.put("a", "v1", 0, data.clone()).await.unwrap();
+        cache.put("a", "v1", 100, data.clone()).await.unwrap();
+        assert_eq!(cache.get("a", "v1", 10, 20).await.unwrap().unwrap(), data.slice(10..20));
+        assert_eq!(cache.get("a", "v1", 110, 200).await.unwrap().unwrap(), data.slice(10..));
+        // ranges are not stitched together, and other versions miss
+        assert!(!cache.contains_range("a", "v1", 90, 110).await.unwrap());
+        assert!(cache.get("a", "v2", 10, 20).await.unwrap().is_none());
+
+        // the range at 100 is the least recently used one
+        cache.get("a", "v1", 0, 1).await.unwrap();
+        cache.put("b", "v1", 0, data.clone()).await.unwrap();
+        assert_eq!(cache.used(), 200);
+        assert!(cache.contains_range("a", "v1", 0, 100).await.unwrap());
+        assert!(!cache.contains_range("a", "v1", 100, 200).await.unwrap());
+
+        // a new version replaces the old one
+        cache.put("a", "v2", 0, data.slice(..50)).await.unwrap();
+        assert!(!cache.contains_range("a", "v1", 0, 10).await.unwrap());
+        assert_eq!(cache.used(), 150);
+
+        cache.evict("b").await.unwrap();
+        assert!(cache.get("b", "v1", 0, 10).await.unwrap().is_none());
+        assert_eq!(cache.used(), 50);
+    }
+}
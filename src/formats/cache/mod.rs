@@ -1,7 +1,21 @@
 mod redis_client;
+mod disk_cache;
+mod memory_cache;
+mod parquet_cache;
+mod range_cache;
+mod warming;
 
 mod cache;
 
 pub use cache::populate_cache;
-pub use redis_client::get_redis_connection;
+pub use disk_cache::DiskCache;
+pub use memory_cache::MemoryRangeCache;
+pub use parquet_cache::{
+    get_parquet_cache, set_parquet_cache, ParquetCache, DEFAULT_PARQUET_CACHE_CAPACITY,
+};
+pub use range_cache::{
+    get_range_cache, set_range_cache, CacheConfig, RangeCache, DEFAULT_CACHE_BLOCK_SIZE,
+};
 pub use redis_client::RedisConnection;
+pub use redis_client::RedisRangeCache;
+pub use warming::{plan_warmup, warm_cache};
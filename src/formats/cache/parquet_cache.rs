@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+
+use parquet::column::page::Page;
+use parquet::file::metadata::ParquetMetaData;
+
+pub const DEFAULT_PARQUET_CACHE_CAPACITY: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ParquetCacheKey {
+    // filename, version
+    Metadata(String, String),
+    // filename, version, row group, column
+    Dictionary(String, String, usize, usize),
+}
+
+#[derive(Clone)]
+enum ParquetCacheValue {
+    Metadata(Arc<ParquetMetaData>),
+    Dictionary(Page),
+}
+
+#[derive(Default)]
+struct ParquetCacheState {
+    // key -> (value, size, tick of the last use)
+    entries: HashMap<ParquetCacheKey, (ParquetCacheValue, u64, u64)>,
+    // tick of the last use -> key, the first entry is the least recently used
+    lru: BTreeMap<u64, ParquetCacheKey>,
+    tick: u64,
+    used: u64,
+}
+
+impl ParquetCacheState {
+    fn get(&mut self, key: &ParquetCacheKey) -> Option<ParquetCacheValue> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (value, _, last_use) = self.entries.get_mut(key)?;
+        self.lru.remove(last_use);
+        self.lru.insert(tick, key.clone());
+        *last_use = tick;
+        Some(value.clone())
+    }
+
+    fn remove(&mut self, key: &ParquetCacheKey) {
+        if let Some((_, size, tick)) = self.entries.remove(key) {
+            self.lru.remove(&tick);
+            self.used -= size;
+        }
+    }
+}
+
+/// Decoded parquet footers and dictionary pages, so that reads of the same files fetch and
+/// decode them once. Entries are only valid for the version of the file they were read from,
+/// like the ranges of a `RangeCache`. Size-bounded, evicting the least recently used entries
+/// first.
+pub struct ParquetCache {
+    capacity: u64,
+    state: Mutex<ParquetCacheState>,
+}
+
+impl ParquetCache {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(ParquetCacheState::default()),
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.state.lock().unwrap().used
+    }
+
+    fn get(&self, key: &ParquetCacheKey) -> Option<ParquetCacheValue> {
+        self.state.lock().unwrap().get(key)
+    }
+
+    fn put(&self, key: ParquetCacheKey, value: ParquetCacheValue, size: u64) {
+        if size > self.capacity {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+        state.tick += 1;
+        let tick = state.tick;
+        state.used += size;
+        state.lru.insert(tick, key.clone());
+        state.entries.insert(key, (value, size, tick));
+
+        while state.used > self.capacity {
+            let Some((_, key)) = state.lru.pop_first() else {
+                break;
+            };
+            state.remove(&key);
+        }
+    }
+
+    pub fn get_metadata(&self, filename: &str, version: &str) -> Option<Arc<ParquetMetaData>> {
+        let key = ParquetCacheKey::Metadata(filename.to_string(), version.to_string());
+        match self.get(&key)? {
+            ParquetCacheValue::Metadata(metadata) => Some(metadata),
+            ParquetCacheValue::Dictionary(_) => None,
+        }
+    }
+
+    pub fn put_metadata(&self, filename: &str, version: &str, metadata: Arc<ParquetMetaData>) {
+        let size = metadata.memory_size() as u64;
+        let key = ParquetCacheKey::Metadata(filename.to_string(), version.to_string());
+        self.put(key, ParquetCacheValue::Metadata(metadata), size);
+    }
+
+    /// The decoded dictionary page of `column` of `row_group`.
+    pub fn get_dictionary(
+        &self,
+        filename: &str,
+        version: &str,
+        row_group: usize,
+        column: usize,
+    ) -> Option<Page> {
+        let key = ParquetCacheKey::Dictionary(
+            filename.to_string(),
+            version.to_string(),
+            row_group,
+            column,
+        );
+        match self.get(&key)? {
+            ParquetCacheValue::Dictionary(page) => Some(page),
+            ParquetCacheValue::Metadata(_) => None,
+        }
+    }
+
+    pub fn put_dictionary(
+        &self,
+        filename: &str,
+        version: &str,
+        row_group: usize,
+        column: usize,
+        page: Page,
+    ) {
+        let size = page.buffer().len() as u64;
+        let key = ParquetCacheKey::Dictionary(
+            filename.to_string(),
+            version.to_string(),
+            row_group,
+            column,
+        );
+        self.put(key, ParquetCacheValue::Dictionary(page), size);
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PARQUET_CACHE: RwLock<Option<Arc<ParquetCache>>> = RwLock::new(Some(Arc::new(
+        ParquetCache::new(DEFAULT_PARQUET_CACHE_CAPACITY)
+    )));
+}
+
+/// The process wide cache of decoded parquet footers and dictionaries, one of
+/// `DEFAULT_PARQUET_CACHE_CAPACITY` bytes by default.
+pub fn get_parquet_cache() -> Option<Arc<ParquetCache>> {
+    PARQUET_CACHE.read().unwrap().clone()
+}
+
+/// Replaces the process wide parquet cache, None disables it.
+pub fn set_parquet_cache(cache: Option<Arc<ParquetCache>>) {
+    *PARQUET_CACHE.write().unwrap() = cache;
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use parquet::basic::Encoding;
+    use parquet::column::page::Page;
+
+    use super::ParquetCache;
+
+    fn dictionary(size: usize) -> Page {
+        Page::DictionaryPage {
+            buf: Bytes::from(vec![0u8; size]),
+            num_values: 1,
+            encoding: Encoding::PLAIN,
+            is_sorted: false,
+        }
+    }
+
+    #[test]
+    fn test_parquet_cache() {
+        let cache = ParquetCache::new(250);
+        cache.put_dictionary("a", "v1", 0, 0, dictionary(100));
+        cache.put_dictionary("a", "v1", 1, 0, dictionary(100));
+        assert!(cache.get_dictionary("a", "v1", 0, 0).is_some());
+        assert!(cache.get_dictionary("a", "v2", 0, 0).is_none());
+        assert!(cache.get_dictionary("a", "v1", 0, 1).is_none());
+
+        // row group 1 is the least recently used one
+        cache.put_dictionary("b", "v1", 0, 0, dictionary(100));
+        assert_eq!(cache.used(), 200);
+        assert!(cache.get_dictionary("a", "v1", 0, 0).is_some());
+        assert!(cache.get_dictionary("a", "v1", 1, 0).is_none());
+
+        // replacing an entry does not count it twice, too large entries are not cached
+        cache.put_dictionary("b", "v1", 0, 0, dictionary(50));
+        assert_eq!(cache.used(), 150);
+        cache.put_dictionary("c", "v1", 0, 0, dictionary(300));
+        assert!(cache.get_dictionary("c", "v1", 0, 0).is_none());
+        assert_eq!(cache.used(), 150);
+    }
+}
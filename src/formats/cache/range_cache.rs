@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use super::disk_cache::{DiskCache, DEFAULT_DISK_CACHE_BLOCK_SIZE, DEFAULT_DISK_CACHE_CAPACITY};
+use super::memory_cache::{MemoryRangeCache, DEFAULT_MEMORY_CACHE_CAPACITY};
+use super::redis_client::{
+    RedisRangeCache, DEFAULT_REDIS_POOL_SIZE, DEFAULT_REDIS_TTL_SECS, DEFAULT_REDIS_URL,
+};
+use crate::lava::error::LavaError;
+
+// small enough for the metadata of an index to not drag in much else, large enough for a
+// partly cached posting list to take few requests
+pub const DEFAULT_CACHE_BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// Caches byte ranges of files, e.g. the metadata pages of the indices a query is going to read.
+/// A file only has one version in the cache at a time: putting a range of a new version of the
+/// file drops the ranges of the old one, and lookups with another version miss.
+///
+/// Readers go through the cache in blocks of `block_size` bytes, aligned to the block size, so
+/// most of the ranges in the cache are blocks. `populate_cache` puts arbitrary ranges.
+#[async_trait]
+pub trait RangeCache: Send + Sync {
+    /// The name of the backend, as accepted by `CacheConfig::from_map`.
+    fn name(&self) -> &'static str;
+
+    fn block_size(&self) -> u64;
+
+    /// Returns [from, to) of `version` of `filename` if a cached range contains it.
+    async fn get(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Option<Bytes>, LavaError>;
+
+    /// Caches `data` as the range starting at `from` of `version` of `filename`.
+    async fn put(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        data: Bytes,
+    ) -> Result<(), LavaError>;
+
+    /// Whether a cached range contains [from, to) of `version` of `filename`.
+    async fn contains_range(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, LavaError>;
+
+    /// Removes all cached ranges of `filename`.
+    async fn evict(&self, filename: &str) -> Result<(), LavaError>;
+}
+
+/// The range in `ranges` that contains [from, to), if any.
+pub(crate) fn containing_range(ranges: &[(u64, u64)], from: u64, to: u64) -> Option<(u64, u64)> {
+    ranges
+        .iter()
+        .find(|(start, end)| *start <= from && to <= *end)
+        .copied()
+}
+
+/// Which range cache backend to use and how to set it up.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheConfig {
+    /// shared by all processes using the same Redis server
+    Redis {
+        url: String,
+        pool_size: usize,
+        block_size: u64,
+        /// seconds after which cached blocks expire
+        ttl: u64,
+    },
+    /// size-bounded LRU cache in the memory of this process
+    Memory { capacity: u64, block_size: u64 },
+    /// shared by all processes on this host using the same directory
+    Disk {
+        dir: String,
+        capacity: u64,
+        block_size: u64,
+    },
+}
+
+fn parse_option<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, LavaError> {
+    value
+        .parse::<T>()
+        .map_err(|_| LavaError::Parse(format!("invalid value for {}: {}", key, value)))
+}
+
+impl CacheConfig {
+    /// Builds the config of `backend` (redis, memory or disk) from string key/value pairs, e.g.
+    /// passed in from Python. All of them take `block_size_kb`, Redis takes `url`,
+    /// `pool_size` and `ttl_secs`, memory `capacity_mb`, disk `dir` and `capacity_mb`.
+    pub fn from_map(backend: &str, options: &HashMap<String, String>) -> Result<Self, LavaError> {
+        let mut config = match backend.to_lowercase().as_str() {
+            "redis" => CacheConfig::Redis {
+                url: DEFAULT_REDIS_URL.to_string(),
+                pool_size: DEFAULT_REDIS_POOL_SIZE,
+                block_size: DEFAULT_CACHE_BLOCK_SIZE,
+                ttl: DEFAULT_REDIS_TTL_SECS,
+            },
+            "memory" => CacheConfig::Memory {
+                capacity: DEFAULT_MEMORY_CACHE_CAPACITY,
+                block_size: DEFAULT_CACHE_BLOCK_SIZE,
+            },
+            "disk" => CacheConfig::Disk {
+                dir: String::new(),
+                capacity: DEFAULT_DISK_CACHE_CAPACITY,
+                block_size: DEFAULT_DISK_CACHE_BLOCK_SIZE,
+            },
+            _ => {
+                return Err(LavaError::Parse(format!(
+                    "cache backend must be redis, memory or disk, got {}",
+                    backend
+                )))
+            }
+        };
+        for (key, value) in options {
+            match (&mut config, key.to_lowercase().as_str()) {
+                (CacheConfig::Redis { url, .. }, "url") => *url = value.clone(),
+                (CacheConfig::Redis { pool_size, .. }, "pool_size") => {
+                    *pool_size = parse_option(key, value)?
+                }
+                (CacheConfig::Redis { ttl, .. }, "ttl_secs") => *ttl = parse_option(key, value)?,
+                (CacheConfig::Memory { capacity, .. }, "capacity_mb")
+                | (CacheConfig::Disk { capacity, .. }, "capacity_mb") => {
+                    *capacity = parse_option::<u64>(key, value)? * 1024 * 1024
+                }
+                (CacheConfig::Disk { dir, .. }, "dir") => *dir = value.clone(),
+                (CacheConfig::Redis { block_size, .. }, "block_size_kb")
+                | (CacheConfig::Memory { block_size, .. }, "block_size_kb")
+                | (CacheConfig::Disk { block_size, .. }, "block_size_kb") => {
+                    *block_size = parse_option::<u64>(key, value)? * 1024
+                }
+                _ => {
+                    return Err(LavaError::Parse(format!(
+                        "unknown option of the {} cache: {}",
+                        backend, key
+                    )))
+                }
+            }
+        }
+        match &config {
+            CacheConfig::Disk { dir, .. } if dir.is_empty() => {
+                Err(LavaError::Parse("the disk cache needs a dir".to_string()))
+            }
+            CacheConfig::Redis { block_size: 0, .. }
+            | CacheConfig::Memory { block_size: 0, .. }
+            | CacheConfig::Disk { block_size: 0, .. } => Err(LavaError::Parse(
+                "cache block size must be positive".to_string(),
+            )),
+            _ => Ok(config),
+        }
+    }
+
+    pub fn build(&self) -> Result<Arc<dyn RangeCache>, LavaError> {
+        Ok(match self {
+            CacheConfig::Redis {
+                url,
+                pool_size,
+                block_size,
+                ttl,
+            } => Arc::new(RedisRangeCache::new(url, *pool_size, *block_size, *ttl)?),
+            CacheConfig::Memory {
+                capacity,
+                block_size,
+            } => Arc::new(MemoryRangeCache::new(*capacity, *block_size)),
+            CacheConfig::Disk {
+                dir,
+                capacity,
+                block_size,
+            } => Arc::new(DiskCache::new(dir, *capacity, *block_size)?),
+        })
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref RANGE_CACHE: RwLock<Option<Arc<dyn RangeCache>>> = RwLock::new(
+        DiskCache::from_env()
+            .unwrap_or_else(|e| {
+                log::warn!("disabling disk cache: {}", e);
+                None
+            })
+            .map(|cache| Arc::new(cache) as Arc<dyn RangeCache>)
+    );
+}
+
+/// The process wide range cache used by new readers and `populate_cache`. By default this is
+/// the disk cache configured by the environment, see `DiskCache::from_env`, if any.
+pub fn get_range_cache() -> Option<Arc<dyn RangeCache>> {
+    RANGE_CACHE.read().unwrap().clone()
+}
+
+/// Replaces the process wide range cache, None disables it. Only affects readers created
+/// afterwards.
+pub fn set_range_cache(cache: Option<Arc<dyn RangeCache>>) {
+    *RANGE_CACHE.write().unwrap() = cache;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::CacheConfig;
+
+    #[test]
+    fn test_cache_config_from_map() {
+        let options = HashMap::from([
+            ("dir".to_string(), "/tmp/rottnest".to_string()),
+            ("capacity_mb".to_string(), "2".to_string()),
+        ]);
+        assert_eq!(
+            CacheConfig::from_map("disk", &options).unwrap(),
+            CacheConfig::Disk {
+                dir: "/tmp/rottnest".to_string(),
+                capacity: 2 * 1024 * 1024,
+                block_size: super::DEFAULT_DISK_CACHE_BLOCK_SIZE,
+            }
+        );
+        assert!(CacheConfig::from_map("memory", &options).is_err());
+        assert!(CacheConfig::from_map("disk", &HashMap::new()).is_err());
+        assert!(CacheConfig::from_map("memcached", &HashMap::new()).is_err());
+    }
+}
@@ -1,9 +1,60 @@
 use crate::lava::error::LavaError;
+use async_trait::async_trait;
+use bytes::Bytes;
 use redis::aio::MultiplexedConnection;
 use std::{
-    env,
+    future::Future,
     ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
 };
+use tokio::sync::Mutex;
+
+use super::range_cache::{containing_range, RangeCache};
+
+pub const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1:6379";
+pub const DEFAULT_REDIS_POOL_SIZE: usize = 4;
+// cached blocks of files that are never read again must not stay on the server forever
+pub const DEFAULT_REDIS_TTL_SECS: u64 = 7 * 24 * 3600;
+
+lazy_static::lazy_static! {
+    /// Drops everything cached for another version of the file, if any, records the version
+    /// and adds the block. The ranges are a set, so concurrent puts never lose each other's.
+    /// The keys of the old version follow the layout of `ranges_key` and `data_key`.
+    /// KEYS: version key, ranges key, data key. ARGV: filename, version, range, data, expiry.
+    static ref PUT_SCRIPT: redis::Script = redis::Script::new(
+        r"
+        local current = redis.call('GET', KEYS[1])
+        if current and current ~= ARGV[2] then
+            local old_ranges = ARGV[1] .. ':' .. current .. ':ranges'
+            for _, range in ipairs(redis.call('SMEMBERS', old_ranges)) do
+                redis.call('DEL', ARGV[1] .. ':' .. current .. ':' .. range)
+            end
+            redis.call('DEL', old_ranges)
+        end
+        redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[5])
+        redis.call('SET', KEYS[3], ARGV[4], 'EX', ARGV[5])
+        redis.call('SADD', KEYS[2], ARGV[3])
+        redis.call('EXPIRE', KEYS[2], ARGV[5])
+        return 1
+        "
+    );
+
+    /// Removes the version, the ranges and the blocks of a file.
+    /// KEYS: version key. ARGV: filename.
+    static ref INVALIDATE_SCRIPT: redis::Script = redis::Script::new(
+        r"
+        local current = redis.call('GET', KEYS[1])
+        if current then
+            local ranges = ARGV[1] .. ':' .. current .. ':ranges'
+            for _, range in ipairs(redis.call('SMEMBERS', ranges)) do
+                redis.call('DEL', ARGV[1] .. ':' .. current .. ':' .. range)
+            end
+            redis.call('DEL', ranges, KEYS[1])
+        end
+        return 1
+        "
+    );
+}
 
 #[derive(Debug, Clone)]
 pub struct RedisConnection {
@@ -24,100 +75,375 @@ impl DerefMut for RedisConnection {
     }
 }
 
-impl RedisConnection {
+fn version_key(filename: &str) -> String {
+    format!("{}:version", filename)
+}
 
-    pub async fn get_ranges(&mut self, filename: &str) -> Result<Vec<(usize, usize)>, LavaError> {
-        let key = format!("{}:range", filename);
-        let res: Vec<u8> = redis::cmd("GET")
-            .arg(key)
+fn ranges_key(filename: &str, version: &str) -> String {
+    format!("{}:{}:ranges", filename, version)
+}
+
+fn range_member(from: u64, to: u64) -> String {
+    format!("{}:{}", from, to)
+}
+
+fn data_key(filename: &str, version: &str, from: u64, to: u64) -> String {
+    format!("{}:{}:{}", filename, version, range_member(from, to))
+}
+
+fn parse_range_member(filename: &str, member: &str) -> Result<(usize, usize), LavaError> {
+    member
+        .split_once(':')
+        .and_then(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)))
+        .ok_or_else(|| {
+            LavaError::Parse(format!("corrupt cached range of {}: {}", filename, member))
+        })
+}
+
+/// Redis rejects an expiry of 0 seconds, a TTL of 0 expires the keys as soon as it can instead.
+fn expiry_secs(ttl: u64) -> u64 {
+    ttl.max(1)
+}
+
+/// [from, to) out of the data cached for [start, end), None if the server evicted or expired the
+/// data but not the ranges.
+fn slice_cached(data: Vec<u8>, start: u64, end: u64, from: u64, to: u64) -> Option<Bytes> {
+    if data.len() as u64 != end - start {
+        return None;
+    }
+    Some(Bytes::from(data).slice((from - start) as usize..(to - start) as usize))
+}
+
+/// Cached data is stored under the version of the file it was read from, so a file that is
+/// rewritten (e.g. by a merge that reuses the index name) can't serve stale bytes. Every file
+/// has a version key, a set of the ranges cached for that version and a key per range, all of
+/// them expire after the TTL unless the file is written to again.
+impl RedisConnection {
+    /// The version cached for `filename`, if any.
+    pub async fn get_version(&mut self, filename: &str) -> Result<Option<String>, LavaError> {
+        Ok(redis::cmd("GET")
+            .arg(version_key(filename))
             .query_async(self.deref_mut())
-            .await?;
-        let ranges = bincode::deserialize(&res)?;
-        Ok(ranges)
+            .await?)
     }
 
-    pub async fn set_ranges(
+    /// The ranges cached for `version` of `filename`, empty if nothing or another version of
+    /// the file is cached.
+    pub async fn get_ranges(
         &mut self,
         filename: &str,
-        ranges: &Vec<(usize, usize)>,
-    ) -> Result<(), LavaError> {
-        let key = format!("{}:range", filename);
-        let bytes = bincode::serialize(ranges)?;
-        redis::cmd("SET")
-            .arg(key)
-            .arg(bytes)
+        version: &str,
+    ) -> Result<Vec<(usize, usize)>, LavaError> {
+        let members: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(ranges_key(filename, version))
             .query_async(self.deref_mut())
             .await?;
-        Ok(())
+        members
+            .iter()
+            .map(|member| parse_range_member(filename, member))
+            .collect()
     }
 
     pub async fn get_data(
         &mut self,
         filename: &str,
+        version: &str,
         from: u64,
         to: u64,
     ) -> Result<Vec<u8>, LavaError> {
-        let key = format!("{}:{}:{}", filename, from, to);
         let res: Vec<u8> = redis::cmd("GET")
-            .arg(key)
+            .arg(data_key(filename, version, from, to))
             .query_async(self.deref_mut())
             .await?;
         Ok(res)
     }
 
-    pub async fn set_data(
+    /// Caches [from, from + data.len()) of `version` of `filename` for `ttl` seconds, dropping
+    /// the data of any other version of the file.
+    pub async fn put_data(
         &mut self,
         filename: &str,
+        version: &str,
         from: u64,
-        to: u64,
-        data: Vec<u8>,
+        data: &[u8],
+        ttl: u64,
     ) -> Result<(), LavaError> {
-        let key = format!("{}:{}:{}", filename, from, to);
-        redis::cmd("SET")
-            .arg(key)
+        let to = from + data.len() as u64;
+        PUT_SCRIPT
+            .key(version_key(filename))
+            .key(ranges_key(filename, version))
+            .key(data_key(filename, version, from, to))
+            .arg(filename)
+            .arg(version)
+            .arg(range_member(from, to))
             .arg(data)
-            .query_async(self.deref_mut())
+            .arg(expiry_secs(ttl))
+            .invoke_async::<()>(self.deref_mut())
             .await?;
         Ok(())
     }
+
+    /// Removes everything cached for `filename`, of any version.
+    pub async fn invalidate(&mut self, filename: &str) -> Result<(), LavaError> {
+        INVALIDATE_SCRIPT
+            .key(version_key(filename))
+            .arg(filename)
+            .invoke_async::<()>(self.deref_mut())
+            .await?;
+        Ok(())
+    }
+}
+
+/// Range cache on a Redis server. Keeps a small pool of multiplexed connections, opened on first
+/// use and shared by all readers. A connection that fails with an I/O error is dropped and
+/// reopened by the next request that gets its slot. Cached blocks expire after `ttl` seconds.
+pub struct RedisRangeCache {
+    client: redis::Client,
+    block_size: u64,
+    ttl: u64,
+    pool: Vec<Mutex<Option<RedisConnection>>>,
+    next: AtomicUsize,
+}
+
+impl RedisRangeCache {
+    pub fn new(url: &str, pool_size: usize, block_size: u64, ttl: u64) -> Result<Self, LavaError> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            block_size,
+            ttl,
+            pool: (0..pool_size.max(1)).map(|_| Mutex::new(None)).collect(),
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// A connection from the pool and its slot, connecting if needed.
+    pub async fn connection(&self) -> Result<(usize, RedisConnection), LavaError> {
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % self.pool.len();
+        let mut conn = self.pool[slot].lock().await;
+        if conn.is_none() {
+            *conn = Some(RedisConnection {
+                conn: self.client.get_multiplexed_tokio_connection().await?,
+            });
+        }
+        Ok((slot, conn.clone().unwrap()))
+    }
+
+    async fn with_connection<T, F, Fut>(&self, f: F) -> Result<T, LavaError>
+    where
+        F: FnOnce(RedisConnection) -> Fut,
+        Fut: Future<Output = Result<T, LavaError>>,
+    {
+        let (slot, conn) = self.connection().await?;
+        let result = f(conn).await;
+        if let Err(LavaError::Redis(e)) = &result {
+            if e.is_io_error() || e.is_connection_dropped() {
+                *self.pool[slot].lock().await = None;
+            }
+        }
+        result
+    }
 }
 
-pub async fn get_redis_connection() -> Result<RedisConnection, LavaError> {
-    let host = env::var("REDIS_HOST").unwrap_or("127.0.0.1".to_string());
-    let port = env::var("REDIS_PORT")
-        .unwrap_or("6379".to_string())
-        .parse::<u16>()
-        .map_err(|e| LavaError::Parse(format!("{:?}", e)))?;
-    let client = redis::Client::open(format!("redis://{}:{}", host, port))?;
-    let conn = client.get_multiplexed_tokio_connection().await?;
-    Ok(RedisConnection { conn })
+fn to_u64_ranges(ranges: Vec<(usize, usize)>) -> Vec<(u64, u64)> {
+    ranges
+        .into_iter()
+        .map(|(start, end)| (start as u64, end as u64))
+        .collect()
+}
+
+#[async_trait]
+impl RangeCache for RedisRangeCache {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    async fn get(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Option<Bytes>, LavaError> {
+        self.with_connection(|mut conn| async move {
+            // blocks are found without going through the ranges
+            let data = conn.get_data(filename, version, from, to).await?;
+            if !data.is_empty() {
+                return Ok(Some(Bytes::from(data)));
+            }
+            let ranges = to_u64_ranges(conn.get_ranges(filename, version).await?);
+            let Some((start, end)) = containing_range(&ranges, from, to) else {
+                return Ok(None);
+            };
+            let data = conn.get_data(filename, version, start, end).await?;
+            Ok(slice_cached(data, start, end, from, to))
+        })
+        .await
+    }
+
+    async fn put(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        data: Bytes,
+    ) -> Result<(), LavaError> {
+        let ttl = self.ttl;
+        self.with_connection(|mut conn| async move {
+            conn.put_data(filename, version, from, &data, ttl).await
+        })
+        .await
+    }
+
+    async fn contains_range(
+        &self,
+        filename: &str,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<bool, LavaError> {
+        self.with_connection(|mut conn| async move {
+            let ranges = to_u64_ranges(conn.get_ranges(filename, version).await?);
+            let Some((start, end)) = containing_range(&ranges, from, to) else {
+                return Ok(false);
+            };
+            // the data of the range may have expired before the set of ranges
+            let exists: bool = redis::cmd("EXISTS")
+                .arg(data_key(filename, version, start, end))
+                .query_async(conn.deref_mut())
+                .await?;
+            Ok(exists)
+        })
+        .await
+    }
+
+    async fn evict(&self, filename: &str) -> Result<(), LavaError> {
+        self.with_connection(|mut conn| async move { conn.invalidate(filename).await })
+            .await
+    }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Arc;
+
+    fn cache() -> RedisRangeCache {
+        RedisRangeCache::new(DEFAULT_REDIS_URL, 1, 1024, 60).unwrap()
+    }
+
+    #[test]
+    fn test_redis_key_layout() {
+        assert_eq!(version_key("s3://a/b"), "s3://a/b:version");
+        assert_eq!(ranges_key("s3://a/b", "v1"), "s3://a/b:v1:ranges");
+        assert_eq!(data_key("s3://a/b", "v1", 0, 10), "s3://a/b:v1:0:10");
+        assert_eq!(
+            parse_range_member("s3://a/b", &range_member(10, 20)).unwrap(),
+            (10, 20)
+        );
+        assert!(parse_range_member("s3://a/b", "10").is_err());
+        assert!(parse_range_member("s3://a/b", "10:x").is_err());
+    }
+
+    #[test]
+    fn test_redis_expiry_and_slicing() {
+        assert_eq!(expiry_secs(0), 1);
+        assert_eq!(expiry_secs(60), 60);
+
+        let data: Vec<u8> = (0..10).collect();
+        assert_eq!(
+            slice_cached(data.clone(), 100, 110, 102, 105).unwrap(),
+            Bytes::from(vec![2, 3, 4])
+        );
+        // data that expired before its range is a miss
+        assert!(slice_cached(vec![], 100, 110, 102, 105).is_none());
+        assert!(slice_cached(data, 100, 120, 102, 105).is_none());
+    }
 
     #[tokio::test]
+    #[ignore = "needs a Redis server"]
     async fn test_redis_connection() {
-        let mut conn = get_redis_connection().await.unwrap();
-        let ranges = vec![(0, 10), (10, 20), (20, 30)];
-        conn.set_ranges("test", &ranges).await.unwrap();
-        let res = conn.get_ranges("test").await.unwrap();
-        assert_eq!(res, ranges);
+        let (_, mut conn) = cache().connection().await.unwrap();
+        conn.invalidate("test").await.unwrap();
+        for from in [0, 10, 20] {
+            conn.put_data("test", "v1", from, &[1; 10], 60)
+                .await
+                .unwrap();
+        }
+        let mut res = conn.get_ranges("test", "v1").await.unwrap();
+        res.sort_unstable();
+        assert_eq!(res, vec![(0, 10), (10, 20), (20, 30)]);
+        // entries of another version of the file are not served
+        assert!(conn.get_ranges("test", "v2").await.unwrap().is_empty());
+        // and are dropped when it is written
+        conn.put_data("test", "v2", 0, &[2; 10], 60).await.unwrap();
+        assert!(conn.get_ranges("test", "v1").await.unwrap().is_empty());
+        assert!(conn.get_data("test", "v1", 0, 10).await.unwrap().is_empty());
+        conn.invalidate("test").await.unwrap();
+        assert!(conn.get_version("test").await.unwrap().is_none());
+        assert!(conn.get_data("test", "v2", 0, 10).await.unwrap().is_empty());
     }
 
     #[tokio::test]
+    #[ignore = "needs a Redis server"]
     async fn test_redis_data() {
-        let mut conn = get_redis_connection().await.unwrap();
+        let (_, mut conn) = cache().connection().await.unwrap();
         let data = vec![1, 2, 3, 4, 5];
-        conn.set_data("test", 0, 5, data.clone()).await.unwrap();
-        let res = conn.get_data("test", 0, 5).await.unwrap();
+        conn.put_data("test_data", "v1", 0, &data, 60)
+            .await
+            .unwrap();
+        let res = conn.get_data("test_data", "v1", 0, 5).await.unwrap();
         assert_eq!(res, data);
+        let ttl: i64 = redis::cmd("TTL")
+            .arg(data_key("test_data", "v1", 0, 5))
+            .query_async(conn.deref_mut())
+            .await
+            .unwrap();
+        assert!(ttl > 0 && ttl <= 60);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server"]
+    async fn test_redis_concurrent_puts() {
+        let cache = Arc::new(cache());
+        cache.evict("test_concurrent").await.unwrap();
+        let puts = (0..32u64).map(|block| {
+            let cache = cache.clone();
+            async move {
+                cache
+                    .put(
+                        "test_concurrent",
+                        "v1",
+                        block * 8,
+                        Bytes::from(vec![block as u8; 8]),
+                    )
+                    .await
+            }
+        });
+        for result in futures::future::join_all(puts).await {
+            result.unwrap();
+        }
+        // no put lost the ranges of another
+        for block in 0..32u64 {
+            assert!(cache
+                .contains_range("test_concurrent", "v1", block * 8, block * 8 + 8)
+                .await
+                .unwrap());
+        }
+        cache.evict("test_concurrent").await.unwrap();
     }
 
     #[tokio::test]
+    #[ignore = "needs a Redis server"]
     async fn test_redis_key_non_exist() {
-        let mut conn = get_redis_connection().await.unwrap();
-        let res = conn.get_data("test_non_exists_key", 0, 5).await.unwrap();
+        let (_, mut conn) = cache().connection().await.unwrap();
+        let res = conn
+            .get_data("test_non_exists_key", "v1", 0, 5)
+            .await
+            .unwrap();
         assert_eq!(0, res.len());
     }
 }
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use crate::formats::readers::{coalesce_ranges, AccessRecord, StorageConfig};
+use crate::lava::error::LavaError;
+
+use super::cache::populate_cache;
+
+/// The bytes of [from, to) not covered by `ranges`, which must be sorted and disjoint.
+fn uncovered(ranges: &[(u64, u64)], from: u64, to: u64) -> u64 {
+    let covered: u64 = ranges
+        .iter()
+        .map(|&(start, end)| end.min(to).saturating_sub(start.max(from)))
+        .sum();
+    (to - from) - covered
+}
+
+/// Turns a recorded trace into the ranges to cache, per file. Ranges are rounded out to blocks of
+/// `block_size`, the unit the cache stores, and taken by how often they were read and then by
+/// size, smaller first, as long as the merged ranges of all files add up to at most
+/// `byte_budget` bytes. Ranges that do not fit are skipped, so smaller ranges read as often
+/// still make it in.
+pub fn plan_warmup(
+    trace: &[AccessRecord],
+    byte_budget: u64,
+    block_size: u64,
+) -> BTreeMap<String, Vec<(usize, usize)>> {
+    let block_size = block_size.max(1);
+    let mut records: Vec<&AccessRecord> = trace.iter().filter(|r| r.length > 0).collect();
+    records.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.length.cmp(&b.length))
+            .then_with(|| a.filename.cmp(&b.filename))
+            .then_with(|| a.offset.cmp(&b.offset))
+    });
+
+    let mut plan: BTreeMap<String, Vec<(u64, u64)>> = BTreeMap::new();
+    let mut planned = 0;
+    for record in records {
+        let from = record.offset / block_size * block_size;
+        let to = (record.offset + record.length).div_ceil(block_size) * block_size;
+        let ranges = plan.entry(record.filename.clone()).or_default();
+        let new_bytes = uncovered(ranges, from, to);
+        if planned + new_bytes > byte_budget {
+            continue;
+        }
+        planned += new_bytes;
+        ranges.push((from, to));
+        *ranges = coalesce_ranges(ranges, 0);
+    }
+
+    plan.into_iter()
+        .filter(|(_, ranges)| !ranges.is_empty())
+        .map(|(filename, ranges)| {
+            let ranges = ranges
+                .into_iter()
+                .map(|(from, to)| (from as usize, to as usize))
+                .collect();
+            (filename, ranges)
+        })
+        .collect()
+}
+
+/// Caches the ranges of `trace` picked by `plan_warmup` in the range cache of `storage_config`.
+/// Returns the plan that was run.
+pub fn warm_cache(
+    trace: &[AccessRecord],
+    byte_budget: u64,
+    storage_config: StorageConfig,
+) -> Result<BTreeMap<String, Vec<(usize, usize)>>, LavaError> {
+    let Some(range_cache) = storage_config.range_cache() else {
+        return Err(LavaError::Unsupported(
+            "warm_cache needs a range cache, none is configured".to_string(),
+        ));
+    };
+    let plan = plan_warmup(trace, byte_budget, range_cache.block_size());
+    populate_cache(plan.clone(), storage_config)?;
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use super::{plan_warmup, warm_cache};
+    use crate::formats::cache::{MemoryRangeCache, RangeCache};
+    use crate::formats::readers::{AccessRecord, StorageConfig};
+
+    fn record(filename: &str, offset: u64, length: u64, count: u64) -> AccessRecord {
+        AccessRecord {
+            filename: filename.to_string(),
+            offset,
+            length,
+            count,
+        }
+    }
+
+    #[test]
+    fn test_plan_warmup() {
+        let trace = vec![
+            record("a", 0, 100, 1),
+            record("a", 50, 100, 5),
+            record("b", 1000, 300, 5),
+            record("a", 120, 20, 3),
+            record("b", 0, 10, 2),
+        ];
+        // a 50..150, b 1000..1300 does not fit, a 120..140 is already covered, b 0..10 fits,
+        // what a 0..100 adds does not
+        let plan = plan_warmup(&trace, 150, 1);
+        assert_eq!(plan["a"], vec![(50, 150)]);
+        assert_eq!(plan["b"], vec![(0, 10)]);
+        // with room for everything, overlapping ranges are merged
+        let plan = plan_warmup(&trace, 1000, 1);
+        assert_eq!(plan["a"], vec![(0, 150)]);
+        assert_eq!(plan["b"], vec![(0, 10), (1000, 1300)]);
+        assert!(plan_warmup(&trace, 0, 1).is_empty());
+        // in blocks of 100, a 0..200 takes the whole budget
+        let plan = plan_warmup(&trace, 200, 100);
+        assert_eq!(plan["a"], vec![(0, 200)]);
+        assert!(!plan.contains_key("b"));
+    }
+
+    #[test]
+    fn test_warm_cache() {
+        let path = std::env::temp_dir().join(format!("rottnest_warm_{}", std::process::id()));
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        let filename = path.to_str().unwrap().to_string();
+
+        let range_cache = Arc::new(MemoryRangeCache::new(1 << 20, 100));
+        let storage_config = StorageConfig::default().with_range_cache(range_cache.clone());
+        let trace = vec![
+            record(&filename, 150, 20, 3),
+            record(&filename, 900, 100, 1),
+        ];
+        warm_cache(&trace, 150, storage_config).unwrap();
+
+        // the block around the hot range is cached, the other range did not fit the budget
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let version = runtime
+            .block_on(crate::formats::readers::get_reader(
+                filename.clone(),
+                Default::default(),
+            ))
+            .unwrap()
+            .version()
+            .unwrap();
+        let block = runtime
+            .block_on(range_cache.get(&filename, &version, 100, 200))
+            .unwrap();
+        assert_eq!(&block.unwrap()[..], &data[100..200]);
+        assert!(!runtime
+            .block_on(range_cache.contains_range(&filename, &version, 900, 1000))
+            .unwrap());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1096 @@
+//! Parquet modular encryption, see
+//! https://github.com/apache/parquet-format/blob/master/Encryption.md. Only decryption: footers,
+//! column metadata, page headers, pages and page indices encrypted with AES-GCM, and pages
+//! encrypted with AES-CTR.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use openssl::symm::{decrypt, decrypt_aead, encrypt_aead, Cipher};
+use parquet::errors::ParquetError;
+use parquet::format::{
+    ColumnCryptoMetaData, ColumnMetaData, CompressionCodec, EncryptionAlgorithm,
+    FileCryptoMetaData, FileMetaData,
+};
+use parquet::thrift::TSerializable;
+use thrift::protocol::{TCompactInputProtocol, TCompactOutputProtocol, TOutputProtocol};
+
+use crate::lava::error::LavaError;
+
+// module types, part of the AAD every module is authenticated with
+const FOOTER: u8 = 0;
+const COLUMN_META_DATA: u8 = 1;
+const DATA_PAGE: u8 = 2;
+const DICTIONARY_PAGE: u8 = 3;
+const DATA_PAGE_HEADER: u8 = 4;
+const DICTIONARY_PAGE_HEADER: u8 = 5;
+const COLUMN_INDEX: u8 = 6;
+const OFFSET_INDEX: u8 = 7;
+
+const LENGTH_LEN: usize = 4;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+// the first byte of a compact thrift FileCryptoMetaData, which starts with a struct, where a
+// FileMetaData starts with an i32
+const FILE_CRYPTO_METADATA_START: u8 = 0x1c;
+
+/// Looks up the key of a footer or column from the key metadata the file stores for it, e.g. the
+/// id of the key in a KMS.
+pub trait KeyRetriever: Send + Sync {
+    fn retrieve_key(&self, key_metadata: &[u8]) -> Result<Vec<u8>, LavaError>;
+}
+
+impl<F> KeyRetriever for F
+where
+    F: Fn(&[u8]) -> Result<Vec<u8>, LavaError> + Send + Sync,
+{
+    fn retrieve_key(&self, key_metadata: &[u8]) -> Result<Vec<u8>, LavaError> {
+        self(key_metadata)
+    }
+}
+
+/// The keys to read encrypted parquet files with. Keys given here are used first, the
+/// `key_retriever` is asked for the others. Columns are named by their dotted path, e.g.
+/// `payload.body`.
+#[derive(Clone, Default)]
+pub struct DecryptionProperties {
+    pub footer_key: Option<Vec<u8>>,
+    pub column_keys: HashMap<String, Vec<u8>>,
+    /// the AAD prefix of files written without storing it
+    pub aad_prefix: Option<Vec<u8>>,
+    pub key_retriever: Option<Arc<dyn KeyRetriever>>,
+}
+
+impl fmt::Debug for DecryptionProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut columns: Vec<_> = self.column_keys.keys().collect();
+        columns.sort();
+        f.debug_struct("DecryptionProperties")
+            .field("footer_key", &self.footer_key.is_some())
+            .field("column_keys", &columns)
+            .field("aad_prefix", &self.aad_prefix.is_some())
+            .field("key_retriever", &self.key_retriever.is_some())
+            .finish()
+    }
+}
+
+impl DecryptionProperties {
+    pub fn with_footer_key(mut self, key: Vec<u8>) -> Self {
+        self.footer_key = Some(key);
+        self
+    }
+
+    pub fn with_column_key(mut self, column: &str, key: Vec<u8>) -> Self {
+        self.column_keys.insert(column.to_string(), key);
+        self
+    }
+
+    pub fn with_aad_prefix(mut self, aad_prefix: Vec<u8>) -> Self {
+        self.aad_prefix = Some(aad_prefix);
+        self
+    }
+
+    pub fn with_key_retriever(mut self, key_retriever: Arc<dyn KeyRetriever>) -> Self {
+        self.key_retriever = Some(key_retriever);
+        self
+    }
+
+    /// Sets the key, or AAD prefix, of the string option `key`: `parquet.footer_key`,
+    /// `parquet.column_key.<column>` or `parquet.aad_prefix`, given in hex. False if `key` is
+    /// none of those.
+    pub fn set_option(&mut self, key: &str, value: &str) -> Result<bool, LavaError> {
+        let lowercase = key.to_lowercase();
+        if lowercase == "parquet.footer_key" {
+            self.footer_key = Some(parse_hex(key, value)?);
+        } else if lowercase == "parquet.aad_prefix" {
+            self.aad_prefix = Some(parse_hex(key, value)?);
+        } else if lowercase.starts_with("parquet.column_key.") {
+            self.column_keys.insert(
+                key["parquet.column_key.".len()..].to_string(),
+                parse_hex(key, value)?,
+            );
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn retrieve(
+        &self,
+        key: Option<&Vec<u8>>,
+        key_metadata: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>, LavaError> {
+        if let Some(key) = key {
+            return Ok(Some(key.clone()));
+        }
+        match (&self.key_retriever, key_metadata) {
+            (Some(key_retriever), Some(key_metadata)) => {
+                key_retriever.retrieve_key(key_metadata).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn footer_key(&self, key_metadata: Option<&[u8]>) -> Result<Option<Vec<u8>>, LavaError> {
+        self.retrieve(self.footer_key.as_ref(), key_metadata)
+    }
+
+    fn column_key(
+        &self,
+        column: &str,
+        key_metadata: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>, LavaError> {
+        self.retrieve(self.column_keys.get(column), key_metadata)
+    }
+}
+
+fn parse_hex(key: &str, value: &str) -> Result<Vec<u8>, LavaError> {
+    let invalid = || LavaError::Parse(format!("{} must be given in hex", key));
+    if !value.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            value
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    // everything encrypted with AES-GCM
+    Gcm,
+    // pages encrypted with AES-CTR, everything else with AES-GCM
+    GcmCtr,
+}
+
+fn gcm_cipher(key: &[u8]) -> Result<Cipher, LavaError> {
+    match key.len() {
+        16 => Ok(Cipher::aes_128_gcm()),
+        24 => Ok(Cipher::aes_192_gcm()),
+        32 => Ok(Cipher::aes_256_gcm()),
+        len => Err(LavaError::Parse(format!(
+            "AES keys are 16, 24 or 32 bytes long, not {}",
+            len
+        ))),
+    }
+}
+
+fn ctr_cipher(key: &[u8]) -> Result<Cipher, LavaError> {
+    match key.len() {
+        16 => Ok(Cipher::aes_128_ctr()),
+        24 => Ok(Cipher::aes_192_ctr()),
+        32 => Ok(Cipher::aes_256_ctr()),
+        len => Err(LavaError::Parse(format!(
+            "AES keys are 16, 24 or 32 bytes long, not {}",
+            len
+        ))),
+    }
+}
+
+/// Decrypts the module at the start of `bytes`: its length, a nonce, the ciphertext and, with
+/// GCM, the tag. Returns the length of the whole module and the plaintext.
+fn decrypt_module(
+    gcm: bool,
+    key: &[u8],
+    aad: &[u8],
+    bytes: &[u8],
+) -> Result<(usize, Vec<u8>), LavaError> {
+    let len = bytes
+        .get(..LENGTH_LEN)
+        .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+        .ok_or_else(|| LavaError::Parse("truncated encrypted module".to_string()))?;
+    let module_len = LENGTH_LEN + len;
+    let min_len = NONCE_LEN + if gcm { TAG_LEN } else { 0 };
+    if len < min_len || module_len > bytes.len() {
+        return Err(LavaError::Parse(format!(
+            "encrypted module of {} bytes does not fit in {}",
+            len,
+            bytes.len() - LENGTH_LEN
+        )));
+    }
+    let nonce = &bytes[LENGTH_LEN..LENGTH_LEN + NONCE_LEN];
+    let ciphertext = &bytes[LENGTH_LEN + NONCE_LEN..module_len];
+    let plaintext = if gcm {
+        let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - TAG_LEN);
+        decrypt_aead(gcm_cipher(key)?, key, Some(nonce), aad, ciphertext, tag)
+    } else {
+        // the counter of the first block is 1
+        let mut iv = nonce.to_vec();
+        iv.extend_from_slice(&1u32.to_be_bytes());
+        decrypt(ctr_cipher(key)?, key, Some(&iv), ciphertext)
+    }
+    .map_err(|_| {
+        LavaError::Parse("encrypted module does not decrypt, wrong key or corrupt file".to_string())
+    })?;
+    Ok((module_len, plaintext))
+}
+
+/// The AAD of a module: the file AAD, the module type, then the ordinals of the row group, the
+/// column and the page it belongs to, if any.
+fn module_aad(file_aad: &[u8], module_type: u8, ordinals: &[usize]) -> Result<Vec<u8>, LavaError> {
+    let mut aad = file_aad.to_vec();
+    aad.push(module_type);
+    for ordinal in ordinals {
+        let ordinal = i16::try_from(*ordinal).map_err(|_| {
+            LavaError::Unsupported(format!("encrypted files with ordinal {}", ordinal))
+        })?;
+        aad.extend_from_slice(&ordinal.to_le_bytes());
+    }
+    Ok(aad)
+}
+
+/// Which page of its column chunk a page is, part of the AAD of encrypted pages. Only data pages
+/// are numbered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PageModule {
+    Dictionary,
+    Data(usize),
+}
+
+impl PageModule {
+    /// The first page of `column`, the dictionary page if it has one.
+    pub(crate) fn first(has_dictionary_page: bool) -> Self {
+        match has_dictionary_page {
+            true => PageModule::Dictionary,
+            false => PageModule::Data(0),
+        }
+    }
+
+    pub(crate) fn next(self) -> Self {
+        match self {
+            PageModule::Dictionary => PageModule::Data(0),
+            PageModule::Data(ordinal) => PageModule::Data(ordinal + 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ColumnKey {
+    Plaintext,
+    Key(Vec<u8>),
+    // the dotted path of a column encrypted with a key that was not given
+    Missing(String),
+}
+
+/// How the columns of an encrypted file are decrypted.
+#[derive(Debug)]
+pub(crate) struct FileDecryptor {
+    algorithm: Algorithm,
+    file_aad: Vec<u8>,
+    columns: Vec<ColumnKey>,
+}
+
+impl FileDecryptor {
+    /// The decryptor of `column` of `row_group`, None if the column is not encrypted.
+    pub(crate) fn column(
+        &self,
+        row_group: usize,
+        column: usize,
+    ) -> Result<Option<ChunkDecryptor>, LavaError> {
+        match self.columns.get(column) {
+            None | Some(ColumnKey::Plaintext) => Ok(None),
+            Some(ColumnKey::Key(key)) => Ok(Some(ChunkDecryptor {
+                algorithm: self.algorithm,
+                key: key.clone(),
+                file_aad: self.file_aad.clone(),
+                row_group,
+                column,
+            })),
+            Some(ColumnKey::Missing(path)) => Err(LavaError::PermissionDenied(format!(
+                "no key to decrypt column {}",
+                path
+            ))),
+        }
+    }
+}
+
+/// Decrypts the modules of one encrypted column chunk.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkDecryptor {
+    algorithm: Algorithm,
+    key: Vec<u8>,
+    file_aad: Vec<u8>,
+    row_group: usize,
+    column: usize,
+}
+
+impl ChunkDecryptor {
+    fn aad(&self, module_type: u8, page: Option<usize>) -> Result<Vec<u8>, LavaError> {
+        let mut ordinals = vec![self.row_group, self.column];
+        ordinals.extend(page);
+        module_aad(&self.file_aad, module_type, &ordinals)
+    }
+
+    /// Decrypts the header of `page` at the start of `bytes`, returning the length of the
+    /// encrypted header and the plaintext one.
+    pub(crate) fn decrypt_page_header(
+        &self,
+        bytes: &[u8],
+        page: PageModule,
+    ) -> Result<(usize, Bytes), LavaError> {
+        let aad = match page {
+            PageModule::Dictionary => self.aad(DICTIONARY_PAGE_HEADER, None)?,
+            PageModule::Data(ordinal) => self.aad(DATA_PAGE_HEADER, Some(ordinal))?,
+        };
+        let (len, header) = decrypt_module(true, &self.key, &aad, bytes)?;
+        Ok((len, header.into()))
+    }
+
+    /// Decrypts `payload`, the whole body of `page` after its header.
+    pub(crate) fn decrypt_page(&self, payload: &[u8], page: PageModule) -> Result<Bytes, LavaError> {
+        let aad = match page {
+            PageModule::Dictionary => self.aad(DICTIONARY_PAGE, None)?,
+            PageModule::Data(ordinal) => self.aad(DATA_PAGE, Some(ordinal))?,
+        };
+        let (len, page) = decrypt_module(self.algorithm == Algorithm::Gcm, &self.key, &aad, payload)?;
+        if len != payload.len() {
+            return Err(LavaError::Parse(format!(
+                "encrypted page of {} bytes in a page of {}",
+                len,
+                payload.len()
+            )));
+        }
+        Ok(page.into())
+    }
+
+    pub(crate) fn decrypt_offset_index(&self, bytes: &[u8]) -> Result<Bytes, LavaError> {
+        let (_, index) = decrypt_module(true, &self.key, &self.aad(OFFSET_INDEX, None)?, bytes)?;
+        Ok(index.into())
+    }
+
+    pub(crate) fn decrypt_column_index(&self, bytes: &[u8]) -> Result<Bytes, LavaError> {
+        let (_, index) = decrypt_module(true, &self.key, &self.aad(COLUMN_INDEX, None)?, bytes)?;
+        Ok(index.into())
+    }
+}
+
+/// The algorithm of an encrypted file and its file AAD, the AAD prefix, stored or given, followed
+/// by the unique part stored in the file.
+fn file_aad(
+    algorithm: &EncryptionAlgorithm,
+    decryption: Option<&DecryptionProperties>,
+) -> Result<(Algorithm, Vec<u8>), LavaError> {
+    let (result, aad_prefix, aad_file_unique, supply_aad_prefix) = match algorithm {
+        EncryptionAlgorithm::AESGCMV1(gcm) => (
+            Algorithm::Gcm,
+            &gcm.aad_prefix,
+            &gcm.aad_file_unique,
+            gcm.supply_aad_prefix,
+        ),
+        EncryptionAlgorithm::AESGCMCTRV1(gcm_ctr) => (
+            Algorithm::GcmCtr,
+            &gcm_ctr.aad_prefix,
+            &gcm_ctr.aad_file_unique,
+            gcm_ctr.supply_aad_prefix,
+        ),
+    };
+    let given = decryption.and_then(|decryption| decryption.aad_prefix.as_ref());
+    let mut aad = match (aad_prefix, given) {
+        (Some(stored), Some(given)) if stored != given => {
+            return Err(LavaError::Parse(
+                "the AAD prefix given is not the one of the file".to_string(),
+            ))
+        }
+        (_, Some(prefix)) | (Some(prefix), None) => prefix.clone(),
+        (None, None) if supply_aad_prefix.unwrap_or(false) => {
+            return Err(LavaError::PermissionDenied(
+                "the file was written with an AAD prefix that must be given".to_string(),
+            ))
+        }
+        (None, None) => vec![],
+    };
+    aad.extend(aad_file_unique.iter().flatten());
+    Ok((result, aad))
+}
+
+fn serialize<T: TSerializable>(value: &T) -> Result<Vec<u8>, LavaError> {
+    let mut bytes = vec![];
+    let mut prot = TCompactOutputProtocol::new(&mut bytes);
+    value.write_to_out_protocol(&mut prot)?;
+    prot.flush()?;
+    Ok(bytes)
+}
+
+/// Decrypts the metadata of the columns of `metadata` that have their own, and returns the key
+/// of every column. The metadata of columns without a key is left as is, or, if the footer does
+/// not keep a plaintext copy, replaced by an empty one, so that the other columns can be read.
+fn decrypt_columns(
+    metadata: &mut FileMetaData,
+    file_aad: &[u8],
+    footer_key: Option<&[u8]>,
+    decryption: Option<&DecryptionProperties>,
+) -> Result<Vec<ColumnKey>, LavaError> {
+    let leaf_types: Vec<_> = metadata
+        .schema
+        .iter()
+        .skip(1)
+        .filter_map(|element| element.type_)
+        .collect();
+    let mut columns: Vec<Option<ColumnKey>> = vec![None; leaf_types.len()];
+    for (row_group, group) in metadata.row_groups.iter_mut().enumerate() {
+        for (column, chunk) in group.columns.iter_mut().enumerate() {
+            let not_in_schema = || {
+                LavaError::Parquet(ParquetError::General(format!(
+                    "row group {} has a column {}, the schema has {} columns",
+                    row_group,
+                    column,
+                    leaf_types.len()
+                )))
+            };
+            let key = match columns.get_mut(column).ok_or_else(not_in_schema)? {
+                Some(key) => key.clone(),
+                resolved => {
+                    let key = match &chunk.crypto_metadata {
+                        None => ColumnKey::Plaintext,
+                        Some(ColumnCryptoMetaData::ENCRYPTIONWITHFOOTERKEY(_)) => {
+                            match footer_key {
+                                Some(key) => ColumnKey::Key(key.to_vec()),
+                                None => ColumnKey::Missing(
+                                    chunk.meta_data.as_ref().map_or_else(
+                                        || format!("{}", column),
+                                        |meta_data| meta_data.path_in_schema.join("."),
+                                    ),
+                                ),
+                            }
+                        }
+                        Some(ColumnCryptoMetaData::ENCRYPTIONWITHCOLUMNKEY(column_key)) => {
+                            let path = column_key.path_in_schema.join(".");
+                            let key = match decryption {
+                                Some(decryption) => decryption
+                                    .column_key(&path, column_key.key_metadata.as_deref())?,
+                                None => None,
+                            };
+                            key.map_or(ColumnKey::Missing(path), ColumnKey::Key)
+                        }
+                    };
+                    *resolved = Some(key.clone());
+                    key
+                }
+            };
+            match (&key, &chunk.encrypted_column_metadata) {
+                (ColumnKey::Key(key), Some(encrypted)) => {
+                    let aad = module_aad(file_aad, COLUMN_META_DATA, &[row_group, column])?;
+                    let (_, plaintext) = decrypt_module(true, key, &aad, encrypted)
+                        .map_err(|e| e.context(format!("row group {}, column {}", row_group, column)))?;
+                    let mut prot = TCompactInputProtocol::new(plaintext.as_slice());
+                    chunk.meta_data = Some(ColumnMetaData::read_from_in_protocol(&mut prot)?);
+                }
+                (ColumnKey::Missing(path), _) if chunk.meta_data.is_none() => {
+                    chunk.meta_data = Some(ColumnMetaData {
+                        type_: *leaf_types.get(column).ok_or_else(not_in_schema)?,
+                        encodings: vec![],
+                        path_in_schema: path.split('.').map(|part| part.to_string()).collect(),
+                        codec: CompressionCodec::UNCOMPRESSED,
+                        num_values: 0,
+                        total_uncompressed_size: 0,
+                        total_compressed_size: 0,
+                        key_value_metadata: None,
+                        data_page_offset: 0,
+                        index_page_offset: None,
+                        dictionary_page_offset: None,
+                        statistics: None,
+                        encoding_stats: None,
+                        bloom_filter_offset: None,
+                        bloom_filter_length: None,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(columns
+        .into_iter()
+        .map(|key| key.unwrap_or(ColumnKey::Plaintext))
+        .collect())
+}
+
+/// Decrypts the footer `metadata_bytes` of an encrypted file, and the metadata of its encrypted
+/// columns, with the keys of `decryption`. Returns the plaintext footer, to decode as the one of
+/// an unencrypted file, and how to decrypt the columns, or None if the file is not encrypted.
+/// Files with an encrypted footer cannot be read without its key, files with a plaintext footer
+/// can, except for their encrypted columns.
+pub(crate) fn decrypt_file_metadata(
+    metadata_bytes: &[u8],
+    decryption: Option<&DecryptionProperties>,
+) -> Result<Option<(Vec<u8>, FileDecryptor)>, LavaError> {
+    let (mut metadata, algorithm, file_aad, footer_key) =
+        if metadata_bytes.first() == Some(&FILE_CRYPTO_METADATA_START) {
+            let mut encrypted_footer = metadata_bytes;
+            let mut prot = TCompactInputProtocol::new(&mut encrypted_footer);
+            let crypto_metadata = FileCryptoMetaData::read_from_in_protocol(&mut prot)?;
+            let (algorithm, file_aad) = file_aad(&crypto_metadata.encryption_algorithm, decryption)?;
+            let footer_key = match decryption {
+                Some(decryption) => decryption.footer_key(crypto_metadata.key_metadata.as_deref())?,
+                None => None,
+            }
+            .ok_or_else(|| {
+                LavaError::PermissionDenied("no key to decrypt the footer".to_string())
+            })?;
+            let (_, footer) = decrypt_module(
+                true,
+                &footer_key,
+                &module_aad(&file_aad, FOOTER, &[])?,
+                encrypted_footer,
+            )?;
+            let mut prot = TCompactInputProtocol::new(footer.as_slice());
+            let metadata = FileMetaData::read_from_in_protocol(&mut prot)?;
+            (metadata, algorithm, file_aad, Some(footer_key))
+        } else {
+            let mut prot = TCompactInputProtocol::new(metadata_bytes);
+            let metadata = FileMetaData::read_from_in_protocol(&mut prot)?;
+            let Some(encryption_algorithm) = &metadata.encryption_algorithm else {
+                return Ok(None);
+            };
+            let (algorithm, file_aad) = file_aad(encryption_algorithm, decryption)?;
+            let footer_key = match decryption {
+                Some(decryption) => {
+                    decryption.footer_key(metadata.footer_signing_key_metadata.as_deref())?
+                }
+                None => None,
+            };
+            // the footer is followed by its signature, the nonce and the tag of encrypting it
+            if let Some(key) = &footer_key {
+                let signature_start = metadata_bytes
+                    .len()
+                    .checked_sub(NONCE_LEN + TAG_LEN)
+                    .ok_or_else(|| LavaError::Parse("footer signature missing".to_string()))?;
+                let (footer, signature) = metadata_bytes.split_at(signature_start);
+                let (nonce, tag) = signature.split_at(NONCE_LEN);
+                let mut expected = [0u8; TAG_LEN];
+                encrypt_aead(
+                    gcm_cipher(key)?,
+                    key,
+                    Some(nonce),
+                    &module_aad(&file_aad, FOOTER, &[])?,
+                    footer,
+                    &mut expected,
+                )
+                .map_err(|e| LavaError::Parse(format!("cannot sign the footer: {}", e)))?;
+                if expected != tag {
+                    return Err(LavaError::Parse(
+                        "footer signature does not match, wrong key or tampered footer".to_string(),
+                    ));
+                }
+            }
+            (metadata, algorithm, file_aad, footer_key)
+        };
+
+    let columns = decrypt_columns(&mut metadata, &file_aad, footer_key.as_deref(), decryption)?;
+    Ok(Some((
+        serialize(&metadata)?,
+        FileDecryptor {
+            algorithm,
+            file_aad,
+            columns,
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ctr_cipher, decrypt_columns, gcm_cipher, module_aad, serialize, DecryptionProperties,
+        COLUMN_INDEX, COLUMN_META_DATA, DATA_PAGE, DATA_PAGE_HEADER, DICTIONARY_PAGE,
+        DICTIONARY_PAGE_HEADER, FOOTER, LENGTH_LEN, NONCE_LEN, OFFSET_INDEX, TAG_LEN,
+    };
+    use crate::formats::parquet::{
+        get_parquet_layout, get_parquet_page_layout, read_indexed_pages, read_rows,
+    };
+    use crate::formats::readers::StorageConfig;
+    use crate::lava::error::LavaError;
+    use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+    use openssl::rand::rand_bytes;
+    use openssl::symm::{encrypt, encrypt_aead};
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+    use parquet::format::{
+        AesGcmCtrV1, AesGcmV1, ColumnChunk, ColumnCryptoMetaData, EncryptionAlgorithm,
+        EncryptionWithColumnKey, EncryptionWithFooterKey, FieldRepetitionType, FileCryptoMetaData,
+        FileMetaData, OffsetIndex, PageHeader, PageType, RowGroup, SchemaElement, Type,
+    };
+    use parquet::thrift::TSerializable;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use thrift::protocol::TCompactInputProtocol;
+
+    const FOOTER_KEY: &[u8] = b"0123456789012345";
+    const TEXT_KEY: &[u8] = b"abcdefghijklmnopqrstuvwxyz012345";
+
+    fn encrypt_module(gcm: bool, key: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand_bytes(&mut nonce).unwrap();
+        let ciphertext = if gcm {
+            let mut tag = [0u8; TAG_LEN];
+            let mut ciphertext = encrypt_aead(
+                gcm_cipher(key).unwrap(),
+                key,
+                Some(&nonce),
+                aad,
+                plaintext,
+                &mut tag,
+            )
+            .unwrap();
+            ciphertext.extend_from_slice(&tag);
+            ciphertext
+        } else {
+            let mut iv = nonce.to_vec();
+            iv.extend_from_slice(&1u32.to_be_bytes());
+            encrypt(ctr_cipher(key).unwrap(), key, Some(&iv), plaintext).unwrap()
+        };
+        let mut module = ((NONCE_LEN + ciphertext.len()) as u32).to_le_bytes().to_vec();
+        module.extend_from_slice(&nonce);
+        module.extend_from_slice(&ciphertext);
+        module
+    }
+
+    fn read_thrift<T: TSerializable>(bytes: &[u8]) -> (T, usize) {
+        let mut cursor = bytes;
+        let value = T::read_from_in_protocol(&mut TCompactInputProtocol::new(&mut cursor)).unwrap();
+        (value, bytes.len() - cursor.len())
+    }
+
+    struct Encryption {
+        encrypted_footer: bool,
+        ctr: bool,
+        // whether the offset and column indices are kept
+        page_index: bool,
+        // the text column gets its own key
+        text_key: bool,
+    }
+
+    /// Writes the plaintext parquet file at `path` encrypted to `encrypted_path`, every column
+    /// with the footer key, except the text column if it has its own.
+    fn encrypt_file(path: &str, encrypted_path: &str, encryption: &Encryption) {
+        let data = std::fs::read(path).unwrap();
+        let footer_start = data.len() - 8;
+        let metadata_len =
+            u32::from_le_bytes(data[footer_start..footer_start + 4].try_into().unwrap()) as usize;
+        let (mut metadata, _): (FileMetaData, _) =
+            read_thrift(&data[footer_start - metadata_len..footer_start]);
+        let aad_file_unique = b"rottnest".to_vec();
+        let file_aad = aad_file_unique.clone();
+        let algorithm = match encryption.ctr {
+            true => EncryptionAlgorithm::AESGCMCTRV1(AesGcmCtrV1 {
+                aad_prefix: None,
+                aad_file_unique: Some(aad_file_unique),
+                supply_aad_prefix: None,
+            }),
+            false => EncryptionAlgorithm::AESGCMV1(AesGcmV1 {
+                aad_prefix: None,
+                aad_file_unique: Some(aad_file_unique),
+                supply_aad_prefix: None,
+            }),
+        };
+
+        let mut out = match encryption.encrypted_footer {
+            true => b"PARE".to_vec(),
+            false => b"PAR1".to_vec(),
+        };
+        for (row_group, group) in metadata.row_groups.iter_mut().enumerate() {
+            group.file_offset = None;
+            group.total_compressed_size = None;
+            for (column, chunk) in group.columns.iter_mut().enumerate() {
+                let aad = |module_type: u8, page: Option<usize>| {
+                    let mut ordinals = vec![row_group, column];
+                    ordinals.extend(page);
+                    module_aad(&file_aad, module_type, &ordinals).unwrap()
+                };
+                let mut meta_data = chunk.meta_data.clone().unwrap();
+                let path = meta_data.path_in_schema.join(".");
+                let own_key = encryption.text_key && path == "text";
+                let key = if own_key { TEXT_KEY } else { FOOTER_KEY };
+
+                let chunk_start = out.len();
+                let start = meta_data
+                    .dictionary_page_offset
+                    .unwrap_or(meta_data.data_page_offset) as usize;
+                let end = start + meta_data.total_compressed_size as usize;
+                let mut locations = vec![];
+                let mut position = start;
+                while position < end {
+                    let (mut header, header_len): (PageHeader, _) =
+                        read_thrift(&data[position..end]);
+                    let payload_start = position + header_len;
+                    position = payload_start + header.compressed_page_size as usize;
+                    let payload = &data[payload_start..position];
+                    let (header_aad, page_aad) = match header.type_ {
+                        PageType::DICTIONARY_PAGE => (
+                            aad(DICTIONARY_PAGE_HEADER, None),
+                            aad(DICTIONARY_PAGE, None),
+                        ),
+                        _ => (
+                            aad(DATA_PAGE_HEADER, Some(locations.len())),
+                            aad(DATA_PAGE, Some(locations.len())),
+                        ),
+                    };
+                    let payload = encrypt_module(!encryption.ctr, key, &page_aad, payload);
+                    header.compressed_page_size = payload.len() as i32;
+                    let header =
+                        encrypt_module(true, key, &header_aad, &serialize(&header).unwrap());
+                    match header_aad[file_aad.len()] {
+                        DICTIONARY_PAGE_HEADER => {
+                            meta_data.dictionary_page_offset = Some(out.len() as i64)
+                        }
+                        _ => {
+                            if locations.is_empty() {
+                                meta_data.data_page_offset = out.len() as i64;
+                            }
+                            locations.push((out.len(), header.len() + payload.len()));
+                        }
+                    }
+                    out.extend_from_slice(&header);
+                    out.extend_from_slice(&payload);
+                }
+                meta_data.total_compressed_size = (out.len() - chunk_start) as i64;
+                chunk.file_offset = chunk_start as i64;
+
+                let index = |offset: Option<i64>, length: Option<i32>| {
+                    let offset = offset.unwrap() as usize;
+                    &data[offset..offset + length.unwrap() as usize]
+                };
+                if encryption.page_index {
+                    let (mut offset_index, _): (OffsetIndex, _) =
+                        read_thrift(index(chunk.offset_index_offset, chunk.offset_index_length));
+                    for (location, (offset, size)) in
+                        offset_index.page_locations.iter_mut().zip(&locations)
+                    {
+                        location.offset = *offset as i64;
+                        location.compressed_page_size = *size as i32;
+                    }
+                    let offset_index = encrypt_module(
+                        true,
+                        key,
+                        &aad(OFFSET_INDEX, None),
+                        &serialize(&offset_index).unwrap(),
+                    );
+                    let column_index = encrypt_module(
+                        true,
+                        key,
+                        &aad(COLUMN_INDEX, None),
+                        index(chunk.column_index_offset, chunk.column_index_length),
+                    );
+                    chunk.offset_index_offset = Some(out.len() as i64);
+                    chunk.offset_index_length = Some(offset_index.len() as i32);
+                    out.extend_from_slice(&offset_index);
+                    chunk.column_index_offset = Some(out.len() as i64);
+                    chunk.column_index_length = Some(column_index.len() as i32);
+                    out.extend_from_slice(&column_index);
+                } else {
+                    chunk.offset_index_offset = None;
+                    chunk.offset_index_length = None;
+                    chunk.column_index_offset = None;
+                    chunk.column_index_length = None;
+                }
+
+                chunk.crypto_metadata = Some(match own_key {
+                    true => ColumnCryptoMetaData::ENCRYPTIONWITHCOLUMNKEY(EncryptionWithColumnKey {
+                        path_in_schema: meta_data.path_in_schema.clone(),
+                        key_metadata: Some(path.clone().into_bytes()),
+                    }),
+                    false => ColumnCryptoMetaData::ENCRYPTIONWITHFOOTERKEY(
+                        EncryptionWithFooterKey::new(),
+                    ),
+                });
+                if own_key || !encryption.encrypted_footer {
+                    chunk.encrypted_column_metadata = Some(encrypt_module(
+                        true,
+                        key,
+                        &aad(COLUMN_META_DATA, None),
+                        &serialize(&meta_data).unwrap(),
+                    ));
+                    // a plaintext footer keeps the metadata without the statistics
+                    meta_data.statistics = None;
+                }
+                chunk.meta_data = match own_key && encryption.encrypted_footer {
+                    true => None,
+                    false => Some(meta_data),
+                };
+            }
+        }
+
+        let footer_aad = module_aad(&file_aad, FOOTER, &[]).unwrap();
+        let footer = if encryption.encrypted_footer {
+            let crypto_metadata = FileCryptoMetaData {
+                encryption_algorithm: algorithm,
+                key_metadata: Some(b"footer".to_vec()),
+            };
+            let mut footer = serialize(&crypto_metadata).unwrap();
+            footer.extend(encrypt_module(
+                true,
+                FOOTER_KEY,
+                &footer_aad,
+                &serialize(&metadata).unwrap(),
+            ));
+            footer
+        } else {
+            metadata.encryption_algorithm = Some(algorithm);
+            metadata.footer_signing_key_metadata = Some(b"footer".to_vec());
+            let mut footer = serialize(&metadata).unwrap();
+            let signed = encrypt_module(true, FOOTER_KEY, &footer_aad, &footer);
+            footer.extend_from_slice(&signed[LENGTH_LEN..LENGTH_LEN + NONCE_LEN]);
+            footer.extend_from_slice(&signed[signed.len() - TAG_LEN..]);
+            footer
+        };
+        out.extend_from_slice(&footer);
+        out.extend_from_slice(&(footer.len() as u32).to_le_bytes());
+        out.extend_from_slice(match encryption.encrypted_footer {
+            true => b"PARE",
+            false => b"PAR1",
+        });
+        std::fs::write(encrypted_path, out).unwrap();
+    }
+
+    fn write_test_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "rottnest_encryption_{}_{}.parquet",
+            name,
+            std::process::id()
+        ));
+        let n = 3000;
+        let id: ArrayRef = Arc::new(Int64Array::from_iter_values(0..n as i64));
+        let text: ArrayRef = Arc::new(StringArray::from_iter_values(
+            (0..n).map(|i| format!("document {}", i % 700)),
+        ));
+        let batch = RecordBatch::try_from_iter(vec![("id", id), ("text", text)]).unwrap();
+        let props = WriterProperties::builder()
+            .set_data_page_row_count_limit(400)
+            .set_write_batch_size(400)
+            .set_max_row_group_size(1000)
+            .build();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn keys() -> StorageConfig {
+        StorageConfig::default().with_decryption(
+            DecryptionProperties::default()
+                .with_footer_key(FOOTER_KEY.to_vec())
+                .with_column_key("text", TEXT_KEY.to_vec()),
+        )
+    }
+
+    #[test]
+    fn test_encrypted_layout_and_reads() {
+        let path = write_test_file("plaintext");
+        let (arrays, layout) = get_parquet_layout("text", &path, StorageConfig::default()).unwrap();
+        let plaintext_rows = read_rows(
+            vec![path.clone(); 2],
+            vec![0, 2],
+            vec![(10, 20), (500, 900)],
+            vec!["id".to_string(), "text".to_string()],
+            StorageConfig::default(),
+            None,
+        )
+        .unwrap();
+
+        for (i, encryption) in [
+            Encryption {
+                encrypted_footer: true,
+                ctr: false,
+                page_index: true,
+                text_key: true,
+            },
+            Encryption {
+                encrypted_footer: false,
+                ctr: true,
+                page_index: false,
+                text_key: true,
+            },
+            Encryption {
+                encrypted_footer: true,
+                ctr: true,
+                page_index: false,
+                text_key: false,
+            },
+        ]
+        .iter()
+        .enumerate()
+        {
+            let encrypted_path = path.replace("plaintext", &format!("encrypted_{}", i));
+            encrypt_file(&path, &encrypted_path, encryption);
+
+            let (encrypted_arrays, encrypted_layout) =
+                get_parquet_layout("text", &encrypted_path, keys()).unwrap();
+            assert_eq!(encrypted_arrays, arrays);
+            assert_eq!(
+                encrypted_layout.data_page_num_rows,
+                layout.data_page_num_rows
+            );
+            assert_eq!(
+                encrypted_layout.row_group_data_pages,
+                layout.row_group_data_pages
+            );
+            assert_ne!(encrypted_layout.data_page_sizes, layout.data_page_sizes);
+            let page_layout = get_parquet_page_layout("text", &encrypted_path, keys()).unwrap();
+            assert_eq!(page_layout, encrypted_layout);
+
+            // every page, given the footer kept with the layout, as an index would
+            let mut row_groups = vec![];
+            for (row_group, num_pages) in encrypted_layout.row_group_data_pages.iter().enumerate()
+            {
+                row_groups.extend(vec![row_group; *num_pages]);
+            }
+            let num_pages = row_groups.len();
+            let pages = read_indexed_pages(
+                "text".to_string(),
+                vec![encrypted_path.clone(); num_pages],
+                row_groups,
+                encrypted_layout
+                    .data_page_offsets
+                    .iter()
+                    .map(|offset| *offset as u64)
+                    .collect(),
+                encrypted_layout.data_page_sizes.clone(),
+                encrypted_layout.dictionary_page_sizes.clone(),
+                keys(),
+                Some(HashMap::from([(
+                    encrypted_path.clone(),
+                    encrypted_layout.metadata_bytes.clone(),
+                )])),
+                Some(true),
+            )
+            .unwrap();
+            let values: Vec<String> = pages
+                .into_iter()
+                .flat_map(|data| {
+                    let array = StringArray::from(data);
+                    array.iter().map(|x| x.unwrap().to_string()).collect::<Vec<_>>()
+                })
+                .collect();
+            assert_eq!(
+                values,
+                (0..3000)
+                    .map(|i| format!("document {}", i % 700))
+                    .collect::<Vec<_>>()
+            );
+
+            let rows = read_rows(
+                vec![encrypted_path.clone(); 2],
+                vec![0, 2],
+                vec![(10, 20), (500, 900)],
+                vec!["id".to_string(), "text".to_string()],
+                keys(),
+                None,
+            )
+            .unwrap();
+            assert_eq!(rows, plaintext_rows);
+
+            std::fs::remove_file(encrypted_path).unwrap();
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encryption_keys() {
+        let path = write_test_file("keys");
+        let encrypted_path = path.replace("keys", "keys_encrypted");
+        let encryption = Encryption {
+            encrypted_footer: true,
+            ctr: false,
+            page_index: true,
+            text_key: true,
+        };
+        encrypt_file(&path, &encrypted_path, &encryption);
+        let (arrays, _) = get_parquet_layout("text", &path, StorageConfig::default()).unwrap();
+        let (id_arrays, _) = get_parquet_layout("id", &path, StorageConfig::default()).unwrap();
+
+        // keys from the key metadata of the file
+        let key_retriever = |key_metadata: &[u8]| match key_metadata {
+            b"footer" => Ok(FOOTER_KEY.to_vec()),
+            b"text" => Ok(TEXT_KEY.to_vec()),
+            _ => Err(LavaError::NotFound("no such key".to_string())),
+        };
+        let storage_config = StorageConfig::default().with_decryption(
+            DecryptionProperties::default().with_key_retriever(Arc::new(key_retriever)),
+        );
+        let (retrieved, _) = get_parquet_layout("text", &encrypted_path, storage_config).unwrap();
+        assert_eq!(retrieved, arrays);
+
+        // hex keys given as storage options, as from Python
+        let hex = |key: &[u8]| key.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let options = HashMap::from([
+            ("parquet.footer_key".to_string(), hex(FOOTER_KEY)),
+            ("parquet.column_key.text".to_string(), hex(TEXT_KEY)),
+        ]);
+        let storage_config = StorageConfig::from_map(Default::default(), &options).unwrap();
+        let (from_options, _) =
+            get_parquet_layout("text", &encrypted_path, storage_config).unwrap();
+        assert_eq!(from_options, arrays);
+
+        // nothing can be read without the footer key
+        assert!(matches!(
+            get_parquet_layout("id", &encrypted_path, StorageConfig::default()),
+            Err(LavaError::PermissionDenied(_))
+        ));
+        // columns with another key can be read without it, the column itself cannot
+        let footer_only = StorageConfig::default().with_decryption(
+            DecryptionProperties::default().with_footer_key(FOOTER_KEY.to_vec()),
+        );
+        let (id_read, _) = get_parquet_layout("id", &encrypted_path, footer_only.clone()).unwrap();
+        assert_eq!(id_read, id_arrays);
+        assert!(matches!(
+            get_parquet_layout("text", &encrypted_path, footer_only),
+            Err(LavaError::PermissionDenied(_))
+        ));
+        // a wrong key does not decrypt
+        let wrong_key = StorageConfig::default().with_decryption(
+            DecryptionProperties::default()
+                .with_footer_key(FOOTER_KEY.to_vec())
+                .with_column_key("text", FOOTER_KEY.to_vec()),
+        );
+        assert!(get_parquet_layout("text", &encrypted_path, wrong_key).is_err());
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(encrypted_path).unwrap();
+    }
+
+    #[test]
+    fn test_decrypt_columns_out_of_schema() {
+        let schema = vec![
+            SchemaElement::new(
+                None,
+                None,
+                None,
+                "schema".to_string(),
+                1,
+                None,
+                None,
+                None,
+                None,
+                None,
+            ),
+            SchemaElement::new(
+                Type::BYTE_ARRAY,
+                None,
+                FieldRepetitionType::OPTIONAL,
+                "text".to_string(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            ),
+        ];
+        let chunk = || ColumnChunk::new(None, 0, None, None, None, None, None, None, None);
+        // a corrupt footer with more column chunks than leaf columns
+        let row_group = RowGroup::new(vec![chunk(), chunk()], 0, 0, None, None, None, None);
+        let mut metadata =
+            FileMetaData::new(1, schema, 0, vec![row_group], None, None, None, None, None);
+        assert!(matches!(
+            decrypt_columns(&mut metadata, b"", None, None),
+            Err(LavaError::Parquet(_))
+        ));
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::array::ArrayData;
+use arrow::buffer::Buffer;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow_array::{Array, ArrayRef};
+use arrow_ipc::{
+    convert::fb_to_schema,
+    reader::{read_footer_length, FileDecoder},
+    root_as_footer, Block, MetadataVersion,
+};
+use bytes::Bytes;
+use tokio::task::JoinSet;
+
+use super::block_layout::{group_blocks, BlockLayout};
+use super::parquet::leaf_array;
+use super::readers::{get_file_size_and_reader, get_reader, AsyncReader, StorageConfig};
+use crate::lava::error::LavaError;
+
+// the footer length and the magic after the footer
+const IPC_TRAILER_SIZE: usize = 10;
+
+/// What decoding the record batches of an Arrow IPC file takes, from its footer.
+struct IpcFooter {
+    schema: SchemaRef,
+    version: MetadataVersion,
+    dictionaries: Vec<Block>,
+    record_batches: Vec<Block>,
+}
+
+async fn get_footer_bytes(reader: &mut AsyncReader, file_size: usize) -> Result<Bytes, LavaError> {
+    if file_size < IPC_TRAILER_SIZE {
+        return Err(LavaError::Parse(format!(
+            "arrow ipc file {} is too small to hold a footer",
+            reader.filename
+        )));
+    }
+    let trailer = reader
+        .read_range((file_size - IPC_TRAILER_SIZE) as u64, file_size as u64)
+        .await?;
+    let trailer: [u8; IPC_TRAILER_SIZE] = trailer.as_ref().try_into().map_err(|_| {
+        LavaError::Parse(format!("short read of the footer of {}", reader.filename))
+    })?;
+    let footer_len =
+        read_footer_length(trailer).map_err(|e| LavaError::from(e).context(&reader.filename))?;
+    if footer_len == 0 || footer_len + IPC_TRAILER_SIZE > file_size {
+        return Err(LavaError::Parse(format!(
+            "arrow ipc file {} has a footer of {} bytes",
+            reader.filename, footer_len
+        )));
+    }
+    let end = (file_size - IPC_TRAILER_SIZE) as u64;
+    reader.read_range(end - footer_len as u64, end).await
+}
+
+fn decode_footer(file_path: &str, footer_bytes: &Bytes) -> Result<IpcFooter, LavaError> {
+    let footer = root_as_footer(footer_bytes).map_err(|e| {
+        LavaError::Parse(format!(
+            "invalid footer of arrow ipc file {}: {}",
+            file_path, e
+        ))
+    })?;
+    let schema = footer
+        .schema()
+        .ok_or_else(|| LavaError::Parse(format!("arrow ipc file {} has no schema", file_path)))?;
+    let blocks = |blocks: Vec<Block>| -> Result<Vec<Block>, LavaError> {
+        match blocks.iter().find(|block| {
+            block.offset() < 0 || block.metaDataLength() < 0 || block.bodyLength() < 0
+        }) {
+            Some(block) => Err(LavaError::Parse(format!(
+                "arrow ipc file {} has an invalid block {:?}",
+                file_path, block
+            ))),
+            None => Ok(blocks),
+        }
+    };
+    Ok(IpcFooter {
+        schema: Arc::new(fb_to_schema(schema)),
+        version: footer.version(),
+        dictionaries: blocks(
+            footer
+                .dictionaries()
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect(),
+        )?,
+        record_batches: blocks(
+            footer
+                .recordBatches()
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect(),
+        )?,
+    })
+}
+
+/// The range of `block` in the file, its message and body.
+fn block_range(block: &Block) -> (u64, u64) {
+    let start = block.offset() as u64;
+    (
+        start,
+        start + block.metaDataLength() as u64 + block.bodyLength() as u64,
+    )
+}
+
+/// The index of the top-level field `column_name` refers to and the path below it: its name, or
+/// a dotted path into a struct field, e.g. `payload.body`.
+fn find_field(schema: &Schema, column_name: &str) -> Result<(usize, Vec<String>), LavaError> {
+    if let Ok(index) = schema.index_of(column_name) {
+        return Ok((index, vec![]));
+    }
+    for (index, field) in schema.fields().iter().enumerate() {
+        if let Some(path) = column_name.strip_prefix(&format!("{}.", field.name())) {
+            return Ok((
+                index,
+                path.split('.').map(|name| name.to_string()).collect(),
+            ));
+        }
+    }
+    Err(LavaError::NotFound(format!(
+        "column {} not found in the arrow ipc schema",
+        column_name
+    )))
+}
+
+/// A decoder of the record batches of the file of `footer`, reading only field `column_index`,
+/// given the dictionary blocks of the footer.
+fn batch_decoder(
+    footer: &IpcFooter,
+    column_index: usize,
+    dictionaries: &[Bytes],
+) -> Result<FileDecoder, LavaError> {
+    let mut decoder =
+        FileDecoder::new(footer.schema.clone(), footer.version).with_projection(vec![column_index]);
+    for (block, bytes) in footer.dictionaries.iter().zip(dictionaries) {
+        decoder
+            .read_dictionary(block, &Buffer::from(bytes.as_ref()))
+            .map_err(|e| LavaError::from(e).context(format!("dictionary at {}", block.offset())))?;
+    }
+    Ok(decoder)
+}
+
+/// Decodes the values at `path` of the record batch `block`, with the bytes `bytes`.
+fn decode_batch(
+    decoder: &FileDecoder,
+    block: &Block,
+    bytes: &Bytes,
+    path: &[String],
+) -> Result<ArrayRef, LavaError> {
+    let batch = decoder
+        .read_record_batch(block, &Buffer::from(bytes.as_ref()))
+        .and_then(|batch| {
+            batch.ok_or_else(|| arrow::error::ArrowError::IpcError("no record batch".to_string()))
+        })
+        .map_err(|e| LavaError::from(e).context(format!("record batch at {}", block.offset())))?;
+    leaf_array(batch.column(0), path)
+}
+
+/// The layout of the Arrow IPC (Feather v2) file `file_path`, a block per record batch, and the
+/// values of column `column_name`, a top-level field or a dotted path into a struct field, batch
+/// by batch. Dictionary encoded values are decoded.
+#[tokio::main]
+pub async fn get_ipc_layout(
+    column_name: &str,
+    file_path: &str,
+    storage_config: StorageConfig,
+) -> Result<(Vec<ArrayData>, BlockLayout), LavaError> {
+    let (file_size, mut reader) =
+        get_file_size_and_reader(file_path.to_string(), storage_config).await?;
+    let footer_bytes = get_footer_bytes(&mut reader, file_size).await?;
+    let footer = decode_footer(file_path, &footer_bytes)?;
+    let (column_index, path) = find_field(&footer.schema, column_name)?;
+
+    let ranges: Vec<(u64, u64)> = footer
+        .dictionaries
+        .iter()
+        .chain(&footer.record_batches)
+        .map(block_range)
+        .collect();
+    let buffers = if ranges.is_empty() {
+        vec![]
+    } else {
+        reader.read_ranges(&ranges).await?
+    };
+    let (dictionaries, batches) = buffers.split_at(footer.dictionaries.len());
+    let decoder = batch_decoder(&footer, column_index, dictionaries)
+        .map_err(|e| e.context(format!("arrow ipc file {}", file_path)))?;
+
+    let mut layout = BlockLayout {
+        metadata_bytes: footer_bytes.clone(),
+        block_offsets: vec![],
+        block_sizes: vec![],
+        block_num_rows: vec![],
+    };
+    let mut arrays = Vec::with_capacity(batches.len());
+    for (block, bytes) in footer.record_batches.iter().zip(batches) {
+        let array = decode_batch(&decoder, block, bytes, &path)
+            .map_err(|e| e.context(format!("arrow ipc file {}", file_path)))?;
+        let (start, end) = block_range(block);
+        layout.block_offsets.push(start as usize);
+        layout.block_sizes.push((end - start) as usize);
+        layout.block_num_rows.push(array.len());
+        arrays.push(array.to_data());
+    }
+    Ok((arrays, layout))
+}
+
+/// Reads the record batches at `block_offsets` of `file_paths`, as in the layout of the files,
+/// and returns the values of column `column_name` of each, in the same order. The footers of the
+/// files are read unless given in `file_footers`, the `metadata_bytes` of their layouts.
+pub async fn read_indexed_batches_async(
+    column_name: String,
+    file_paths: Vec<String>,
+    block_offsets: Vec<u64>,
+    block_sizes: Vec<usize>,
+    storage_config: StorageConfig,
+    file_footers: Option<HashMap<String, Bytes>>,
+) -> Result<Vec<ArrayData>, LavaError> {
+    let num_blocks = file_paths.len();
+    let file_blocks = group_blocks(file_paths, block_offsets, block_sizes)?;
+    let mut file_footers = file_footers.unwrap_or_default();
+    let start_time = std::time::Instant::now();
+
+    let mut read_set = JoinSet::new();
+    for (file_path, blocks) in file_blocks {
+        let footer_bytes = file_footers.remove(&file_path);
+        let column_name = column_name.clone();
+        let storage_config = storage_config.clone();
+        read_set.spawn(async move {
+            let (footer_bytes, mut reader) = match footer_bytes {
+                Some(footer_bytes) => (
+                    footer_bytes,
+                    get_reader(file_path.clone(), storage_config).await?,
+                ),
+                None => {
+                    let (file_size, mut reader) =
+                        get_file_size_and_reader(file_path.clone(), storage_config).await?;
+                    (get_footer_bytes(&mut reader, file_size).await?, reader)
+                }
+            };
+            let footer = decode_footer(&file_path, &footer_bytes)?;
+            let (column_index, path) = find_field(&footer.schema, &column_name)?;
+
+            let batches: HashMap<u64, Block> = footer
+                .record_batches
+                .iter()
+                .map(|block| (block.offset() as u64, *block))
+                .collect();
+            let mut wanted = Vec::with_capacity(blocks.len());
+            for (idx, offset, size) in blocks {
+                match batches.get(&offset) {
+                    Some(block) if block_range(block) == (offset, offset + size as u64) => {
+                        wanted.push((idx, *block))
+                    }
+                    _ => {
+                        return Err(LavaError::Parse(format!(
+                            "no record batch of {} bytes at {} in arrow ipc file {}",
+                            size, offset, file_path
+                        )))
+                    }
+                }
+            }
+
+            let ranges: Vec<(u64, u64)> = footer
+                .dictionaries
+                .iter()
+                .chain(wanted.iter().map(|(_, block)| block))
+                .map(block_range)
+                .collect();
+            let buffers = reader.read_ranges(&ranges).await?;
+            let (dictionaries, batches) = buffers.split_at(footer.dictionaries.len());
+            let decoder = batch_decoder(&footer, column_index, dictionaries)
+                .map_err(|e| e.context(format!("arrow ipc file {}", file_path)))?;
+            let mut arrays = Vec::with_capacity(wanted.len());
+            for ((idx, block), bytes) in wanted.iter().zip(batches) {
+                let array = decode_batch(&decoder, block, bytes, &path)
+                    .map_err(|e| e.context(format!("arrow ipc file {}", file_path)))?;
+                arrays.push((*idx, array.to_data()));
+            }
+            Ok::<_, LavaError>(arrays)
+        });
+    }
+
+    let mut results: Vec<Option<ArrayData>> = vec![None; num_blocks];
+    while let Some(res) = read_set.join_next().await {
+        let arrays = res.map_err(|e| LavaError::Parse(format!("join error: {:?}", e)))??;
+        for (idx, data) in arrays {
+            results[idx] = Some(data);
+        }
+    }
+
+    storage_config.record_stage("read batches", start_time);
+
+    Ok(results.into_iter().map(|data| data.unwrap()).collect())
+}
+
+pub fn read_indexed_batches(
+    column_name: String,
+    file_paths: Vec<String>,
+    block_offsets: Vec<u64>,
+    block_sizes: Vec<usize>,
+    storage_config: StorageConfig,
+    file_footers: Option<HashMap<String, Bytes>>,
+) -> Result<Vec<ArrayData>, LavaError> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    let res = rt.block_on(read_indexed_batches_async(
+        column_name,
+        file_paths,
+        block_offsets,
+        block_sizes,
+        storage_config,
+        file_footers,
+    ));
+    rt.shutdown_background();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow::array::make_array;
+    use arrow::compute::{cast, concat};
+    use arrow::datatypes::{DataType, Field, Fields, Int32Type};
+    use arrow_array::{
+        Array, ArrayRef, DictionaryArray, Int32Array, RecordBatch, StringArray, StructArray,
+        UInt64Array,
+    };
+    use arrow_ipc::writer::{FileWriter, IpcWriteOptions};
+    use arrow_ipc::CompressionType;
+
+    use super::{get_ipc_layout, read_indexed_batches};
+    use crate::formats::readers::StorageConfig;
+    use crate::lava::{build_lava_uuid, search_lava_uuid};
+
+    #[test]
+    fn test_ipc_layout_and_read_batches() {
+        let path = std::env::temp_dir().join(format!("rottnest_ipc_{}.arrow", std::process::id()));
+        let file_path = path.to_str().unwrap().to_string();
+
+        let text = |batch: usize| -> Vec<String> {
+            (0..100 + batch)
+                .map(|i| format!("batch {} line {}", batch, i))
+                .collect()
+        };
+        let options = IpcWriteOptions::default()
+            .try_with_compression(Some(CompressionType::LZ4_FRAME))
+            .unwrap();
+        let mut writer = None;
+        for batch in 0..4 {
+            let lines = text(batch);
+            let body: ArrayRef = Arc::new(StringArray::from(lines.clone()));
+            // IPC files hold one dictionary per field, shared by all batches
+            let level = DictionaryArray::<Int32Type>::try_new(
+                Int32Array::from(vec![(batch % 2) as i32; lines.len()]),
+                Arc::new(StringArray::from(vec!["info", "warn"])),
+            )
+            .unwrap();
+            let payload: ArrayRef = Arc::new(StructArray::new(
+                Fields::from(vec![Field::new("body", DataType::Utf8, false)]),
+                vec![body],
+                None,
+            ));
+            let batch = RecordBatch::try_from_iter(vec![
+                ("level", Arc::new(level) as ArrayRef),
+                ("payload", payload),
+            ])
+            .unwrap();
+            writer
+                .get_or_insert_with(|| {
+                    let file = std::fs::File::create(&path).unwrap();
+                    FileWriter::try_new_with_options(file, &batch.schema(), options.clone())
+                        .unwrap()
+                })
+                .write(&batch)
+                .unwrap();
+        }
+        writer.unwrap().finish().unwrap();
+
+        let strings = |data: arrow::array::ArrayData| -> Vec<String> {
+            let array = make_array(data);
+            let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+            array.iter().map(|x| x.unwrap().to_string()).collect()
+        };
+        let (arrays, layout) =
+            get_ipc_layout("payload.body", &file_path, StorageConfig::default()).unwrap();
+        assert_eq!(layout.block_num_rows, vec![100, 101, 102, 103]);
+        for (batch, data) in arrays.into_iter().enumerate() {
+            assert_eq!(strings(data), text(batch));
+        }
+        let (arrays, _) = get_ipc_layout("level", &file_path, StorageConfig::default()).unwrap();
+        assert_eq!(strings(arrays[1].clone()), vec!["warn"; 101]);
+
+        // in the order asked for, with the footer read from the file or given
+        let wanted = [3, 0, 2];
+        let footers = HashMap::from([(file_path.clone(), layout.metadata_bytes.clone())]);
+        for file_footers in [None, Some(footers)] {
+            let arrays = read_indexed_batches(
+                "payload.body".to_string(),
+                wanted.iter().map(|_| file_path.clone()).collect(),
+                wanted
+                    .iter()
+                    .map(|i| layout.block_offsets[*i] as u64)
+                    .collect(),
+                wanted.iter().map(|i| layout.block_sizes[*i]).collect(),
+                StorageConfig::default(),
+                file_footers,
+            )
+            .unwrap();
+            for (batch, data) in wanted.iter().zip(arrays) {
+                assert_eq!(strings(data), text(*batch));
+            }
+        }
+        assert!(read_indexed_batches(
+            "payload.body".to_string(),
+            vec![file_path.clone()],
+            vec![layout.block_offsets[0] as u64 + 8],
+            vec![layout.block_sizes[0]],
+            StorageConfig::default(),
+            None,
+        )
+        .is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_index_ipc_file() {
+        let path = std::env::temp_dir().join(format!("rottnest_ipc_index_{}", std::process::id()));
+        let (file_path, index_path) = (
+            format!("{}.arrow", path.display()),
+            format!("{}.lava", path.display()),
+        );
+        let id = |batch: usize, i: usize| format!("{:016x}{:016x}", batch * 7919 + 1, i * 104729);
+
+        let mut writer = None;
+        for batch in 0..3 {
+            let ids: ArrayRef = Arc::new(StringArray::from(
+                (0..50).map(|i| id(batch, i)).collect::<Vec<_>>(),
+            ));
+            let batch = RecordBatch::try_from_iter(vec![("id", ids)]).unwrap();
+            writer
+                .get_or_insert_with(|| {
+                    let file = std::fs::File::create(&file_path).unwrap();
+                    FileWriter::try_new(file, &batch.schema()).unwrap()
+                })
+                .write(&batch)
+                .unwrap();
+        }
+        writer.unwrap().finish().unwrap();
+
+        // uids are the blocks of the layout, from 1 on as in the Python layouts
+        let (arrays, layout) = get_ipc_layout("id", &file_path, StorageConfig::default()).unwrap();
+        let arrays: Vec<ArrayRef> = arrays
+            .into_iter()
+            .map(|data| cast(&make_array(data), &DataType::LargeUtf8).unwrap())
+            .collect();
+        let values = concat(&arrays.iter().map(|x| x.as_ref()).collect::<Vec<_>>()).unwrap();
+        let uids = UInt64Array::from(
+            layout
+                .block_num_rows
+                .iter()
+                .enumerate()
+                .flat_map(|(block, num_rows)| vec![block as u64 + 1; *num_rows])
+                .collect::<Vec<_>>(),
+        );
+        build_lava_uuid(
+            index_path.clone(),
+            values.to_data(),
+            uids.to_data(),
+            StorageConfig::default(),
+        )
+        .unwrap();
+
+        let query = id(1, 42);
+        let hits = search_lava_uuid(
+            vec![index_path.clone()],
+            query.clone(),
+            10,
+            StorageConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(hits, vec![(0, 2)]);
+        let blocks: Vec<usize> = hits.iter().map(|(_, uid)| *uid as usize - 1).collect();
+        let arrays = read_indexed_batches(
+            "id".to_string(),
+            vec![file_path.clone(); blocks.len()],
+            blocks
+                .iter()
+                .map(|block| layout.block_offsets[*block] as u64)
+                .collect(),
+            blocks
+                .iter()
+                .map(|block| layout.block_sizes[*block])
+                .collect(),
+            StorageConfig::default(),
+            None,
+        )
+        .unwrap();
+        let array = make_array(arrays[0].clone());
+        let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+        assert!(array.iter().any(|value| value == Some(query.as_str())));
+
+        std::fs::remove_file(file_path).unwrap();
+        std::fs::remove_file(index_path).unwrap();
+    }
+}
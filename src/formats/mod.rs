@@ -1,9 +1,30 @@
 pub mod readers;
+pub mod writers;
 pub mod cache;
 pub mod parquet;
+pub mod encryption;
+pub mod predicate;
+pub mod ipc;
+pub mod text;
+mod block_layout;
 
 pub use parquet::get_parquet_layout;
+pub use parquet::get_parquet_page_layout;
+pub use parquet::stream_parquet_layout;
 pub use parquet::read_indexed_pages;
+pub use parquet::read_rows;
+pub use parquet::read_filtered_rows;
 pub use parquet::MatchResult;
 pub use parquet::ParquetLayout;
+pub use parquet::ParquetLayoutStream;
+pub use parquet::RowGroupLayout;
+pub use encryption::DecryptionProperties;
+pub use encryption::KeyRetriever;
+pub use predicate::Literal;
+pub use predicate::Predicate;
+pub use block_layout::BlockLayout;
+pub use ipc::get_ipc_layout;
+pub use ipc::read_indexed_batches;
+pub use text::get_text_layout;
+pub use text::read_indexed_lines;
 pub use cache::populate_cache;
\ No newline at end of file
@@ -1,29 +1,41 @@
-use arrow::array::ArrayData;
-use arrow::datatypes::ToByteSlice;
-use arrow::error::ArrowError;
-use arrow_array::{Array, BinaryArray, StringArray};
+use arrow::array::{make_array, new_empty_array, new_null_array, ArrayData, UInt64Array};
+use arrow::buffer::NullBuffer;
+use arrow::compute::{cast, concat, filter_record_batch, take};
+use arrow::datatypes::{DataType, Field, Schema, ToByteSlice};
+use arrow_array::{cast::AsArray, Array, ArrayRef, RecordBatch, RecordBatchOptions};
 
 use log::debug;
 use parquet::{
-    arrow::array_reader::make_byte_array_reader,
-    basic::{Encoding, Type},
-    column::page::Page,
+    arrow::{
+        array_reader::RowGroups,
+        arrow_reader::{ParquetRecordBatchReader, RowSelection, RowSelector},
+        parquet_to_arrow_field_levels, parquet_to_arrow_schema, parquet_to_arrow_schema_by_columns,
+        FieldLevels, ProjectionMask,
+    },
+    basic::{ColumnOrder, Encoding, Type},
+    column::page::{Page, PageIterator},
     compression::{create_codec, Codec, CodecOptionsBuilder},
+    encodings::rle::RleDecoder,
     errors::ParquetError,
     file::{
-        footer::{decode_footer, decode_metadata},
-        metadata::ParquetMetaData,
+        footer::decode_metadata,
+        metadata::{ColumnChunkMetaData, ParquetMetaData, RowGroupMetaData},
         reader::*,
         statistics, FOOTER_SIZE,
     },
-    format::{PageHeader, PageType},
+    format::{ColumnIndex, OffsetIndex, PageHeader, PageLocation, PageType},
+    schema::types::SchemaDescriptor,
     thrift::TSerializable,
-    util::InMemoryPageIterator,
+    util::{bit_util::num_required_bits, InMemoryPageIterator},
 };
 use thrift::protocol::TCompactInputProtocol;
 
 use bytes::Bytes;
-use std::{collections::BTreeMap, hash::Hash, io::Read};
+use std::{
+    collections::BTreeMap,
+    hash::{Hash, Hasher},
+    io::Read,
+};
 use std::{convert::TryFrom, sync::Arc};
 
 use futures::stream::{self, StreamExt};
@@ -33,12 +45,20 @@ use std::collections::HashMap;
 use tokio::{self};
 
 use crate::{
+    formats::cache::get_parquet_cache,
+    formats::encryption::{self, ChunkDecryptor, DecryptionProperties, FileDecryptor, PageModule},
     formats::readers::{get_file_size_and_reader, get_reader, AsyncReader},
     lava::error::LavaError,
 };
 
-use super::readers::ReaderType;
+use super::predicate::{intersect_rows, Literal, PageStats, Predicate, ValueStats};
+use super::readers::StorageConfig;
 use serde::{Deserialize, Serialize};
+
+/// Page headers are rejected past this size. They only get that large with huge statistics, or
+/// when the file is corrupt.
+const MAX_PAGE_HEADER_SIZE: usize = 16 * 1024 * 1024;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinSet;
 
 async fn get_metadata_bytes(
@@ -46,20 +66,34 @@ async fn get_metadata_bytes(
     file_size: usize,
 ) -> Result<Bytes, LavaError> {
     // check file is large enough to hold footer
+    if file_size < FOOTER_SIZE {
+        return Err(LavaError::Parse(format!(
+            "parquet file {} is too small to hold a footer",
+            reader.filename
+        )));
+    }
 
-    let footer: [u8; 8] = reader
-        .read_range(file_size as u64 - 8, file_size as u64)
-        .await?
-        .to_byte_slice()
-        .try_into()
-        .unwrap();
-
-    let metadata_len = decode_footer(&footer)?;
+    let footer = reader
+        .read_range((file_size - FOOTER_SIZE) as u64, file_size as u64)
+        .await?;
+    let footer: [u8; FOOTER_SIZE] = footer.to_byte_slice().try_into().map_err(|_| {
+        LavaError::Parse(format!("short read of the footer of {}", reader.filename))
+    })?;
+
+    // PARE ends files with an encrypted footer
+    if &footer[4..] != b"PAR1" && &footer[4..] != b"PARE" {
+        return Err(LavaError::Parse(format!(
+            "parquet file {} has a corrupt footer",
+            reader.filename
+        )));
+    }
+    let metadata_len = u32::from_le_bytes(footer[..4].try_into().unwrap()) as usize;
     let footer_metadata_len = FOOTER_SIZE + metadata_len;
 
-    if footer_metadata_len > file_size as usize {
-        return Err(LavaError::from(ParquetError::General(
-            "Invalid Parquet file. Size is smaller than footer".to_string(),
+    if footer_metadata_len > file_size {
+        return Err(LavaError::Parse(format!(
+            "parquet file {} is smaller than its footer says",
+            reader.filename
         )));
     }
 
@@ -71,6 +105,36 @@ async fn get_metadata_bytes(
     Ok(bytes)
 }
 
+/// Decodes the footer metadata `metadata_bytes` of `file_path`, decrypting it with the keys of
+/// `decryption` if the file is encrypted, in which case the decryptor of its columns is returned
+/// too.
+fn decode_file_metadata(
+    file_path: &str,
+    metadata_bytes: &Bytes,
+    decryption: Option<&DecryptionProperties>,
+) -> Result<(ParquetMetaData, Option<Arc<FileDecryptor>>), LavaError> {
+    let context = |e: LavaError| e.context(format!("metadata of parquet file {}", file_path));
+    match encryption::decrypt_file_metadata(metadata_bytes, decryption).map_err(context)? {
+        None => Ok((
+            decode_metadata(metadata_bytes.to_byte_slice())
+                .map_err(|e| context(LavaError::from(e)))?,
+            None,
+        )),
+        Some((metadata_bytes, decryptor)) => Ok((
+            decode_metadata(&metadata_bytes).map_err(|e| context(LavaError::from(e)))?,
+            Some(Arc::new(decryptor)),
+        )),
+    }
+}
+
+/// Whether pages of `page_type` are decoded, the others, e.g. index pages, are skipped.
+fn is_decoded(page_type: PageType) -> bool {
+    matches!(
+        page_type,
+        PageType::DICTIONARY_PAGE | PageType::DATA_PAGE | PageType::DATA_PAGE_V2
+    )
+}
+
 pub(crate) fn decode_page(
     page_header: PageHeader,
     buffer: Bytes,
@@ -81,29 +145,41 @@ pub(crate) fn decode_page(
     let mut can_decompress = true;
 
     if let Some(ref header_v2) = page_header.data_page_header_v2 {
-        offset = (header_v2.definition_levels_byte_length + header_v2.repetition_levels_byte_length)
-            as usize;
+        let levels_len = header_v2.definition_levels_byte_length as i64
+            + header_v2.repetition_levels_byte_length as i64;
+        if levels_len < 0
+            || levels_len as usize > buffer.len()
+            || levels_len > page_header.uncompressed_page_size as i64
+        {
+            return Err(LavaError::Parse(format!(
+                "levels of {} bytes do not fit in the page",
+                levels_len
+            )));
+        }
+        offset = levels_len as usize;
         // When is_compressed flag is missing the page is considered compressed
         can_decompress = header_v2.is_compressed.unwrap_or(true);
     }
 
-    // TODO: page header could be huge because of statistics. We should set a
-    // maximum page header size and abort if that is exceeded.
     let buffer = match decompressor {
         Some(decompressor) if can_decompress => {
             let uncompressed_size = page_header.uncompressed_page_size as usize;
             let mut decompressed = Vec::with_capacity(uncompressed_size);
-            let compressed = &buffer.as_ref()[offset..];
+            // the levels of a v2 page are never compressed, and a page of nulls has no values
             decompressed.extend_from_slice(&buffer.as_ref()[..offset]);
-            decompressor.decompress(
-                compressed,
-                &mut decompressed,
-                Some(uncompressed_size - offset),
-            )?;
+            if uncompressed_size > offset {
+                decompressor.decompress(
+                    &buffer.as_ref()[offset..],
+                    &mut decompressed,
+                    Some(uncompressed_size - offset),
+                )?;
+            }
 
             if decompressed.len() != uncompressed_size {
-                return Err(LavaError::from(ParquetError::General(
-                    "messed decompression".to_string(),
+                return Err(LavaError::Parse(format!(
+                    "page decompressed to {} bytes instead of {}",
+                    decompressed.len(),
+                    uncompressed_size
                 )));
             }
 
@@ -156,8 +232,10 @@ pub(crate) fn decode_page(
             }
         }
         _ => {
-            // For unknown page type (e.g., INDEX_PAGE), skip and read next.
-            unimplemented!("Page type {:?} is not supported", page_header.type_)
+            return Err(LavaError::Unsupported(format!(
+                "page type {:?}",
+                page_header.type_
+            )))
         }
     };
 
@@ -178,58 +256,423 @@ fn read_page_header<C: ChunkReader>(
         }
     }
 
-    let input = reader.get_read(offset)?;
+    let input = reader.get_read(offset)?.take(MAX_PAGE_HEADER_SIZE as u64);
     let mut tracked = TrackedRead(input, 0);
     let mut prot = TCompactInputProtocol::new(&mut tracked);
-    let header = PageHeader::read_from_in_protocol(&mut prot)?;
+    let header = match PageHeader::read_from_in_protocol(&mut prot) {
+        Ok(header) => header,
+        Err(_) if tracked.1 >= MAX_PAGE_HEADER_SIZE => {
+            return Err(LavaError::Parse(format!(
+                "page header larger than {} bytes",
+                MAX_PAGE_HEADER_SIZE
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if header.compressed_page_size < 0 || header.uncompressed_page_size < 0 {
+        return Err(LavaError::Parse(format!(
+            "negative page size in page header {:?}",
+            header
+        )));
+    }
     Ok((tracked.1, header))
 }
 
+/// Reads the header of `page` at `start` of `bytes`, a column chunk or part of one, decrypting it
+/// first if the chunk is encrypted. Returns the length of the header in the file with it.
+fn read_chunk_page_header(
+    bytes: &Bytes,
+    start: u64,
+    decryptor: Option<&ChunkDecryptor>,
+    page: PageModule,
+) -> Result<(usize, PageHeader), LavaError> {
+    let Some(decryptor) = decryptor else {
+        return read_page_header(bytes, start);
+    };
+    let (header_len, header) = decryptor.decrypt_page_header(&bytes[start as usize..], page)?;
+    let (_, header) = read_page_header(&header, 0)?;
+    Ok((header_len, header))
+}
+
+/// The body of `page` after its header, decrypted if the chunk is encrypted.
+fn page_payload(
+    payload: Bytes,
+    decryptor: Option<&ChunkDecryptor>,
+    page: PageModule,
+) -> Result<Bytes, LavaError> {
+    match decryptor {
+        Some(decryptor) => decryptor.decrypt_page(&payload, page),
+        None => Ok(payload),
+    }
+}
+
+/// The index of the leaf column `column_name` refers to: its dotted path, e.g. `payload.body` or
+/// `embedding.list.element`, the path of a field with exactly one leaf below it, e.g. `embedding`,
+/// or the name of the leaf if no other leaf has it.
+pub(crate) fn find_column(
+    schema_descr: &SchemaDescriptor,
+    column_name: &str,
+) -> Result<usize, LavaError> {
+    let columns = schema_descr.columns();
+    if let Some(index) = columns
+        .iter()
+        .position(|column| column.path().string() == column_name)
+    {
+        return Ok(index);
+    }
+    let prefix = format!("{}.", column_name);
+    let below: Vec<usize> = (0..columns.len())
+        .filter(|i| columns[*i].path().string().starts_with(&prefix))
+        .collect();
+    if let [index] = below[..] {
+        return Ok(index);
+    }
+    let named: Vec<usize> = (0..columns.len())
+        .filter(|i| columns[*i].name() == column_name)
+        .collect();
+    match named[..] {
+        [index] => Ok(index),
+        [] if below.is_empty() => Err(LavaError::NotFound(format!(
+            "column {} not found in the parquet schema",
+            column_name
+        ))),
+        _ => Err(LavaError::Parse(format!(
+            "column {} is ambiguous, use the dotted path of one of its leaves: {}",
+            column_name,
+            below
+                .iter()
+                .chain(named.iter())
+                .map(|i| columns[*i].path().string())
+                .join(", ")
+        ))),
+    }
+}
+
+/// The number of records starting in data page `page` of a column repeated up to
+/// `max_rep_level`, and whether the page starts with the end of a record of the previous page.
+/// Only v1 pages of repeated columns can split records, so only those have their repetition
+/// levels decoded.
+fn page_records(page: &Page, max_rep_level: i16) -> Result<(usize, bool), LavaError> {
+    let (buf, num_values) = match page {
+        Page::DataPageV2 { num_rows, .. } => return Ok((*num_rows as usize, false)),
+        Page::DataPage { num_values, .. } if max_rep_level == 0 => {
+            return Ok((*num_values as usize, false))
+        }
+        Page::DataPage {
+            buf,
+            num_values,
+            rep_level_encoding: Encoding::RLE,
+            ..
+        } => (buf, *num_values as usize),
+        Page::DataPage {
+            rep_level_encoding, ..
+        } => {
+            return Err(LavaError::Unsupported(format!(
+                "repetition levels encoded with {}",
+                rep_level_encoding
+            )))
+        }
+        Page::DictionaryPage { .. } => return Ok((0, false)),
+    };
+    // v1 levels are prefixed with their length
+    let len = buf
+        .get(..4)
+        .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+        .filter(|len| 4 + len <= buf.len())
+        .ok_or_else(|| LavaError::Parse("truncated repetition levels".to_string()))?;
+    let mut decoder = RleDecoder::new(num_required_bits(max_rep_level as u64));
+    decoder.set_data(buf.slice(4..4 + len));
+    let mut levels = vec![0i16; num_values];
+    if decoder.get_batch(&mut levels)? != num_values {
+        return Err(LavaError::Parse("truncated repetition levels".to_string()));
+    }
+    let num_records = levels.iter().filter(|level| **level == 0).count();
+    Ok((num_records, levels.first().is_some_and(|level| *level != 0)))
+}
+
+/// The decoded pages of the leaf column `column_index`, one Vec per column chunk, as row groups
+/// of a file with just that column.
+struct ColumnPages {
+    column_index: usize,
+    num_rows: usize,
+    pages: Vec<Vec<Page>>,
+}
+
+impl RowGroups for ColumnPages {
+    fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    fn column_chunks(&self, i: usize) -> parquet::errors::Result<Box<dyn PageIterator>> {
+        if i != self.column_index {
+            return Err(ParquetError::General(format!(
+                "only the pages of column {} are loaded, not of {}",
+                self.column_index, i
+            )));
+        }
+        Ok(Box::new(InMemoryPageIterator::new(self.pages.clone())))
+    }
+}
+
+/// Assembles the records of a leaf column from its pages. Leaves of structs decode to the array
+/// of the leaf, with the nulls of the structs they are in, leaves of lists (or maps) to the
+/// outermost list, one entry per record. The types are the ones of the Arrow schema the file was
+/// written with, if any, so e.g. LargeUtf8 columns stay LargeUtf8, except that dictionary
+/// encoded columns decode to their values.
+#[derive(Clone)]
+struct ColumnDecoder {
+    column_index: usize,
+    max_rep_level: i16,
+    levels: FieldLevels,
+    data_type: DataType,
+    // names of the fields below the top level one, leading to the leaf
+    path: Vec<String>,
+}
+
+impl ColumnDecoder {
+    fn new(metadata: &ParquetMetaData, column_index: usize) -> Result<Self, LavaError> {
+        let schema_descr = metadata.file_metadata().schema_descr();
+        let column_descr = schema_descr.column(column_index);
+        let schema = parquet_to_arrow_schema(
+            schema_descr,
+            metadata.file_metadata().key_value_metadata(),
+        )?;
+        let mask = ProjectionMask::leaves(schema_descr, [column_index]);
+        let levels = parquet_to_arrow_field_levels(schema_descr, mask.clone(), Some(schema.fields()))?;
+        let projected = parquet_to_arrow_schema_by_columns(
+            schema_descr,
+            mask,
+            metadata.file_metadata().key_value_metadata(),
+        )?;
+        let path = column_descr.path().parts()[1..].to_vec();
+        let empty = new_empty_array(projected.field(0).data_type());
+        let data_type = leaf_array(&empty, &path)?.data_type().clone();
+        Ok(Self {
+            column_index,
+            max_rep_level: column_descr.max_rep_level(),
+            levels,
+            data_type,
+            path,
+        })
+    }
+
+    /// Decodes the records of `pages` picked by `selection`, all of them if None, in batches of
+    /// `batch_size` records.
+    fn decode(
+        &self,
+        pages: Vec<Vec<Page>>,
+        num_rows: usize,
+        selection: Option<RowSelection>,
+        batch_size: usize,
+    ) -> Result<Vec<ArrayData>, LavaError> {
+        let pages = ColumnPages {
+            column_index: self.column_index,
+            num_rows,
+            pages,
+        };
+        let reader = ParquetRecordBatchReader::try_new_with_row_groups(
+            &self.levels,
+            &pages,
+            batch_size.max(1),
+            selection,
+        )?;
+        reader
+            .map(|batch| Ok(leaf_array(batch?.column(0), &self.path)?.to_data()))
+            .collect()
+    }
+
+    /// Decodes the records starting in the first data page of `pages`, which may continue into
+    /// the following ones, into one array.
+    fn decode_page(&self, pages: Vec<Page>) -> Result<ArrayData, LavaError> {
+        let first_page = pages
+            .iter()
+            .find(|page| !matches!(page, Page::DictionaryPage { .. }))
+            .ok_or_else(|| LavaError::Parse("no data page to decode".to_string()))?;
+        let (num_records, continued) = page_records(first_page, self.max_rep_level)?;
+        let num_rows = num_records + continued as usize;
+        // the end of a record of the previous page reads as a record of its own
+        let mut selectors = vec![];
+        if continued {
+            selectors.push(RowSelector::skip(1));
+        }
+        selectors.push(RowSelector::select(num_records));
+
+        let array = self.decode_selected(vec![pages], num_rows, selectors.into(), num_records)?;
+        Ok(array.to_data())
+    }
+
+    /// Decodes the `num_selected` records of `pages` picked by `selection` into one array.
+    fn decode_selected(
+        &self,
+        pages: Vec<Vec<Page>>,
+        num_rows: usize,
+        selection: RowSelection,
+        num_selected: usize,
+    ) -> Result<ArrayRef, LavaError> {
+        let arrays = self.decode(pages, num_rows, Some(selection), num_selected)?;
+        match arrays.len() {
+            0 => Ok(new_empty_array(&self.data_type)),
+            1 => Ok(make_array(arrays.into_iter().next().unwrap())),
+            _ => {
+                let arrays: Vec<ArrayRef> = arrays.into_iter().map(make_array).collect();
+                let arrays: Vec<&dyn Array> = arrays.iter().map(|array| array.as_ref()).collect();
+                Ok(concat(&arrays)?)
+            }
+        }
+    }
+}
+
+/// The array of the leaf at `path` below `array`: structs on the way are unwrapped, with their
+/// nulls applied to their fields, anything else, e.g. a list, is returned as is.
+pub(crate) fn leaf_array(array: &ArrayRef, path: &[String]) -> Result<ArrayRef, LavaError> {
+    match array.data_type() {
+        DataType::Struct(_) if !path.is_empty() => {
+            let array = array.as_struct();
+            let field = array.column_by_name(&path[0]).ok_or_else(|| {
+                LavaError::Parse(format!("field {} missing from the decoded struct", path[0]))
+            })?;
+            let field = match NullBuffer::union(array.nulls(), field.nulls()) {
+                Some(nulls) if field.data_type() != &DataType::Null => make_array(
+                    field.to_data().into_builder().nulls(Some(nulls)).build()?,
+                ),
+                _ => field.clone(),
+            };
+            leaf_array(&field, &path[1..])
+        }
+        DataType::Dictionary(_, value_type) => Ok(cast(array, value_type)?),
+        _ => Ok(array.clone()),
+    }
+}
+
+/// The decoded metadata of a parquet file, and the version of the file it is valid for: the
+/// version the reader looked up, or a hash of the footer if only the footer is known. Data cached
+/// for the file is cached under this version. Nothing of encrypted files is cached, so that a
+/// query only ever reads what its own keys decrypt.
+#[derive(Clone)]
+struct ParquetFile {
+    metadata: Arc<ParquetMetaData>,
+    version: String,
+    decryptor: Option<Arc<FileDecryptor>>,
+}
+
+impl ParquetFile {
+    /// The decryptor of `column` of `row_group`, None if it is not encrypted.
+    fn column_decryptor(
+        &self,
+        row_group: usize,
+        column: usize,
+    ) -> Result<Option<ChunkDecryptor>, LavaError> {
+        column_decryptor(self.decryptor.as_deref(), row_group, column)
+    }
+}
+
+fn column_decryptor(
+    decryptor: Option<&FileDecryptor>,
+    row_group: usize,
+    column: usize,
+) -> Result<Option<ChunkDecryptor>, LavaError> {
+    match decryptor {
+        Some(decryptor) => decryptor.column(row_group, column),
+        None => Ok(None),
+    }
+}
+
+/// The version of a file only known by its footer `metadata_bytes`.
+fn footer_version(metadata_bytes: &Bytes) -> String {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    metadata_bytes.hash(&mut hasher);
+    format!("footer-{:016x}", hasher.finish())
+}
+
+/// Decodes the footer `metadata_bytes` of `file_path`, or takes it from the parquet cache if
+/// `version` of the file was decoded before.
+fn decode_cached_metadata(
+    file_path: &str,
+    version: Option<String>,
+    metadata_bytes: &Bytes,
+    decryption: Option<&DecryptionProperties>,
+) -> Result<ParquetFile, LavaError> {
+    let version = version.unwrap_or_else(|| footer_version(metadata_bytes));
+    let parquet_cache = get_parquet_cache();
+    if let Some(metadata) = parquet_cache
+        .as_ref()
+        .and_then(|cache| cache.get_metadata(file_path, &version))
+    {
+        return Ok(ParquetFile {
+            metadata,
+            version,
+            decryptor: None,
+        });
+    }
+    let (metadata, decryptor) = decode_file_metadata(file_path, metadata_bytes, decryption)?;
+    let metadata = Arc::new(metadata);
+    if let (Some(cache), None) = (parquet_cache, &decryptor) {
+        cache.put_metadata(file_path, &version, metadata.clone());
+    }
+    Ok(ParquetFile {
+        metadata,
+        version,
+        decryptor,
+    })
+}
+
 async fn parse_metadatas(
     file_paths: &Vec<String>,
-    reader_type: ReaderType,
-) -> HashMap<String, ParquetMetaData> {
+    storage_config: StorageConfig,
+) -> Result<HashMap<String, ParquetFile>, LavaError> {
     let iter = file_paths.iter().dedup();
 
     let handles = stream::iter(iter)
         .map(|file_path: &String| {
             let file_path = file_path.clone();
-            let reader_type = reader_type.clone();
+            let storage_config = storage_config.clone();
 
             tokio::spawn(async move {
                 let (file_size, mut reader) =
-                    get_file_size_and_reader(file_path.clone(), reader_type)
-                        .await
-                        .unwrap();
+                    get_file_size_and_reader(file_path.clone(), storage_config.clone()).await?;
+
+                // footers of files of a known version are only fetched the first time
+                if let Some(version) = reader.version() {
+                    if let Some(metadata) = get_parquet_cache()
+                        .and_then(|cache| cache.get_metadata(&file_path, &version))
+                    {
+                        return Ok((
+                            file_path,
+                            ParquetFile {
+                                metadata,
+                                version,
+                                decryptor: None,
+                            },
+                        ));
+                    }
+                }
 
-                let metadata_bytes = get_metadata_bytes(&mut reader, file_size as usize)
-                    .await
-                    .unwrap();
+                let metadata_bytes = get_metadata_bytes(&mut reader, file_size).await?;
 
-                let metadata = decode_metadata(metadata_bytes.to_byte_slice())
-                    .map_err(LavaError::from)
-                    .unwrap();
-                (file_path, metadata)
+                let file = decode_cached_metadata(
+                    &file_path,
+                    reader.version(),
+                    &metadata_bytes,
+                    storage_config.decryption.as_deref(),
+                )?;
+                Ok::<_, LavaError>((file_path, file))
             })
         })
         .collect::<Vec<_>>()
         .await;
-    let res: Vec<Result<(String, ParquetMetaData), tokio::task::JoinError>> =
-        futures::future::join_all(handles).await;
+    let res = futures::future::join_all(handles).await;
 
     let mut metadatas = HashMap::new();
 
     for elem in res {
-        let _ = match elem {
-            Ok((k, v)) => metadatas.insert(k, v),
-            Err(_) => None,
-        };
+        let (k, v) = elem.map_err(|e| LavaError::Parse(format!("join error: {:?}", e)))??;
+        metadatas.insert(k, v);
     }
 
-    metadatas
+    Ok(metadatas)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ParquetLayout {
     pub num_row_groups: usize,
     pub metadata_bytes: Bytes,
@@ -240,378 +683,1141 @@ pub struct ParquetLayout {
     pub row_group_data_pages: Vec<usize>,
 }
 
-#[tokio::main]
-pub async fn get_parquet_layout(
+/// The layout of the data pages of one column chunk, the part of a `ParquetLayout` of one row
+/// group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowGroupLayout {
+    pub row_group: usize,
+    pub dictionary_page_sizes: Vec<usize>, // 0 means no dict page
+    pub data_page_sizes: Vec<usize>,
+    pub data_page_offsets: Vec<usize>,
+    pub data_page_num_rows: Vec<usize>,
+}
+
+impl ParquetLayout {
+    /// Appends the pages of the next row group.
+    pub fn push_row_group(&mut self, row_group: RowGroupLayout) {
+        self.row_group_data_pages
+            .push(row_group.data_page_sizes.len());
+        self.dictionary_page_sizes
+            .extend(row_group.dictionary_page_sizes);
+        self.data_page_sizes.extend(row_group.data_page_sizes);
+        self.data_page_offsets.extend(row_group.data_page_offsets);
+        self.data_page_num_rows.extend(row_group.data_page_num_rows);
+    }
+}
+
+/// `get_parquet_layout` holds at most about this many bytes of row groups being read at once.
+pub const DEFAULT_LAYOUT_MEMORY_BUDGET: usize = 1024 * 1024 * 1024;
+
+// the memory budget of a layout stream is counted in permits of this many bytes
+const LAYOUT_BUDGET_UNIT: usize = 1024;
+
+// the values of a column chunk, in batches of up to 10_000 records, and its layout
+type RowGroupValues = (Vec<ArrayData>, RowGroupLayout);
+
+// a row group read for a layout stream, with the memory it holds
+type RowGroupMessage = (
+    usize,
+    Result<RowGroupValues, LavaError>,
+    OwnedSemaphorePermit,
+);
+
+/// The values and layout of column `column_name` of `file_path`, collected from
+/// `stream_parquet_layout`. This holds the whole column in memory, callers that can take it row
+/// group by row group should use the stream instead.
+pub fn get_parquet_layout(
     column_name: &str,
     file_path: &str,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<(Vec<arrow::array::ArrayData>, ParquetLayout), LavaError> {
-    let (file_size, mut reader) =
-        get_file_size_and_reader(file_path.to_string(), reader_type).await?;
-    let metadata_bytes = get_metadata_bytes(&mut reader, file_size as usize).await?;
-    let metadata = decode_metadata(metadata_bytes.to_byte_slice()).map_err(LavaError::from)?;
-
-    let codec_options = CodecOptionsBuilder::default()
-        .set_backward_compatible_lz4(false)
-        .build();
-
+    let mut stream = stream_parquet_layout(
+        column_name,
+        file_path,
+        storage_config,
+        DEFAULT_LAYOUT_MEMORY_BUDGET,
+    )?;
     let mut parquet_layout = ParquetLayout {
-        num_row_groups: metadata.num_row_groups(),
-        metadata_bytes: metadata_bytes,
+        num_row_groups: stream.num_row_groups,
+        metadata_bytes: stream.metadata_bytes.clone(),
         dictionary_page_sizes: vec![],
         data_page_sizes: vec![],
         data_page_offsets: vec![],
         data_page_num_rows: vec![],
         row_group_data_pages: vec![],
     };
+    let mut arrays = vec![];
+    for row_group in &mut stream {
+        let (row_group_arrays, layout) = row_group?;
+        arrays.extend(row_group_arrays);
+        parquet_layout.push_row_group(layout);
+    }
+    Ok((arrays, parquet_layout))
+}
 
-    let mut pages: Vec<Vec<parquet::column::page::Page>> = Vec::new();
-    let mut total_values = 0;
+/// The layout of column `column_name` of `file_path`, without its values. If the file has an
+/// offset index (part of the page index) for the column, the layout comes from it, so only the
+/// footer and the offset index are read. Otherwise the column chunks are read to walk their page
+/// headers, like `get_parquet_layout` does.
+#[tokio::main]
+pub async fn get_parquet_page_layout(
+    column_name: &str,
+    file_path: &str,
+    storage_config: StorageConfig,
+) -> Result<ParquetLayout, LavaError> {
+    let (file_size, mut reader) =
+        get_file_size_and_reader(file_path.to_string(), storage_config.clone()).await?;
+    let metadata_bytes = get_metadata_bytes(&mut reader, file_size).await?;
+    let (metadata, decryptor) = decode_file_metadata(
+        file_path,
+        &metadata_bytes,
+        storage_config.decryption.as_deref(),
+    )?;
+    let decryptor = decryptor.as_deref();
+    let column_index = find_column(metadata.file_metadata().schema_descr(), column_name)?;
+
+    if let Some(layout) = offset_index_layout(
+        &mut reader,
+        &metadata,
+        decryptor,
+        metadata_bytes.clone(),
+        column_index,
+    )
+    .await?
+    {
+        return Ok(layout);
+    }
+    scan_parquet_layout(&mut reader, &metadata, decryptor, metadata_bytes, column_index).await
+}
 
-    let column_index = metadata
-        .file_metadata()
-        .schema_descr()
-        .columns()
-        .iter()
-        .position(|column| column.name() == column_name)
-        .expect(&format!(
-            "column {} not found in parquet file {}",
-            column_name, file_path
-        ));
+/// The range of the offset index of `column` in the file, if it has one.
+fn offset_index_range(column: &ColumnChunkMetaData) -> Option<(u64, u64)> {
+    match (column.offset_index_offset(), column.offset_index_length()) {
+        (Some(offset), Some(length)) if length > 0 => {
+            Some((offset as u64, offset as u64 + length as u64))
+        }
+        _ => None,
+    }
+}
 
-    //TODO: @rain we should parallelize this across row groups using tokio
-    // this need to refactor the ParquetLayout data structure, since it won't cost too much time, postpone for now.
+/// The range of the dictionary page of `column`, if any: it is the first page of the column
+/// chunk, right before the data pages.
+fn dictionary_page_range(
+    column: &ColumnChunkMetaData,
+    locations: &[PageLocation],
+) -> Option<(u64, u64)> {
+    match (column.dictionary_page_offset(), locations.first()) {
+        (Some(offset), Some(first)) if offset < first.offset => {
+            Some((offset as u64, first.offset as u64))
+        }
+        _ => None,
+    }
+}
 
-    for row_group in 0..metadata.num_row_groups() {
-        let column = metadata.row_group(row_group).column(column_index);
-        let mut start = column
-            .dictionary_page_offset()
-            .unwrap_or_else(|| column.data_page_offset()) as u64;
-        let end = start + column.compressed_size() as u64;
+/// The page locations of the offset index of a column chunk of a row group of `num_rows` rows.
+fn decode_page_locations(
+    offset_index: &Bytes,
+    num_rows: i64,
+) -> Result<Vec<PageLocation>, LavaError> {
+    let mut prot = TCompactInputProtocol::new(offset_index.as_ref());
+    let locations = OffsetIndex::read_from_in_protocol(&mut prot)?.page_locations;
+    let mut next_row = 0;
+    for location in &locations {
+        if location.offset < 0
+            || location.compressed_page_size < 0
+            || location.first_row_index < next_row
+            || location.first_row_index >= num_rows
+            || (next_row == 0 && location.first_row_index != 0)
+        {
+            return Err(LavaError::Parse(format!(
+                "invalid page location {:?}",
+                location
+            )));
+        }
+        next_row = location.first_row_index + 1;
+    }
+    Ok(locations)
+}
 
-        let compression_scheme = column.compression();
-        let mut codec = create_codec(compression_scheme, &codec_options).unwrap();
-        //.unwrap();
+/// The page locations of `offset_index`, decrypted if the column chunk is encrypted.
+fn decode_offset_index(
+    offset_index: Bytes,
+    decryptor: Option<&ChunkDecryptor>,
+    num_rows: i64,
+) -> Result<Vec<PageLocation>, LavaError> {
+    match decryptor {
+        Some(decryptor) => {
+            decode_page_locations(&decryptor.decrypt_offset_index(&offset_index)?, num_rows)
+        }
+        None => decode_page_locations(&offset_index, num_rows),
+    }
+}
 
-        let mut total_data_pages: usize = 0;
+/// The range of the column index (the other part of the page index) of `column`, if it has one.
+fn column_index_range(column: &ColumnChunkMetaData) -> Option<(u64, u64)> {
+    match (column.column_index_offset(), column.column_index_length()) {
+        (Some(offset), Some(length)) if length > 0 => {
+            Some((offset as u64, offset as u64 + length as u64))
+        }
+        _ => None,
+    }
+}
 
-        let column_chunk_bytes = reader.read_range(start, end).await?;
+/// A min or max statistic of a column of `physical_type`, plain encoded without a length prefix,
+/// as a literal, if the column decodes to `data_type` in an order the literal keeps. Unsigned
+/// integers, decimals, timestamps and the like are not, and are not pruned on.
+fn stats_literal(physical_type: Type, data_type: &DataType, bytes: &[u8]) -> Option<Literal> {
+    match (physical_type, data_type) {
+        (Type::BOOLEAN, DataType::Boolean) => bytes.first().map(|byte| Literal::Bool(*byte != 0)),
+        (Type::INT32, DataType::Int8 | DataType::Int16 | DataType::Int32) => Some(Literal::Int(
+            i32::from_le_bytes(bytes.try_into().ok()?) as i64,
+        )),
+        (Type::INT64, DataType::Int64) => {
+            Some(Literal::Int(i64::from_le_bytes(bytes.try_into().ok()?)))
+        }
+        (Type::FLOAT, DataType::Float32) => Some(f32::from_le_bytes(bytes.try_into().ok()?) as f64)
+            .filter(|value| !value.is_nan())
+            .map(Literal::Float),
+        (Type::DOUBLE, DataType::Float64) => Some(f64::from_le_bytes(bytes.try_into().ok()?))
+            .filter(|value| !value.is_nan())
+            .map(Literal::Float),
+        // truncated statistics may not be valid UTF-8, those are not used
+        (Type::BYTE_ARRAY, DataType::Utf8 | DataType::LargeUtf8) => std::str::from_utf8(bytes)
+            .ok()
+            .map(|value| Literal::Str(value.to_string())),
+        _ => None,
+    }
+}
 
-        let mut column_chunk_pages: Vec<parquet::column::page::Page> = Vec::new();
+/// What the statistics of `column` in the footer tell about its values, decoded as `data_type`.
+fn chunk_stats(column: &ColumnChunkMetaData, data_type: &DataType) -> Option<ValueStats> {
+    let stats = column.statistics()?;
+    let literal = |bytes| stats_literal(column.column_type(), data_type, bytes);
+    let (min, max) = if stats.has_min_max_set() && !stats.is_min_max_deprecated() {
+        (literal(stats.min_bytes()), literal(stats.max_bytes()))
+    } else {
+        (None, None)
+    };
+    // a missing null count reads as 0, so no nulls are only certain for required columns
+    let has_nulls = if column.column_descr().max_def_level() == 0 {
+        Some(false)
+    } else if stats.null_count() > 0 {
+        Some(true)
+    } else {
+        None
+    };
+    let num_values = column.num_values() as u64;
+    Some(ValueStats {
+        min,
+        max,
+        has_nulls,
+        all_null: num_values > 0 && stats.null_count() == num_values,
+    })
+}
 
-        let end = end - start;
-        let column_chunk_offset = start;
-        start = 0;
+/// What the column index `column_index` of `column` tells about the values of each page, decoded
+/// as `data_type`.
+fn decode_page_stats(
+    column_index: &Bytes,
+    column: &ColumnChunkMetaData,
+    data_type: &DataType,
+) -> Result<Vec<ValueStats>, LavaError> {
+    let mut prot = TCompactInputProtocol::new(column_index.as_ref());
+    let index = ColumnIndex::read_from_in_protocol(&mut prot)?;
+    let num_pages = index.null_pages.len();
+    if index.min_values.len() != num_pages
+        || index.max_values.len() != num_pages
+        || index
+            .null_counts
+            .as_ref()
+            .is_some_and(|counts| counts.len() != num_pages)
+    {
+        return Err(LavaError::Parse(format!(
+            "column index of {} pages with {} min and {} max values",
+            num_pages,
+            index.min_values.len(),
+            index.max_values.len()
+        )));
+    }
+    let required = column.column_descr().max_def_level() == 0;
+    let stats = (0..num_pages)
+        .map(|i| {
+            let all_null = index.null_pages[i];
+            let literal = |bytes: &Vec<u8>| match all_null {
+                true => None,
+                false => stats_literal(column.column_type(), data_type, bytes),
+            };
+            let has_nulls = match required {
+                true => Some(false),
+                false => index.null_counts.as_ref().map(|counts| counts[i] > 0),
+            };
+            ValueStats {
+                min: literal(&index.min_values[i]),
+                max: literal(&index.max_values[i]),
+                has_nulls,
+                all_null,
+            }
+        })
+        .collect();
+    Ok(stats)
+}
 
-        let mut dictionary_page_size: usize = 0;
+/// The layout of column `column_index` from its offset index, None if a row group has none.
+async fn offset_index_layout(
+    reader: &mut AsyncReader,
+    metadata: &ParquetMetaData,
+    decryptor: Option<&FileDecryptor>,
+    metadata_bytes: Bytes,
+    column_index: usize,
+) -> Result<Option<ParquetLayout>, LavaError> {
+    let mut ranges = Vec::with_capacity(metadata.num_row_groups());
+    for row_group in metadata.row_groups() {
+        match offset_index_range(row_group.column(column_index)) {
+            Some(range) => ranges.push(range),
+            None => return Ok(None),
+        }
+    }
 
-        while start != end {
-            // this takes a slice of the entire thing for each page, granted it won't read the entire thing,
-            // the thrift will terminate after reading the necessary things. @Rain the alternative is to feed it
-            // chunks at a time in a loop until a valid header is returned, like before how we are using the reader in rust-test
+    let mut parquet_layout = ParquetLayout {
+        num_row_groups: metadata.num_row_groups(),
+        metadata_bytes,
+        dictionary_page_sizes: vec![],
+        data_page_sizes: vec![],
+        data_page_offsets: vec![],
+        data_page_num_rows: vec![],
+        row_group_data_pages: vec![],
+    };
+    let offset_indices = if ranges.is_empty() {
+        vec![]
+    } else {
+        reader.read_ranges(&ranges).await?
+    };
+    for (i, (row_group, offset_index)) in
+        metadata.row_groups().iter().zip(offset_indices).enumerate()
+    {
+        let chunk_decryptor = column_decryptor(decryptor, i, column_index)?;
+        let locations = decode_offset_index(offset_index, chunk_decryptor.as_ref(), row_group.num_rows())
+            .map_err(|e| {
+                e.context(format!(
+                    "parquet file {}, row group {}: offset index",
+                    reader.filename, i
+                ))
+            })?;
 
-            let (header_len, header) = read_page_header(&column_chunk_bytes, start)?;
-            // println!("{} {} {:?}", start, header_len, header);
+        let dictionary_page_size = dictionary_page_range(row_group.column(column_index), &locations)
+            .map_or(0, |(start, end)| (end - start) as usize);
+        // pages of a column with an offset index start at record boundaries
+        for (i, location) in locations.iter().enumerate() {
+            let next_row = locations
+                .get(i + 1)
+                .map_or(row_group.num_rows(), |next| next.first_row_index);
+            parquet_layout.data_page_offsets.push(location.offset as usize);
+            parquet_layout
+                .data_page_sizes
+                .push(location.compressed_page_size as usize);
+            parquet_layout
+                .dictionary_page_sizes
+                .push(dictionary_page_size);
+            parquet_layout
+                .data_page_num_rows
+                .push((next_row - location.first_row_index) as usize);
+        }
+        parquet_layout.row_group_data_pages.push(locations.len());
+    }
+    Ok(Some(parquet_layout))
+}
 
-            let page_header = header.clone();
+/// The range of the file of the whole column chunk `column`, starting with its dictionary page.
+fn column_chunk_range(column: &ColumnChunkMetaData) -> (u64, u64) {
+    let start = column
+        .dictionary_page_offset()
+        .unwrap_or_else(|| column.data_page_offset()) as u64;
+    (start, start + column.compressed_size() as u64)
+}
 
-            let page: Page = match page_header.type_ {
-                PageType::DICTIONARY_PAGE => {
-                    dictionary_page_size = page_header.compressed_page_size as usize + header_len;
-                    let page: Page = decode_page(
-                        page_header,
-                        column_chunk_bytes.slice(
-                            (start as usize + header_len)
-                                ..(start as usize + dictionary_page_size as usize),
-                        ),
-                        Type::BYTE_ARRAY,
-                        codec.as_mut(),
-                    )
-                    .unwrap();
-                    start += dictionary_page_size as u64;
-                    page
-                }
-                PageType::DATA_PAGE | PageType::DATA_PAGE_V2 => {
-                    let compressed_page_size = page_header.compressed_page_size;
-                    parquet_layout
-                        .data_page_sizes
-                        .push(compressed_page_size as usize + header_len);
-                    parquet_layout
-                        .data_page_offsets
-                        .push((column_chunk_offset + start) as usize);
-
-                    parquet_layout
-                        .dictionary_page_sizes
-                        .push(dictionary_page_size);
-                    total_data_pages += 1;
-
-                    let page = decode_page(
-                        page_header,
-                        column_chunk_bytes.slice(
-                            (start as usize + header_len)
-                                ..(start as usize + header_len + compressed_page_size as usize),
-                        ),
-                        Type::BYTE_ARRAY,
-                        codec.as_mut(),
-                    )
-                    .unwrap();
+/// The layout of the column chunk `column` of `row_group` of `filename` from its page headers,
+/// given `column_chunk_bytes`, the whole chunk, decrypted with `decryptor` if it is encrypted.
+/// The decoded pages are returned too if `keep_pages`, to decode the values from.
+fn scan_column_chunk(
+    filename: &str,
+    column: &ColumnChunkMetaData,
+    row_group: usize,
+    max_rep_level: i16,
+    column_chunk_bytes: &Bytes,
+    decryptor: Option<&ChunkDecryptor>,
+    keep_pages: bool,
+) -> Result<(RowGroupLayout, Vec<Page>), LavaError> {
+    let codec_options = CodecOptionsBuilder::default()
+        .set_backward_compatible_lz4(false)
+        .build();
+    let physical_type = column.column_type();
+    let mut codec = create_codec(column.compression(), &codec_options)?;
 
-                    parquet_layout
-                        .data_page_num_rows
-                        .push(page.num_values() as usize);
-                    total_values += page.num_values() as usize;
+    let mut layout = RowGroupLayout {
+        row_group,
+        dictionary_page_sizes: vec![],
+        data_page_sizes: vec![],
+        data_page_offsets: vec![],
+        data_page_num_rows: vec![],
+    };
+    let mut column_chunk_pages: Vec<Page> = Vec::new();
+
+    let (column_chunk_offset, _) = column_chunk_range(column);
+    let end = column_chunk_bytes.len() as u64;
+    let mut start = 0;
+    let mut dictionary_page_size: usize = 0;
+    let mut page_module = PageModule::first(column.dictionary_page_offset().is_some());
+
+    while start < end {
+        // this takes a slice of the entire thing for each page, granted it won't read the entire thing,
+        // the thrift will terminate after reading the necessary things. @Rain the alternative is to feed it
+        // chunks at a time in a loop until a valid header is returned, like before how we are using the reader in rust-test
+
+        let page_error = |e: LavaError| {
+            e.context(format!(
+                "parquet file {}, row group {}: page at {}",
+                filename,
+                row_group,
+                column_chunk_offset + start
+            ))
+        };
+        let (header_len, page_header) =
+            read_chunk_page_header(column_chunk_bytes, start, decryptor, page_module)
+                .map_err(page_error)?;
+        let compressed_page_size = page_header.compressed_page_size as usize;
+        let page_size = compressed_page_size + header_len;
+        if start + page_size as u64 > end {
+            return Err(page_error(LavaError::Parse(
+                "page ends after its column chunk".to_string(),
+            )));
+        }
+        if !is_decoded(page_header.type_) {
+            // e.g. index pages, which nothing reads
+            start += page_size as u64;
+            continue;
+        }
 
-                    start += compressed_page_size as u64 + header_len as u64;
-                    page
-                }
-                _ => {
-                    // For unknown page type (e.g., INDEX_PAGE), skip and read next.
-                    unimplemented!("Page type {:?} is not supported", page_header.type_)
+        let page_type = page_header.type_;
+        let payload = page_payload(
+            column_chunk_bytes.slice((start as usize + header_len)..(start as usize + page_size)),
+            decryptor,
+            page_module,
+        )
+        .map_err(page_error)?;
+        let page = decode_page(page_header, payload, physical_type, codec.as_mut())
+            .map_err(page_error)?;
+        page_module = page_module.next();
+
+        if page_type == PageType::DICTIONARY_PAGE {
+            dictionary_page_size = page_size;
+        } else {
+            layout.data_page_sizes.push(page_size);
+            layout
+                .data_page_offsets
+                .push((column_chunk_offset + start) as usize);
+            layout.dictionary_page_sizes.push(dictionary_page_size);
+
+            // a page the last record of which continues in this one covers this one too,
+            // and so do the pages before it if that record started even earlier
+            let (num_rows, continued) = page_records(&page, max_rep_level).map_err(page_error)?;
+            let page_index = layout.data_page_sizes.len() - 1;
+            if continued {
+                for previous in (0..page_index).rev() {
+                    layout.data_page_sizes[previous] += page_size;
+                    if layout.data_page_num_rows[previous] > 0 {
+                        break;
+                    }
                 }
-            };
+            }
+            layout.data_page_num_rows.push(num_rows);
+        }
+        start += page_size as u64;
 
+        if keep_pages {
             column_chunk_pages.push(page);
         }
-
-        pages.push(column_chunk_pages);
-        parquet_layout.row_group_data_pages.push(total_data_pages);
     }
 
-    let page_iterator = InMemoryPageIterator::new(pages);
-    let mut array_reader = make_byte_array_reader(
-        Box::new(page_iterator),
-        metadata.row_group(0).schema_descr().column(column_index),
-        None,
-    )
-    .unwrap();
-    // let array = array_reader.next_batch(total_values as usize).unwrap();
+    Ok((layout, column_chunk_pages))
+}
 
-    // instead of reading in total_values at once, we need to read 10_000 at a time and collect results into a Vec<Arc<dyn Array>>
+/// The layout of column `column_index` from the page headers of its column chunks, which are read
+/// in full, one row group after the other.
+async fn scan_parquet_layout(
+    reader: &mut AsyncReader,
+    metadata: &ParquetMetaData,
+    decryptor: Option<&FileDecryptor>,
+    metadata_bytes: Bytes,
+    column_index: usize,
+) -> Result<ParquetLayout, LavaError> {
+    let max_rep_level = metadata
+        .file_metadata()
+        .schema_descr()
+        .column(column_index)
+        .max_rep_level();
+    let mut parquet_layout = ParquetLayout {
+        num_row_groups: metadata.num_row_groups(),
+        metadata_bytes,
+        dictionary_page_sizes: vec![],
+        data_page_sizes: vec![],
+        data_page_offsets: vec![],
+        data_page_num_rows: vec![],
+        row_group_data_pages: vec![],
+    };
+    for row_group in 0..metadata.num_row_groups() {
+        let column = metadata.row_group(row_group).column(column_index);
+        let (start, end) = column_chunk_range(column);
+        let column_chunk_bytes = reader.read_range(start, end).await?;
+        let (layout, _) = scan_column_chunk(
+            &reader.filename,
+            column,
+            row_group,
+            max_rep_level,
+            &column_chunk_bytes,
+            column_decryptor(decryptor, row_group, column_index)?.as_ref(),
+            false,
+        )?;
+        parquet_layout.push_row_group(layout);
+    }
+    Ok(parquet_layout)
+}
 
-    let mut arrays: Vec<ArrayData> = Vec::new();
+/// Reads the column chunk of `row_group` of the column `decoder` decodes, and returns its values
+/// and layout.
+async fn read_row_group_layout(
+    mut reader: AsyncReader,
+    metadata: Arc<ParquetMetaData>,
+    decryptor: Option<Arc<FileDecryptor>>,
+    decoder: ColumnDecoder,
+    row_group: usize,
+) -> Result<RowGroupValues, LavaError> {
+    let column = metadata.row_group(row_group).column(decoder.column_index);
+    let chunk_decryptor = column_decryptor(decryptor.as_deref(), row_group, decoder.column_index)?;
+    let (start, end) = column_chunk_range(column);
+    let column_chunk_bytes = reader.read_range(start, end).await?;
+    let (layout, pages) = scan_column_chunk(
+        &reader.filename,
+        column,
+        row_group,
+        decoder.max_rep_level,
+        &column_chunk_bytes,
+        chunk_decryptor.as_ref(),
+        true,
+    )?;
+    drop(column_chunk_bytes);
+
+    // instead of decoding all the records at once, we decode 10_000 at a time
+    let num_rows = layout.data_page_num_rows.iter().sum();
+    let arrays = decoder
+        .decode(vec![pages], num_rows, None, 10_000)
+        .map_err(|e| {
+            e.context(format!(
+                "parquet file {}, row group {}",
+                reader.filename, row_group
+            ))
+        })?;
+    Ok((arrays, layout))
+}
 
-    for _ in (0..total_values).step_by(10_000) {
-        let array = array_reader.next_batch(10_000).unwrap();
-        let new_array: Result<
-            &arrow_array::GenericByteArray<arrow::datatypes::GenericStringType<i32>>,
-            ArrowError,
-        > = array.as_any().downcast_ref::<StringArray>().ok_or_else(|| {
-            ArrowError::ParseError("Expects string array as first argument".to_string())
-        });
+/// The memory the column chunk `column` takes while its layout is read, in permits of
+/// `LAYOUT_BUDGET_UNIT` bytes: the chunk as read, its decompressed pages and the arrays decoded
+/// from them, taken to be as large as the pages. At most `budget`, so that every row group can
+/// be read on its own.
+fn row_group_cost(column: &ColumnChunkMetaData, budget: u32) -> u32 {
+    let bytes = column.compressed_size().max(0) as u64 + 2 * column.uncompressed_size().max(0) as u64;
+    bytes
+        .div_ceil(LAYOUT_BUDGET_UNIT as u64)
+        .clamp(1, budget as u64) as u32
+}
 
-        let data = match new_array {
-            Ok(_) => new_array.unwrap().to_data(),
-            Err(_) => array
-                .as_any()
-                .downcast_ref::<BinaryArray>()
-                .ok_or_else(|| {
-                    ArrowError::ParseError(
-                        "Expects string or binary array as first argument".to_string(),
-                    )
-                })
-                .unwrap()
-                .to_data(),
+/// Starts reading the row groups of the column `decoder` decodes, in order, each as soon as the
+/// memory budget `semaphore` has room for it, and sends their values and layouts to `sender`.
+async fn produce_row_group_layouts(
+    reader: AsyncReader,
+    metadata: Arc<ParquetMetaData>,
+    decryptor: Option<Arc<FileDecryptor>>,
+    decoder: ColumnDecoder,
+    semaphore: Arc<Semaphore>,
+    budget: u32,
+    sender: mpsc::UnboundedSender<RowGroupMessage>,
+) {
+    for row_group in 0..metadata.num_row_groups() {
+        let cost = row_group_cost(
+            metadata.row_group(row_group).column(decoder.column_index),
+            budget,
+        );
+        let Ok(permit) = semaphore.clone().acquire_many_owned(cost).await else {
+            return;
         };
-        arrays.push(data);
+        let reader = reader.clone();
+        let metadata = metadata.clone();
+        let decryptor = decryptor.clone();
+        let decoder = decoder.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let result =
+                read_row_group_layout(reader, metadata, decryptor, decoder, row_group).await;
+            // the stream is gone if this fails, nobody wants the row group anymore
+            let _ = sender.send((row_group, result, permit));
+        });
     }
+}
 
-    Ok((arrays, parquet_layout))
+/// The values and layout of a column of a parquet file row group by row group, in order, see
+/// `stream_parquet_layout`. Row groups are read ahead concurrently, as far as the memory budget
+/// allows. The iteration ends after the first error.
+pub struct ParquetLayoutStream {
+    pub num_row_groups: usize,
+    pub metadata_bytes: Bytes,
+    runtime: Option<tokio::runtime::Runtime>,
+    receiver: mpsc::UnboundedReceiver<RowGroupMessage>,
+    // row groups read before the ones before them, with the memory they hold
+    pending: BTreeMap<usize, (Result<RowGroupValues, LavaError>, OwnedSemaphorePermit)>,
+    next_row_group: usize,
 }
 
-#[derive(Debug, Clone)]
-pub struct MatchResult {
-    pub file_path: String,
-    pub column_index: usize,
-    pub row_group: usize,
-    pub offset_in_row_group: usize,
-    pub matched: String,
+impl ParquetLayoutStream {
+    /// Stops reading row groups.
+    fn finish(&mut self) {
+        self.next_row_group = self.num_row_groups;
+        self.pending.clear();
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
 }
 
-pub async fn read_indexed_pages_async(
-    column_name: String,
-    file_paths: Vec<String>,
-    row_groups: Vec<usize>,
-    page_offsets: Vec<u64>,
-    page_sizes: Vec<usize>,
-    dict_page_sizes: Vec<usize>, // 0 means no dict page
-    reader_type: ReaderType,
+impl Iterator for ParquetLayoutStream {
+    type Item = Result<RowGroupValues, LavaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_row_group >= self.num_row_groups {
+            return None;
+        }
+        loop {
+            // the memory of the row group is the caller's from here on
+            if let Some((result, _permit)) = self.pending.remove(&self.next_row_group) {
+                self.next_row_group += 1;
+                if result.is_err() || self.next_row_group == self.num_row_groups {
+                    self.finish();
+                }
+                return Some(result);
+            }
+            match self.receiver.blocking_recv() {
+                Some((row_group, result, permit)) => {
+                    self.pending.insert(row_group, (result, permit));
+                }
+                None => {
+                    let row_group = self.next_row_group;
+                    self.finish();
+                    return Some(Err(LavaError::Parse(format!(
+                        "row group {} of the layout was never read",
+                        row_group
+                    ))));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ParquetLayoutStream {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// The values and layout of column `column_name` of `file_path`, streamed row group by row group
+/// as in `get_parquet_layout`, for callers that consume them incrementally. Row groups are read
+/// and decoded concurrently, with at most about `memory_budget` bytes of column chunks, pages and
+/// arrays held by the stream at once; a single row group larger than that is still read, alone.
+/// Must not be called, nor iterated, from within an async runtime.
+pub fn stream_parquet_layout(
+    column_name: &str,
+    file_path: &str,
+    storage_config: StorageConfig,
+    memory_budget: usize,
+) -> Result<ParquetLayoutStream, LavaError> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let decryption = storage_config.decryption.clone();
+    let (reader, metadata_bytes) = runtime.block_on(async {
+        let (file_size, mut reader) =
+            get_file_size_and_reader(file_path.to_string(), storage_config).await?;
+        let metadata_bytes = get_metadata_bytes(&mut reader, file_size).await?;
+        Ok::<_, LavaError>((reader, metadata_bytes))
+    })?;
+    let (metadata, decryptor) =
+        decode_file_metadata(file_path, &metadata_bytes, decryption.as_deref())?;
+    let metadata = Arc::new(metadata);
+    let column_index = find_column(metadata.file_metadata().schema_descr(), column_name)?;
+    let decoder = ColumnDecoder::new(&metadata, column_index)?;
+    // fail now rather than on the first row group without the key of the column
+    if metadata.num_row_groups() > 0 {
+        column_decryptor(decryptor.as_deref(), 0, column_index)?;
+    }
+
+    let budget = (memory_budget / LAYOUT_BUDGET_UNIT).clamp(1, u32::MAX as usize) as u32;
+    let semaphore = Arc::new(Semaphore::new(budget as usize));
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let num_row_groups = metadata.num_row_groups();
+    runtime.spawn(produce_row_group_layouts(
+        reader, metadata, decryptor, decoder, semaphore, budget, sender,
+    ));
+
+    Ok(ParquetLayoutStream {
+        num_row_groups,
+        metadata_bytes,
+        runtime: Some(runtime),
+        receiver,
+        pending: BTreeMap::new(),
+        next_row_group: 0,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    pub file_path: String,
+    pub column_index: usize,
+    pub row_group: usize,
+    pub offset_in_row_group: usize,
+    pub matched: String,
+}
+
+/// Decodes the records starting in the first data page of `page_bytes`, read at `page_offset`,
+/// which may hold the following pages too, if the last record continues in them. Encrypted pages
+/// are decrypted as the data pages of their chunk starting with `decryption`.
+fn decode_indexed_page(
+    decoder: &ColumnDecoder,
+    physical_type: Type,
+    mut codec: Option<Box<dyn Codec>>,
+    dict_page: Option<Page>,
+    page_offset: u64,
+    page_bytes: Bytes,
+    decryption: Option<(&ChunkDecryptor, PageModule)>,
+) -> Result<ArrayData, LavaError> {
+    let mut pages: Vec<parquet::column::page::Page> = dict_page.into_iter().collect();
+
+    pages.extend(decode_pages(
+        &page_bytes,
+        page_offset,
+        physical_type,
+        &mut codec,
+        decryption,
+    )?);
+
+    decoder
+        .decode_page(pages)
+        .map_err(|e| e.context(format!("page at {}", page_offset)))
+}
+
+/// Decodes the dictionary page `bytes`, read at `offset` of the file, decrypted with
+/// `decryptor` if its chunk is encrypted.
+fn decode_dictionary_page(
+    bytes: &Bytes,
+    offset: u64,
+    physical_type: Type,
+    codec: &mut Option<Box<dyn Codec>>,
+    decryptor: Option<&ChunkDecryptor>,
+) -> Result<Page, LavaError> {
+    let decryption = decryptor.map(|decryptor| (decryptor, PageModule::Dictionary));
+    decode_pages(bytes, offset, physical_type, codec, decryption)?
+        .into_iter()
+        .find(|page| matches!(page, Page::DictionaryPage { .. }))
+        .ok_or_else(|| LavaError::Parse(format!("no dictionary page at {}", offset)))
+}
+
+/// Decodes the consecutive pages, headers included, making up `bytes`, read at `offset` of the
+/// file. Pages that are not dictionary or data pages are skipped. If the chunk is encrypted,
+/// `decryption` is its decryptor and which page of the chunk the first page is.
+fn decode_pages(
+    bytes: &Bytes,
+    offset: u64,
+    physical_type: Type,
+    codec: &mut Option<Box<dyn Codec>>,
+    decryption: Option<(&ChunkDecryptor, PageModule)>,
+) -> Result<Vec<Page>, LavaError> {
+    let decryptor = decryption.map(|(decryptor, _)| decryptor);
+    let mut page_module = decryption.map_or(PageModule::Data(0), |(_, page)| page);
+    let mut pages = vec![];
+    let mut start = 0;
+    while start < bytes.len() {
+        let page_error = |e: LavaError| e.context(format!("page at {}", offset + start as u64));
+        let (header_len, header) =
+            read_chunk_page_header(bytes, start as u64, decryptor, page_module)
+                .map_err(page_error)?;
+        let end = start + header_len + header.compressed_page_size as usize;
+        if end > bytes.len() {
+            return Err(page_error(LavaError::Parse(
+                "page ends after the fetched range".to_string(),
+            )));
+        }
+        if is_decoded(header.type_) {
+            let payload = page_payload(bytes.slice(start + header_len..end), decryptor, page_module)
+                .map_err(page_error)?;
+            pages.push(
+                decode_page(header, payload, physical_type, codec.as_mut()).map_err(page_error)?,
+            );
+            page_module = page_module.next();
+        }
+        start = end;
+    }
+    Ok(pages)
+}
+
+/// The ordinals of the data pages of the encrypted column chunk `column_index` of `row_group`, by
+/// their offsets. Encrypted pages are authenticated with them, and a page read at an offset does
+/// not know it otherwise. They come from the offset index if the file has one, otherwise from the
+/// page headers of the whole column chunk.
+async fn data_page_ordinals(
+    reader: &mut AsyncReader,
+    metadata: &ParquetMetaData,
+    decryptor: &ChunkDecryptor,
+    row_group: usize,
+    column_index: usize,
+) -> Result<HashMap<u64, usize>, LavaError> {
+    let row_group_metadata = metadata.row_group(row_group);
+    let column = row_group_metadata.column(column_index);
+    let offsets: Vec<usize> = match offset_index_range(column) {
+        Some((start, end)) => {
+            let offset_index = reader.read_range(start, end).await?;
+            decode_offset_index(offset_index, Some(decryptor), row_group_metadata.num_rows())?
+                .iter()
+                .map(|location| location.offset as usize)
+                .collect()
+        }
+        None => {
+            let (start, end) = column_chunk_range(column);
+            let column_chunk_bytes = reader.read_range(start, end).await?;
+            let max_rep_level = metadata
+                .file_metadata()
+                .schema_descr()
+                .column(column_index)
+                .max_rep_level();
+            let (layout, _) = scan_column_chunk(
+                &reader.filename,
+                column,
+                row_group,
+                max_rep_level,
+                &column_chunk_bytes,
+                Some(decryptor),
+                false,
+            )?;
+            layout.data_page_offsets
+        }
+    };
+    Ok(offsets
+        .into_iter()
+        .enumerate()
+        .map(|(ordinal, offset)| (offset as u64, ordinal))
+        .collect())
+}
+
+/// The metadata of `file_paths`, decoded from `file_metadatas` if given, e.g. kept in an index,
+/// otherwise read from the files.
+async fn load_metadatas(
+    file_paths: &Vec<String>,
+    storage_config: StorageConfig,
+    file_metadatas: Option<HashMap<String, Bytes>>,
+) -> Result<HashMap<String, ParquetFile>, LavaError> {
+    match file_metadatas {
+        Some(file_metadatas) => {
+            debug!("using the provided metadata of {} files", file_metadatas.len());
+            let mut metadatas: HashMap<String, ParquetFile> = HashMap::new();
+            for (key, value) in file_metadatas.into_iter() {
+                let file =
+                    decode_cached_metadata(&key, None, &value, storage_config.decryption.as_deref())?;
+                metadatas.insert(key, file);
+            }
+            Ok(metadatas)
+        }
+        None => parse_metadatas(file_paths, storage_config).await,
+    }
+}
+
+/// A page requested from `read_indexed_pages_async`, `idx` is its position in the request.
+#[derive(Clone, Copy)]
+struct RequestedPage {
+    idx: usize,
+    row_group: usize,
+    offset: u64,
+    size: usize,
+    dict_size: usize,
+}
+
+pub async fn read_indexed_pages_async(
+    column_name: String,
+    file_paths: Vec<String>,
+    row_groups: Vec<usize>,
+    page_offsets: Vec<u64>,
+    page_sizes: Vec<usize>,
+    dict_page_sizes: Vec<usize>, // 0 means no dict page
+    storage_config: StorageConfig,
     file_metadatas: Option<HashMap<String, Bytes>>,
     in_order: Option<bool>,
 ) -> Result<Vec<ArrayData>, LavaError> {
-    // current implementation might re-read dictionary pages, this should be optimized
     // we are assuming that all the files are either on disk or cloud.
 
     let codec_options = CodecOptionsBuilder::default()
         .set_backward_compatible_lz4(false)
         .build();
 
-    let metadatas = match file_metadatas {
-        Some(file_metadatas) => {
-            println!("Using provided file metadatas");
-            let mut metadatas: HashMap<String, ParquetMetaData> = HashMap::new();
-            for (key, value) in file_metadatas.into_iter() {
-                metadatas.insert(
-                    key,
-                    decode_metadata(value.to_byte_slice())
-                        .map_err(LavaError::from)
-                        .unwrap(),
-                );
-            }
-            metadatas
-        }
-        None => parse_metadatas(&file_paths, reader_type.clone()).await,
-    };
+    let metadatas = load_metadatas(&file_paths, storage_config.clone(), file_metadatas).await?;
+    let parquet_cache = get_parquet_cache();
 
     let in_order: bool = in_order.unwrap_or(true);
 
-    let mut reader = get_reader(file_paths[0].clone(), reader_type.clone())
-        .await
-        .unwrap();
+    if file_paths.is_empty() {
+        return Ok(vec![]);
+    }
+    let reader = get_reader(file_paths[0].clone(), storage_config.clone()).await?;
 
-    let iter = izip!(
+    let start = std::time::Instant::now();
+
+    // group the requested pages by file, so that all the dictionary and data pages of a file
+    // are fetched with one coalesced read_ranges call.
+    let mut file_pages: BTreeMap<String, Vec<RequestedPage>> = BTreeMap::new();
+    for (idx, (file_path, row_group, offset, size, dict_size)) in izip!(
         file_paths,
         row_groups,
         page_offsets,
         page_sizes,
         dict_page_sizes
-    );
-
-    let start = std::time::Instant::now();
-
-    let mut future_handles: Vec<tokio::task::JoinHandle<ArrayData>> = vec![];
-    let mut join_set = JoinSet::new();
-
-    let iter: Vec<_> = stream::iter(iter)
-        .map(
-            |(file_path, row_group, page_offset, page_size, dict_page_size)| {
-                let column_index = metadatas[&file_path]
-                    .file_metadata()
-                    .schema_descr()
-                    .columns()
-                    .iter()
-                    .position(|column| column.name() == column_name)
-                    .expect(&format!(
-                        "column {} not found in parquet file {}",
-                        column_name, file_path
-                    ));
-                let column_descriptor = metadatas[&file_path]
-                    .row_group(row_group)
-                    .schema_descr()
-                    .column(column_index);
-
-                let compression_scheme = metadatas[&file_path]
-                    .row_group(row_group)
-                    .column(column_index)
-                    .compression();
-                let dict_page_offset = metadatas[&file_path]
-                    .row_group(row_group)
-                    .column(column_index)
-                    .dictionary_page_offset();
-                let mut codec = create_codec(compression_scheme, &codec_options)
-                    .unwrap()
-                    .unwrap();
-
-                let mut reader_c = reader.clone();
-                reader_c.update_filename(file_path).unwrap();
-
-                let future = async move {
-                    let mut pages: Vec<parquet::column::page::Page> = Vec::new();
-                    if dict_page_size > 0 {
-                        let start = dict_page_offset.unwrap() as u64;
-                        let dict_page_bytes = reader_c
-                            .read_range(start, start + dict_page_size as u64)
-                            .await
-                            .unwrap();
-                        let dict_page_bytes = Bytes::from(dict_page_bytes);
-                        let (dict_header_len, dict_header) =
-                            read_page_header(&dict_page_bytes, 0).unwrap();
-                        let dict_page = decode_page(
-                            dict_header,
-                            dict_page_bytes.slice(dict_header_len..dict_page_size),
-                            Type::BYTE_ARRAY,
-                            Some(&mut codec),
-                        )
-                        .unwrap();
-                        pages.push(dict_page);
-                    }
+    )
+    .enumerate()
+    {
+        file_pages.entry(file_path).or_default().push(RequestedPage {
+            idx,
+            row_group,
+            offset,
+            size,
+            dict_size,
+        });
+    }
+    let num_pages: usize = file_pages.values().map(|pages| pages.len()).sum();
+
+    // the dictionaries of every file by row group, from the parquet cache or fetched with the
+    // pages, each of them once however many of its pages are read
+    let mut file_dictionaries: HashMap<String, HashMap<usize, Page>> = HashMap::new();
+    let mut fetch_set = JoinSet::new();
+    for (file_path, pages) in file_pages.iter() {
+        let file = metadatas.get(file_path).ok_or_else(|| {
+            LavaError::NotFound(format!("no metadata for parquet file {}", file_path))
+        })?;
+        let metadata = &file.metadata;
+        let column_index = find_column(metadata.file_metadata().schema_descr(), &column_name)?;
+
+        let dictionaries = file_dictionaries.entry(file_path.clone()).or_default();
+        let mut dict_ranges: BTreeMap<usize, (u64, u64)> = BTreeMap::new();
+        let mut chunk_decryptors: BTreeMap<usize, ChunkDecryptor> = BTreeMap::new();
+        for RequestedPage {
+            row_group,
+            dict_size,
+            ..
+        } in pages.iter()
+        {
+            if *row_group >= metadata.num_row_groups() {
+                return Err(LavaError::Parse(format!(
+                    "row group {} is not in parquet file {}",
+                    row_group, file_path
+                )));
+            }
+            if let Some(decryptor) = file
+                .column_decryptor(*row_group, column_index)
+                .map_err(|e| e.context(format!("parquet file {}", file_path)))?
+            {
+                chunk_decryptors.insert(*row_group, decryptor);
+            }
+            if *dict_size == 0
+                || dictionaries.contains_key(row_group)
+                || dict_ranges.contains_key(row_group)
+            {
+                continue;
+            }
+            if let Some(page) = parquet_cache.as_ref().and_then(|cache| {
+                cache.get_dictionary(file_path, &file.version, *row_group, column_index)
+            }) {
+                dictionaries.insert(*row_group, page);
+                continue;
+            }
+            let dict_page_offset = metadata
+                .row_group(*row_group)
+                .column(column_index)
+                .dictionary_page_offset()
+                .ok_or_else(|| {
+                    LavaError::Parse(format!(
+                        "parquet file {}, row group {}: column {} has no dictionary page",
+                        file_path, row_group, column_name
+                    ))
+                })? as u64;
+            dict_ranges.insert(
+                *row_group,
+                (dict_page_offset, dict_page_offset + *dict_size as u64),
+            );
+        }
+        let mut ranges: Vec<(u64, u64)> = dict_ranges.values().copied().collect();
+        ranges.extend(
+            pages
+                .iter()
+                .map(|page| (page.offset, page.offset + page.size as u64)),
+        );
+
+        let mut reader_c = reader.clone();
+        let file_path = file_path.clone();
+        let pages = pages.clone();
+        let metadata = metadata.clone();
+
+        fetch_set.spawn(async move {
+            reader_c.update_filename(file_path.clone()).await?;
+            // encrypted pages are authenticated with their ordinal in the column chunk
+            let mut ordinals: HashMap<usize, HashMap<u64, usize>> = HashMap::new();
+            for (row_group, decryptor) in &chunk_decryptors {
+                let row_group_ordinals = data_page_ordinals(
+                    &mut reader_c,
+                    &metadata,
+                    decryptor,
+                    *row_group,
+                    column_index,
+                )
+                .await
+                .map_err(|e| {
+                    e.context(format!(
+                        "parquet file {}, row group {}",
+                        file_path, row_group
+                    ))
+                })?;
+                ordinals.insert(*row_group, row_group_ordinals);
+            }
 
-                    let page_bytes = reader_c
-                        .read_range(page_offset, page_offset + page_size as u64)
-                        .await
-                        .unwrap();
-                    let (header_len, header) = read_page_header(&page_bytes, 0).unwrap();
-                    let page: Page = decode_page(
-                        header,
-                        page_bytes.slice(header_len..page_size),
-                        Type::BYTE_ARRAY,
-                        Some(&mut codec),
-                    )
-                    .unwrap();
-                    let num_values = page.num_values();
-
-                    pages.push(page);
-                    let page_iterator = InMemoryPageIterator::new(vec![pages]);
-                    let mut array_reader = make_byte_array_reader(
-                        Box::new(page_iterator),
-                        column_descriptor.clone(),
-                        None,
-                    )
-                    .unwrap();
-                    let array = array_reader.next_batch(num_values as usize).unwrap();
-
-                    let new_array: Result<
-                        &arrow_array::GenericByteArray<arrow::datatypes::GenericStringType<i32>>,
-                        ArrowError,
-                    > = array.as_any().downcast_ref::<StringArray>().ok_or_else(|| {
-                        ArrowError::ParseError("Expects string array as first argument".to_string())
-                    });
-
-                    let data = match new_array {
-                        Ok(_) => new_array.unwrap().to_data(),
-                        Err(_) => array
-                            .as_any()
-                            .downcast_ref::<BinaryArray>()
-                            .ok_or_else(|| {
-                                ArrowError::ParseError(
-                                    "Expects string or binary array as first argument".to_string(),
-                                )
-                            })
-                            .unwrap()
-                            .to_data(),
+            let mut buffers = reader_c.read_ranges(&ranges).await?.into_iter();
+            let dict_pages = dict_ranges
+                .into_iter()
+                .map(|(row_group, (offset, _))| (row_group, offset, buffers.next().unwrap()))
+                .collect::<Vec<_>>();
+            let pages = pages
+                .into_iter()
+                .map(|requested| {
+                    let page_bytes = buffers.next().unwrap();
+                    let page = match ordinals.get(&requested.row_group) {
+                        Some(row_group_ordinals) => PageModule::Data(
+                            *row_group_ordinals.get(&requested.offset).ok_or_else(|| {
+                                LavaError::Parse(format!(
+                                    "parquet file {}, row group {}: no data page at {}",
+                                    file_path, requested.row_group, requested.offset
+                                ))
+                            })?,
+                        ),
+                        None => PageModule::Data(0),
                     };
+                    Ok((requested, page, page_bytes))
+                })
+                .collect::<Result<Vec<_>, LavaError>>()?;
+            Ok::<_, LavaError>((file_path, dict_pages, pages))
+        });
+    }
 
-                    data
-                };
+    let mut decode_set: JoinSet<Result<(usize, ArrayData), LavaError>> = JoinSet::new();
+    let mut results: Vec<Option<ArrayData>> = vec![None; num_pages];
+    let mut result_inner: Vec<ArrayData> = vec![];
+
+    while let Some(res) = fetch_set.join_next().await {
+        let (file_path, dict_pages, pages) =
+            res.map_err(|e| LavaError::Parse(format!("join error: {:?}", e)))??;
+        let file = &metadatas[&file_path];
+        let metadata = &file.metadata;
+        let column_index = find_column(metadata.file_metadata().schema_descr(), &column_name)?;
+        let decoder = Arc::new(ColumnDecoder::new(metadata, column_index)?);
+
+        let dictionaries = file_dictionaries.entry(file_path.clone()).or_default();
+        for (row_group, offset, bytes) in dict_pages {
+            let column = metadata.row_group(row_group).column(column_index);
+            let mut codec = create_codec(column.compression(), &codec_options)?;
+            let decryptor = file.column_decryptor(row_group, column_index)?;
+            let page = decode_dictionary_page(
+                &bytes,
+                offset,
+                column.column_type(),
+                &mut codec,
+                decryptor.as_ref(),
+            )
+            .map_err(|e| {
+                e.context(format!(
+                    "parquet file {}, row group {}",
+                    file_path, row_group
+                ))
+            })?;
+            if let (Some(cache), None) = (&parquet_cache, &file.decryptor) {
+                cache.put_dictionary(
+                    &file_path,
+                    &file.version,
+                    row_group,
+                    column_index,
+                    page.clone(),
+                );
+            }
+            dictionaries.insert(row_group, page);
+        }
 
-                if in_order {
-                    let handle = tokio::spawn(future);
-                    future_handles.push(handle);
-                } else {
-                    join_set.spawn(future);
-                }
-            },
-        )
-        .collect::<Vec<_>>()
-        .await;
+        for (requested, page, page_bytes) in pages {
+            let RequestedPage {
+                idx,
+                row_group,
+                offset: page_offset,
+                dict_size,
+                ..
+            } = requested;
+            let physical_type = metadata.row_group(row_group).column(column_index).column_type();
+            let compression_scheme = metadata
+                .row_group(row_group)
+                .column(column_index)
+                .compression();
+            let codec = create_codec(compression_scheme, &codec_options)?;
+            let dict_page = match dict_size > 0 {
+                true => dictionaries.get(&row_group).cloned(),
+                false => None,
+            };
+            let decoder = decoder.clone();
+            let file_path = file_path.clone();
+            let decryptor = file.column_decryptor(row_group, column_index)?;
+
+            decode_set.spawn(async move {
+                decode_indexed_page(
+                    &decoder,
+                    physical_type,
+                    codec,
+                    dict_page,
+                    page_offset,
+                    page_bytes,
+                    decryptor.as_ref().map(|decryptor| (decryptor, page)),
+                )
+                .map(|data| (idx, data))
+                .map_err(|e| {
+                    e.context(format!(
+                        "parquet file {}, row group {}",
+                        file_path, row_group
+                    ))
+                })
+            });
+        }
+    }
 
     // it is absolutely crucial to collect results in the same order.
+    while let Some(res) = decode_set.join_next().await {
+        let (idx, data) = res.map_err(|e| LavaError::Parse(format!("join error: {:?}", e)))??;
+        if in_order {
+            results[idx] = Some(data);
+        } else {
+            result_inner.push(data);
+        }
+    }
 
     let result: Vec<ArrayData> = if in_order {
-        let res: Vec<std::prelude::v1::Result<ArrayData, tokio::task::JoinError>> =
-            futures::future::join_all(future_handles).await;
-        res.into_iter().map(|res| res.unwrap()).collect()
+        results.into_iter().map(|data| data.unwrap()).collect()
     } else {
-        let mut result_inner: Vec<ArrayData> = vec![];
-        while let Some(res) = join_set.join_next().await {
-            result_inner.push(res.unwrap());
-        }
         result_inner
     };
 
-    join_set.shutdown().await;
-
-    let end = std::time::Instant::now();
-    println!("read_indexed_pages_async took {:?}", end - start);
+    storage_config.record_stage("read pages", start);
 
     Ok(result)
 }
@@ -623,14 +1829,13 @@ pub fn read_indexed_pages(
     page_offsets: Vec<u64>,
     page_sizes: Vec<usize>,
     dict_page_sizes: Vec<usize>, // 0 means no dict page
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
     file_metadatas: Option<HashMap<String, Bytes>>,
     in_order: Option<bool>,
 ) -> Result<Vec<ArrayData>, LavaError> {
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
-        .build()
-        .unwrap();
+        .build()?;
 
     let res = rt.block_on(read_indexed_pages_async(
         column_name,
@@ -639,10 +1844,1560 @@ pub fn read_indexed_pages(
         page_offsets,
         page_sizes,
         dict_page_sizes,
-        reader_type,
+        storage_config,
         file_metadatas,
         in_order,
     ));
     rt.shutdown_background();
     res
 }
+
+/// The ranges of the file to fetch to decode some rows of a column chunk, and which rows of the
+/// pages in those ranges they are.
+struct ChunkRead {
+    // the dictionary page, if needed, then data pages, each range one or more whole pages
+    ranges: Vec<(u64, u64)>,
+    // the first page of every range, to decrypt encrypted pages with
+    first_pages: Vec<PageModule>,
+    num_rows: usize,
+    selection: RowSelection,
+    num_selected: usize,
+}
+
+impl ChunkRead {
+    /// Plans reading `rows`, sorted disjoint ranges of rows, of `column` of `row_group`: just the
+    /// pages covering them if the page `locations` from the offset index are known, otherwise the
+    /// whole column chunk.
+    fn new(
+        row_group: &RowGroupMetaData,
+        column: &ColumnChunkMetaData,
+        locations: Option<Vec<PageLocation>>,
+        rows: &[(usize, usize)],
+    ) -> Self {
+        let num_selected = rows.iter().map(|(start, end)| end - start).sum();
+        let total_rows = row_group.num_rows() as usize;
+        let Some(locations) = locations else {
+            let start = column
+                .dictionary_page_offset()
+                .unwrap_or_else(|| column.data_page_offset()) as u64;
+            return Self {
+                ranges: vec![(start, start + column.compressed_size() as u64)],
+                first_pages: vec![PageModule::first(column.dictionary_page_offset().is_some())],
+                num_rows: total_rows,
+                selection: RowSelection::from_consecutive_ranges(
+                    rows.iter().map(|(start, end)| *start..*end),
+                    total_rows,
+                ),
+                num_selected,
+            };
+        };
+
+        // the rows of the pages that are skipped do not count
+        let mut ranges: Vec<(u64, u64)> = dictionary_page_range(column, &locations)
+            .into_iter()
+            .collect();
+        let mut first_pages = vec![PageModule::Dictionary; ranges.len()];
+        let mut num_rows = 0;
+        let mut selected = vec![];
+        for (i, location) in locations.iter().enumerate() {
+            let first_row = location.first_row_index as usize;
+            let next_row = locations
+                .get(i + 1)
+                .map_or(total_rows, |next| next.first_row_index as usize);
+            let page_rows: Vec<(usize, usize)> = rows
+                .iter()
+                .map(|(start, end)| (*start.max(&first_row), *end.min(&next_row)))
+                .filter(|(start, end)| start < end)
+                .collect();
+            if page_rows.is_empty() {
+                continue;
+            }
+            let offset = location.offset as u64;
+            ranges.push((offset, offset + location.compressed_page_size as u64));
+            first_pages.push(PageModule::Data(i));
+            for (start, end) in page_rows {
+                selected.push(num_rows + start - first_row..num_rows + end - first_row);
+            }
+            num_rows += next_row - first_row;
+        }
+        Self {
+            ranges,
+            first_pages,
+            num_rows,
+            selection: RowSelection::from_consecutive_ranges(selected.into_iter(), num_rows),
+            num_selected,
+        }
+    }
+}
+
+/// Reads `rows` of every row group in `row_groups` of the file of `reader`, for each of the
+/// `columns`, returning the arrays of every row group in the same order.
+async fn read_file_rows(
+    mut reader: AsyncReader,
+    file: ParquetFile,
+    columns: Vec<(usize, ColumnDecoder)>,
+    row_groups: Vec<(usize, Vec<(usize, usize)>)>,
+) -> Result<Vec<Vec<ArrayRef>>, LavaError> {
+    let metadata = &file.metadata;
+    let chunks: Vec<_> = row_groups
+        .iter()
+        .flat_map(|(i, rows)| {
+            let row_group = metadata.row_group(*i);
+            columns
+                .iter()
+                .map(move |(column_index, _)| (*i, row_group, rows, *column_index))
+        })
+        .collect();
+
+    // the offset indices of all the chunks first, then the pages they point to
+    let index_ranges: Vec<(u64, u64)> = chunks
+        .iter()
+        .filter_map(|(_, row_group, _, column_index)| {
+            offset_index_range(row_group.column(*column_index))
+        })
+        .collect();
+    let mut offset_indices = if index_ranges.is_empty() {
+        vec![]
+    } else {
+        reader.read_ranges(&index_ranges).await?
+    }
+    .into_iter();
+    let mut reads = Vec::with_capacity(chunks.len());
+    let mut decryptors = Vec::with_capacity(chunks.len());
+    for (i, row_group, rows, column_index) in &chunks {
+        let column = row_group.column(*column_index);
+        let decryptor = file
+            .column_decryptor(*i, *column_index)
+            .map_err(|e| e.context(format!("parquet file {}", reader.filename)))?;
+        let locations = match offset_index_range(column) {
+            Some(_) => Some(
+                decode_offset_index(
+                    offset_indices.next().unwrap(),
+                    decryptor.as_ref(),
+                    row_group.num_rows(),
+                )
+                .map_err(|e| {
+                        e.context(format!(
+                            "parquet file {}, row group {}: offset index",
+                            reader.filename, i
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        reads.push(ChunkRead::new(row_group, column, locations, rows));
+        decryptors.push(decryptor);
+    }
+
+    let ranges: Vec<(u64, u64)> = reads
+        .iter()
+        .flat_map(|read| read.ranges.iter().copied())
+        .collect();
+    let mut buffers = if ranges.is_empty() {
+        vec![]
+    } else {
+        reader.read_ranges(&ranges).await?
+    }
+    .into_iter();
+
+    let codec_options = CodecOptionsBuilder::default()
+        .set_backward_compatible_lz4(false)
+        .build();
+    let mut arrays: Vec<Vec<ArrayRef>> = vec![];
+    for (i, ((row_group_index, row_group, _, column_index), read, decryptor)) in
+        izip!(chunks.iter(), reads, decryptors).enumerate()
+    {
+        let chunk_error = |e: LavaError| {
+            e.context(format!(
+                "parquet file {}, row group {}",
+                reader.filename, row_group_index
+            ))
+        };
+        let column = row_group.column(*column_index);
+        let mut codec = create_codec(column.compression(), &codec_options)?;
+        let mut pages = vec![];
+        for ((offset, _), first_page) in read.ranges.iter().zip(&read.first_pages) {
+            let bytes = buffers.next().unwrap();
+            pages.extend(
+                decode_pages(
+                    &bytes,
+                    *offset,
+                    column.column_type(),
+                    &mut codec,
+                    decryptor.as_ref().map(|decryptor| (decryptor, *first_page)),
+                )
+                .map_err(chunk_error)?,
+            );
+        }
+        let decoder = &columns[i % columns.len()].1;
+        let array = decoder
+            .decode_selected(
+                vec![pages],
+                read.num_rows,
+                read.selection,
+                read.num_selected,
+            )
+            .map_err(chunk_error)?;
+        if i % columns.len() == 0 {
+            arrays.push(vec![]);
+        }
+        arrays.last_mut().unwrap().push(array);
+    }
+    Ok(arrays)
+}
+
+/// Reads rows `row_ranges`, half-open ranges of rows of the row group, of row groups `row_groups`
+/// of `file_paths`, e.g. the hits of a query, and returns the `columns` of those rows, in the
+/// order of the hits, as one record batch. Columns are named like `get_parquet_layout` takes
+/// them. For every column only the pages covering the rows are fetched, found with the offset
+/// index if the file has one, otherwise the whole column chunks are.
+pub async fn read_rows_async(
+    file_paths: Vec<String>,
+    row_groups: Vec<usize>,
+    row_ranges: Vec<(usize, usize)>,
+    columns: Vec<String>,
+    storage_config: StorageConfig,
+    file_metadatas: Option<HashMap<String, Bytes>>,
+) -> Result<RecordBatch, LavaError> {
+    if file_paths.len() != row_groups.len() || file_paths.len() != row_ranges.len() {
+        return Err(LavaError::Parse(
+            "file_paths, row_groups and row_ranges must have the same length".to_string(),
+        ));
+    }
+    let metadatas = load_metadatas(&file_paths, storage_config.clone(), file_metadatas).await?;
+    let start_time = std::time::Instant::now();
+
+    let batch = read_hit_rows(
+        &file_paths,
+        &row_groups,
+        &row_ranges,
+        &columns,
+        &storage_config,
+        &metadatas,
+    )
+    .await?;
+
+    storage_config.record_stage("read rows", start_time);
+
+    Ok(batch)
+}
+
+// row group -> sorted disjoint ranges of its rows
+type RowGroupRows = BTreeMap<usize, Vec<(usize, usize)>>;
+
+/// The rows to read of every row group of every file of the hits, sorted and merged.
+fn hit_row_groups(
+    file_paths: &[String],
+    row_groups: &[usize],
+    row_ranges: &[(usize, usize)],
+    metadatas: &HashMap<String, ParquetFile>,
+) -> Result<BTreeMap<String, RowGroupRows>, LavaError> {
+    let mut wanted: BTreeMap<String, RowGroupRows> = BTreeMap::new();
+    for (file_path, row_group, (start, end)) in izip!(file_paths, row_groups, row_ranges) {
+        let metadata = &metadatas
+            .get(file_path)
+            .ok_or_else(|| {
+                LavaError::NotFound(format!("no metadata for parquet file {}", file_path))
+            })?
+            .metadata;
+        if *row_group >= metadata.num_row_groups()
+            || start > end
+            || *end > metadata.row_group(*row_group).num_rows() as usize
+        {
+            return Err(LavaError::Parse(format!(
+                "rows {}..{} of row group {} are not in parquet file {}",
+                start, end, row_group, file_path
+            )));
+        }
+        wanted
+            .entry(file_path.clone())
+            .or_default()
+            .entry(*row_group)
+            .or_default()
+            .push((*start, *end));
+    }
+    for rows in wanted.values_mut().flat_map(|row_groups| row_groups.values_mut()) {
+        rows.sort();
+        let mut merged: Vec<(usize, usize)> = vec![];
+        for (start, end) in rows.drain(..).filter(|(start, end)| start < end) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *rows = merged;
+    }
+    Ok(wanted)
+}
+
+/// `read_rows` of files whose `metadatas` are loaded.
+async fn read_hit_rows(
+    file_paths: &[String],
+    row_groups: &[usize],
+    row_ranges: &[(usize, usize)],
+    columns: &[String],
+    storage_config: &StorageConfig,
+    metadatas: &HashMap<String, ParquetFile>,
+) -> Result<RecordBatch, LavaError> {
+    let wanted = hit_row_groups(file_paths, row_groups, row_ranges, metadatas)?;
+
+    let mut read_set = JoinSet::new();
+    for (file_path, file_row_groups) in wanted.iter() {
+        let file = &metadatas[file_path];
+        let metadata = &file.metadata;
+        let schema_descr = metadata.file_metadata().schema_descr();
+        let mut decoders = Vec::with_capacity(columns.len());
+        for column_name in columns {
+            let column_index = find_column(schema_descr, column_name)?;
+            decoders.push((column_index, ColumnDecoder::new(metadata, column_index)?));
+        }
+        let reader = get_reader(file_path.clone(), storage_config.clone()).await?;
+        let file = file.clone();
+        let file_path = file_path.clone();
+        let file_row_groups: Vec<(usize, Vec<(usize, usize)>)> = file_row_groups
+            .iter()
+            .map(|(row_group, rows)| (*row_group, rows.clone()))
+            .collect();
+        read_set.spawn(async move {
+            let arrays = read_file_rows(reader, file, decoders, file_row_groups).await?;
+            Ok::<_, LavaError>((file_path, arrays))
+        });
+    }
+    let mut file_arrays: HashMap<String, Vec<Vec<ArrayRef>>> = HashMap::new();
+    while let Some(res) = read_set.join_next().await {
+        let (file_path, arrays) =
+            res.map_err(|e| LavaError::Parse(format!("join error: {:?}", e)))??;
+        file_arrays.insert(file_path, arrays);
+    }
+
+    // the rows read, row group after row group, and where the hits are among them
+    let mut row_group_arrays: Vec<Vec<ArrayRef>> = vec![];
+    let mut first_rows: HashMap<(&str, usize), usize> = HashMap::new();
+    let mut num_rows = 0;
+    for (file_path, file_row_groups) in wanted.iter() {
+        let arrays = file_arrays.remove(file_path).unwrap();
+        for ((row_group, rows), arrays) in file_row_groups.iter().zip(arrays) {
+            first_rows.insert((file_path.as_str(), *row_group), num_rows);
+            num_rows += rows.iter().map(|(start, end)| end - start).sum::<usize>();
+            row_group_arrays.push(arrays);
+        }
+    }
+    let mut indices: Vec<u64> = vec![];
+    for (file_path, row_group, (start, end)) in izip!(file_paths, row_groups, row_ranges) {
+        if start >= end {
+            continue;
+        }
+        let rows = &wanted[file_path][row_group];
+        let mut index = first_rows[&(file_path.as_str(), *row_group)];
+        for (merged_start, merged_end) in rows {
+            if merged_end <= start {
+                index += merged_end - merged_start;
+            } else {
+                index += start - merged_start;
+                break;
+            }
+        }
+        indices.extend(index as u64..(index + end - start) as u64);
+    }
+    let indices = UInt64Array::from(indices);
+
+    let mut fields = Vec::with_capacity(columns.len());
+    let mut arrays = Vec::with_capacity(columns.len());
+    for (i, column_name) in columns.iter().enumerate() {
+        let column_arrays: Vec<&dyn Array> = row_group_arrays
+            .iter()
+            .map(|arrays| arrays[i].as_ref())
+            .collect();
+        let array = match column_arrays.len() {
+            0 => new_null_array(&DataType::Null, 0),
+            _ => take(concat(&column_arrays)?.as_ref(), &indices, None)?,
+        };
+        fields.push(Field::new(column_name, array.data_type().clone(), true));
+        arrays.push(array);
+    }
+    Ok(RecordBatch::try_new_with_options(
+        Arc::new(Schema::new(fields)),
+        arrays,
+        &RecordBatchOptions::new().with_row_count(Some(indices.len())),
+    )?)
+}
+
+pub fn read_rows(
+    file_paths: Vec<String>,
+    row_groups: Vec<usize>,
+    row_ranges: Vec<(usize, usize)>,
+    columns: Vec<String>,
+    storage_config: StorageConfig,
+    file_metadatas: Option<HashMap<String, Bytes>>,
+) -> Result<RecordBatch, LavaError> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    let res = rt.block_on(read_rows_async(
+        file_paths,
+        row_groups,
+        row_ranges,
+        columns,
+        storage_config,
+        file_metadatas,
+    ));
+    rt.shutdown_background();
+    res
+}
+
+/// Drops the rows of `row_groups`, the rows to read of the file of `reader`, that cannot match
+/// `predicate`: whole row groups whose statistics rule it out, then pages whose column index
+/// does, for the columns of the predicate that have a page index.
+async fn prune_rows(
+    reader: &mut AsyncReader,
+    file: &ParquetFile,
+    predicate: &Predicate,
+    row_groups: RowGroupRows,
+) -> Result<RowGroupRows, LavaError> {
+    let metadata = &file.metadata;
+    let schema_descr = metadata.file_metadata().schema_descr();
+    let mut columns = vec![];
+    for name in predicate.columns() {
+        let column_index = find_column(schema_descr, &name)?;
+        let data_type = ColumnDecoder::new(metadata, column_index)?.data_type;
+        columns.push((name, column_index, data_type));
+    }
+
+    let row_groups: Vec<(usize, Vec<(usize, usize)>)> = row_groups
+        .into_iter()
+        .filter(|(i, _)| {
+            let row_group = metadata.row_group(*i);
+            let stats = columns
+                .iter()
+                .filter_map(|(name, column_index, data_type)| {
+                    let stats = chunk_stats(row_group.column(*column_index), data_type)?;
+                    Some((name.clone(), stats))
+                })
+                .collect();
+            predicate.might_match(&stats)
+        })
+        .collect();
+
+    // the column index and the offset index of every chunk that has both
+    let mut chunks = vec![];
+    let mut ranges = vec![];
+    for (position, (i, _)) in row_groups.iter().enumerate() {
+        for (name, column_index, data_type) in &columns {
+            let column = metadata.row_group(*i).column(*column_index);
+            // page statistics are in the order of the column, which old files do not define
+            if metadata.file_metadata().column_order(*column_index) == ColumnOrder::UNDEFINED {
+                continue;
+            }
+            if let (Some(column_index_range), Some(offset_index_range)) =
+                (column_index_range(column), offset_index_range(column))
+            {
+                ranges.push(column_index_range);
+                ranges.push(offset_index_range);
+                chunks.push((position, name, *column_index, column, data_type));
+            }
+        }
+    }
+    let mut buffers = if ranges.is_empty() {
+        vec![]
+    } else {
+        reader.read_ranges(&ranges).await?
+    }
+    .into_iter();
+
+    let mut pages: Vec<PageStats> = vec![HashMap::new(); row_groups.len()];
+    for (position, name, leaf, column, data_type) in chunks {
+        let i = row_groups[position].0;
+        let num_rows = metadata.row_group(i).num_rows();
+        let (column_index, offset_index) = (buffers.next().unwrap(), buffers.next().unwrap());
+        let index_error = |e: LavaError| {
+            e.context(format!(
+                "parquet file {}, row group {}: page index",
+                reader.filename, i
+            ))
+        };
+        let decryptor = file.column_decryptor(i, leaf).map_err(index_error)?;
+        let locations =
+            decode_offset_index(offset_index, decryptor.as_ref(), num_rows).map_err(index_error)?;
+        let column_index = match &decryptor {
+            Some(decryptor) => decryptor
+                .decrypt_column_index(&column_index)
+                .map_err(index_error)?,
+            None => column_index,
+        };
+        let stats = decode_page_stats(&column_index, column, data_type).map_err(index_error)?;
+        if stats.len() != locations.len() {
+            return Err(index_error(LavaError::Parse(format!(
+                "{} pages in the column index, {} in the offset index",
+                stats.len(),
+                locations.len()
+            ))));
+        }
+        let page_rows = locations.iter().enumerate().map(|(j, location)| {
+            let next_row = locations
+                .get(j + 1)
+                .map_or(num_rows, |next| next.first_row_index);
+            (location.first_row_index as usize, next_row as usize)
+        });
+        pages[position].insert(name.clone(), page_rows.zip(stats).collect());
+    }
+
+    Ok(row_groups
+        .into_iter()
+        .zip(pages)
+        .map(|((i, rows), pages)| {
+            let num_rows = metadata.row_group(i).num_rows() as usize;
+            (
+                i,
+                intersect_rows(&rows, &predicate.might_match_rows(&pages, num_rows)),
+            )
+        })
+        .filter(|(_, rows)| !rows.is_empty())
+        .collect())
+}
+
+/// Like `read_rows`, but returns only the rows of the hits that match `predicate`, in the order
+/// of the hits. Row groups and pages whose statistics rule out a match are not read, the rest are
+/// read with the columns of the predicate, which are then filtered on exactly.
+pub async fn read_filtered_rows_async(
+    file_paths: Vec<String>,
+    row_groups: Vec<usize>,
+    row_ranges: Vec<(usize, usize)>,
+    columns: Vec<String>,
+    predicate: Predicate,
+    storage_config: StorageConfig,
+    file_metadatas: Option<HashMap<String, Bytes>>,
+) -> Result<RecordBatch, LavaError> {
+    if file_paths.len() != row_groups.len() || file_paths.len() != row_ranges.len() {
+        return Err(LavaError::Parse(
+            "file_paths, row_groups and row_ranges must have the same length".to_string(),
+        ));
+    }
+    let metadatas = load_metadatas(&file_paths, storage_config.clone(), file_metadatas).await?;
+    let start_time = std::time::Instant::now();
+
+    let wanted = hit_row_groups(&file_paths, &row_groups, &row_ranges, &metadatas)?;
+    let mut prune_set = JoinSet::new();
+    for (file_path, file_row_groups) in wanted {
+        let file = metadatas[&file_path].clone();
+        let predicate = predicate.clone();
+        let storage_config = storage_config.clone();
+        prune_set.spawn(async move {
+            let mut reader = get_reader(file_path.clone(), storage_config).await?;
+            let rows = prune_rows(&mut reader, &file, &predicate, file_row_groups).await?;
+            Ok::<_, LavaError>((file_path, rows))
+        });
+    }
+    let mut matchable: HashMap<String, RowGroupRows> = HashMap::new();
+    while let Some(res) = prune_set.join_next().await {
+        let (file_path, rows) =
+            res.map_err(|e| LavaError::Parse(format!("join error: {:?}", e)))??;
+        matchable.insert(file_path, rows);
+    }
+    storage_config.record_stage("prune rows", start_time);
+    let start_time = std::time::Instant::now();
+
+    // the hits cut down to the rows that might match
+    let mut hits = (vec![], vec![], vec![]);
+    for (file_path, row_group, rows) in izip!(&file_paths, &row_groups, &row_ranges) {
+        let Some(matchable) = matchable[file_path].get(row_group) else {
+            continue;
+        };
+        for rows in intersect_rows(&[*rows], matchable) {
+            hits.0.push(file_path.clone());
+            hits.1.push(*row_group);
+            hits.2.push(rows);
+        }
+    }
+    let mut read_columns = columns.clone();
+    for column in predicate.columns() {
+        if !read_columns.contains(&column) {
+            read_columns.push(column);
+        }
+    }
+    let mut batch = read_hit_rows(
+        &hits.0,
+        &hits.1,
+        &hits.2,
+        &read_columns,
+        &storage_config,
+        &metadatas,
+    )
+    .await?;
+    // without any rows, e.g. all pruned, the columns are not typed to compare with
+    if batch.num_rows() > 0 {
+        batch = filter_record_batch(&batch, &predicate.evaluate(&batch)?)?;
+    }
+    let batch = batch.project(&(0..columns.len()).collect::<Vec<_>>())?;
+
+    storage_config.record_stage("read rows", start_time);
+
+    Ok(batch)
+}
+
+pub fn read_filtered_rows(
+    file_paths: Vec<String>,
+    row_groups: Vec<usize>,
+    row_ranges: Vec<(usize, usize)>,
+    columns: Vec<String>,
+    predicate: Predicate,
+    storage_config: StorageConfig,
+    file_metadatas: Option<HashMap<String, Bytes>>,
+) -> Result<RecordBatch, LavaError> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    let res = rt.block_on(read_filtered_rows_async(
+        file_paths,
+        row_groups,
+        row_ranges,
+        columns,
+        predicate,
+        storage_config,
+        file_metadatas,
+    ));
+    rt.shutdown_background();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_page, decode_pages, get_parquet_layout, get_parquet_page_layout, page_records,
+        read_filtered_rows, read_indexed_pages, read_rows, stream_parquet_layout, ColumnDecoder,
+        ParquetLayout, MAX_PAGE_HEADER_SIZE,
+    };
+    use crate::formats::cache::MemoryRangeCache;
+    use crate::formats::predicate::{Literal, Predicate};
+    use crate::formats::readers::{
+        register_reader, unregister_reader, ClonableReader, Reader, ReaderFactory, StorageConfig,
+    };
+    use crate::lava::error::LavaError;
+    use arrow::array::make_array;
+    use arrow::buffer::NullBuffer;
+    use arrow::compute::{concat, take};
+    use arrow::datatypes::{DataType, Field, Fields, Float32Type, Int32Type};
+    use arrow_array::{
+        Array, ArrayRef, Decimal128Array, FixedSizeBinaryArray, Float64Array, Int32Array,
+        Int64Array, LargeStringArray, ListArray, RecordBatch, StringArray, StructArray,
+        TimestampMicrosecondArray, UInt64Array,
+    };
+    use parquet::{
+        arrow::ArrowWriter,
+        basic::{Compression, Encoding, Type},
+        column::page::Page,
+        compression::{create_codec, CodecOptionsBuilder},
+        file::{
+            properties::WriterProperties,
+            reader::{FileReader, SerializedFileReader},
+        },
+        format::{DataPageHeader, DataPageHeaderV2, FileMetaData, PageHeader, PageType, Statistics},
+        thrift::TSerializable,
+        util::{DataPageBuilder, DataPageBuilderImpl},
+    };
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use thrift::protocol::{TCompactInputProtocol, TCompactOutputProtocol, TOutputProtocol};
+    use std::ops::Bound;
+    use std::sync::Arc;
+
+    fn write_test_file(name: &str, dictionary: bool) -> (String, Vec<String>) {
+        let path = std::env::temp_dir().join(format!(
+            "rottnest_{}_{}.parquet",
+            name,
+            std::process::id()
+        ));
+        let values: Vec<String> = (0..5000).map(|i| format!("document {}", i % 700)).collect();
+        let array: ArrayRef = Arc::new(StringArray::from(values.clone()));
+        let batch = RecordBatch::try_from_iter(vec![("text", array)]).unwrap();
+
+        let props = WriterProperties::builder()
+            .set_dictionary_enabled(dictionary)
+            .set_data_page_row_count_limit(400)
+            .set_write_batch_size(400)
+            .set_max_row_group_size(2000)
+            .build();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        (path.to_str().unwrap().to_string(), values)
+    }
+
+    fn check_round_trip(name: &str, dictionary: bool) {
+        let (path, values) = write_test_file(name, dictionary);
+        let (arrays, layout) = get_parquet_layout("text", &path, StorageConfig::default()).unwrap();
+
+        let decoded: Vec<String> = arrays
+            .into_iter()
+            .flat_map(|data| {
+                let array = make_array(data);
+                let array = array.as_any().downcast_ref::<StringArray>().unwrap().clone();
+                array.iter().map(|x| x.unwrap().to_string()).collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(decoded, values);
+        assert_eq!(layout.num_row_groups, 3);
+        assert!(layout.data_page_offsets.len() > layout.num_row_groups);
+        // from the offset index
+        assert_eq!(
+            get_parquet_page_layout("text", &path, StorageConfig::default()).unwrap(),
+            layout
+        );
+
+        let mut row_groups = vec![];
+        for (row_group, num_pages) in layout.row_group_data_pages.iter().enumerate() {
+            row_groups.extend(std::iter::repeat(row_group).take(*num_pages));
+        }
+
+        // fetch every other page, in reverse order
+        let wanted: Vec<usize> = (0..layout.data_page_offsets.len()).rev().step_by(2).collect();
+        let pages = read_indexed_pages(
+            "text".to_string(),
+            wanted.iter().map(|_| path.clone()).collect(),
+            wanted.iter().map(|i| row_groups[*i]).collect(),
+            wanted.iter().map(|i| layout.data_page_offsets[*i] as u64).collect(),
+            wanted.iter().map(|i| layout.data_page_sizes[*i]).collect(),
+            wanted.iter().map(|i| layout.dictionary_page_sizes[*i]).collect(),
+            StorageConfig::default(),
+            None,
+            Some(true),
+        )
+        .unwrap();
+
+        let first_rows: Vec<usize> = layout
+            .data_page_num_rows
+            .iter()
+            .scan(0, |acc, x| {
+                let first = *acc;
+                *acc += x;
+                Some(first)
+            })
+            .collect();
+        for (i, data) in wanted.iter().zip(pages) {
+            let array = make_array(data);
+            let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+            assert_eq!(array.len(), layout.data_page_num_rows[*i]);
+            assert_eq!(array.value(0), values[first_rows[*i]]);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_layout_and_read_pages_plain() {
+        check_round_trip("plain", false);
+    }
+
+    #[test]
+    fn test_layout_and_read_pages_dictionary() {
+        check_round_trip("dictionary", true);
+    }
+
+    #[test]
+    fn test_stream_parquet_layout() {
+        let (path, values) = write_test_file("stream", true);
+        let (arrays, layout) = get_parquet_layout("text", &path, StorageConfig::default()).unwrap();
+
+        // a budget smaller than any row group reads them one at a time
+        for memory_budget in [1, 1024 * 1024 * 1024] {
+            let stream =
+                stream_parquet_layout("text", &path, StorageConfig::default(), memory_budget)
+                    .unwrap();
+            let mut streamed = ParquetLayout {
+                num_row_groups: stream.num_row_groups,
+                metadata_bytes: stream.metadata_bytes.clone(),
+                dictionary_page_sizes: vec![],
+                data_page_sizes: vec![],
+                data_page_offsets: vec![],
+                data_page_num_rows: vec![],
+                row_group_data_pages: vec![],
+            };
+            let mut decoded = vec![];
+            for (i, row_group) in stream.enumerate() {
+                let (row_group_arrays, row_group_layout) = row_group.unwrap();
+                assert_eq!(row_group_layout.row_group, i);
+                let num_rows: usize = row_group_arrays.iter().map(|data| data.len()).sum();
+                assert_eq!(num_rows, row_group_layout.data_page_num_rows.iter().sum::<usize>());
+                decoded.extend(row_group_arrays);
+                streamed.push_row_group(row_group_layout);
+            }
+            assert_eq!(streamed, layout);
+            assert_eq!(decoded, arrays);
+        }
+        assert_eq!(
+            arrays.iter().map(|data| data.len()).sum::<usize>(),
+            values.len()
+        );
+        assert!(stream_parquet_layout("missing", &path, StorageConfig::default(), 1).is_err());
+
+        // a stream can be dropped before it is done
+        let mut stream = stream_parquet_layout("text", &path, StorageConfig::default(), 1).unwrap();
+        assert!(stream.next().unwrap().is_ok());
+        drop(stream);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_layout_and_read_pages_typed() {
+        let path =
+            std::env::temp_dir().join(format!("rottnest_typed_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let n = 3000;
+        let columns: Vec<(&str, ArrayRef)> = vec![
+            ("id", Arc::new(Int64Array::from_iter_values(0..n))),
+            (
+                "small",
+                Arc::new(Int32Array::from_iter(
+                    (0..n as i32).map(|i| (i % 7 != 0).then_some(i)),
+                )),
+            ),
+            (
+                "score",
+                Arc::new(Float64Array::from_iter_values((0..n).map(|i| i as f64 / 3.0))),
+            ),
+            (
+                "uuid",
+                Arc::new(
+                    FixedSizeBinaryArray::try_from_iter((0..n).map(|i| (i as u128).to_be_bytes()))
+                        .unwrap(),
+                ),
+            ),
+            (
+                "ts",
+                Arc::new(TimestampMicrosecondArray::from_iter_values(0..n).with_timezone("UTC")),
+            ),
+            (
+                "amount",
+                Arc::new(
+                    Decimal128Array::from_iter_values((0..n).map(|i| i as i128 * 101))
+                        .with_precision_and_scale(12, 2)
+                        .unwrap(),
+                ),
+            ),
+            (
+                "title",
+                Arc::new(LargeStringArray::from_iter_values(
+                    (0..n).map(|i| format!("title {}", i % 50)),
+                )),
+            ),
+        ];
+        let batch = RecordBatch::try_from_iter(columns.clone()).unwrap();
+        let props = WriterProperties::builder()
+            .set_data_page_row_count_limit(400)
+            .set_write_batch_size(400)
+            .set_max_row_group_size(1000)
+            .build();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        for (name, expected) in columns {
+            let (arrays, layout) =
+                get_parquet_layout(name, &path, StorageConfig::default()).unwrap();
+            let arrays: Vec<ArrayRef> = arrays.into_iter().map(make_array).collect();
+            let arrays: Vec<&dyn Array> = arrays.iter().map(|array| array.as_ref()).collect();
+            assert_eq!(concat(&arrays).unwrap().to_data(), expected.to_data(), "{}", name);
+
+            // the last page of the second row group
+            let page = layout.row_group_data_pages[0] + layout.row_group_data_pages[1] - 1;
+            let first_row: usize = layout.data_page_num_rows[..page].iter().sum();
+            let pages = read_indexed_pages(
+                name.to_string(),
+                vec![path.clone()],
+                vec![1],
+                vec![layout.data_page_offsets[page] as u64],
+                vec![layout.data_page_sizes[page]],
+                vec![layout.dictionary_page_sizes[page]],
+                StorageConfig::default(),
+                None,
+                Some(true),
+            )
+            .unwrap();
+            assert_eq!(
+                pages[0],
+                expected
+                    .slice(first_row, layout.data_page_num_rows[page])
+                    .to_data(),
+                "{}",
+                name
+            );
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_layout_and_read_pages_nested() {
+        let path =
+            std::env::temp_dir().join(format!("rottnest_nested_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let n = 3000;
+        let body: ArrayRef = Arc::new(StringArray::from_iter(
+            (0..n).map(|i| (i % 5 != 0).then(|| format!("body {}", i))),
+        ));
+        let id: ArrayRef = Arc::new(Int64Array::from_iter_values(0..n as i64));
+        let payload = StructArray::try_new(
+            Fields::from(vec![
+                Field::new("body", DataType::Utf8, true),
+                Field::new("id", DataType::Int64, false),
+            ]),
+            vec![body, id],
+            Some(NullBuffer::from_iter((0..n).map(|i| i % 7 != 0))),
+        )
+        .unwrap();
+        let embedding = ListArray::from_iter_primitive::<Float32Type, _, _>((0..n).map(|i| {
+            (i % 11 != 0).then(|| (0..i % 4).map(|j| Some((i * j) as f32)).collect::<Vec<_>>())
+        }));
+        let batch = RecordBatch::try_from_iter(vec![
+            ("payload", Arc::new(payload.clone()) as ArrayRef),
+            ("embedding", Arc::new(embedding.clone()) as ArrayRef),
+        ])
+        .unwrap();
+        let props = WriterProperties::builder()
+            .set_data_page_row_count_limit(400)
+            .set_write_batch_size(400)
+            .set_max_row_group_size(1000)
+            .build();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        // the body of a null payload is null
+        let nulls = NullBuffer::union(payload.nulls(), payload.column(0).nulls());
+        let body = make_array(
+            payload.column(0).to_data().into_builder().nulls(nulls).build().unwrap(),
+        );
+        let expected: Vec<(&str, ArrayRef)> = vec![
+            ("payload.body", body.clone()),
+            ("body", body),
+            ("embedding", Arc::new(embedding)),
+        ];
+        for (name, expected) in expected {
+            let (arrays, layout) =
+                get_parquet_layout(name, &path, StorageConfig::default()).unwrap();
+            let arrays: Vec<ArrayRef> = arrays.into_iter().map(make_array).collect();
+            let arrays: Vec<&dyn Array> = arrays.iter().map(|array| array.as_ref()).collect();
+            assert_eq!(concat(&arrays).unwrap().to_data(), expected.to_data(), "{}", name);
+            assert_eq!(layout.data_page_num_rows.iter().sum::<usize>(), n);
+            assert_eq!(
+                get_parquet_page_layout(name, &path, StorageConfig::default()).unwrap(),
+                layout
+            );
+
+            let page = layout.row_group_data_pages[0] + 1;
+            let first_row: usize = layout.data_page_num_rows[..page].iter().sum();
+            let pages = read_indexed_pages(
+                name.to_string(),
+                vec![path.clone()],
+                vec![1],
+                vec![layout.data_page_offsets[page] as u64],
+                vec![layout.data_page_sizes[page]],
+                vec![layout.dictionary_page_sizes[page]],
+                StorageConfig::default(),
+                None,
+                Some(true),
+            )
+            .unwrap();
+            assert_eq!(
+                pages[0],
+                expected
+                    .slice(first_row, layout.data_page_num_rows[page])
+                    .to_data(),
+                "{}",
+                name
+            );
+        }
+        assert!(get_parquet_layout("payload", &path, StorageConfig::default()).is_err());
+        assert!(get_parquet_layout("missing", &path, StorageConfig::default()).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_page_layout_without_offset_index() {
+        let (path, _) = write_test_file("no_offset_index", true);
+        let (_, layout) = get_parquet_layout("text", &path, StorageConfig::default()).unwrap();
+        strip_offset_index(&path);
+
+        let page_layout = get_parquet_page_layout("text", &path, StorageConfig::default()).unwrap();
+        assert_eq!(page_layout.data_page_offsets, layout.data_page_offsets);
+        assert_eq!(page_layout.data_page_sizes, layout.data_page_sizes);
+        assert_eq!(page_layout.dictionary_page_sizes, layout.dictionary_page_sizes);
+        assert_eq!(page_layout.data_page_num_rows, layout.data_page_num_rows);
+        assert_eq!(page_layout.row_group_data_pages, layout.row_group_data_pages);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Rewrites the footer of the file at `path` without the locations of the offset index, the
+    /// old footer stays in the file.
+    fn strip_offset_index(path: &str) {
+        let data = std::fs::read(path).unwrap();
+        let footer_start = data.len() - 8;
+        let metadata_len =
+            u32::from_le_bytes(data[footer_start..footer_start + 4].try_into().unwrap()) as usize;
+        let mut prot =
+            TCompactInputProtocol::new(&data[footer_start - metadata_len..footer_start]);
+        let mut metadata = FileMetaData::read_from_in_protocol(&mut prot).unwrap();
+        for row_group in metadata.row_groups.iter_mut() {
+            for column in row_group.columns.iter_mut() {
+                column.offset_index_offset = None;
+                column.offset_index_length = None;
+            }
+        }
+        let mut footer = vec![];
+        let mut prot = TCompactOutputProtocol::new(&mut footer);
+        metadata.write_to_out_protocol(&mut prot).unwrap();
+        prot.flush().unwrap();
+        let mut rewritten = data[..footer_start - metadata_len].to_vec();
+        rewritten.extend_from_slice(&footer);
+        rewritten.extend_from_slice(&(footer.len() as u32).to_le_bytes());
+        rewritten.extend_from_slice(b"PAR1");
+        std::fs::write(path, rewritten).unwrap();
+    }
+
+    #[test]
+    fn test_read_rows() {
+        let n = 3000;
+        let id: ArrayRef = Arc::new(Int64Array::from_iter_values(0..n as i64));
+        let text: ArrayRef = Arc::new(StringArray::from_iter_values(
+            (0..n).map(|i| format!("document {}", i % 700)),
+        ));
+        let embedding: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Float32Type, _, _>(
+            (0..n).map(|i| Some((0..i % 4).map(|j| Some((i * j) as f32)).collect::<Vec<_>>())),
+        ));
+        let batch = RecordBatch::try_from_iter(vec![
+            ("id", id),
+            ("text", text),
+            ("embedding", embedding),
+        ])
+        .unwrap();
+        let props = WriterProperties::builder()
+            .set_data_page_row_count_limit(200)
+            .set_write_batch_size(200)
+            .set_max_row_group_size(1000)
+            .build();
+        // the second file has no offset index, so whole column chunks are read
+        let mut paths = vec![];
+        for name in ["rows_indexed", "rows_scanned"] {
+            let path = std::env::temp_dir().join(format!(
+                "rottnest_{}_{}.parquet",
+                name,
+                std::process::id()
+            ));
+            let path = path.to_str().unwrap().to_string();
+            let file = std::fs::File::create(&path).unwrap();
+            let mut writer =
+                ArrowWriter::try_new(file, batch.schema(), Some(props.clone())).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+            paths.push(path);
+        }
+        strip_offset_index(&paths[1]);
+
+        // (file, row group, rows), out of order, overlapping and across pages
+        let hits = [
+            (1, 2, (10, 15)),
+            (0, 0, (195, 205)),
+            (0, 2, (999, 1000)),
+            (0, 0, (200, 203)),
+            (1, 0, (0, 0)),
+            (0, 1, (0, 1)),
+        ];
+        let result = read_rows(
+            hits.iter().map(|(file, _, _)| paths[*file].clone()).collect(),
+            hits.iter().map(|(_, row_group, _)| *row_group).collect(),
+            hits.iter().map(|(_, _, rows)| *rows).collect(),
+            vec!["text".to_string(), "id".to_string(), "embedding".to_string()],
+            StorageConfig::default(),
+            None,
+        )
+        .unwrap();
+
+        let rows: Vec<u64> = hits
+            .iter()
+            .flat_map(|(_, row_group, (start, end))| {
+                (row_group * 1000 + start) as u64..(row_group * 1000 + end) as u64
+            })
+            .collect();
+        let rows = UInt64Array::from(rows);
+        assert_eq!(result.num_rows(), rows.len());
+        for (name, column) in [("text", 0), ("id", 1), ("embedding", 2)] {
+            assert_eq!(result.schema().field(column).name(), name);
+            let expected = take(batch.column_by_name(name).unwrap(), &rows, None).unwrap();
+            assert_eq!(result.column(column).to_data(), expected.to_data(), "{}", name);
+        }
+
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_filtered_rows() {
+        let n = 3000;
+        let id: ArrayRef = Arc::new(Int64Array::from_iter_values(0..n as i64));
+        let level: ArrayRef = Arc::new(StringArray::from_iter_values((0..n).map(|i| {
+            if i % 1000 >= 900 {
+                "error"
+            } else {
+                "info"
+            }
+        })));
+        let user: ArrayRef = Arc::new(StringArray::from_iter(
+            (0..n).map(|i| (i % 10 != 0).then(|| format!("user {}", i % 50))),
+        ));
+        let batch =
+            RecordBatch::try_from_iter(vec![("id", id), ("level", level), ("user", user)]).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("rottnest_filtered_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        // without dictionaries, which would be most of what is read
+        let props = WriterProperties::builder()
+            .set_dictionary_enabled(false)
+            .set_data_page_row_count_limit(100)
+            .set_write_batch_size(100)
+            .set_max_row_group_size(1000)
+            .build();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        // all of every row group, and some rows twice
+        let hits = [
+            (0, (0, 1000)),
+            (1, (0, 1000)),
+            (2, (0, 1000)),
+            (1, (950, 960)),
+        ];
+        let read = |predicate: Predicate, storage_config: StorageConfig| {
+            let result = read_filtered_rows(
+                hits.iter().map(|_| path.clone()).collect(),
+                hits.iter().map(|(row_group, _)| *row_group).collect(),
+                hits.iter().map(|(_, rows)| *rows).collect(),
+                vec!["id".to_string(), "user".to_string()],
+                predicate,
+                storage_config,
+                None,
+            )
+            .unwrap();
+            assert_eq!(result.num_columns(), 2);
+            if result.num_rows() == 0 {
+                return vec![];
+            }
+            let ids = result
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            ids.values().to_vec()
+        };
+
+        let storage_config = StorageConfig::default().with_access_recording();
+        let errors_after = Predicate::And(vec![
+            Predicate::Range(
+                "id".to_string(),
+                Bound::Included(Literal::Int(1500)),
+                Bound::Excluded(Literal::Float(2950.5)),
+            ),
+            Predicate::In("level".to_string(), vec![Literal::Str("error".to_string())]),
+        ]);
+        // in the order of the hits
+        let expected: Vec<i64> = (1900..2000).chain(2900..2951).chain(1950..1960).collect();
+        assert_eq!(read(errors_after, storage_config.clone()), expected);
+        // row group 0 is ruled out by its statistics, of row group 1 only the last pages are read
+        let metadata = SerializedFileReader::new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .metadata()
+            .clone();
+        let read_of = |row_group: usize| {
+            let columns = metadata.row_group(row_group).columns();
+            let start = columns.iter().map(|c| c.byte_range().0).min().unwrap();
+            let end = columns
+                .iter()
+                .map(|c| c.byte_range().0 + c.byte_range().1)
+                .max()
+                .unwrap();
+            let read: u64 = storage_config
+                .access_trace()
+                .unwrap()
+                .iter()
+                .filter(|record| record.offset < end && record.offset + record.length > start)
+                .map(|record| record.length)
+                .sum();
+            (read, end - start)
+        };
+        assert_eq!(read_of(0).0, 0);
+        assert!(read_of(1).0 < read_of(1).1 / 2);
+
+        let null_users = Predicate::And(vec![
+            Predicate::IsNull("user".to_string()),
+            Predicate::Eq("id".to_string(), Literal::Int(2010)),
+        ]);
+        assert_eq!(read(null_users, StorageConfig::default()), vec![2010]);
+        let none = Predicate::Eq("level".to_string(), Literal::Str("debug".to_string()));
+        assert!(read(none, StorageConfig::default()).is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_split_records() {
+        let path =
+            std::env::temp_dir().join(format!("rottnest_split_{}.parquet", std::process::id()));
+        let numbers: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(
+            vec![Some(vec![Some(0)])],
+        ));
+        let batch = RecordBatch::try_from_iter(vec![("numbers", numbers)]).unwrap();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        let column_descr = metadata.file_metadata().schema_descr().column(0);
+
+        // records [1, 2], [3, 4, 5] and [6], the second one split across the pages
+        let page = |rep_levels: &[i16], values: &[i32]| {
+            let mut builder =
+                DataPageBuilderImpl::new(column_descr.clone(), values.len() as u32, false);
+            builder.add_rep_levels(column_descr.max_rep_level(), rep_levels);
+            let max_def_level = column_descr.max_def_level();
+            builder.add_def_levels(max_def_level, &vec![max_def_level; values.len()]);
+            builder.add_values::<parquet::data_type::Int32Type>(Encoding::PLAIN, values);
+            builder.consume()
+        };
+        let first = page(&[0, 1, 0, 1], &[1, 2, 3, 4]);
+        let second = page(&[1, 0], &[5, 6]);
+        assert_eq!(page_records(&first, 1).unwrap(), (2, false));
+        assert_eq!(page_records(&second, 1).unwrap(), (1, true));
+
+        let decoder = ColumnDecoder::new(metadata, 0).unwrap();
+        let expected = ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(vec![Some(1), Some(2)]),
+            Some(vec![Some(3), Some(4), Some(5)]),
+            Some(vec![Some(6)]),
+        ]);
+        assert_eq!(
+            decoder.decode_page(vec![first, second.clone()]).unwrap(),
+            expected.slice(0, 2).to_data()
+        );
+        assert_eq!(
+            decoder.decode_page(vec![second]).unwrap(),
+            expected.slice(2, 1).to_data()
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn page_header(type_: PageType, size: i32) -> PageHeader {
+        PageHeader {
+            type_,
+            uncompressed_page_size: size,
+            compressed_page_size: size,
+            crc: None,
+            data_page_header: None,
+            index_page_header: None,
+            dictionary_page_header: None,
+            data_page_header_v2: None,
+        }
+    }
+
+    fn serialize(header: &PageHeader) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut prot = TCompactOutputProtocol::new(&mut bytes);
+        header.write_to_out_protocol(&mut prot).unwrap();
+        prot.flush().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_decode_malformed_pages() {
+        let (path, _) = write_test_file("malformed", false);
+        let layout = get_parquet_page_layout("text", &path, StorageConfig::default()).unwrap();
+        let offset = layout.data_page_offsets[0];
+        let data_page =
+            std::fs::read(&path).unwrap()[offset..offset + layout.data_page_sizes[0]].to_vec();
+        std::fs::remove_file(path).unwrap();
+
+        // pages of other types are skipped
+        let mut bytes = serialize(&page_header(PageType::INDEX_PAGE, 3));
+        bytes.extend([1, 2, 3]);
+        bytes.extend(&data_page);
+        let pages = decode_pages(&bytes.into(), 100, Type::BYTE_ARRAY, &mut None, None).unwrap();
+        assert!(matches!(pages[..], [Page::DataPage { .. }]));
+        assert!(decode_page(
+            page_header(PageType::INDEX_PAGE, 0),
+            vec![].into(),
+            Type::BYTE_ARRAY,
+            None
+        )
+        .is_err());
+
+        let truncated = data_page[..data_page.len() - 1].to_vec();
+        let err = decode_pages(&truncated.into(), 100, Type::BYTE_ARRAY, &mut None, None)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("page at 100"));
+
+        // huge statistics
+        let mut header = page_header(PageType::DATA_PAGE, 0);
+        header.data_page_header = Some(DataPageHeader {
+            num_values: 0,
+            encoding: Encoding::PLAIN.into(),
+            definition_level_encoding: Encoding::RLE.into(),
+            repetition_level_encoding: Encoding::RLE.into(),
+            statistics: Some(Statistics {
+                max_value: Some(vec![0; MAX_PAGE_HEADER_SIZE]),
+                ..Default::default()
+            }),
+        });
+        let err = decode_pages(&serialize(&header).into(), 0, Type::BYTE_ARRAY, &mut None, None)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("page header larger than"));
+
+        // a compressed v2 page of nulls, with levels and no values
+        let mut header = page_header(PageType::DATA_PAGE_V2, 2);
+        header.data_page_header_v2 = Some(DataPageHeaderV2 {
+            num_values: 3,
+            num_nulls: 3,
+            num_rows: 3,
+            encoding: Encoding::PLAIN.into(),
+            definition_levels_byte_length: 2,
+            repetition_levels_byte_length: 0,
+            is_compressed: Some(true),
+            statistics: None,
+        });
+        let options = CodecOptionsBuilder::default().build();
+        let mut codec = create_codec(Compression::SNAPPY, &options)
+            .unwrap()
+            .unwrap();
+        let page = decode_page(header, vec![6, 0].into(), Type::INT32, Some(&mut codec)).unwrap();
+        assert_eq!(page.buffer().as_ref(), &[6, 0]);
+    }
+
+    #[test]
+    fn test_corrupt_page_errors() {
+        let (path, _) = write_test_file("corrupt", false);
+        let layout = get_parquet_page_layout("text", &path, StorageConfig::default()).unwrap();
+        let offset = layout.data_page_offsets[1];
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[offset..offset + 8].fill(0xff);
+        std::fs::write(&path, bytes).unwrap();
+
+        let check = |err: crate::lava::error::LavaError| {
+            let message = err.to_string();
+            assert!(message.contains(&path), "{}", message);
+            assert!(message.contains("row group 0"), "{}", message);
+            assert!(
+                message.contains(&format!("page at {}", offset)),
+                "{}",
+                message
+            );
+        };
+        check(get_parquet_layout("text", &path, StorageConfig::default()).unwrap_err());
+        check(
+            read_indexed_pages(
+                "text".to_string(),
+                vec![path.clone()],
+                vec![0],
+                vec![offset as u64],
+                vec![layout.data_page_sizes[1]],
+                vec![0],
+                StorageConfig::default(),
+                None,
+                Some(true),
+            )
+            .unwrap_err(),
+        );
+        let first_row = layout.data_page_num_rows[0];
+        check(
+            read_rows(
+                vec![path.clone()],
+                vec![0],
+                vec![(first_row, first_row + 1)],
+                vec!["text".to_string()],
+                StorageConfig::default(),
+                None,
+            )
+            .unwrap_err(),
+        );
+        // pages before the corrupt one are still read
+        let batch = read_rows(
+            vec![path.clone()],
+            vec![0],
+            vec![(0, first_row)],
+            vec!["text".to_string()],
+            StorageConfig::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(batch.num_rows(), first_row);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_indexed_pages_cached() {
+        let (path, _) = write_test_file("cached", true);
+        let layout = get_parquet_page_layout("text", &path, StorageConfig::default()).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let dict_page_offsets: Vec<u64> = reader
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|row_group| row_group.column(0).dictionary_page_offset().unwrap() as u64)
+            .collect();
+        let mut row_groups = vec![];
+        for (row_group, num_pages) in layout.row_group_data_pages.iter().enumerate() {
+            row_groups.extend(vec![row_group; *num_pages]);
+        }
+
+        let read = |storage_config: StorageConfig| {
+            let num_pages = layout.data_page_offsets.len();
+            read_indexed_pages(
+                "text".to_string(),
+                vec![path.clone(); num_pages],
+                row_groups.clone(),
+                layout
+                    .data_page_offsets
+                    .iter()
+                    .map(|offset| *offset as u64)
+                    .collect(),
+                layout.data_page_sizes.clone(),
+                layout.dictionary_page_sizes.clone(),
+                storage_config,
+                None,
+                Some(true),
+            )
+            .unwrap()
+        };
+
+        // several pages of every row group, but each dictionary is fetched once
+        let storage_config = StorageConfig::default().with_access_recording();
+        let pages = read(storage_config.clone());
+        let trace = storage_config.access_trace().unwrap();
+        for offset in &dict_page_offsets {
+            let reads: Vec<_> = trace.iter().filter(|r| r.offset == *offset).collect();
+            assert_eq!(reads.len(), 1);
+            assert_eq!(reads[0].count, 1);
+        }
+
+        // the next read takes the footer and the dictionaries from the parquet cache
+        let storage_config = StorageConfig::default().with_access_recording();
+        assert_eq!(read(storage_config.clone()), pages);
+        let trace = storage_config.access_trace().unwrap();
+        assert_eq!(trace.len(), layout.data_page_offsets.len());
+        assert!(trace
+            .iter()
+            .all(|r| layout.data_page_offsets.contains(&(r.offset as usize))));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Serves local files under the `pagecache://` scheme, local readers bypass the range cache.
+    #[derive(Clone)]
+    struct RemoteFile {
+        path: String,
+    }
+
+    #[async_trait]
+    impl Reader for RemoteFile {
+        fn update_filename(&mut self, filename: String) -> Result<(), LavaError> {
+            self.path = filename.trim_start_matches("pagecache://").to_string();
+            Ok(())
+        }
+
+        async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
+            let data = std::fs::read(&self.path)?;
+            Ok(Bytes::from(data).slice(from as usize..to as usize))
+        }
+
+        async fn read_usize_from_end(&mut self, offset: i64, n: u64) -> Result<Vec<u64>, LavaError> {
+            let from = std::fs::metadata(&self.path)?.len() as i64 + offset;
+            self.read_usize_from_start(from as u64, n).await
+        }
+
+        async fn read_usize_from_start(&mut self, offset: u64, n: u64) -> Result<Vec<u64>, LavaError> {
+            let bytes = self.read_range(offset, offset + n * 8).await?;
+            Ok(bytes
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect())
+        }
+    }
+
+    struct RemoteFileFactory;
+
+    #[async_trait]
+    impl ReaderFactory for RemoteFileFactory {
+        async fn get_file_size_and_reader(
+            &self,
+            file: String,
+        ) -> Result<(usize, Box<dyn ClonableReader>), LavaError> {
+            let mut reader = RemoteFile { path: String::new() };
+            reader.update_filename(file)?;
+            let file_size = std::fs::metadata(&reader.path)?.len() as usize;
+            Ok((file_size, Box::new(reader)))
+        }
+    }
+
+    #[test]
+    fn test_read_indexed_pages_range_cache() {
+        let (path, _) = write_test_file("range_cache", false);
+        let layout = get_parquet_page_layout("text", &path, StorageConfig::default()).unwrap();
+        let mut row_groups = vec![];
+        for (row_group, num_pages) in layout.row_group_data_pages.iter().enumerate() {
+            row_groups.extend(vec![row_group; *num_pages]);
+        }
+        register_reader("pagecache", Arc::new(RemoteFileFactory));
+        let remote_path = format!("pagecache://{}", path);
+        let range_cache = Arc::new(MemoryRangeCache::new(1 << 24, 4096));
+
+        let read = |storage_config: StorageConfig| {
+            let num_pages = layout.data_page_offsets.len();
+            read_indexed_pages(
+                "text".to_string(),
+                vec![remote_path.clone(); num_pages],
+                row_groups.clone(),
+                layout
+                    .data_page_offsets
+                    .iter()
+                    .map(|offset| *offset as u64)
+                    .collect(),
+                layout.data_page_sizes.clone(),
+                layout.dictionary_page_sizes.clone(),
+                storage_config,
+                None,
+                Some(true),
+            )
+            .unwrap()
+        };
+
+        let storage_config = StorageConfig::default()
+            .with_range_cache(range_cache.clone())
+            .with_query_stats();
+        let pages = read(storage_config.clone());
+        assert!(storage_config.query_stats().unwrap().cache_misses > 0);
+
+        // the pages were cached by the first read
+        let storage_config = StorageConfig::default()
+            .with_range_cache(range_cache.clone())
+            .with_query_stats();
+        assert_eq!(read(storage_config.clone()), pages);
+        let stats = storage_config.query_stats().unwrap();
+        assert!(stats.cache_hits > 0);
+        assert_eq!((stats.cache_misses, stats.requests), (0, 0));
+
+        unregister_reader("pagecache");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,381 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use arrow::array::{BooleanArray, Float64Array, Int64Array};
+use arrow::compute::kernels::cmp::{eq, gt, gt_eq, lt, lt_eq};
+use arrow::compute::{and, cast, cast_with_options, is_null, or, CastOptions};
+use arrow::datatypes::DataType;
+use arrow_array::{Array, ArrayRef, Datum, RecordBatch, Scalar, StringArray};
+
+use crate::lava::error::LavaError;
+
+/// A value a column is compared with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Literal {
+    /// The order of two values, None if they are not comparable, e.g. a number and a string.
+    /// Integers and floats compare as floats.
+    fn compare(&self, other: &Literal) -> Option<Ordering> {
+        match (self, other) {
+            (Literal::Bool(a), Literal::Bool(b)) => a.partial_cmp(b),
+            (Literal::Int(a), Literal::Int(b)) => a.partial_cmp(b),
+            (Literal::Int(a), Literal::Float(b)) => (*a as f64).partial_cmp(b),
+            (Literal::Float(a), Literal::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Literal::Float(a), Literal::Float(b)) => a.partial_cmp(b),
+            (Literal::Str(a), Literal::Str(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+
+    fn to_array(&self) -> ArrayRef {
+        match self {
+            Literal::Bool(value) => Arc::new(BooleanArray::from(vec![*value])),
+            Literal::Int(value) => Arc::new(Int64Array::from(vec![*value])),
+            Literal::Float(value) => Arc::new(Float64Array::from(vec![*value])),
+            Literal::Str(value) => Arc::new(StringArray::from(vec![value.as_str()])),
+        }
+    }
+}
+
+/// What the statistics of a column chunk or of a page tell about its values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ValueStats {
+    // bounds of the non-null values, None if unknown
+    pub min: Option<Literal>,
+    pub max: Option<Literal>,
+    // whether any value is null, None if unknown
+    pub has_nulls: Option<bool>,
+    pub all_null: bool,
+}
+
+impl ValueStats {
+    /// Whether some of the values might lie between `lower` and `upper`.
+    fn overlaps(&self, lower: Bound<&Literal>, upper: Bound<&Literal>) -> bool {
+        if self.all_null {
+            return false;
+        }
+        let below = match (&self.max, lower) {
+            (Some(max), Bound::Included(lower)) => max.compare(lower) == Some(Ordering::Less),
+            (Some(max), Bound::Excluded(lower)) => {
+                matches!(max.compare(lower), Some(Ordering::Less | Ordering::Equal))
+            }
+            _ => false,
+        };
+        let above = match (&self.min, upper) {
+            (Some(min), Bound::Included(upper)) => min.compare(upper) == Some(Ordering::Greater),
+            (Some(min), Bound::Excluded(upper)) => {
+                matches!(
+                    min.compare(upper),
+                    Some(Ordering::Greater | Ordering::Equal)
+                )
+            }
+            _ => false,
+        };
+        !below && !above
+    }
+}
+
+// column -> (rows, statistics) of each of its pages in a row group
+pub(crate) type PageStats = HashMap<String, Vec<((usize, usize), ValueStats)>>;
+
+/// A filter on the rows of a file, on one or more of its columns, named like `read_rows` takes
+/// them. Comparisons with null never match, `IsNull` has to ask for nulls.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Eq(String, Literal),
+    Range(String, Bound<Literal>, Bound<Literal>),
+    In(String, Vec<Literal>),
+    IsNull(String),
+    And(Vec<Predicate>),
+}
+
+impl Predicate {
+    /// The columns the predicate looks at, each once.
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns: Vec<String> = vec![];
+        match self {
+            Predicate::And(predicates) => {
+                for column in predicates.iter().flat_map(|predicate| predicate.columns()) {
+                    if !columns.contains(&column) {
+                        columns.push(column);
+                    }
+                }
+            }
+            Predicate::Eq(column, _)
+            | Predicate::Range(column, _, _)
+            | Predicate::In(column, _)
+            | Predicate::IsNull(column) => columns.push(column.clone()),
+        }
+        columns
+    }
+
+    /// Whether a value with `stats` might match, for a predicate on a single column.
+    fn leaf_might_match(&self, stats: &ValueStats) -> bool {
+        match self {
+            Predicate::Eq(_, value) => {
+                stats.overlaps(Bound::Included(value), Bound::Included(value))
+            }
+            Predicate::Range(_, lower, upper) => stats.overlaps(lower.as_ref(), upper.as_ref()),
+            Predicate::In(_, values) => values
+                .iter()
+                .any(|value| stats.overlaps(Bound::Included(value), Bound::Included(value))),
+            Predicate::IsNull(_) => stats.has_nulls != Some(false),
+            Predicate::And(_) => true,
+        }
+    }
+
+    /// Whether some of the rows with `stats`, the statistics of (some of) the columns of a row
+    /// group, might match. False only if none can.
+    pub(crate) fn might_match(&self, stats: &HashMap<String, ValueStats>) -> bool {
+        match self {
+            Predicate::And(predicates) => predicates
+                .iter()
+                .all(|predicate| predicate.might_match(stats)),
+            _ => stats
+                .get(&self.columns()[0])
+                .is_none_or(|stats| self.leaf_might_match(stats)),
+        }
+    }
+
+    /// The rows of a row group of `num_rows` rows that might match, as sorted disjoint half-open
+    /// ranges, given the statistics of the pages of (some of) its columns, each page with the
+    /// rows it holds.
+    pub(crate) fn might_match_rows(
+        &self,
+        pages: &PageStats,
+        num_rows: usize,
+    ) -> Vec<(usize, usize)> {
+        match self {
+            Predicate::And(predicates) => {
+                predicates
+                    .iter()
+                    .fold(vec![(0, num_rows)], |rows, predicate| {
+                        intersect_rows(&rows, &predicate.might_match_rows(pages, num_rows))
+                    })
+            }
+            _ => match pages.get(&self.columns()[0]) {
+                Some(pages) => {
+                    let mut rows: Vec<(usize, usize)> = vec![];
+                    for ((start, end), _) in pages
+                        .iter()
+                        .filter(|(_, stats)| self.leaf_might_match(stats))
+                    {
+                        match rows.last_mut() {
+                            Some(last) if last.1 == *start => last.1 = *end,
+                            _ => rows.push((*start, *end)),
+                        }
+                    }
+                    rows
+                }
+                None => vec![(0, num_rows)],
+            },
+        }
+    }
+
+    /// Which rows of `batch`, with a column for every column of the predicate, match.
+    pub(crate) fn evaluate(&self, batch: &RecordBatch) -> Result<BooleanArray, LavaError> {
+        let column = |name: &str| {
+            batch.column_by_name(name).ok_or_else(|| {
+                LavaError::NotFound(format!("column {} of the filter was not read", name))
+            })
+        };
+        let matches = match self {
+            Predicate::And(predicates) => {
+                let mut matches = BooleanArray::from(vec![true; batch.num_rows()]);
+                for predicate in predicates {
+                    matches = and(&matches, &predicate.evaluate(batch)?)?;
+                }
+                matches
+            }
+            Predicate::IsNull(name) => is_null(column(name)?)?,
+            Predicate::Eq(name, value) => {
+                let (array, value) = comparable(column(name)?, value)?;
+                eq(&array, &value)?
+            }
+            Predicate::In(name, values) => {
+                let mut matches = BooleanArray::from(vec![false; batch.num_rows()]);
+                for value in values {
+                    let (array, value) = comparable(column(name)?, value)?;
+                    matches = or(&matches, &eq(&array, &value)?)?;
+                }
+                matches
+            }
+            Predicate::Range(name, lower, upper) => {
+                let mut matches = BooleanArray::from(vec![true; batch.num_rows()]);
+                let bounds = [
+                    (lower.as_ref(), gt_eq as Comparison, gt as Comparison),
+                    (upper.as_ref(), lt_eq, lt),
+                ];
+                for (bound, included, excluded) in bounds {
+                    let (value, compare) = match bound {
+                        Bound::Included(value) => (value, included),
+                        Bound::Excluded(value) => (value, excluded),
+                        Bound::Unbounded => continue,
+                    };
+                    let (array, value) = comparable(column(name)?, value)?;
+                    matches = and(&matches, &compare(&array, &value)?)?;
+                }
+                matches
+            }
+        };
+        Ok(matches)
+    }
+}
+
+type Comparison = fn(&dyn Datum, &dyn Datum) -> Result<BooleanArray, arrow::error::ArrowError>;
+
+/// `array` and `value` as the same type: integers compared with a float are compared as floats,
+/// otherwise `value` is cast to the type of `array`.
+fn comparable(
+    array: &ArrayRef,
+    value: &Literal,
+) -> Result<(ArrayRef, Scalar<ArrayRef>), LavaError> {
+    if matches!(value, Literal::Float(_)) && array.data_type().is_integer() {
+        let array = cast(array, &DataType::Float64)?;
+        return Ok((array, Scalar::new(value.to_array())));
+    }
+    let options = CastOptions {
+        safe: false,
+        ..Default::default()
+    };
+    let value = cast_with_options(&value.to_array(), array.data_type(), &options).map_err(|e| {
+        LavaError::Parse(format!(
+            "cannot compare {:?} with values of type {}: {}",
+            value,
+            array.data_type(),
+            e
+        ))
+    })?;
+    Ok((array.clone(), Scalar::new(value)))
+}
+
+/// The ranges of rows in both `a` and `b`, sorted disjoint half-open ranges.
+pub(crate) fn intersect_rows(a: &[(usize, usize)], b: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut rows = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let start = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        if start < end {
+            rows.push((start, end));
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ops::Bound;
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, BooleanArray, Int32Array, RecordBatch, StringArray};
+
+    use super::{intersect_rows, Literal, Predicate, ValueStats};
+
+    fn stats(min: i64, max: i64, has_nulls: Option<bool>) -> ValueStats {
+        ValueStats {
+            min: Some(Literal::Int(min)),
+            max: Some(Literal::Int(max)),
+            has_nulls,
+            all_null: false,
+        }
+    }
+
+    #[test]
+    fn test_predicate_pruning_and_evaluation() {
+        let id = || "id".to_string();
+        let range = |lower, upper| Predicate::Range(id(), lower, upper);
+        let chunk = HashMap::from([(id(), stats(10, 20, Some(false)))]);
+        assert!(Predicate::Eq(id(), Literal::Int(10)).might_match(&chunk));
+        assert!(!Predicate::Eq(id(), Literal::Int(21)).might_match(&chunk));
+        assert!(Predicate::Eq(id(), Literal::Float(15.5)).might_match(&chunk));
+        assert!(!range(Bound::Excluded(Literal::Int(20)), Bound::Unbounded).might_match(&chunk));
+        assert!(range(Bound::Included(Literal::Int(20)), Bound::Unbounded).might_match(&chunk));
+        assert!(!range(Bound::Unbounded, Bound::Excluded(Literal::Int(10))).might_match(&chunk));
+        assert!(!Predicate::In(id(), vec![Literal::Int(1), Literal::Int(30)]).might_match(&chunk));
+        assert!(!Predicate::IsNull(id()).might_match(&chunk));
+        // strings are not comparable with numbers, unknown columns might match
+        assert!(Predicate::Eq(id(), Literal::Str("a".to_string())).might_match(&chunk));
+        assert!(Predicate::IsNull("other".to_string()).might_match(&chunk));
+
+        let pages = HashMap::from([
+            (
+                id(),
+                vec![
+                    ((0, 10), stats(0, 9, None)),
+                    ((10, 20), stats(10, 19, None)),
+                    ((20, 30), stats(20, 29, None)),
+                ],
+            ),
+            (
+                "name".to_string(),
+                vec![
+                    ((0, 15), ValueStats::default()),
+                    (
+                        (15, 30),
+                        ValueStats {
+                            all_null: true,
+                            ..Default::default()
+                        },
+                    ),
+                ],
+            ),
+        ]);
+        let predicate = Predicate::And(vec![
+            range(Bound::Included(Literal::Int(12)), Bound::Unbounded),
+            Predicate::Eq("name".to_string(), Literal::Str("a".to_string())),
+        ]);
+        // whole pages of id and name
+        assert_eq!(predicate.might_match_rows(&pages, 30), vec![(10, 15)]);
+        assert_eq!(
+            intersect_rows(&[(0, 10), (20, 30)], &[(5, 25)]),
+            vec![(5, 10), (20, 25)]
+        );
+
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "id",
+                Arc::new(Int32Array::from(vec![Some(1), Some(5), None, Some(9)])) as ArrayRef,
+            ),
+            (
+                "name",
+                Arc::new(StringArray::from(vec!["a", "b", "a", "c"])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let predicate = Predicate::And(vec![
+            range(
+                Bound::Excluded(Literal::Int(1)),
+                Bound::Included(Literal::Float(9.0)),
+            ),
+            Predicate::In(
+                "name".to_string(),
+                vec![Literal::Str("b".to_string()), Literal::Str("c".to_string())],
+            ),
+        ]);
+        let matches = predicate.evaluate(&batch).unwrap();
+        assert_eq!(
+            matches,
+            BooleanArray::from(vec![Some(false), Some(true), None, Some(true)])
+        );
+        let matches = Predicate::IsNull(id()).evaluate(&batch).unwrap();
+        assert_eq!(matches, BooleanArray::from(vec![false, false, true, false]));
+        assert!(Predicate::Eq(id(), Literal::Str("x".to_string()))
+            .evaluate(&batch)
+            .is_err());
+    }
+}
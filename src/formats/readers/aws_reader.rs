@@ -2,8 +2,13 @@ use bytes::{Bytes, BytesMut};
 use std::ops::{Deref, DerefMut};
 
 use async_trait::async_trait;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::{DisplayErrorContext, SdkError};
 use aws_sdk_s3::Client;
 
+use super::retry::{error_from_status, RetryPolicy};
+use super::Reader;
+use super::storage::StorageConfig;
 use crate::lava::error::LavaError;
 
 #[derive(Clone)]
@@ -12,6 +17,29 @@ pub struct AsyncAwsReader {
     pub bucket: String,
     pub filename: String,
     pub file_size: u64,
+    // the ETag of the object, set by stat
+    pub version: Option<String>,
+    pub retry_policy: RetryPolicy,
+}
+
+/// Tells permanent failures (missing bucket or key, access denied) apart from throttling and
+/// network errors that are worth retrying.
+pub(crate) fn classify_sdk_error<E>(err: SdkError<E, HttpResponse>, bucket: &str, filename: &str) -> LavaError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let message = format!("s3://{}/{}: {}", bucket, filename, DisplayErrorContext(&err));
+    match &err {
+        SdkError::TimeoutError(_) => LavaError::Timeout(message),
+        SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            LavaError::Unavailable(message)
+        }
+        SdkError::ServiceError(service_error) => {
+            error_from_status(service_error.raw().status().as_u16(), message.clone())
+                .unwrap_or(LavaError::AwsSdk(message))
+        }
+        _ => LavaError::AwsSdk(message),
+    }
 }
 
 impl Deref for AsyncAwsReader {
@@ -35,22 +63,10 @@ impl AsyncAwsReader {
             bucket,
             filename,
             file_size: 0,
+            version: None,
+            retry_policy: RetryPolicy::from_env(),
         }
     }
-
-    async fn stat(&self) -> Result<u64, LavaError> {
-        let (bucket, filename) = (&self.bucket, &self.filename);
-        self.head_object()
-            .bucket(bucket)
-            .key(filename)
-            .send()
-            .await
-            .map_err(|e| LavaError::AwsSdk(e.to_string()))
-            .map(|res| match res.content_length() {
-                Some(size) if size > 0 => size as u64,
-                _ => 0,
-            })
-    }
 }
 
 #[async_trait]
@@ -65,53 +81,77 @@ impl super::Reader for AsyncAwsReader {
         let filename = tokens[1..].join("/");
         self.bucket = bucket;
         self.filename = filename;
+        self.file_size = 0;
+        self.version = None;
 
         Ok(())
     }
 
+    async fn stat(&mut self) -> Result<u64, LavaError> {
+        let (bucket, filename) = (&self.bucket, &self.filename);
+        let response = self
+            .retry_policy
+            .retry(|| async {
+                self.head_object()
+                    .bucket(bucket)
+                    .key(filename)
+                    .send()
+                    .await
+                    .map_err(|e| classify_sdk_error(e, bucket, filename))
+            })
+            .await?;
+        self.version = response
+            .e_tag()
+            .map(|e_tag| e_tag.to_string())
+            .or(response.last_modified().map(|t| t.to_string()));
+        self.file_size = match response.content_length() {
+            Some(size) if size > 0 => size as u64,
+            _ => 0,
+        };
+        Ok(self.file_size)
+    }
+
     async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
         if from >= to {
             return Err(LavaError::Io(std::io::ErrorKind::InvalidData.into()));
         }
 
         let total = to - from;
-        let mut res = BytesMut::with_capacity(total as usize);
         let (bucket, filename) = (&self.bucket, &self.filename);
 
-        let mut object = loop {
-            let this_result = self
-                .get_object()
-                .bucket(bucket)
-                .key(filename)
-                .set_range(Some(format!("bytes={}-{}", from, to - 1)))
-                .send()
-                .await;
-
-            if let Ok(res) = this_result {
-                break res;
-            }
-        };
-
-        while let Some(chunk) = object
-            .body
-            .try_next()
+        self.retry_policy
+            .retry(|| async {
+                let mut object = self
+                    .get_object()
+                    .bucket(bucket)
+                    .key(filename)
+                    .set_range(Some(format!("bytes={}-{}", from, to - 1)))
+                    .send()
+                    .await
+                    .map_err(|e| classify_sdk_error(e, bucket, filename))?;
+
+                let mut res = BytesMut::with_capacity(total as usize);
+                while let Some(chunk) = object.body.try_next().await.map_err(|e| {
+                    LavaError::Unavailable(format!("s3://{}/{}: {}", bucket, filename, e))
+                })? {
+                    res.extend_from_slice(&chunk);
+                }
+
+                if res.len() < total as usize {
+                    return Err(LavaError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+                }
+
+                Ok(res.freeze())
+            })
             .await
-            .map_err(|e| LavaError::AwsSdk(e.to_string()))?
-        {
-            res.extend_from_slice(&chunk);
-        }
-
-        if res.len() < total as usize {
-            return Err(LavaError::Io(std::io::ErrorKind::Interrupted.into()));
-        }
-
-        Ok(res.freeze())
     }
 
     async fn read_usize_from_end(&mut self, offset: i64, n: u64) -> Result<Vec<u64>, LavaError> {
         let mut result: Vec<u64> = vec![];
         if self.file_size == 0 {
-            panic!("file size of reader is uninitialized");
+            return Err(LavaError::Parse(
+                "file size of reader is uninitialized".to_string(),
+            ));
         }
         let from = self.file_size as i64 + offset;
         let to = from + (n as i64) * 8;
@@ -136,38 +176,22 @@ impl super::Reader for AsyncAwsReader {
         });
         Ok(result)
     }
-}
-
-#[derive(Clone)]
-pub struct Config(aws_config::SdkConfig);
-
-impl Config {
-    pub async fn from_env() -> Self {
-        let config = aws_config::load_from_env().await;
-        Config(config)
-    }
-}
-
-#[derive(Clone)]
-pub struct Operator(aws_sdk_s3::Client);
 
-impl From<Config> for Operator {
-    fn from(config: Config) -> Self {
-        Operator(aws_sdk_s3::Client::new(&config.0))
+    fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
     }
-}
 
-impl Operator {
-    fn into_inner(self) -> aws_sdk_s3::Client {
-        self.0
+    fn version(&self) -> Option<String> {
+        self.version.clone()
     }
 }
 
 pub(crate) async fn get_file_size_and_reader(
     file: String,
+    storage_config: &StorageConfig,
 ) -> Result<(usize, AsyncAwsReader), LavaError> {
     // Extract filename
-    let mut reader = get_reader(file.clone()).await?;
+    let mut reader = get_reader(file.clone(), storage_config).await?;
     // Get the file size
     let file_size = reader.stat().await?;
     if file_size == 0 {
@@ -178,22 +202,22 @@ pub(crate) async fn get_file_size_and_reader(
     Ok((file_size as usize, reader))
 }
 
-pub(crate) async fn get_reader(file: String) -> Result<AsyncAwsReader, LavaError> {
+pub(crate) async fn get_reader(
+    file: String,
+    storage_config: &StorageConfig,
+) -> Result<AsyncAwsReader, LavaError> {
     // Extract filename
     if !file.starts_with("s3://") {
         return Err(LavaError::Parse("File scheme not supported".to_string()));
     }
 
-    let config = Config::from_env().await;
-    let operator = Operator::from(config);
-
     let tokens = file[5..].split('/').collect::<Vec<_>>();
     let bucket = tokens[0].to_string();
     let filename = tokens[1..].join("/");
 
     // Create the reader
     Ok(AsyncAwsReader::new(
-        operator.into_inner(),
+        storage_config.s3_client().await,
         bucket.clone(),
         filename.clone(),
     ))
@@ -4,6 +4,9 @@ use reqwest::{self, Client};
 
 use std::ops::{Deref, DerefMut};
 
+use super::retry::{error_from_status, RetryPolicy};
+use super::Reader;
+use super::storage::StorageConfig;
 use crate::lava::error::LavaError;
 
 #[derive(Clone)]
@@ -11,6 +14,18 @@ pub struct AsyncHttpReader {
     reader: Client,
     pub url: String,
     pub file_size: u64,
+    // the ETag or Last-Modified header, set by stat
+    pub version: Option<String>,
+    pub retry_policy: RetryPolicy,
+}
+
+fn check_status(response: reqwest::Response, url: &str) -> Result<reqwest::Response, LavaError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = format!("{}: HTTP status {}", url, status);
+    Err(error_from_status(status.as_u16(), message.clone()).unwrap_or(LavaError::Parse(message)))
 }
 
 impl Deref for AsyncHttpReader {
@@ -32,26 +47,10 @@ impl AsyncHttpReader {
             reader,
             url,
             file_size: 0,
+            version: None,
+            retry_policy: RetryPolicy::from_env(),
         }
     }
-
-    async fn stat(&self) -> Result<u64, LavaError> {
-        let response = self.head(&self.url).send().await?;
-
-        // Checking the response status
-        let length: u64 = if response.status().is_success() {
-            // Retrieving the Content-Length header which indicates the size of the file
-            if let Some(content_length) = response.headers().get(reqwest::header::CONTENT_LENGTH) {
-                content_length.to_str().unwrap().parse().unwrap()
-            } else {
-                panic!("Content-Length header is missing.");
-            }
-        } else {
-            panic!("Failed to retrieve headers, status: {}", response.status());
-        };
-
-        Ok(length)
-    }
 }
 
 #[async_trait]
@@ -59,28 +58,53 @@ impl super::Reader for AsyncHttpReader {
 
     fn update_filename(&mut self, filename: String) -> Result<(), LavaError> {
         self.url = filename;
+        self.file_size = 0;
+        self.version = None;
         Ok(())
     }
 
+    async fn stat(&mut self) -> Result<u64, LavaError> {
+        let url = &self.url;
+        let response = self
+            .retry_policy
+            .retry(|| async { check_status(self.head(url).send().await?, url) })
+            .await?;
+        self.version = [reqwest::header::ETAG, reqwest::header::LAST_MODIFIED]
+            .iter()
+            .find_map(|header| response.headers().get(header))
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+
+        // Retrieving the Content-Length header which indicates the size of the file
+        self.file_size = response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|content_length| content_length.to_str().ok())
+            .and_then(|content_length| content_length.parse().ok())
+            .ok_or(LavaError::Parse(format!(
+                "{}: Content-Length header is missing or invalid",
+                url
+            )))?;
+        Ok(self.file_size)
+    }
+
     async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
         if from >= to {
             return Err(LavaError::Io(std::io::ErrorKind::InvalidData.into()));
         }
 
         let url = &self.url;
-        let response = self
-            .get(url)
-            .header("Range", format!("bytes={}-{}", from, to - 1))
-            .send()
-            .await?;
-
-        let content: Bytes = if response.status().is_success() {
-            response.bytes().await?
-        } else {
-            panic!("Failed to retrieve content, status: {}", response.status());
-        };
-
-        Ok(content)
+        self.retry_policy
+            .retry(|| async {
+                let response = self
+                    .get(url)
+                    .header("Range", format!("bytes={}-{}", from, to - 1))
+                    .send()
+                    .await?;
+                let content: Bytes = check_status(response, url)?.bytes().await?;
+                Ok(content)
+            })
+            .await
     }
 
     async fn read_usize_from_end(&mut self, offset: i64, n: u64) -> Result<Vec<u64>, LavaError> {
@@ -108,15 +132,26 @@ impl super::Reader for AsyncHttpReader {
         });
         Ok(result)
     }
+
+    fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    fn version(&self) -> Option<String> {
+        self.version.clone()
+    }
 }
 
-pub(crate) async fn get_reader(url: String) -> Result<(usize, AsyncHttpReader), LavaError> {
+pub(crate) async fn get_reader(
+    url: String,
+    storage_config: &StorageConfig,
+) -> Result<(usize, AsyncHttpReader), LavaError> {
     // Determine the operator based on the file scheme
     if !url.starts_with("http://") && !url.starts_with("https://") {
         return Err(LavaError::Parse("File scheme not supported".to_string()));
     }
 
-    let mut reader = AsyncHttpReader::new(reqwest::Client::new(), url);
+    let mut reader = AsyncHttpReader::new(storage_config.http_client()?, url);
     let file_size = reader.stat().await?;
     if file_size == 0 {
         return Err(LavaError::Parse("File size is zero".to_string()));
@@ -0,0 +1,193 @@
+use std::env;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// S3 starts answering with SlowDown well before this, but a single process rarely needs more
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 256;
+
+/// Limits on the requests the remote readers send. None means unlimited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadLimits {
+    pub max_concurrent_requests: Option<usize>,
+    pub max_bytes_per_second: Option<u64>,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: Some(DEFAULT_MAX_CONCURRENT_REQUESTS),
+            max_bytes_per_second: None,
+        }
+    }
+}
+
+impl ReadLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_concurrent_requests: None,
+            max_bytes_per_second: None,
+        }
+    }
+
+    /// The default limits, overridden by the ROTTNEST_MAX_CONCURRENT_REQUESTS and
+    /// ROTTNEST_MAX_BYTES_PER_SECOND environment variables (0 means unlimited).
+    pub fn from_env() -> Self {
+        let mut limits = Self::default();
+        if let Some(value) = env_u64("ROTTNEST_MAX_CONCURRENT_REQUESTS") {
+            limits.max_concurrent_requests = (value > 0).then_some(value as usize);
+        }
+        if let Some(value) = env_u64("ROTTNEST_MAX_BYTES_PER_SECOND") {
+            limits.max_bytes_per_second = (value > 0).then_some(value);
+        }
+        limits
+    }
+}
+
+fn env_u64(key: &str) -> Option<u64> {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+}
+
+/// Token bucket holding up to one second worth of bytes. A request larger than what's in the
+/// bucket still goes through, but the bucket goes into debt and the requests after it wait
+/// until the debt is paid off.
+struct TokenBucket {
+    bytes_per_second: f64,
+    // (available tokens, time of the last refill)
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second as f64;
+        Self {
+            bytes_per_second,
+            state: Mutex::new((bytes_per_second, Instant::now())),
+        }
+    }
+
+    async fn take(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(state.1).as_secs_f64() * self.bytes_per_second;
+            state.0 = (state.0 + refill).min(self.bytes_per_second) - bytes as f64;
+            state.1 = now;
+            if state.0 < 0.0 {
+                Duration::from_secs_f64(-state.0 / self.bytes_per_second)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Bounds the number of requests in flight and the bandwidth of the readers sharing it.
+pub struct ReadLimiter {
+    limits: ReadLimits,
+    semaphore: Option<Arc<Semaphore>>,
+    bandwidth: Option<TokenBucket>,
+}
+
+impl ReadLimiter {
+    pub fn new(limits: ReadLimits) -> Self {
+        Self {
+            semaphore: limits
+                .max_concurrent_requests
+                .map(|n| Arc::new(Semaphore::new(n.clamp(1, Semaphore::MAX_PERMITS)))),
+            bandwidth: limits.max_bytes_per_second.map(TokenBucket::new),
+            limits,
+        }
+    }
+
+    pub fn limits(&self) -> &ReadLimits {
+        &self.limits
+    }
+
+    /// Waits until a request for `bytes` bytes may be sent. The request counts as in flight
+    /// until the returned permit is dropped.
+    pub async fn acquire(&self, bytes: u64) -> Option<OwnedSemaphorePermit> {
+        if let Some(bandwidth) = &self.bandwidth {
+            bandwidth.take(bytes).await;
+        }
+        match &self.semaphore {
+            // the semaphore is never closed
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await.unwrap()),
+            None => None,
+        }
+    }
+
+    /// The number of requests currently holding a permit, always 0 without a concurrency limit.
+    pub fn in_flight(&self) -> usize {
+        match (&self.semaphore, self.limits.max_concurrent_requests) {
+            (Some(semaphore), Some(max)) => max.max(1) - semaphore.available_permits(),
+            _ => 0,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref READ_LIMITER: RwLock<Arc<ReadLimiter>> =
+        RwLock::new(Arc::new(ReadLimiter::new(ReadLimits::from_env())));
+}
+
+/// The process wide limiter shared by all remote readers, configured from the environment by
+/// default.
+pub fn get_read_limiter() -> Arc<ReadLimiter> {
+    READ_LIMITER.read().unwrap().clone()
+}
+
+/// Replaces the process wide limits. Only affects readers created afterwards.
+pub fn set_read_limits(limits: ReadLimits) {
+    *READ_LIMITER.write().unwrap() = Arc::new(ReadLimiter::new(limits));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::{ReadLimiter, ReadLimits};
+
+    #[tokio::test]
+    async fn test_read_limiter() {
+        let limiter = Arc::new(ReadLimiter::new(ReadLimits {
+            max_concurrent_requests: Some(2),
+            max_bytes_per_second: None,
+        }));
+        let max_seen = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let limiter = limiter.clone();
+                let max_seen = max_seen.clone();
+                tokio::spawn(async move {
+                    let _permit = limiter.acquire(0).await;
+                    max_seen.fetch_max(limiter.in_flight(), Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(max_seen.load(Ordering::SeqCst), 2);
+        assert_eq!(limiter.in_flight(), 0);
+
+        let limiter = ReadLimiter::new(ReadLimits {
+            max_concurrent_requests: None,
+            max_bytes_per_second: Some(10_000),
+        });
+        let start = Instant::now();
+        // the first second worth of bytes is free, the rest has to wait for the refill
+        limiter.acquire(10_000).await;
+        limiter.acquire(2_000).await;
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+}
@@ -10,12 +10,14 @@ use tokio::{
     io::{AsyncReadExt, AsyncSeekExt},
 };
 
+use super::Reader;
 use crate::lava::error::LavaError;
 
 pub struct AsyncLocalReader {
     reader: File,
     pub file_size: u64,
     pub filename: String,
+    pub version: Option<String>,
 }
 
 impl Deref for AsyncLocalReader {
@@ -39,38 +41,59 @@ impl Clone for AsyncLocalReader {
             reader,
             filename: self.filename.clone(),
             file_size: self.file_size,
+            version: self.version.clone(),
         }
     }
 }
 
+/// Turns a failure to open `filename` into NotFound/PermissionDenied so callers can tell
+/// them apart from transient IO errors.
+pub(super) fn open_error(e: std::io::Error, filename: &str) -> LavaError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => LavaError::NotFound(filename.to_string()),
+        std::io::ErrorKind::PermissionDenied => LavaError::PermissionDenied(filename.to_string()),
+        _ => LavaError::Io(e),
+    }
+}
+
+/// The version of a local file, its modification time and size.
+pub(super) fn local_version(metadata: &std::fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?;
+    let modified = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(format!("{}-{}", modified.as_nanos(), metadata.len()))
+}
+
 impl AsyncLocalReader {
     pub fn new(reader: File, filename: String) -> Self {
         Self {
             reader,
             filename,
             file_size: 0,
+            version: None,
         }
     }
-
-    async fn stat(&self) -> Result<u64, LavaError> {
-        self.metadata()
-            .await
-            .map_err(|e| LavaError::Io(e))
-            .map(|m| m.len())
-    }
 }
 
 #[async_trait]
 impl super::Reader for AsyncLocalReader {
 
     fn update_filename(&mut self, filename: String) -> Result<(), LavaError> {
-        let std_fs = std::fs::File::open(filename.clone()).map_err(|e| LavaError::Io(e))?;
-        self.file_size = std_fs.metadata().map_err(|e| LavaError::Io(e))?.len();
+        let std_fs = std::fs::File::open(filename.clone()).map_err(|e| open_error(e, &filename))?;
+        let metadata = std_fs.metadata().map_err(LavaError::Io)?;
+        self.file_size = metadata.len();
+        self.version = local_version(&metadata);
         self.reader = File::from_std(std_fs);
         self.filename = filename;
         Ok(())
     }
 
+    async fn stat(&mut self) -> Result<u64, LavaError> {
+        let metadata = self.metadata().await.map_err(LavaError::Io)?;
+        self.version = local_version(&metadata);
+        self.file_size = metadata.len();
+        Ok(self.file_size)
+    }
+
     async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
         if from >= to {
             return Err(LavaError::Io(std::io::ErrorKind::InvalidData.into()));
@@ -112,10 +135,16 @@ impl super::Reader for AsyncLocalReader {
         });
         Ok(result)
     }
+
+    fn version(&self) -> Option<String> {
+        self.version.clone()
+    }
 }
 
 pub(crate) async fn get_reader(filename: String) -> Result<(usize, AsyncLocalReader), LavaError> {
-    let file = File::open(filename.clone()).await.map_err(|e| LavaError::Io(e))?;
+    let file = File::open(filename.clone())
+        .await
+        .map_err(|e| open_error(e, &filename))?;
     let mut reader = AsyncLocalReader::new(file, filename);
     let file_size = reader.stat().await?;
 
@@ -0,0 +1,86 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use memmap2::Mmap;
+
+use super::local_reader::{local_version, open_error};
+use crate::lava::error::LavaError;
+
+/// A local reader that maps the whole file into memory. `read_range` hands out slices of the
+/// mapping, so reads don't cost a syscall or a copy. The file must not be truncated while it is
+/// mapped, which is fine for index files since they are never modified once written.
+#[derive(Clone)]
+pub struct AsyncMmapReader {
+    data: Bytes,
+    pub file_size: u64,
+    pub filename: String,
+    pub version: Option<String>,
+}
+
+fn map_file(filename: &str) -> Result<(Bytes, Option<String>), LavaError> {
+    let file = std::fs::File::open(filename).map_err(|e| open_error(e, filename))?;
+    let metadata = file.metadata()?;
+    if metadata.len() == 0 {
+        return Err(LavaError::Parse("File size is zero".to_string()));
+    }
+    // SAFETY: the mapping is read only and index files are not modified after they are written
+    let mmap = unsafe { Mmap::map(&file)? };
+    Ok((Bytes::from_owner(mmap), local_version(&metadata)))
+}
+
+#[async_trait]
+impl super::Reader for AsyncMmapReader {
+    fn update_filename(&mut self, filename: String) -> Result<(), LavaError> {
+        (self.data, self.version) = map_file(&filename)?;
+        self.file_size = self.data.len() as u64;
+        self.filename = filename;
+        Ok(())
+    }
+
+    async fn stat(&mut self) -> Result<u64, LavaError> {
+        Ok(self.file_size)
+    }
+
+    async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
+        if from >= to {
+            return Err(LavaError::Io(std::io::ErrorKind::InvalidData.into()));
+        }
+        if to > self.file_size {
+            return Err(LavaError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(self.data.slice(from as usize..to as usize))
+    }
+
+    async fn read_usize_from_end(&mut self, offset: i64, n: u64) -> Result<Vec<u64>, LavaError> {
+        let from = self.file_size as i64 + offset;
+        if from < 0 {
+            return Err(LavaError::Io(std::io::ErrorKind::InvalidInput.into()));
+        }
+        self.read_usize_from_start(from as u64, n).await
+    }
+
+    async fn read_usize_from_start(&mut self, offset: u64, n: u64) -> Result<Vec<u64>, LavaError> {
+        let bytes = self.read_range(offset, offset + n * 8).await?;
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect())
+    }
+
+    fn version(&self) -> Option<String> {
+        self.version.clone()
+    }
+}
+
+pub(crate) fn get_reader(filename: String) -> Result<(usize, AsyncMmapReader), LavaError> {
+    let (data, version) = map_file(&filename)?;
+    let file_size = data.len();
+    Ok((
+        file_size,
+        AsyncMmapReader {
+            data,
+            file_size: file_size as u64,
+            filename,
+            version,
+        },
+    ))
+}
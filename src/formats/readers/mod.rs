@@ -1,21 +1,42 @@
-use crate::formats::cache;
+use crate::formats::cache::{self, RangeCache};
 use crate::lava::error::LavaError;
 use async_trait::async_trait;
 use bytes::Bytes;
 use local_reader::AsyncLocalReader;
 use serde::de::DeserializeOwned;
 use std::collections::BTreeMap;
-use std::{env, os};
+use std::os;
+use tokio::sync::OwnedSemaphorePermit;
 use std::{
     io::Read,
     ops::{Deref, DerefMut},
+    sync::Arc,
 };
 use zstd::stream::read::Decoder;
 
-use self::{aws_reader::AsyncAwsReader, http_reader::AsyncHttpReader};
+use self::{aws_reader::AsyncAwsReader, http_reader::AsyncHttpReader, mmap_reader::AsyncMmapReader};
+#[cfg(feature = "opendal")]
+pub use opendal_reader::AsyncOpendalReader;
 mod aws_reader;
 mod http_reader;
+mod limiter;
 mod local_reader;
+mod mmap_reader;
+#[cfg(feature = "opendal")]
+mod opendal_reader;
+mod registry;
+mod retry;
+mod stats;
+mod storage;
+mod trace;
+
+pub use limiter::{get_read_limiter, set_read_limits, ReadLimiter, ReadLimits};
+pub use registry::{register_reader, unregister_reader, ClonableReader, ReaderFactory};
+pub(crate) use aws_reader::classify_sdk_error;
+pub use retry::RetryPolicy;
+pub use stats::{QueryStats, QueryStatsCollector};
+pub use storage::{ObjectStoreOptions, StorageConfig};
+pub use trace::{get_access_recorder, set_access_recorder, AccessRecord, AccessRecorder};
 
 #[async_trait]
 pub trait Reader: Send + Sync {
@@ -23,14 +44,40 @@ pub trait Reader: Send + Sync {
     async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError>;
     async fn read_usize_from_end(&mut self, offset: i64, n: u64) -> Result<Vec<u64>, LavaError>;
     async fn read_usize_from_start(&mut self, offset: u64, n: u64) -> Result<Vec<u64>, LavaError>;
+
+    /// Looks up the size of the file, and its version if the reader knows how. 0 means unknown,
+    /// reads of such files bypass the range cache.
+    async fn stat(&mut self) -> Result<u64, LavaError> {
+        Ok(0)
+    }
+
+    /// Readers that talk to a remote service should retry their requests with this policy.
+    fn set_retry_policy(&mut self, _retry_policy: RetryPolicy) {}
+
+    /// Identifies the contents of the file (ETag, mtime and size, ...), captured when the file
+    /// size is looked up. Cached data is only valid for the version it was read from, None
+    /// means unknown.
+    fn version(&self) -> Option<String> {
+        None
+    }
 }
 
 pub const READER_BUFFER_SIZE: usize = 4 * 1024 * 1024;
 pub const WRITER_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+// ranges closer than this are fetched with a single request by read_ranges
+pub const DEFAULT_COALESCE_GAP: u64 = 256 * 1024;
 
 pub struct AsyncReader {
     pub reader: ClonableAsyncReader,
     pub filename: String,
+    pub coalesce_gap: u64,
+    // 0 if unknown, remote reads only go through the range cache if the size is known
+    pub file_size: u64,
+    pub range_cache: Option<Arc<dyn RangeCache>>,
+    // every request of a remote reader waits for all of these, the query limiter comes first
+    pub limiters: Vec<Arc<ReadLimiter>>,
+    pub query_stats: Option<Arc<QueryStatsCollector>>,
+    pub access_recorder: Option<Arc<AccessRecorder>>,
 }
 
 impl Deref for AsyncReader {
@@ -52,18 +99,32 @@ impl Clone for AsyncReader {
         Self {
             reader: match &self.reader {
                 ClonableAsyncReader::Local(reader) => ClonableAsyncReader::Local(reader.clone()),
+                ClonableAsyncReader::Mmap(reader) => ClonableAsyncReader::Mmap(reader.clone()),
                 ClonableAsyncReader::AwsSdk(reader) => ClonableAsyncReader::AwsSdk(reader.clone()),
                 ClonableAsyncReader::Http(reader) => ClonableAsyncReader::Http(reader.clone()),
+                #[cfg(feature = "opendal")]
+                ClonableAsyncReader::Opendal(reader) => ClonableAsyncReader::Opendal(reader.clone()),
+                ClonableAsyncReader::Custom(reader) => ClonableAsyncReader::Custom(reader.box_clone()),
             },
             filename: self.filename.clone(),
+            coalesce_gap: self.coalesce_gap,
+            file_size: self.file_size,
+            range_cache: self.range_cache.clone(),
+            limiters: self.limiters.clone(),
+            query_stats: self.query_stats.clone(),
+            access_recorder: self.access_recorder.clone(),
         }
     }
 }
 
 pub enum ClonableAsyncReader {
     Local(AsyncLocalReader),
+    Mmap(AsyncMmapReader),
     AwsSdk(AsyncAwsReader),
     Http(AsyncHttpReader),
+    #[cfg(feature = "opendal")]
+    Opendal(AsyncOpendalReader),
+    Custom(Box<dyn ClonableReader>),
 }
 
 impl Deref for ClonableAsyncReader {
@@ -72,8 +133,12 @@ impl Deref for ClonableAsyncReader {
     fn deref(&self) -> &Self::Target {
         match self {
             ClonableAsyncReader::Local(reader) => reader,
+            ClonableAsyncReader::Mmap(reader) => reader,
             ClonableAsyncReader::AwsSdk(reader) => reader,
             ClonableAsyncReader::Http(reader) => reader,
+            #[cfg(feature = "opendal")]
+            ClonableAsyncReader::Opendal(reader) => reader,
+            ClonableAsyncReader::Custom(reader) => reader.as_ref(),
         }
     }
 }
@@ -82,8 +147,12 @@ impl DerefMut for ClonableAsyncReader {
     fn deref_mut(&mut self) -> &mut Self::Target {
         match self {
             ClonableAsyncReader::Local(reader) => reader,
+            ClonableAsyncReader::Mmap(reader) => reader,
             ClonableAsyncReader::AwsSdk(reader) => reader,
             ClonableAsyncReader::Http(reader) => reader,
+            #[cfg(feature = "opendal")]
+            ClonableAsyncReader::Opendal(reader) => reader,
+            ClonableAsyncReader::Custom(reader) => reader.as_mut(),
         }
     }
 }
@@ -94,49 +163,244 @@ pub static READ_RANGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 impl AsyncReader {
     pub fn new(reader: ClonableAsyncReader, filename: String) -> Self {
-        Self { reader, filename }
+        Self {
+            reader,
+            filename,
+            coalesce_gap: DEFAULT_COALESCE_GAP,
+            file_size: 0,
+            range_cache: cache::get_range_cache(),
+            limiters: vec![get_read_limiter()],
+            query_stats: None,
+            access_recorder: get_access_recorder(),
+        }
+    }
+
+    /// Adds the limiter of the query, if any, in front of the process wide one, reports to the
+    /// query stats and uses the range cache and access recorder of the query.
+    fn apply_storage_config(&mut self, storage_config: &StorageConfig) {
+        self.range_cache = storage_config.range_cache();
+        if let Some(limiter) = &storage_config.read_limiter {
+            self.limiters.insert(0, limiter.clone());
+        }
+        self.query_stats = storage_config.query_stats.clone();
+        self.access_recorder = storage_config.access_recorder();
     }
 
-    pub fn update_filename(&mut self, filename: String) -> Result<(), LavaError> {
-        self.deref_mut().update_filename(filename)
+    fn is_local(&self) -> bool {
+        matches!(
+            self.reader,
+            ClonableAsyncReader::Local(_) | ClonableAsyncReader::Mmap(_)
+        )
+    }
+
+    /// Waits until a request for `bytes` bytes may be sent, the request counts as in flight until
+    /// the returned permits are dropped. Local reads are never limited.
+    async fn acquire_limiters(&self, bytes: u64) -> Vec<OwnedSemaphorePermit> {
+        let mut permits = vec![];
+        if self.is_local() {
+            return permits;
+        }
+        for limiter in &self.limiters {
+            permits.extend(limiter.acquire(bytes).await);
+        }
+        permits
+    }
+
+    /// Reads [from, to) from the underlying reader, subject to the limiters.
+    async fn fetch_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
+        let _permits = self.acquire_limiters(to - from).await;
+        if let Some(stats) = &self.query_stats {
+            stats.record_read(to - from);
+        }
+        self.deref_mut().read_range(from, to).await
+    }
+
+    pub fn set_range_cache(&mut self, range_cache: Option<Arc<dyn RangeCache>>) {
+        self.range_cache = range_cache;
+    }
+
+    pub fn set_coalesce_gap(&mut self, coalesce_gap: u64) {
+        self.coalesce_gap = coalesce_gap;
+    }
+
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.deref_mut().set_retry_policy(retry_policy)
+    }
+
+    /// Points the reader to another file. Its size and version are looked up like those of a new
+    /// reader, so reads of it go through the range cache.
+    pub async fn update_filename(&mut self, filename: String) -> Result<(), LavaError> {
+        self.deref_mut().update_filename(filename.clone())?;
+        self.filename = filename;
+        self.file_size = 0;
+        self.stat_for_range_cache().await
+    }
+
+    /// Looks up the size and version of the file if its reads can go through the range cache.
+    /// Without a range cache they are not needed, and the request is skipped.
+    async fn stat_for_range_cache(&mut self) -> Result<(), LavaError> {
+        if self.range_cache.is_none() || self.is_local() {
+            return Ok(());
+        }
+        // readers of registered schemes can only be sized by their factory
+        if let (ClonableAsyncReader::Custom(_), Some(factory)) =
+            (&self.reader, registry::lookup_reader_factory(&self.filename))
+        {
+            let (file_size, reader) = factory
+                .get_file_size_and_reader(self.filename.clone())
+                .await?;
+            self.reader = ClonableAsyncReader::Custom(reader);
+            self.file_size = file_size as u64;
+            return Ok(());
+        }
+        self.file_size = self.deref_mut().stat().await?;
+        Ok(())
     }
 
     pub async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
         if from >= to {
             return Err(LavaError::Io(std::io::ErrorKind::InvalidData.into()));
         }
+        if let Some(recorder) = &self.access_recorder {
+            recorder.record(&self.filename, from, to);
+        }
+        self.load_range(from, to).await
+    }
+
+    /// `read_range` without recording the access, for ranges the caller did not ask for as is.
+    async fn load_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
         READ_RANGE_COUNTER.fetch_add(1, Ordering::SeqCst);
 
-        // only check the cache if self.filename has extension .lava
-        if self.filename.ends_with(".lava") {
-            if "true"
-                == env::var_os("CACHE_ENABLE")
-                    .map(|s| s.to_ascii_lowercase())
-                    .unwrap_or_default()
-            {
-                // let path = std::path::Path::new(&value);
-                // find path/filename.cache
-                let mut conn = cache::get_redis_connection().await?;
-                println!("looking in cache: {}", self.filename);
-                let ranges = conn.get_ranges(&self.filename).await?;
-
-                // see if this exists
-                if ranges.len() > 0 {
-                    for ((start, end)) in ranges {
-                        if from >= start as u64 && to <= end as u64 {
-                            println!("cache hit");
-                            let data = conn.get_data(&self.filename, from, to).await?;
-                            let data = data
-                                [(from - start as u64) as usize..(to - start as u64) as usize]
-                                .to_vec();
-                            return Ok(Bytes::from(data));
-                        }
-                    }
+        match self.range_cache.clone() {
+            Some(range_cache) if to <= self.file_size && !self.is_local() => {
+                // blocks of a rewritten file must not be served, the size is the best we can do
+                // for readers that don't know the version of the file
+                let version = self
+                    .deref()
+                    .version()
+                    .unwrap_or_else(|| format!("size-{}", self.file_size));
+                self.read_range_through_cache(&range_cache, &version, from, to)
+                    .await
+            }
+            _ => self.fetch_range(from, to).await,
+        }
+    }
+
+    /// Serves [from, to) from the blocks in the range cache, fetching the missing blocks (runs of
+    /// adjacent missing blocks with one request) and adding them to the cache.
+    async fn read_range_through_cache(
+        &mut self,
+        range_cache: &Arc<dyn RangeCache>,
+        version: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Bytes, LavaError> {
+        let block_size = range_cache.block_size();
+        let first_block = from / block_size;
+        let last_block = (to - 1) / block_size;
+        let file_size = self.file_size;
+        let block_range = |block: u64| {
+            let start = block * block_size;
+            (start, (start + block_size).min(file_size))
+        };
+
+        // the cache is best effort, failed lookups and writes must not fail the read
+        let lookups = (first_block..=last_block).map(|block| {
+            let (start, end) = block_range(block);
+            range_cache.get(&self.filename, version, start, end)
+        });
+        let mut blocks: Vec<Option<Bytes>> = futures::future::join_all(lookups)
+            .await
+            .into_iter()
+            .map(|lookup| {
+                lookup.unwrap_or_else(|e| {
+                    log::warn!("failed to read from {} cache: {}", range_cache.name(), e);
+                    None
+                })
+            })
+            .collect();
+        if let Some(stats) = &self.query_stats {
+            for block in blocks.iter() {
+                match block {
+                    Some(_) => stats.record_cache_hit(),
+                    None => stats.record_cache_miss(),
                 }
             }
         }
 
-        self.deref_mut().read_range(from, to).await
+        let mut i = 0;
+        while i < blocks.len() {
+            if blocks[i].is_some() {
+                i += 1;
+                continue;
+            }
+            let mut j = i;
+            while j < blocks.len() && blocks[j].is_none() {
+                j += 1;
+            }
+            let start = block_range(first_block + i as u64).0;
+            let end = block_range(first_block + j as u64 - 1).1;
+            let data = self.fetch_range(start, end).await?;
+            for (k, block) in blocks.iter_mut().enumerate().take(j).skip(i) {
+                let (block_start, block_end) = block_range(first_block + k as u64);
+                let block_data =
+                    data.slice((block_start - start) as usize..(block_end - start) as usize);
+                if let Err(e) = range_cache
+                    .put(&self.filename, version, block_start, block_data.clone())
+                    .await
+                {
+                    log::warn!("failed to write to {} cache: {}", range_cache.name(), e);
+                }
+                *block = Some(block_data);
+            }
+            i = j;
+        }
+
+        let blocks: Vec<Bytes> = blocks.into_iter().flatten().collect();
+        let offset = (from - first_block * block_size) as usize;
+        let len = (to - from) as usize;
+        if blocks.len() == 1 {
+            return Ok(blocks[0].slice(offset..offset + len));
+        }
+        let mut result = Vec::with_capacity(len);
+        for block in blocks {
+            result.extend_from_slice(&block);
+        }
+        Ok(Bytes::from(result).slice(offset..offset + len))
+    }
+
+    /// Reads several ranges at once. Ranges that overlap or are within `coalesce_gap` bytes of
+    /// each other are merged into one request, the merged requests are issued concurrently and
+    /// the results are sliced back out in the order of `ranges`.
+    pub async fn read_ranges(&mut self, ranges: &[(u64, u64)]) -> Result<Vec<Bytes>, LavaError> {
+        if ranges.iter().any(|(from, to)| from >= to) {
+            return Err(LavaError::Io(std::io::ErrorKind::InvalidData.into()));
+        }
+
+        if let Some(recorder) = &self.access_recorder {
+            for &(from, to) in ranges {
+                recorder.record(&self.filename, from, to);
+            }
+        }
+        let merged = coalesce_ranges(ranges, self.coalesce_gap);
+        let buffers: Vec<Bytes> = if merged.len() == 1 {
+            vec![self.load_range(merged[0].0, merged[0].1).await?]
+        } else {
+            futures::future::try_join_all(merged.iter().map(|&(from, to)| {
+                let mut reader = self.clone();
+                async move { reader.load_range(from, to).await }
+            }))
+            .await?
+        };
+
+        Ok(ranges
+            .iter()
+            .map(|&(from, to)| {
+                let i = merged.partition_point(|&(start, _)| start <= from) - 1;
+                let start = merged[i].0;
+                buffers[i].slice((from - start) as usize..(to - start) as usize)
+            })
+            .collect())
     }
 
     // theoretically we should try to return different types here, but Vec<u64> is def. the most common
@@ -146,15 +410,24 @@ impl AsyncReader {
         to: u64,
     ) -> Result<Vec<u64>, LavaError> {
         let compressed_posting_list_offsets = self.read_range(from, to).await?;
-        let mut decompressor = Decoder::new(&compressed_posting_list_offsets[..])?;
-        let mut serialized_posting_list_offsets: Vec<u8> =
-            Vec::with_capacity(compressed_posting_list_offsets.len() as usize);
-        decompressor.read_to_end(&mut serialized_posting_list_offsets)?;
-        let result: Vec<u64> = bincode::deserialize(&serialized_posting_list_offsets)?;
-        Ok(result)
+        decompress(&compressed_posting_list_offsets)
     }
 
     pub async fn read_usize_from_end(&mut self, n: u64) -> Result<Vec<u64>, LavaError> {
+        // the range is only known if the file size is
+        if (self.range_cache.is_some() || self.access_recorder.is_some())
+            && self.file_size >= 8 * n
+        {
+            let bytes = self.read_range(self.file_size - 8 * n, self.file_size).await?;
+            return Ok(bytes
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect());
+        }
+        let _permits = self.acquire_limiters(8 * n).await;
+        if let Some(stats) = &self.query_stats {
+            stats.record_read(8 * n);
+        }
         self.deref_mut()
             .read_usize_from_end(-8 * (n as i64), n)
             .await
@@ -165,31 +438,60 @@ impl AsyncReader {
 pub enum ReaderType {
     #[default]
     Local,
+    // local files, memory mapped
+    Mmap,
     AwsSdk,
     Http,
+    // any service supported by OpenDAL, local files included
+    #[cfg(feature = "opendal")]
+    Opendal,
 }
 
 impl From<String> for ReaderType {
     fn from(value: String) -> Self {
         match value.to_lowercase().as_str() {
             "local" => ReaderType::Local,
+            "mmap" => ReaderType::Mmap,
             "aws" => ReaderType::AwsSdk,
             "http" => ReaderType::Http,
+            #[cfg(feature = "opendal")]
+            "opendal" => ReaderType::Opendal,
             _ => Default::default(),
         }
     }
 }
 
+/// The reader for `file`: the OpenDAL reader if asked for, otherwise by the scheme of the file.
+/// Schemes the built-in readers don't know go to OpenDAL if it is enabled.
+fn reader_type_for(file: &str, storage_config: &StorageConfig) -> ReaderType {
+    let is_http = file.starts_with("http://") || file.starts_with("https://");
+    #[cfg(feature = "opendal")]
+    if storage_config.reader_type == ReaderType::Opendal
+        || (file.contains("://") && !is_http && !file.starts_with("s3://"))
+    {
+        return ReaderType::Opendal;
+    }
+    if is_http {
+        ReaderType::Http
+    } else if file.starts_with("s3://") {
+        ReaderType::AwsSdk
+    } else if storage_config.reader_type == ReaderType::Mmap {
+        ReaderType::Mmap
+    } else {
+        Default::default()
+    }
+}
+
 pub async fn get_file_sizes_and_readers(
     files: &[String],
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<(Vec<usize>, Vec<AsyncReader>), LavaError> {
     let tasks: Vec<_> = files
         .iter()
         .map(|file| {
             let file = file.clone();
-            let reader_type = reader_type.clone();
-            tokio::spawn(async move { get_file_size_and_reader(file, reader_type).await })
+            let storage_config = storage_config.clone();
+            tokio::spawn(async move { get_file_size_and_reader(file, storage_config).await })
         })
         .collect();
 
@@ -221,14 +523,14 @@ pub async fn get_file_sizes_and_readers(
 
 pub async fn get_readers(
     files: &[String],
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<Vec<AsyncReader>, LavaError> {
     let tasks: Vec<_> = files
         .iter()
         .map(|file| {
             let file = file.clone();
-            let reader_type = reader_type.clone();
-            tokio::spawn(async move { get_reader(file, reader_type).await })
+            let storage_config = storage_config.clone();
+            tokio::spawn(async move { get_reader(file, storage_config).await })
         })
         .collect();
 
@@ -258,71 +560,117 @@ pub async fn get_readers(
 
 pub async fn get_file_size_and_reader(
     file: String,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<(usize, AsyncReader), LavaError> {
-    // always choose opendal for none s3 file
-    let reader_type = if file.starts_with("http://") || file.starts_with("https://") {
-        ReaderType::Http
-    } else if file.starts_with("s3://") {
-        ReaderType::AwsSdk
-    } else {
-        Default::default()
-    };
+    // schemes registered by the user take precedence over the built-in readers
+    if let Some(factory) = registry::lookup_reader_factory(&file) {
+        let (file_size, reader) = factory.get_file_size_and_reader(file.clone()).await?;
+        let mut reader = AsyncReader::new(ClonableAsyncReader::Custom(reader), file);
+        reader.file_size = file_size as u64;
+        reader.apply_storage_config(&storage_config);
+        return Ok((file_size, reader));
+    }
+
+    let reader_type = reader_type_for(&file, &storage_config);
 
-    let (file_size, reader) = match reader_type {
+    let (file_size, mut reader) = match reader_type {
         ReaderType::Local => {
             let (file_size, reader) = local_reader::get_reader(file).await?;
             let filename = reader.filename.clone();
             let reader = AsyncReader::new(ClonableAsyncReader::Local(reader), filename);
             (file_size, reader)
         }
+        ReaderType::Mmap => {
+            let (file_size, reader) = mmap_reader::get_reader(file)?;
+            let filename = reader.filename.clone();
+            let reader = AsyncReader::new(ClonableAsyncReader::Mmap(reader), filename);
+            (file_size, reader)
+        }
         ReaderType::AwsSdk => {
-            let (file_size, reader) = aws_reader::get_file_size_and_reader(file).await?;
+            let (file_size, reader) = aws_reader::get_file_size_and_reader(file, &storage_config).await?;
             let filename = reader.filename.clone();
             let async_reader = AsyncReader::new(ClonableAsyncReader::AwsSdk(reader), filename);
             (file_size, async_reader)
         }
         ReaderType::Http => {
-            let (file_size, reader) = http_reader::get_reader(file).await?;
+            let (file_size, reader) = http_reader::get_reader(file, &storage_config).await?;
             let filename = reader.url.clone();
             let async_reader = AsyncReader::new(ClonableAsyncReader::Http(reader), filename);
             (file_size, async_reader)
         }
+        #[cfg(feature = "opendal")]
+        ReaderType::Opendal => {
+            let (file_size, reader) =
+                opendal_reader::get_file_size_and_reader(file, &storage_config).await?;
+            let filename = reader.filename.clone();
+            let async_reader = AsyncReader::new(ClonableAsyncReader::Opendal(reader), filename);
+            (file_size, async_reader)
+        }
     };
+    reader.file_size = file_size as u64;
+    reader.apply_storage_config(&storage_config);
 
     Ok((file_size, reader))
 }
 
-pub async fn get_reader(file: String, reader_type: ReaderType) -> Result<AsyncReader, LavaError> {
-    // always choose opendal for none s3 file
-    let reader_type = if file.starts_with("http://") || file.starts_with("https://") {
-        ReaderType::Http
-    } else if file.starts_with("s3://") {
-        ReaderType::AwsSdk
-    } else {
-        Default::default()
-    };
+/// The reader for `file`. Its size and version are only looked up if its reads go through the
+/// range cache, which needs them.
+pub async fn get_reader(
+    file: String,
+    storage_config: StorageConfig,
+) -> Result<AsyncReader, LavaError> {
+    if let Some(factory) = registry::lookup_reader_factory(&file) {
+        // only the factory knows the size of the file
+        if storage_config.range_cache().is_some() {
+            let (_file_size, reader) = get_file_size_and_reader(file, storage_config).await?;
+            return Ok(reader);
+        }
+        let reader = factory.get_reader(file.clone()).await?;
+        let mut reader = AsyncReader::new(ClonableAsyncReader::Custom(reader), file);
+        reader.apply_storage_config(&storage_config);
+        return Ok(reader);
+    }
 
-    let reader = match reader_type {
+    let reader_type = reader_type_for(&file, &storage_config);
+
+    let mut reader = match reader_type {
         ReaderType::Local => {
             let (_file_size, reader) = local_reader::get_reader(file).await?;
             let filename = reader.filename.clone();
-            let reader = AsyncReader::new(ClonableAsyncReader::Local(reader), filename);
-            reader
+            AsyncReader::new(ClonableAsyncReader::Local(reader), filename)
+        }
+        ReaderType::Mmap => {
+            let (_file_size, reader) = mmap_reader::get_reader(file)?;
+            let filename = reader.filename.clone();
+            AsyncReader::new(ClonableAsyncReader::Mmap(reader), filename)
         }
         ReaderType::AwsSdk => {
-            let reader = aws_reader::get_reader(file).await?;
+            let reader = aws_reader::get_reader(file, &storage_config).await?;
             let filename = reader.filename.clone();
-            let async_reader = AsyncReader::new(ClonableAsyncReader::AwsSdk(reader), filename);
-            async_reader
+            AsyncReader::new(ClonableAsyncReader::AwsSdk(reader), filename)
         }
         ReaderType::Http => {
-            let (_file_size, reader) = http_reader::get_reader(file).await?;
+            let (file_size, reader) = http_reader::get_reader(file, &storage_config).await?;
             let filename = reader.url.clone();
-            let async_reader = AsyncReader::new(ClonableAsyncReader::Http(reader), filename);
+            let mut async_reader = AsyncReader::new(ClonableAsyncReader::Http(reader), filename);
+            async_reader.file_size = file_size as u64;
+            async_reader
+        }
+        // the reader needs the file size for read_usize_from_end
+        #[cfg(feature = "opendal")]
+        ReaderType::Opendal => {
+            let (file_size, reader) =
+                opendal_reader::get_file_size_and_reader(file, &storage_config).await?;
+            let filename = reader.filename.clone();
+            let mut async_reader = AsyncReader::new(ClonableAsyncReader::Opendal(reader), filename);
+            async_reader.file_size = file_size as u64;
             async_reader
         }
     };
+    reader.apply_storage_config(&storage_config);
+    if reader.file_size == 0 {
+        reader.stat_for_range_cache().await?;
+    }
 
     Ok(reader)
 }
@@ -336,9 +684,258 @@ where
     T: DeserializeOwned,
 {
     let compressed = reader.read_range(start, start + size).await?;
-    let mut decompressor = Decoder::new(&compressed[..]).unwrap();
-    let mut decompressed = Vec::new();
-    std::io::copy(&mut decompressor, &mut decompressed)?;
+    decompress(&compressed)
+}
+
+/// Decompresses a zstd-compressed bincode buffer, the format used by all the lava metadata.
+pub fn decompress<T>(compressed: &[u8]) -> Result<T, LavaError>
+where
+    T: DeserializeOwned,
+{
+    let mut decompressor = Decoder::new(compressed)?;
+    let mut decompressed = Vec::with_capacity(compressed.len());
+    decompressor.read_to_end(&mut decompressed)?;
     let result: T = bincode::deserialize(&decompressed)?;
     Ok(result)
 }
+
+/// Sorts `ranges` and merges the ones that overlap or are at most `gap` bytes apart.
+pub fn coalesce_ranges(ranges: &[(u64, u64)], gap: u64) -> Vec<(u64, u64)> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(sorted.len());
+    for (from, to) in sorted {
+        match merged.last_mut() {
+            Some(last) if from <= last.1.saturating_add(gap) => last.1 = last.1.max(to),
+            _ => merged.push((from, to)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        coalesce_ranges, get_file_size_and_reader, get_reader, local_reader, AsyncReader,
+        ClonableAsyncReader, ReaderType, StorageConfig,
+    };
+    use crate::formats::cache::{DiskCache, MemoryRangeCache, RangeCache};
+    use bytes::Bytes;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_coalesce_ranges() {
+        let ranges = vec![(100, 200), (0, 10), (15, 20), (150, 160), (1000, 1001)];
+        assert_eq!(
+            coalesce_ranges(&ranges, 5),
+            vec![(0, 20), (100, 200), (1000, 1001)]
+        );
+        assert_eq!(
+            coalesce_ranges(&ranges, 0),
+            vec![(0, 10), (15, 20), (100, 200), (1000, 1001)]
+        );
+        assert_eq!(coalesce_ranges(&ranges, 1000), vec![(0, 1001)]);
+    }
+
+    #[tokio::test]
+    async fn test_read_ranges() {
+        let path =
+            std::env::temp_dir().join(format!("rottnest_read_ranges_{}", std::process::id()));
+        let data: Vec<u8> = (0..=255u8).cycle().take(10_000).collect();
+        std::fs::File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let mut reader = get_reader(path.to_str().unwrap().to_string(), ReaderType::Local.into())
+            .await
+            .unwrap();
+        let ranges = vec![(9000, 10_000), (0, 16), (8, 32), (5000, 5001)];
+        for gap in [0, 64, 100_000] {
+            reader.set_coalesce_gap(gap);
+            let result = reader.read_ranges(&ranges).await.unwrap();
+            for ((from, to), bytes) in ranges.iter().zip(result) {
+                assert_eq!(&bytes[..], &data[*from as usize..*to as usize]);
+            }
+        }
+        assert!(reader.read_ranges(&[(10, 10)]).await.is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_range_through_disk_cache() {
+        let path = std::env::temp_dir().join(format!("rottnest_disk_read_{}", std::process::id()));
+        let cache_dir =
+            std::env::temp_dir().join(format!("rottnest_disk_read_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let data: Vec<u8> = (0..=255u8).cycle().take(1050).collect();
+        std::fs::File::create(&path).unwrap().write_all(&data).unwrap();
+
+        // local files bypass the disk cache, so pretend this one is remote
+        let (file_size, local) = local_reader::get_reader(path.to_str().unwrap().to_string())
+            .await
+            .unwrap();
+        let mut reader = AsyncReader::new(
+            ClonableAsyncReader::Custom(Box::new(local)),
+            path.to_str().unwrap().to_string(),
+        );
+        reader.file_size = file_size as u64;
+        let disk_cache = Arc::new(DiskCache::new(&cache_dir, 1 << 20, 100).unwrap());
+        reader.set_range_cache(Some(disk_cache.clone()));
+
+        for (from, to) in [(150, 160), (50, 420), (0, 1050), (990, 1050), (399, 401)] {
+            let bytes = reader.read_range(from, to).await.unwrap();
+            assert_eq!(&bytes[..], &data[from as usize..to as usize]);
+        }
+        // 11 blocks and the index of the file
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 12);
+        assert_eq!(
+            reader.read_usize_from_end(1).await.unwrap()[0],
+            u64::from_le_bytes(data[1042..].try_into().unwrap())
+        );
+
+        // a rewritten file of the same size has another version, its blocks are fetched again
+        let data: Vec<u8> = data.iter().map(|b| b.wrapping_add(1)).collect();
+        let file = std::fs::File::create(&path).unwrap();
+        (&file).write_all(&data).unwrap();
+        file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(1))
+            .unwrap();
+        let (file_size, local) = local_reader::get_reader(path.to_str().unwrap().to_string())
+            .await
+            .unwrap();
+        let mut reader = AsyncReader::new(
+            ClonableAsyncReader::Custom(Box::new(local)),
+            path.to_str().unwrap().to_string(),
+        );
+        reader.file_size = file_size as u64;
+        reader.set_range_cache(Some(disk_cache.clone()));
+        assert_eq!(&reader.read_range(150, 160).await.unwrap()[..], &data[150..160]);
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mmap_reader() {
+        let path = std::env::temp_dir().join(format!("rottnest_mmap_{}", std::process::id()));
+        let data: Vec<u8> = (0..100u64).flat_map(|i| i.to_le_bytes()).collect();
+        std::fs::File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let (file_size, mut reader) =
+            get_file_size_and_reader(path.to_str().unwrap().to_string(), "mmap".to_string().into())
+                .await
+                .unwrap();
+        assert!(matches!(reader.reader, ClonableAsyncReader::Mmap(_)));
+        assert_eq!(file_size, 800);
+        assert_eq!(&reader.read_range(8, 24).await.unwrap()[..], &data[8..24]);
+        assert_eq!(reader.read_usize_from_end(2).await.unwrap(), vec![98, 99]);
+        assert_eq!(
+            reader.clone().read_ranges(&[(0, 8), (792, 800)]).await.unwrap(),
+            vec![&data[0..8], &data[792..800]]
+        );
+        assert!(reader.read_range(790, 801).await.is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_query_stats() {
+        let path = std::env::temp_dir().join(format!("rottnest_stats_{}", std::process::id()));
+        std::fs::File::create(&path).unwrap().write_all(&[0u8; 1000]).unwrap();
+
+        let storage_config = StorageConfig::default().with_query_stats();
+        let mut reader = get_reader(path.to_str().unwrap().to_string(), storage_config.clone())
+            .await
+            .unwrap();
+        reader.read_range(0, 100).await.unwrap();
+        reader.clone().read_range(100, 600).await.unwrap();
+        reader.read_usize_from_end(2).await.unwrap();
+        storage_config.record_stage("read", std::time::Instant::now());
+
+        let stats = storage_config.query_stats().unwrap();
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.bytes_read, 616);
+        assert_eq!(stats.largest_read, 500);
+        assert_eq!(stats.stages.len(), 1);
+        assert!(StorageConfig::default().query_stats().is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_range_through_range_cache() {
+        let path = std::env::temp_dir().join(format!("rottnest_range_cache_{}", std::process::id()));
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        std::fs::File::create(&path).unwrap().write_all(&data).unwrap();
+
+        // local files bypass the cache, so pretend this one is remote
+        let (file_size, local) = local_reader::get_reader(path.to_str().unwrap().to_string())
+            .await
+            .unwrap();
+        let version = local.version.clone().unwrap();
+        let mut reader = AsyncReader::new(
+            ClonableAsyncReader::Custom(Box::new(local)),
+            path.to_str().unwrap().to_string(),
+        );
+        reader.file_size = file_size as u64;
+        let range_cache = Arc::new(MemoryRangeCache::new(1 << 20, 100));
+        reader.set_range_cache(Some(range_cache.clone()));
+        let storage_config = StorageConfig::default().with_query_stats();
+        reader.query_stats = storage_config.query_stats.clone();
+
+        // blocks 2 and 5 are cached, the holes around them take one request each
+        for block in [200, 500] {
+            let bytes = Bytes::copy_from_slice(&data[block..block + 100]);
+            range_cache
+                .put(&reader.filename, &version, block as u64, bytes)
+                .await
+                .unwrap();
+        }
+        assert_eq!(&reader.read_range(150, 650).await.unwrap()[..], &data[150..650]);
+        let stats = storage_config.query_stats().unwrap();
+        assert_eq!((stats.cache_hits, stats.cache_misses), (2, 4));
+        assert_eq!((stats.requests, stats.bytes_read), (3, 400));
+
+        // the holes were written back
+        assert_eq!(&reader.read_range(100, 700).await.unwrap()[..], &data[100..700]);
+        let stats = storage_config.query_stats().unwrap();
+        assert_eq!((stats.cache_hits, stats.requests), (8, 3));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_filename_stat() {
+        let path = std::env::temp_dir().join(format!("rottnest_update_stat_{}", std::process::id()));
+        std::fs::File::create(&path).unwrap().write_all(&[1u8; 1000]).unwrap();
+        let empty_path = path.with_extension("empty");
+        std::fs::File::create(&empty_path).unwrap();
+        let filename = path.to_str().unwrap().to_string();
+
+        // local files bypass the cache, so pretend this one is remote
+        let (_, local) = local_reader::get_reader(filename.clone()).await.unwrap();
+        let mut reader = AsyncReader::new(
+            ClonableAsyncReader::Custom(Box::new(local)),
+            filename.clone(),
+        );
+
+        // without a range cache the size is not needed and not looked up
+        reader.set_range_cache(None);
+        reader.update_filename(filename.clone()).await.unwrap();
+        assert_eq!(reader.file_size, 0);
+
+        reader.set_range_cache(Some(Arc::new(MemoryRangeCache::new(1 << 20, 100))));
+        reader.update_filename(filename.clone()).await.unwrap();
+        assert_eq!(reader.file_size, 1000);
+
+        // empty files are fine, their reads bypass the cache
+        reader
+            .update_filename(empty_path.to_str().unwrap().to_string())
+            .await
+            .unwrap();
+        assert_eq!(reader.file_size, 0);
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(empty_path).unwrap();
+    }
+}
@@ -1,183 +1,270 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
 use async_trait::async_trait;
 use bytes::Bytes;
-use opendal::services::{Fs, S3};
-use opendal::{FuturesAsyncReader, Operator};
-use std::env;
-use std::io::SeekFrom;
-use std::ops::{Deref, DerefMut};
-use futures::{AsyncSeekExt, AsyncReadExt};
-
+use opendal::{ErrorKind, Operator, Scheme};
 
+use super::retry::RetryPolicy;
+use super::Reader;
+use super::storage::StorageConfig;
 use crate::lava::error::LavaError;
 
-pub const READER_BUFFER_SIZE: usize = 4 * 1024 * 1024;
-
+/// Reads through an OpenDAL operator, which gives us every storage service OpenDAL supports
+/// (GCS, Azure Blob, WebHDFS, WebDAV, ...) with one reader. Operators are cheap to clone, so
+/// clones of the reader share the connection pool.
+#[derive(Clone)]
 pub struct AsyncOpendalReader {
-    reader: FuturesAsyncReader,
+    operator: Operator,
+    // the bucket or container the operator is bound to, if any
+    bucket: Option<String>,
+    // the path of the file within the operator
+    path: String,
     pub filename: String,
+    pub file_size: u64,
+    // the ETag or last modification time, set by stat
+    pub version: Option<String>,
+    pub retry_policy: RetryPolicy,
 }
 
-impl Deref for AsyncOpendalReader {
-    type Target = FuturesAsyncReader;
+fn bucket_of(config: &HashMap<String, String>) -> Option<String> {
+    config.get("bucket").or(config.get("container")).cloned()
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.reader
+pub(crate) fn classify_opendal_error(err: opendal::Error, filename: &str) -> LavaError {
+    let message = format!("{}: {}", filename, err);
+    match err.kind() {
+        ErrorKind::NotFound => LavaError::NotFound(message),
+        ErrorKind::PermissionDenied => LavaError::PermissionDenied(message),
+        ErrorKind::Unsupported | ErrorKind::ConfigInvalid => LavaError::Unsupported(message),
+        ErrorKind::RateLimited => LavaError::Unavailable(message),
+        _ if err.is_temporary() => LavaError::Unavailable(message),
+        _ => LavaError::Parse(message),
     }
 }
 
-impl DerefMut for AsyncOpendalReader {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.reader
+/// Splits `scheme://bucket/path` into the OpenDAL service, its config and the path within it.
+/// Paths without a scheme are local files. The `opendal` object store options are passed on to
+/// the service as is, on top of the ones derived from the other object store options.
+fn operator_config(
+    file: &str,
+    storage_config: &StorageConfig,
+) -> Result<(Scheme, HashMap<String, String>, String), LavaError> {
+    let mut config: HashMap<String, String> = storage_config.object_store.opendal.clone();
+    let Some((scheme, rest)) = file.split_once("://") else {
+        config.insert("root".to_string(), "/".to_string());
+        let path = std::path::absolute(file)?;
+        return Ok((Scheme::Fs, config, path.to_string_lossy().to_string()));
+    };
+    let (bucket, path) = rest.split_once('/').unwrap_or((rest, ""));
+
+    let scheme = match scheme {
+        "gs" => Scheme::Gcs,
+        "az" | "abfs" => Scheme::Azblob,
+        scheme => Scheme::from_str(scheme)
+            .map_err(|e| LavaError::Unsupported(format!("{}: {}", file, e)))?,
+    };
+    match scheme {
+        Scheme::S3 => {
+            let options = &storage_config.object_store;
+            config.insert("bucket".to_string(), bucket.to_string());
+            for (key, value) in [
+                ("endpoint", &options.endpoint_url),
+                ("region", &options.region),
+                ("access_key_id", &options.access_key_id),
+                ("secret_access_key", &options.secret_access_key),
+                ("session_token", &options.session_token),
+            ] {
+                if let Some(value) = value {
+                    config.entry(key.to_string()).or_insert(value.clone());
+                }
+            }
+        }
+        Scheme::Gcs => {
+            config.insert("bucket".to_string(), bucket.to_string());
+        }
+        Scheme::Azblob => {
+            config.insert("container".to_string(), bucket.to_string());
+        }
+        Scheme::Fs | Scheme::Memory => {
+            config.insert("root".to_string(), "/".to_string());
+            return Ok((scheme, config, format!("{}/{}", bucket, path)));
+        }
+        // everything else is configured through the options, e.g. the endpoint of WebDAV
+        _ => return Ok((scheme, config, rest.to_string())),
     }
+    Ok((scheme, config, path.to_string()))
 }
 
 impl AsyncOpendalReader {
-    pub fn new(reader: FuturesAsyncReader, filename: String) -> Self {
-        Self { reader, filename }
+    pub fn new(operator: Operator, bucket: Option<String>, path: String, filename: String) -> Self {
+        Self {
+            operator,
+            bucket,
+            path,
+            filename,
+            file_size: 0,
+            version: None,
+            retry_policy: RetryPolicy::from_env(),
+        }
     }
 }
 
 #[async_trait]
 impl super::Reader for AsyncOpendalReader {
+    fn update_filename(&mut self, file: String) -> Result<(), LavaError> {
+        let (scheme, config, path) = operator_config(&file, &StorageConfig::default())?;
+        if scheme != self.operator.info().scheme() || bucket_of(&config) != self.bucket {
+            return Err(LavaError::Parse(format!(
+                "cannot read {} with a {} reader",
+                file,
+                self.operator.info().scheme()
+            )));
+        }
+        self.path = path;
+        self.filename = file;
+        self.file_size = 0;
+        self.version = None;
+        Ok(())
+    }
+
+    async fn stat(&mut self) -> Result<u64, LavaError> {
+        let metadata = self
+            .retry_policy
+            .retry(|| async {
+                self.operator
+                    .stat(&self.path)
+                    .await
+                    .map_err(|e| classify_opendal_error(e, &self.filename))
+            })
+            .await?;
+        self.version = metadata
+            .etag()
+            .map(|etag| etag.to_string())
+            .or(metadata.last_modified().map(|t| t.to_rfc3339()));
+        self.file_size = metadata.content_length();
+        Ok(self.file_size)
+    }
+
     async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
         if from >= to {
             return Err(LavaError::Io(std::io::ErrorKind::InvalidData.into()));
         }
 
-        let reader = self;
-
-        let total = to - from;
-        let mut res = vec![0; total as usize];
-        reader.seek(SeekFrom::Start(from)).await?;
-        reader.read_exact(&mut res).await?;
-        Ok(res.into())
+        self.retry_policy
+            .retry(|| async {
+                let buffer = self
+                    .operator
+                    .read_with(&self.path)
+                    .range(from..to)
+                    .await
+                    .map_err(|e| classify_opendal_error(e, &self.filename))?;
+                if buffer.len() < (to - from) as usize {
+                    return Err(LavaError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+                }
+                Ok(buffer.to_bytes())
+            })
+            .await
     }
 
     async fn read_usize_from_end(&mut self, offset: i64, n: u64) -> Result<Vec<u64>, LavaError> {
-        let reader = self;
-        reader.seek(SeekFrom::End(offset)).await?;
-        let mut result: Vec<u64> = vec![];
-        for _print in 0..n {
-            let mut buffer = vec![0; 8];
-            reader.read_exact(&mut buffer).await?;
-            result.push(u64::from_le_bytes([
-                buffer[0], buffer[1], buffer[2], buffer[3], buffer[4], buffer[5], buffer[6],
-                buffer[7],
-            ]));
+        if self.file_size == 0 {
+            return Err(LavaError::Parse(
+                "file size of reader is uninitialized".to_string(),
+            ));
         }
-        Ok(result)
+        let from = (self.file_size as i64 + offset) as u64;
+        self.read_usize_from_start(from, n).await
     }
 
     async fn read_usize_from_start(&mut self, offset: u64, n: u64) -> Result<Vec<u64>, LavaError> {
-        let reader = self;
-        reader.seek(SeekFrom::Start(offset as u64)).await?;
-        let mut result: Vec<u64> = vec![];
-        for _ in 0..n {
-            let mut buffer = vec![0; 8];
-            reader.read(&mut buffer).await?;
-            result.push(u64::from_le_bytes([
-                buffer[0], buffer[1], buffer[2], buffer[3], buffer[4], buffer[5], buffer[6],
-                buffer[7],
-            ]));
-        }
-        Ok(result)
+        let bytes = self.read_range(offset, offset + n * 8).await?;
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect())
     }
-}
-
-//dupilcate code for now
-pub(crate) struct S3Builder(S3);
 
-impl From<&str> for S3Builder {
-    fn from(file: &str) -> Self {
-        let mut builder = S3::default();
-        let mut iter = file[5..].split("/");
-
-        builder.bucket(iter.next().expect("malformed path"));
-        // Set the region. This is required for some services, if you don't care about it, for example Minio service, just set it to "auto", it will be ignored.
-        if let Ok(value) = env::var("AWS_ENDPOINT_URL") {
-            builder.endpoint(&value);
-        }
-        if let Ok(value) = env::var("AWS_REGION") {
-            builder.region(&value);
-        }
-        if let Ok(_value) = env::var("AWS_VIRTUAL_HOST_STYLE") {
-            builder.enable_virtual_host_style();
-        }
-
-        S3Builder(builder)
+    fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
     }
-}
 
-pub(crate) struct FsBuilder(Fs);
-
-impl From<&str> for FsBuilder {
-    fn from(folder: &str) -> Self {
-        let mut builder = Fs::default();
-        // let current_path = env::current_dir().expect("no path");
-        builder.root(folder);
-        FsBuilder(builder)
+    fn version(&self) -> Option<String> {
+        self.version.clone()
     }
 }
 
-pub(crate) struct Operators(Operator);
-
-impl From<S3Builder> for Operators {
-    fn from(builder: S3Builder) -> Self {
-        Operators(
-            Operator::new(builder.0)
-                .expect("S3 builder construction error")
-                .finish(),
-        )
-    }
+pub(crate) async fn get_reader(
+    file: String,
+    storage_config: &StorageConfig,
+) -> Result<AsyncOpendalReader, LavaError> {
+    let (scheme, config, path) = operator_config(&file, storage_config)?;
+    let bucket = bucket_of(&config);
+    let operator = storage_config.opendal_operator(scheme, config)?;
+    Ok(AsyncOpendalReader::new(operator, bucket, path, file))
 }
 
-impl Operators {
-    pub(crate) fn into_inner(self) -> Operator {
-        self.0
-    }
+pub(crate) async fn get_file_size_and_reader(
+    file: String,
+    storage_config: &StorageConfig,
+) -> Result<(usize, AsyncOpendalReader), LavaError> {
+    let mut reader = get_reader(file, storage_config).await?;
+    reader.file_size = reader.stat().await?;
+    Ok((reader.file_size as usize, reader))
 }
 
-impl From<FsBuilder> for Operators {
-    fn from(builder: FsBuilder) -> Self {
-        Operators(
-            Operator::new(builder.0)
-                .expect("Fs Builder construction error")
-                .finish(),
-        )
-    }
-}
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
 
-pub(crate) async fn get_reader(
-    file: String,
-) -> Result<(usize, AsyncOpendalReader), LavaError> {
-    // Determine the operator based on the file scheme
-    let operator = if file.starts_with("s3://") {
-        Operators::from(S3Builder::from(file.as_str())).into_inner()
-    } else {
-        let current_path = env::current_dir()?;
-        Operators::from(FsBuilder::from(current_path.to_str().expect("no path"))).into_inner()
+    use super::operator_config;
+    use crate::formats::readers::{
+        get_file_size_and_reader, ClonableAsyncReader, ReaderType, StorageConfig,
     };
+    use crate::lava::error::LavaError;
 
-    // Extract filename
-    let filename = if file.starts_with("s3://") {
-        file[5..].split('/').collect::<Vec<_>>()[1..].join("/")
-    } else {
-        file.clone()
-    };
+    #[tokio::test]
+    async fn test_opendal_memory_and_fs() {
+        let data: Vec<u8> = (0..100u64).flat_map(|i| i.to_le_bytes()).collect();
+
+        // memory operators are shared through the config, so the file written here is visible
+        // to the readers created from it
+        let storage_config = StorageConfig::default();
+        let (scheme, config, path) =
+            operator_config("memory://bucket/a.lava", &storage_config).unwrap();
+        let operator = storage_config.opendal_operator(scheme, config).unwrap();
+        operator.write(&path, data.clone()).await.unwrap();
+
+        let (file_size, mut reader) =
+            get_file_size_and_reader("memory://bucket/a.lava".to_string(), storage_config.clone())
+                .await
+                .unwrap();
+        assert!(matches!(reader.reader, ClonableAsyncReader::Opendal(_)));
+        assert_eq!(file_size, 800);
+        assert_eq!(&reader.read_range(8, 24).await.unwrap()[..], &data[8..24]);
+        assert_eq!(reader.read_usize_from_end(2).await.unwrap(), vec![98, 99]);
+        assert!(matches!(
+            get_file_size_and_reader("memory://bucket/b.lava".to_string(), storage_config).await,
+            Err(LavaError::NotFound(_))
+        ));
 
-    // Get the file size
-    let file_size: u64 = operator.stat(&filename).await?.content_length();
-
-    // Create the reader
-    let reader: AsyncOpendalReader = AsyncOpendalReader::new(
-        operator
-            .clone()
-            .reader_with(&filename)
-            .chunk(READER_BUFFER_SIZE)
-            .await?
-            .into_futures_async_read(0..file_size as u64),
-        filename.clone(),
-    );
-
-    Ok((file_size as usize, reader))
+        let path = std::env::temp_dir().join(format!("rottnest_opendal_{}", std::process::id()));
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        let (file_size, mut reader) = get_file_size_and_reader(
+            path.to_str().unwrap().to_string(),
+            ReaderType::Opendal.into(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(reader.reader, ClonableAsyncReader::Opendal(_)));
+        assert_eq!(file_size, 800);
+        assert_eq!(
+            reader.read_ranges(&[(0, 8), (792, 800)]).await.unwrap(),
+            vec![&data[0..8], &data[792..800]]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
 }
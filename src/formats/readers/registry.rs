@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use crate::lava::error::LavaError;
+
+use super::Reader;
+
+/// A `Reader` that can be cloned behind a trait object. Every reader handed out by a
+/// `ReaderFactory` must implement this so that `AsyncReader` stays `Clone`.
+pub trait ClonableReader: Reader {
+    fn box_clone(&self) -> Box<dyn ClonableReader>;
+}
+
+impl<T> ClonableReader for T
+where
+    T: Reader + Clone + 'static,
+{
+    fn box_clone(&self) -> Box<dyn ClonableReader> {
+        Box::new(self.clone())
+    }
+}
+
+/// Creates readers for every path that starts with the scheme it was registered under.
+#[async_trait]
+pub trait ReaderFactory: Send + Sync {
+    async fn get_file_size_and_reader(
+        &self,
+        file: String,
+    ) -> Result<(usize, Box<dyn ClonableReader>), LavaError>;
+
+    async fn get_reader(&self, file: String) -> Result<Box<dyn ClonableReader>, LavaError> {
+        let (_file_size, reader) = self.get_file_size_and_reader(file).await?;
+        Ok(reader)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref READER_REGISTRY: RwLock<HashMap<String, Arc<dyn ReaderFactory>>> =
+        RwLock::new(HashMap::new());
+}
+
+fn normalize_scheme(scheme: &str) -> String {
+    scheme.trim_end_matches("://").to_lowercase()
+}
+
+/// Registers `factory` for all paths of the form `{scheme}://...`. Registered schemes take
+/// precedence over the built-in local, s3 and http readers, so this can also be used to
+/// override how e.g. `s3://` paths are opened.
+pub fn register_reader(scheme: &str, factory: Arc<dyn ReaderFactory>) {
+    READER_REGISTRY
+        .write()
+        .unwrap()
+        .insert(normalize_scheme(scheme), factory);
+}
+
+/// Removes the factory registered for `scheme`, returning it if there was one.
+pub fn unregister_reader(scheme: &str) -> Option<Arc<dyn ReaderFactory>> {
+    READER_REGISTRY
+        .write()
+        .unwrap()
+        .remove(&normalize_scheme(scheme))
+}
+
+/// Returns the factory registered for the scheme of `file`, if any.
+pub(crate) fn lookup_reader_factory(file: &str) -> Option<Arc<dyn ReaderFactory>> {
+    let (scheme, _) = file.split_once("://")?;
+    READER_REGISTRY
+        .read()
+        .unwrap()
+        .get(&normalize_scheme(scheme))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+
+    use super::{register_reader, unregister_reader, ClonableReader, ReaderFactory};
+    use crate::formats::readers::{get_file_size_and_reader, get_reader, Reader};
+    use crate::lava::error::LavaError;
+
+    #[derive(Clone)]
+    struct MemReader {
+        files: Arc<HashMap<String, Bytes>>,
+        filename: String,
+    }
+
+    #[async_trait]
+    impl Reader for MemReader {
+        fn update_filename(&mut self, filename: String) -> Result<(), LavaError> {
+            self.filename = filename;
+            Ok(())
+        }
+
+        async fn read_range(&mut self, from: u64, to: u64) -> Result<Bytes, LavaError> {
+            let data = &self.files[&self.filename];
+            Ok(data.slice(from as usize..to as usize))
+        }
+
+        async fn read_usize_from_end(
+            &mut self,
+            offset: i64,
+            n: u64,
+        ) -> Result<Vec<u64>, LavaError> {
+            let from = self.files[&self.filename].len() as i64 + offset;
+            self.read_usize_from_start(from as u64, n).await
+        }
+
+        async fn read_usize_from_start(
+            &mut self,
+            offset: u64,
+            n: u64,
+        ) -> Result<Vec<u64>, LavaError> {
+            let bytes = self.read_range(offset, offset + n * 8).await?;
+            Ok(bytes
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect())
+        }
+    }
+
+    struct MemReaderFactory(Arc<HashMap<String, Bytes>>);
+
+    #[async_trait]
+    impl ReaderFactory for MemReaderFactory {
+        async fn get_file_size_and_reader(
+            &self,
+            file: String,
+        ) -> Result<(usize, Box<dyn ClonableReader>), LavaError> {
+            let size = self
+                .0
+                .get(&file)
+                .ok_or(LavaError::Parse(format!("{} not found", file)))?
+                .len();
+            let reader = MemReader {
+                files: self.0.clone(),
+                filename: file,
+            };
+            Ok((size, Box::new(reader)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_scheme_is_used() {
+        let mut files = HashMap::new();
+        let mut data = Vec::new();
+        for i in 0..4u64 {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        files.insert("mem://a".to_string(), Bytes::from(data));
+        register_reader("mem", Arc::new(MemReaderFactory(Arc::new(files))));
+
+        let (file_size, mut reader) =
+            get_file_size_and_reader("mem://a".to_string(), Default::default())
+                .await
+                .unwrap();
+        assert_eq!(file_size, 32);
+        assert_eq!(reader.read_usize_from_end(2).await.unwrap(), vec![2, 3]);
+
+        let mut cloned = reader.clone();
+        assert_eq!(
+            cloned.read_range(0, 8).await.unwrap(),
+            Bytes::from(0u64.to_le_bytes().to_vec())
+        );
+
+        assert!(get_reader("mem://b".to_string(), Default::default())
+            .await
+            .is_err());
+
+        unregister_reader("mem://");
+        assert!(get_reader("mem://a".to_string(), Default::default())
+            .await
+            .is_err());
+    }
+}
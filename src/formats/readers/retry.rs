@@ -0,0 +1,199 @@
+use std::env;
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::lava::error::LavaError;
+
+/// How a reader retries a failed request. Only errors for which `LavaError::is_retryable`
+/// returns true are retried, permanent failures (missing object, access denied, ...) are
+/// returned right away.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// total number of attempts, including the first one
+    pub max_attempts: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub backoff_multiplier: f64,
+    /// sleep a random duration in [0, backoff] instead of the full backoff
+    pub jitter: bool,
+    /// timeout of a single attempt, None means no timeout
+    pub request_timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            backoff_multiplier: 2.0,
+            jitter: true,
+            request_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+fn env_u64(key: &str) -> Option<u64> {
+    env::var(key).ok().and_then(|value| value.parse::<u64>().ok())
+}
+
+impl RetryPolicy {
+    /// A policy that tries every request exactly once, without a timeout.
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            request_timeout: None,
+            ..Default::default()
+        }
+    }
+
+    /// The default policy, overridden by the ROTTNEST_RETRY_MAX_ATTEMPTS,
+    /// ROTTNEST_RETRY_INITIAL_BACKOFF_MS, ROTTNEST_RETRY_MAX_BACKOFF_MS and
+    /// ROTTNEST_REQUEST_TIMEOUT_MS (0 disables the timeout) environment variables.
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Some(value) = env_u64("ROTTNEST_RETRY_MAX_ATTEMPTS") {
+            policy.max_attempts = value.max(1) as usize;
+        }
+        if let Some(value) = env_u64("ROTTNEST_RETRY_INITIAL_BACKOFF_MS") {
+            policy.initial_backoff = Duration::from_millis(value);
+        }
+        if let Some(value) = env_u64("ROTTNEST_RETRY_MAX_BACKOFF_MS") {
+            policy.max_backoff = Duration::from_millis(value);
+        }
+        if let Some(value) = env_u64("ROTTNEST_REQUEST_TIMEOUT_MS") {
+            policy.request_timeout = match value {
+                0 => None,
+                value => Some(Duration::from_millis(value)),
+            };
+        }
+        policy
+    }
+
+    /// The time to wait after the `attempt`-th failed attempt (0-based).
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64()
+            * self.backoff_multiplier.powi(attempt.min(i32::MAX as usize) as i32);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        if self.jitter {
+            Duration::from_secs_f64(rand::thread_rng().gen_range(0.0..=backoff))
+        } else {
+            Duration::from_secs_f64(backoff)
+        }
+    }
+
+    /// Runs `f` until it succeeds, fails with a non-retryable error or runs out of attempts.
+    pub async fn retry<T, F, Fut>(&self, mut f: F) -> Result<T, LavaError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, LavaError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = match self.request_timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, f()).await {
+                    Ok(result) => result,
+                    Err(_) => Err(LavaError::Timeout(format!(
+                        "request did not finish within {:?}",
+                        timeout
+                    ))),
+                },
+                None => f().await,
+            };
+
+            match result {
+                Err(e) if e.is_retryable() && attempt + 1 < self.max_attempts => {
+                    log::warn!("retrying failed request (attempt {}): {}", attempt + 1, e);
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Maps the HTTP status of a failed request to a `LavaError`, returning None for statuses
+/// that don't fall in one of the known classes.
+pub(crate) fn error_from_status(status: u16, message: String) -> Option<LavaError> {
+    match status {
+        404 => Some(LavaError::NotFound(message)),
+        401 | 403 => Some(LavaError::PermissionDenied(message)),
+        408 | 429 | 500..=599 => Some(LavaError::Unavailable(message)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use crate::lava::error::LavaError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+            request_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(800));
+        assert_eq!(policy.backoff(100), Duration::from_secs(10));
+        assert!(RetryPolicy::default().backoff(2) <= Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_errors() {
+        let attempts = AtomicUsize::new(0);
+        let result = fast_policy()
+            .retry(|| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(LavaError::Unavailable("slow down".to_string())),
+                    1 => {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        Ok(1)
+                    }
+                    _ => Ok(2),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_permanent_errors() {
+        let attempts = AtomicUsize::new(0);
+        let result: Result<(), LavaError> = fast_policy()
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(LavaError::NotFound("s3://no-such-bucket/a.lava".to_string()))
+            })
+            .await;
+        assert!(matches!(result, Err(LavaError::NotFound(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let attempts = AtomicUsize::new(0);
+        let result: Result<(), LavaError> = fast_policy()
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(LavaError::Unavailable("slow down".to_string()))
+            })
+            .await;
+        assert!(matches!(result, Err(LavaError::Unavailable(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// I/O statistics of a single query.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryStats {
+    /// requests sent to the underlying readers, reads served by a cache are not counted
+    pub requests: u64,
+    pub bytes_read: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub largest_read: u64,
+    /// wall time per stage, in the order the stages first finished
+    pub stages: Vec<(String, Duration)>,
+}
+
+/// Collects the `QueryStats` of a query, shared by all of its readers.
+#[derive(Debug, Default)]
+pub struct QueryStatsCollector {
+    requests: AtomicU64,
+    bytes_read: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    largest_read: AtomicU64,
+    stages: Mutex<Vec<(String, Duration)>>,
+}
+
+impl QueryStatsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_read(&self, bytes: u64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        self.largest_read.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adds `duration` to the wall time of `stage`, a stage that runs several times is summed up.
+    pub fn record_stage(&self, stage: &str, duration: Duration) {
+        let mut stages = self.stages.lock().unwrap();
+        match stages.iter_mut().find(|(name, _)| name == stage) {
+            Some((_, total)) => *total += duration,
+            None => stages.push((stage.to_string(), duration)),
+        }
+    }
+
+    pub fn snapshot(&self) -> QueryStats {
+        QueryStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            largest_read: self.largest_read.load(Ordering::Relaxed),
+            stages: self.stages.lock().unwrap().clone(),
+        }
+    }
+}
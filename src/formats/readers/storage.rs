@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use aws_config::{timeout::TimeoutConfig, BehaviorVersion, Region};
+use aws_sdk_s3::config::Credentials;
+use aws_smithy_http_client::tls::{self, rustls_provider::CryptoMode};
+use tokio::sync::OnceCell;
+
+use super::{
+    get_access_recorder, AccessRecord, AccessRecorder, QueryStats, QueryStatsCollector,
+    ReadLimiter, ReadLimits, ReaderType,
+};
+use crate::formats::cache::{self, RangeCache};
+use crate::formats::encryption::DecryptionProperties;
+use crate::lava::error::LavaError;
+
+/// Where and how to reach the object store. Every field left as None falls back to the usual
+/// AWS environment variables and config files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectStoreOptions {
+    pub endpoint_url: Option<String>,
+    pub region: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    /// use `{endpoint}/{bucket}/{key}` instead of `{bucket}.{endpoint}/{key}`, needed by most
+    /// S3 compatible stores
+    pub force_path_style: bool,
+    pub connect_timeout: Option<Duration>,
+    /// connection pool settings of the http and S3 clients
+    pub pool_max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout: Option<Duration>,
+    /// service config of the OpenDAL reader, given as `opendal.<key>` in `from_map`
+    pub opendal: HashMap<String, String>,
+}
+
+fn parse_option<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, LavaError> {
+    value
+        .parse::<T>()
+        .map_err(|_| LavaError::Parse(format!("invalid value for {}: {}", key, value)))
+}
+
+impl ObjectStoreOptions {
+    /// Builds the options from string key/value pairs, e.g. passed in from Python. Durations are
+    /// given in milliseconds.
+    pub fn from_map(options: &HashMap<String, String>) -> Result<Self, LavaError> {
+        let mut result = Self::default();
+        for (key, value) in options {
+            match key.to_lowercase().as_str() {
+                "endpoint_url" | "endpoint" => result.endpoint_url = Some(value.clone()),
+                "region" => result.region = Some(value.clone()),
+                "access_key_id" => result.access_key_id = Some(value.clone()),
+                "secret_access_key" => result.secret_access_key = Some(value.clone()),
+                "session_token" => result.session_token = Some(value.clone()),
+                "addressing_style" => {
+                    result.force_path_style = match value.to_lowercase().as_str() {
+                        "path" => true,
+                        "virtual" => false,
+                        _ => {
+                            return Err(LavaError::Parse(format!(
+                                "addressing_style must be path or virtual, got {}",
+                                value
+                            )))
+                        }
+                    }
+                }
+                "connect_timeout_ms" => {
+                    result.connect_timeout = Some(Duration::from_millis(parse_option(key, value)?))
+                }
+                "pool_max_idle_per_host" => {
+                    result.pool_max_idle_per_host = Some(parse_option(key, value)?)
+                }
+                "pool_idle_timeout_ms" => {
+                    result.pool_idle_timeout =
+                        Some(Duration::from_millis(parse_option(key, value)?))
+                }
+                lowercase if lowercase.starts_with("opendal.") => {
+                    result
+                        .opendal
+                        .insert(key["opendal.".len()..].to_string(), value.clone());
+                }
+                _ => return Err(LavaError::Parse(format!("unknown storage option: {}", key))),
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Everything a query needs to open its files. The object store clients are created on first
+/// use and shared by all clones of the config, so pass clones of one config to all the readers
+/// of a query instead of creating a new config per file.
+#[derive(Clone, Default)]
+pub struct StorageConfig {
+    pub reader_type: ReaderType,
+    pub object_store: ObjectStoreOptions,
+    /// limits of this query, applied on top of the process wide ones
+    pub read_limiter: Option<Arc<ReadLimiter>>,
+    /// collects the I/O statistics of the readers created from this config, if set
+    pub query_stats: Option<Arc<QueryStatsCollector>>,
+    /// overrides the process wide range cache, see `range_cache`
+    pub range_cache: Option<Arc<dyn RangeCache>>,
+    /// overrides the process wide access recorder, see `access_recorder`
+    pub access_recorder: Option<Arc<AccessRecorder>>,
+    /// the keys to read encrypted parquet files with
+    pub decryption: Option<Arc<DecryptionProperties>>,
+    s3_client: Arc<OnceCell<aws_sdk_s3::Client>>,
+    http_client: Arc<OnceLock<reqwest::Client>>,
+    // keyed by scheme and service config
+    #[cfg(feature = "opendal")]
+    opendal_operators: Arc<std::sync::Mutex<HashMap<String, opendal::Operator>>>,
+}
+
+impl fmt::Debug for StorageConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StorageConfig")
+            .field("reader_type", &self.reader_type)
+            .field("object_store", &self.object_store)
+            .field(
+                "read_limits",
+                &self.read_limiter.as_ref().map(|limiter| limiter.limits()),
+            )
+            .field(
+                "range_cache",
+                &self.range_cache.as_ref().map(|cache| cache.name()),
+            )
+            .field("access_recording", &self.access_recorder.is_some())
+            .field("decryption", &self.decryption)
+            .finish()
+    }
+}
+
+impl From<ReaderType> for StorageConfig {
+    fn from(reader_type: ReaderType) -> Self {
+        Self::new(reader_type, ObjectStoreOptions::default())
+    }
+}
+
+impl From<String> for StorageConfig {
+    fn from(value: String) -> Self {
+        ReaderType::from(value).into()
+    }
+}
+
+impl StorageConfig {
+    pub fn new(reader_type: ReaderType, object_store: ObjectStoreOptions) -> Self {
+        Self {
+            reader_type,
+            object_store,
+            read_limiter: None,
+            query_stats: None,
+            range_cache: None,
+            access_recorder: None,
+            decryption: None,
+            s3_client: Default::default(),
+            http_client: Default::default(),
+            #[cfg(feature = "opendal")]
+            opendal_operators: Default::default(),
+        }
+    }
+
+    /// Builds the config from string key/value pairs. `max_concurrent_requests` and
+    /// `max_bytes_per_second` set the limits of the query, `parquet.*` keys the keys of encrypted
+    /// parquet files, see `DecryptionProperties::set_option`, everything else is passed on to
+    /// `ObjectStoreOptions::from_map`.
+    pub fn from_map(
+        reader_type: ReaderType,
+        options: &HashMap<String, String>,
+    ) -> Result<Self, LavaError> {
+        let mut limits = ReadLimits::unlimited();
+        let mut decryption = DecryptionProperties::default();
+        let mut has_decryption = false;
+        let mut object_store_options = HashMap::new();
+        for (key, value) in options {
+            if decryption.set_option(key, value)? {
+                has_decryption = true;
+                continue;
+            }
+            match key.to_lowercase().as_str() {
+                "max_concurrent_requests" => {
+                    limits.max_concurrent_requests = Some(parse_option(key, value)?)
+                }
+                "max_bytes_per_second" => {
+                    limits.max_bytes_per_second = Some(parse_option(key, value)?)
+                }
+                _ => {
+                    object_store_options.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        let mut config = Self::new(
+            reader_type,
+            ObjectStoreOptions::from_map(&object_store_options)?,
+        );
+        if has_decryption {
+            config = config.with_decryption(decryption);
+        }
+        Ok(match limits == ReadLimits::unlimited() {
+            true => config,
+            false => config.with_read_limits(limits),
+        })
+    }
+
+    /// Limits the requests of all the readers created from this config and its clones.
+    pub fn with_read_limits(mut self, limits: ReadLimits) -> Self {
+        self.read_limiter = Some(Arc::new(ReadLimiter::new(limits)));
+        self
+    }
+
+    /// Collects the `QueryStats` of the query run with this config, see `query_stats`.
+    pub fn with_query_stats(mut self) -> Self {
+        self.query_stats = Some(Arc::new(QueryStatsCollector::new()));
+        self
+    }
+
+    /// Reads encrypted parquet files with the keys of `decryption`.
+    pub fn with_decryption(mut self, decryption: DecryptionProperties) -> Self {
+        self.decryption = Some(Arc::new(decryption));
+        self
+    }
+
+    /// Reads through `range_cache` instead of the process wide one.
+    pub fn with_range_cache(mut self, range_cache: Arc<dyn RangeCache>) -> Self {
+        self.range_cache = Some(range_cache);
+        self
+    }
+
+    /// The range cache of the readers created from this config, if any.
+    pub fn range_cache(&self) -> Option<Arc<dyn RangeCache>> {
+        self.range_cache.clone().or_else(cache::get_range_cache)
+    }
+
+    /// Records the ranges read by the query run with this config, see `access_trace`.
+    pub fn with_access_recording(mut self) -> Self {
+        self.access_recorder = Some(Arc::new(AccessRecorder::new()));
+        self
+    }
+
+    /// The access recorder of the readers created from this config, if any.
+    pub fn access_recorder(&self) -> Option<Arc<AccessRecorder>> {
+        self.access_recorder.clone().or_else(get_access_recorder)
+    }
+
+    /// The ranges read so far, None unless the config or the process records accesses.
+    pub fn access_trace(&self) -> Option<Vec<AccessRecord>> {
+        self.access_recorder().map(|recorder| recorder.trace())
+    }
+
+    /// The statistics collected so far, None unless the config was built `with_query_stats`.
+    pub fn query_stats(&self) -> Option<QueryStats> {
+        self.query_stats.as_ref().map(|stats| stats.snapshot())
+    }
+
+    /// Records the wall time since `start` as the stage `stage` of the query.
+    pub fn record_stage(&self, stage: &str, start: Instant) {
+        let duration = start.elapsed();
+        log::debug!("{}: {:?}", stage, duration);
+        if let Some(stats) = &self.query_stats {
+            stats.record_stage(stage, duration);
+        }
+    }
+
+    /// The S3 client shared by all readers created from this config.
+    pub async fn s3_client(&self) -> aws_sdk_s3::Client {
+        self.s3_client
+            .get_or_init(|| async {
+                let options = &self.object_store;
+                let mut loader = aws_config::defaults(BehaviorVersion::latest());
+                if let Some(region) = &options.region {
+                    loader = loader.region(Region::new(region.clone()));
+                }
+                if let Some(endpoint_url) = &options.endpoint_url {
+                    loader = loader.endpoint_url(endpoint_url);
+                }
+                if let (Some(access_key_id), Some(secret_access_key)) =
+                    (&options.access_key_id, &options.secret_access_key)
+                {
+                    loader = loader.credentials_provider(Credentials::new(
+                        access_key_id,
+                        secret_access_key,
+                        options.session_token.clone(),
+                        None,
+                        "rottnest",
+                    ));
+                }
+                if options.pool_max_idle_per_host.is_some() || options.pool_idle_timeout.is_some() {
+                    // the same client the SDK would create, with the pool options applied
+                    let mut builder = aws_smithy_http_client::Builder::new();
+                    builder
+                        .set_pool_max_idle_per_host(options.pool_max_idle_per_host)
+                        .set_pool_idle_timeout(options.pool_idle_timeout.map(Some));
+                    loader = loader.http_client(
+                        builder
+                            .tls_provider(tls::Provider::Rustls(CryptoMode::AwsLc))
+                            .build_https(),
+                    );
+                }
+                if let Some(connect_timeout) = options.connect_timeout {
+                    loader = loader.timeout_config(
+                        TimeoutConfig::builder()
+                            .connect_timeout(connect_timeout)
+                            .build(),
+                    );
+                }
+                let sdk_config = loader.load().await;
+                let s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+                    .force_path_style(options.force_path_style)
+                    .build();
+                aws_sdk_s3::Client::from_conf(s3_config)
+            })
+            .await
+            .clone()
+    }
+
+    /// The OpenDAL operator of the service `scheme` with `config`, shared by all readers created
+    /// from this config.
+    #[cfg(feature = "opendal")]
+    pub fn opendal_operator(
+        &self,
+        scheme: opendal::Scheme,
+        config: HashMap<String, String>,
+    ) -> Result<opendal::Operator, LavaError> {
+        let mut key: Vec<_> = config.iter().collect();
+        key.sort();
+        let key = format!("{}:{:?}", scheme, key);
+        let mut operators = self.opendal_operators.lock().unwrap();
+        if let Some(operator) = operators.get(&key) {
+            return Ok(operator.clone());
+        }
+        let operator = opendal::Operator::via_map(scheme, config)
+            .map_err(|e| super::opendal_reader::classify_opendal_error(e, scheme.into_static()))?;
+        operators.insert(key, operator.clone());
+        Ok(operator)
+    }
+
+    /// The http client shared by all readers created from this config.
+    pub fn http_client(&self) -> Result<reqwest::Client, LavaError> {
+        if let Some(client) = self.http_client.get() {
+            return Ok(client.clone());
+        }
+        let options = &self.object_store;
+        let mut builder = reqwest::Client::builder();
+        if let Some(connect_timeout) = options.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(pool_max_idle_per_host) = options.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(pool_max_idle_per_host);
+        }
+        if let Some(pool_idle_timeout) = options.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(pool_idle_timeout);
+        }
+        let client = builder.build()?;
+        Ok(self.http_client.get_or_init(|| client).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::{ObjectStoreOptions, StorageConfig};
+
+    #[test]
+    fn test_object_store_options_from_map() {
+        let map: HashMap<String, String> = [
+            ("endpoint_url", "http://localhost:9000"),
+            ("region", "us-west-2"),
+            ("addressing_style", "path"),
+            ("connect_timeout_ms", "500"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let options = ObjectStoreOptions::from_map(&map).unwrap();
+        assert_eq!(
+            options.endpoint_url.as_deref(),
+            Some("http://localhost:9000")
+        );
+        assert_eq!(options.region.as_deref(), Some("us-west-2"));
+        assert!(options.force_path_style);
+        assert_eq!(options.connect_timeout, Some(Duration::from_millis(500)));
+
+        let bad: HashMap<String, String> = [("endpoint_ur".to_string(), "x".to_string())]
+            .into_iter()
+            .collect();
+        assert!(ObjectStoreOptions::from_map(&bad).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_clients_are_shared() {
+        let config = StorageConfig::new(
+            Default::default(),
+            ObjectStoreOptions {
+                region: Some("us-east-1".to_string()),
+                ..Default::default()
+            },
+        );
+        let cloned = config.clone();
+        let client = cloned.s3_client().await;
+        assert_eq!(
+            client.config().region().map(|r| r.to_string()),
+            Some("us-east-1".to_string())
+        );
+        // the client built through the clone is the one the original config hands out
+        assert!(config.s3_client.initialized());
+
+        config.http_client().unwrap();
+        assert!(cloned.http_client.get().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_s3_client_pool_options() {
+        let options = |pool_max_idle_per_host| ObjectStoreOptions {
+            region: Some("us-east-1".to_string()),
+            pool_max_idle_per_host,
+            pool_idle_timeout: pool_max_idle_per_host.map(|_| Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let config = StorageConfig::new(Default::default(), options(None));
+        let default_client = config.s3_client().await.config().http_client();
+        // the SDK builds its own client unless the pool is configured
+        let config = StorageConfig::new(Default::default(), options(Some(2)));
+        let pooled_client = config.s3_client().await.config().http_client();
+        assert!(default_client.is_none());
+        let pooled_client = format!("{:?}", pooled_client.unwrap());
+        assert!(pooled_client.contains("idle_timeout: Some(5s), max_idle_per_host: 2"));
+    }
+
+    #[test]
+    fn test_storage_config_from_map() {
+        let map: HashMap<String, String> =
+            [("region", "us-west-2"), ("max_concurrent_requests", "16")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+        let config = StorageConfig::from_map(Default::default(), &map).unwrap();
+        assert_eq!(config.object_store.region.as_deref(), Some("us-west-2"));
+        let limits = config.read_limiter.as_ref().unwrap().limits();
+        assert_eq!(limits.max_concurrent_requests, Some(16));
+        assert_eq!(limits.max_bytes_per_second, None);
+
+        let map: HashMap<String, String> = [("region".to_string(), "us-west-2".to_string())]
+            .into_iter()
+            .collect();
+        assert!(StorageConfig::from_map(Default::default(), &map)
+            .unwrap()
+            .read_limiter
+            .is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+/// A range of a file read by the recorded queries and how many times it was read.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessRecord {
+    pub filename: String,
+    pub offset: u64,
+    pub length: u64,
+    pub count: u64,
+}
+
+/// Records the ranges the readers sharing it read, as requested by the caller, i.e. before the
+/// ranges are coalesced, cached or split into blocks.
+#[derive(Debug, Default)]
+pub struct AccessRecorder {
+    // (filename, offset, length) -> count
+    accesses: Mutex<HashMap<(String, u64, u64), u64>>,
+}
+
+impl AccessRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, filename: &str, from: u64, to: u64) {
+        let mut accesses = self.accesses.lock().unwrap();
+        *accesses
+            .entry((filename.to_string(), from, to - from))
+            .or_insert(0) += 1;
+    }
+
+    /// The ranges recorded so far, most frequently read first.
+    pub fn trace(&self) -> Vec<AccessRecord> {
+        let mut trace: Vec<AccessRecord> = self
+            .accesses
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((filename, offset, length), count)| AccessRecord {
+                filename: filename.clone(),
+                offset: *offset,
+                length: *length,
+                count: *count,
+            })
+            .collect();
+        trace.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.filename.cmp(&b.filename))
+                .then_with(|| a.offset.cmp(&b.offset))
+        });
+        trace
+    }
+
+    pub fn clear(&self) {
+        self.accesses.lock().unwrap().clear();
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref ACCESS_RECORDER: RwLock<Option<Arc<AccessRecorder>>> = RwLock::new(None);
+}
+
+/// The process wide recorder of new readers, None (not recording) by default.
+pub fn get_access_recorder() -> Option<Arc<AccessRecorder>> {
+    ACCESS_RECORDER.read().unwrap().clone()
+}
+
+/// Replaces the process wide recorder, None stops recording. Only affects readers created
+/// afterwards.
+pub fn set_access_recorder(recorder: Option<Arc<AccessRecorder>>) {
+    *ACCESS_RECORDER.write().unwrap() = recorder;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::formats::readers::{get_file_size_and_reader, AccessRecord, StorageConfig};
+
+    #[tokio::test]
+    async fn test_access_recording() {
+        let path = std::env::temp_dir().join(format!("rottnest_trace_{}", std::process::id()));
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(&[0u8; 1000])
+            .unwrap();
+        let filename = path.to_str().unwrap().to_string();
+
+        let storage_config = StorageConfig::default().with_access_recording();
+        let (_, mut reader) = get_file_size_and_reader(filename.clone(), storage_config.clone())
+            .await
+            .unwrap();
+        reader.read_range(0, 10).await.unwrap();
+        // recorded as requested, not as coalesced
+        reader.read_ranges(&[(0, 10), (20, 30)]).await.unwrap();
+        reader.read_usize_from_end(1).await.unwrap();
+
+        let record = |offset, length, count| AccessRecord {
+            filename: filename.clone(),
+            offset,
+            length,
+            count,
+        };
+        assert_eq!(
+            storage_config.access_trace().unwrap(),
+            vec![record(0, 10, 2), record(20, 10, 1), record(992, 8, 1)]
+        );
+        assert!(StorageConfig::default().access_trace().is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+use std::sync::Arc;
+
+use arrow::array::ArrayData;
+use arrow_array::{Array, ArrayRef, StringArray};
+use bytes::Bytes;
+use serde_json::Value;
+use tokio::task::JoinSet;
+
+use super::block_layout::{group_blocks, BlockLayout};
+use super::readers::{get_file_size_and_reader, get_reader, StorageConfig};
+use crate::lava::error::LavaError;
+
+pub const DEFAULT_LINES_PER_BLOCK: usize = 1000;
+
+// text files are scanned for line breaks in reads of this size
+const TEXT_READ_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The lines of `bytes`, whole lines of a text file, without their line breaks.
+fn split_lines(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    bytes
+        .split(|byte| *byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+/// The value at the dotted `path` of the JSON object `line`, strings as is and other values as
+/// JSON, None if it is missing or null.
+fn json_value(line: &[u8], path: &[&str]) -> Result<Option<String>, serde_json::Error> {
+    let mut value = &serde_json::from_slice::<Value>(line)?;
+    for name in path {
+        match value.get(name) {
+            Some(inner) => value = inner,
+            None => return Ok(None),
+        }
+    }
+    Ok(match value {
+        Value::Null => None,
+        Value::String(string) => Some(string.clone()),
+        other => Some(other.to_string()),
+    })
+}
+
+/// The values of the lines of the block at `offset` of `file_path`: the lines themselves, or the
+/// values of `column_name` if the lines are JSON objects. Blank lines of JSON files are null.
+fn line_values(
+    file_path: &str,
+    offset: u64,
+    bytes: &[u8],
+    column_name: Option<&str>,
+) -> Result<ArrayRef, LavaError> {
+    let values: StringArray = match column_name {
+        None => split_lines(bytes)
+            .map(|line| Some(String::from_utf8_lossy(line)))
+            .collect(),
+        Some(column_name) => {
+            let path: Vec<&str> = column_name.split('.').collect();
+            let mut line_offset = offset;
+            let mut values = vec![];
+            for line in split_lines(bytes) {
+                let value = if line.iter().all(|byte| byte.is_ascii_whitespace()) {
+                    None
+                } else {
+                    json_value(line, &path).map_err(|e| {
+                        LavaError::Parse(format!(
+                            "invalid JSON line at {} of {}: {}",
+                            line_offset, file_path, e
+                        ))
+                    })?
+                };
+                values.push(value);
+                line_offset += line.len() as u64 + 1;
+            }
+            values.into_iter().collect()
+        }
+    };
+    Ok(Arc::new(values))
+}
+
+/// The layout of the text file `file_path`, in blocks of `lines_per_block` lines, and the values
+/// of the lines block by block. Newline-delimited JSON files are read as the values of
+/// `column_name`, a dotted path into the objects, other files as the lines themselves. The blocks
+/// are the sampled line offsets an index can point at, the lines in between are found again when
+/// the block is read.
+#[tokio::main]
+pub async fn get_text_layout(
+    file_path: &str,
+    column_name: Option<&str>,
+    lines_per_block: usize,
+    storage_config: StorageConfig,
+) -> Result<(Vec<ArrayData>, BlockLayout), LavaError> {
+    if lines_per_block == 0 {
+        return Err(LavaError::Parse(
+            "lines_per_block must be at least 1".to_string(),
+        ));
+    }
+    let (file_size, mut reader) =
+        get_file_size_and_reader(file_path.to_string(), storage_config).await?;
+    let file_size = file_size as u64;
+
+    let mut layout = BlockLayout {
+        metadata_bytes: Bytes::new(),
+        block_offsets: vec![],
+        block_sizes: vec![],
+        block_num_rows: vec![],
+    };
+    let mut arrays = vec![];
+    let mut finish_block = |block_offset: u64, block: &[u8]| -> Result<(), LavaError> {
+        let values = line_values(file_path, block_offset, block, column_name)?;
+        layout.block_offsets.push(block_offset as usize);
+        layout.block_sizes.push(block.len());
+        layout.block_num_rows.push(values.len());
+        arrays.push(values.to_data());
+        Ok(())
+    };
+
+    // the bytes of the current block read so far
+    let mut block = vec![];
+    let mut block_offset = 0;
+    let mut block_lines = 0;
+    let mut offset = 0;
+    while offset < file_size {
+        let end = (offset + TEXT_READ_SIZE).min(file_size);
+        let bytes = reader.read_range(offset, end).await?;
+        let mut start = 0;
+        for (position, _) in bytes.iter().enumerate().filter(|(_, byte)| **byte == b'\n') {
+            block_lines += 1;
+            if block_lines == lines_per_block {
+                block.extend_from_slice(&bytes[start..=position]);
+                finish_block(block_offset, &block)?;
+                block_offset += block.len() as u64;
+                block.clear();
+                block_lines = 0;
+                start = position + 1;
+            }
+        }
+        block.extend_from_slice(&bytes[start..]);
+        offset = end;
+    }
+    if !block.is_empty() {
+        finish_block(block_offset, &block)?;
+    }
+    Ok((arrays, layout))
+}
+
+/// Reads the blocks of lines at `block_offsets` of `file_paths`, as in the layout of the files,
+/// and returns the values of their lines, in the same order.
+pub async fn read_indexed_lines_async(
+    file_paths: Vec<String>,
+    block_offsets: Vec<u64>,
+    block_sizes: Vec<usize>,
+    column_name: Option<String>,
+    storage_config: StorageConfig,
+) -> Result<Vec<ArrayData>, LavaError> {
+    let num_blocks = file_paths.len();
+    let file_blocks = group_blocks(file_paths, block_offsets, block_sizes)?;
+    let start_time = std::time::Instant::now();
+
+    let mut read_set = JoinSet::new();
+    for (file_path, blocks) in file_blocks {
+        let column_name = column_name.clone();
+        let storage_config = storage_config.clone();
+        read_set.spawn(async move {
+            let mut reader = get_reader(file_path.clone(), storage_config).await?;
+            let ranges: Vec<(u64, u64)> = blocks
+                .iter()
+                .map(|(_, offset, size)| (*offset, offset + *size as u64))
+                .collect();
+            let buffers = reader.read_ranges(&ranges).await?;
+            let mut arrays = Vec::with_capacity(blocks.len());
+            for ((idx, offset, _), bytes) in blocks.iter().zip(buffers) {
+                let values = line_values(&file_path, *offset, &bytes, column_name.as_deref())?;
+                arrays.push((*idx, values.to_data()));
+            }
+            Ok::<_, LavaError>(arrays)
+        });
+    }
+
+    let mut results: Vec<Option<ArrayData>> = vec![None; num_blocks];
+    while let Some(res) = read_set.join_next().await {
+        let arrays = res.map_err(|e| LavaError::Parse(format!("join error: {:?}", e)))??;
+        for (idx, data) in arrays {
+            results[idx] = Some(data);
+        }
+    }
+
+    storage_config.record_stage("read lines", start_time);
+
+    Ok(results.into_iter().map(|data| data.unwrap()).collect())
+}
+
+pub fn read_indexed_lines(
+    file_paths: Vec<String>,
+    block_offsets: Vec<u64>,
+    block_sizes: Vec<usize>,
+    column_name: Option<String>,
+    storage_config: StorageConfig,
+) -> Result<Vec<ArrayData>, LavaError> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    let res = rt.block_on(read_indexed_lines_async(
+        file_paths,
+        block_offsets,
+        block_sizes,
+        column_name,
+        storage_config,
+    ));
+    rt.shutdown_background();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use arrow::array::{make_array, ArrayData};
+    use arrow_array::{Array, StringArray};
+
+    use super::{get_text_layout, read_indexed_lines};
+    use crate::formats::readers::StorageConfig;
+
+    fn strings(data: ArrayData) -> Vec<Option<String>> {
+        let array = make_array(data);
+        let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+        array.iter().map(|x| x.map(|x| x.to_string())).collect()
+    }
+
+    #[test]
+    fn test_text_layout_and_read_lines() {
+        let path = std::env::temp_dir().join(format!("rottnest_text_{}.json", std::process::id()));
+        let file_path = path.to_str().unwrap().to_string();
+        let body = |i: usize| (!i.is_multiple_of(7)).then(|| format!("line {}", i));
+        let mut lines = vec![];
+        for i in 0..25 {
+            lines.push(match body(i) {
+                Some(body) => format!(r#"{{"id": {}, "meta": {{"body": "{}"}}}}"#, i, body),
+                None => format!(r#"{{"id": {}}}"#, i),
+            });
+        }
+        // windows line breaks and no line break at the end
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(lines[..20].join("\n").as_bytes()).unwrap();
+        file.write_all(b"\r\n").unwrap();
+        file.write_all(lines[20..].join("\r\n").as_bytes()).unwrap();
+        drop(file);
+
+        let (arrays, layout) =
+            get_text_layout(&file_path, Some("meta.body"), 10, StorageConfig::default()).unwrap();
+        assert_eq!(layout.block_num_rows, vec![10, 10, 5]);
+        assert_eq!(layout.block_offsets[1], lines[..10].join("\n").len() + 1);
+        for (block, data) in arrays.into_iter().enumerate() {
+            let expected: Vec<Option<String>> =
+                (block * 10..(block * 10 + 10).min(25)).map(body).collect();
+            assert_eq!(strings(data), expected);
+        }
+
+        let wanted = [2, 0];
+        let read = |column_name: Option<&str>| {
+            read_indexed_lines(
+                wanted.iter().map(|_| file_path.clone()).collect(),
+                wanted
+                    .iter()
+                    .map(|i| layout.block_offsets[*i] as u64)
+                    .collect(),
+                wanted.iter().map(|i| layout.block_sizes[*i]).collect(),
+                column_name.map(|x| x.to_string()),
+                StorageConfig::default(),
+            )
+        };
+        let arrays = read(Some("id")).unwrap();
+        assert_eq!(strings(arrays[0].clone())[0], Some("20".to_string()));
+        assert_eq!(strings(arrays[1].clone())[9], Some("9".to_string()));
+        // as plain text
+        let arrays = read(None).unwrap();
+        assert_eq!(strings(arrays[0].clone())[4], Some(lines[24].clone()));
+        assert_eq!(strings(arrays[1].clone())[0], Some(lines[0].clone()));
+
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(b"{\"id\": 1}\nnot json\n")
+            .unwrap();
+        let error = get_text_layout(&file_path, Some("id"), 10, StorageConfig::default());
+        assert!(error.unwrap_err().to_string().contains("at 10 of"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
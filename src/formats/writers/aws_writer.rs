@@ -0,0 +1,410 @@
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+
+use crate::formats::readers::{classify_sdk_error, RetryPolicy};
+use crate::lava::error::LavaError;
+
+// S3 rejects parts smaller than 5MB, except for the last one
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+pub const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+
+pub(crate) fn parse_s3_path(file: &str) -> Result<(String, String), LavaError> {
+    match file
+        .strip_prefix("s3://")
+        .and_then(|path| path.split_once('/'))
+    {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+            Ok((bucket.to_string(), key.to_string()))
+        }
+        _ => Err(LavaError::Parse(format!("malformed s3 path: {}", file))),
+    }
+}
+
+/// Streams the output to S3 with a multipart upload. Parts are uploaded as soon as they are
+/// full, so at most one part is held in memory. Outputs smaller than one part are written with
+/// a single PutObject when the writer is finished.
+pub struct AsyncAwsWriter {
+    client: Client,
+    bucket: String,
+    key: String,
+    part_size: usize,
+    pub retry_policy: RetryPolicy,
+    buffer: Vec<u8>,
+    position: u64,
+    upload_id: Option<String>,
+    completed_parts: Vec<CompletedPart>,
+}
+
+impl AsyncAwsWriter {
+    pub fn new(client: Client, file: &str) -> Result<Self, LavaError> {
+        let (bucket, key) = parse_s3_path(file)?;
+        Ok(Self {
+            client,
+            bucket,
+            key,
+            part_size: DEFAULT_PART_SIZE,
+            retry_policy: RetryPolicy::from_env(),
+            buffer: Vec::with_capacity(DEFAULT_PART_SIZE),
+            position: 0,
+            upload_id: None,
+            completed_parts: vec![],
+        })
+    }
+
+    pub fn set_part_size(&mut self, part_size: usize) {
+        self.part_size = part_size.max(MIN_PART_SIZE);
+    }
+
+    async fn upload_part(&mut self) -> Result<(), LavaError> {
+        let (bucket, key) = (&self.bucket, &self.key);
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let response = self
+                    .retry_policy
+                    .retry(|| async {
+                        self.client
+                            .create_multipart_upload()
+                            .bucket(bucket)
+                            .key(key)
+                            .send()
+                            .await
+                            .map_err(|e| classify_sdk_error(e, bucket, key))
+                    })
+                    .await?;
+                let upload_id = response.upload_id().ok_or(LavaError::AwsSdk(format!(
+                    "s3://{}/{}: no upload id returned",
+                    bucket, key
+                )))?;
+                self.upload_id = Some(upload_id.to_string());
+                upload_id.to_string()
+            }
+        };
+
+        let part_number = self.completed_parts.len() as i32 + 1;
+        let body = Bytes::from(std::mem::take(&mut self.buffer));
+        let response = self
+            .retry_policy
+            .retry(|| async {
+                self.client
+                    .upload_part()
+                    .bucket(bucket)
+                    .key(key)
+                    .upload_id(&upload_id)
+                    .part_number(part_number)
+                    .body(ByteStream::from(body.clone()))
+                    .send()
+                    .await
+                    .map_err(|e| classify_sdk_error(e, bucket, key))
+            })
+            .await?;
+        self.completed_parts.push(
+            CompletedPart::builder()
+                .set_e_tag(response.e_tag().map(|e| e.to_string()))
+                .part_number(part_number)
+                .build(),
+        );
+        self.buffer = Vec::with_capacity(self.part_size);
+        Ok(())
+    }
+
+    async fn complete(&mut self) -> Result<(), LavaError> {
+        if self.upload_id.is_some() && !self.buffer.is_empty() {
+            self.upload_part().await?;
+        }
+        let (bucket, key) = (&self.bucket, &self.key);
+        let Some(upload_id) = self.upload_id.clone() else {
+            let body = Bytes::from(std::mem::take(&mut self.buffer));
+            self.retry_policy
+                .retry(|| async {
+                    self.client
+                        .put_object()
+                        .bucket(bucket)
+                        .key(key)
+                        .body(ByteStream::from(body.clone()))
+                        .send()
+                        .await
+                        .map_err(|e| classify_sdk_error(e, bucket, key))
+                })
+                .await?;
+            return Ok(());
+        };
+
+        let completed = CompletedMultipartUpload::builder()
+            .set_parts(Some(self.completed_parts.clone()))
+            .build();
+        self.retry_policy
+            .retry(|| async {
+                self.client
+                    .complete_multipart_upload()
+                    .bucket(bucket)
+                    .key(key)
+                    .upload_id(&upload_id)
+                    .multipart_upload(completed.clone())
+                    .send()
+                    .await
+                    .map_err(|e| classify_sdk_error(e, bucket, key))
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Aborts the multipart upload so the uploaded parts don't keep costing storage.
+    async fn abort(&mut self) {
+        if let Some(upload_id) = self.upload_id.take() {
+            let result = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+            if let Err(e) = result {
+                log::warn!(
+                    "failed to abort upload of s3://{}/{}: {}",
+                    self.bucket,
+                    self.key,
+                    classify_sdk_error(e, &self.bucket, &self.key)
+                );
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl super::Writer for AsyncAwsWriter {
+    async fn write_all(&mut self, mut buf: &[u8]) -> Result<(), LavaError> {
+        self.position += buf.len() as u64;
+        while !buf.is_empty() {
+            let n = buf
+                .len()
+                .min(self.part_size.saturating_sub(self.buffer.len()));
+            self.buffer.extend_from_slice(&buf[..n]);
+            buf = &buf[n..];
+            if self.buffer.len() >= self.part_size {
+                if let Err(e) = self.upload_part().await {
+                    self.abort().await;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    async fn finish(&mut self) -> Result<(), LavaError> {
+        if let Err(e) = self.complete().await {
+            self.abort().await;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) async fn copy_object(client: &Client, from: &str, to: &str) -> Result<(), LavaError> {
+    let (from_bucket, from_key) = parse_s3_path(from)?;
+    let (bucket, key) = parse_s3_path(to)?;
+    client
+        .copy_object()
+        .copy_source(format!("{}/{}", from_bucket, from_key))
+        .bucket(&bucket)
+        .key(&key)
+        .send()
+        .await
+        .map_err(|e| classify_sdk_error(e, &bucket, &key))?;
+    Ok(())
+}
+
+pub(crate) async fn delete_object(client: &Client, file: &str) -> Result<(), LavaError> {
+    let (bucket, key) = parse_s3_path(file)?;
+    client
+        .delete_object()
+        .bucket(&bucket)
+        .key(&key)
+        .send()
+        .await
+        .map_err(|e| classify_sdk_error(e, &bucket, &key))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use aws_sdk_s3::config::http::{HttpRequest, HttpResponse};
+    use aws_sdk_s3::config::{
+        BehaviorVersion, Credentials, Region, RequestChecksumCalculation, RuntimeComponents,
+    };
+    use aws_sdk_s3::primitives::SdkBody;
+    use aws_sdk_s3::Client;
+    use aws_smithy_runtime_api::client::http::{
+        HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpConnector,
+    };
+
+    use super::{parse_s3_path, AsyncAwsWriter, MIN_PART_SIZE};
+    use crate::formats::writers::Writer;
+    use crate::lava::error::LavaError;
+
+    /// Answers the S3 calls of a writer in memory and records them as (method, path and query,
+    /// body size). Uploads of part `fail_part` are refused.
+    #[derive(Debug, Clone, Default)]
+    struct FakeS3 {
+        requests: Arc<Mutex<Vec<(String, String, usize)>>>,
+        fail_part: Option<usize>,
+    }
+
+    impl FakeS3 {
+        fn respond(&self, request: &HttpRequest) -> HttpResponse {
+            let uri = request
+                .uri()
+                .trim_start_matches("http://s3.test")
+                .to_string();
+            let size = request.body().bytes().map_or(0, |body| body.len());
+            self.requests
+                .lock()
+                .unwrap()
+                .push((request.method().to_string(), uri.clone(), size));
+            let (status, body) = match request.method() {
+                "POST" if uri.contains("?uploads") => (
+                    200,
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>a.lava</Key>\
+                     <UploadId>upload</UploadId></InitiateMultipartUploadResult>",
+                ),
+                "PUT" if self
+                    .fail_part
+                    .is_some_and(|part| uri.contains(&format!("partNumber={}&", part))) =>
+                {
+                    (403, "<Error><Code>AccessDenied</Code></Error>")
+                }
+                "POST" => (
+                    200,
+                    "<CompleteMultipartUploadResult><ETag>etag</ETag></CompleteMultipartUploadResult>",
+                ),
+                "DELETE" => (204, ""),
+                _ => (200, ""),
+            };
+            let mut response = HttpResponse::new(status.try_into().unwrap(), SdkBody::from(body));
+            response.headers_mut().insert("ETag", "\"etag\"");
+            response
+        }
+
+        fn client(&self) -> Client {
+            let config = aws_sdk_s3::Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("us-east-1"))
+                .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+                .endpoint_url("http://s3.test")
+                .force_path_style(true)
+                .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+                .http_client(self.clone())
+                .build();
+            Client::from_conf(config)
+        }
+
+        fn requests(&self) -> Vec<(String, String, usize)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl HttpConnector for FakeS3 {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            HttpConnectorFuture::ready(Ok(self.respond(&request)))
+        }
+    }
+
+    impl HttpClient for FakeS3 {
+        fn http_connector(
+            &self,
+            _: &HttpConnectorSettings,
+            _: &RuntimeComponents,
+        ) -> SharedHttpConnector {
+            SharedHttpConnector::new(self.clone())
+        }
+    }
+
+    fn methods(requests: &[(String, String, usize)]) -> Vec<&str> {
+        requests
+            .iter()
+            .map(|(method, _, _)| method.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload() {
+        let s3 = FakeS3::default();
+        let mut writer = AsyncAwsWriter::new(s3.client(), "s3://bucket/a.lava").unwrap();
+        writer.set_part_size(MIN_PART_SIZE);
+        let chunk = vec![7u8; 1024 * 1024];
+        for _ in 0..12 {
+            writer.write_all(&chunk).await.unwrap();
+        }
+        assert_eq!(writer.position(), 12 * 1024 * 1024);
+        writer.finish().await.unwrap();
+
+        let requests = s3.requests();
+        assert_eq!(
+            methods(&requests),
+            vec!["POST", "PUT", "PUT", "PUT", "POST"]
+        );
+        assert!(requests[0].1.contains("/bucket/a.lava?uploads"));
+        let parts: Vec<usize> = requests[1..4].iter().map(|(_, _, size)| *size).collect();
+        assert_eq!(parts, vec![MIN_PART_SIZE, MIN_PART_SIZE, 2 * 1024 * 1024]);
+        for (part, (_, uri, _)) in requests[1..4].iter().enumerate() {
+            assert!(uri.contains(&format!("partNumber={}&uploadId=upload", part + 1)));
+        }
+        assert!(requests[4].1.contains("uploadId=upload"));
+    }
+
+    #[tokio::test]
+    async fn test_small_upload() {
+        let s3 = FakeS3::default();
+        let mut writer = AsyncAwsWriter::new(s3.client(), "s3://bucket/a.lava").unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        writer.finish().await.unwrap();
+        assert_eq!(
+            s3.requests(),
+            vec![(
+                "PUT".to_string(),
+                "/bucket/a.lava?x-id=PutObject".to_string(),
+                5
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload_aborted_on_error() {
+        let s3 = FakeS3 {
+            fail_part: Some(2),
+            ..Default::default()
+        };
+        let mut writer = AsyncAwsWriter::new(s3.client(), "s3://bucket/a.lava").unwrap();
+        writer.set_part_size(MIN_PART_SIZE);
+        let err = writer
+            .write_all(&vec![7u8; 3 * MIN_PART_SIZE])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, LavaError::PermissionDenied(_)), "{}", err);
+
+        let requests = s3.requests();
+        assert_eq!(methods(&requests), vec!["POST", "PUT", "PUT", "DELETE"]);
+        assert!(requests[3].1.contains("uploadId=upload"));
+    }
+
+    #[test]
+    fn test_parse_s3_path() {
+        assert_eq!(
+            parse_s3_path("s3://bucket/a/b.lava").unwrap(),
+            ("bucket".to_string(), "a/b.lava".to_string())
+        );
+        assert!(parse_s3_path("s3://bucket").is_err());
+        assert!(parse_s3_path("s3:///key").is_err());
+        assert!(parse_s3_path("/tmp/a.lava").is_err());
+    }
+}
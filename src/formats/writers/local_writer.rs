@@ -0,0 +1,40 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use async_trait::async_trait;
+
+use crate::formats::readers::WRITER_BUFFER_SIZE;
+use crate::lava::error::LavaError;
+
+pub struct LocalWriter {
+    writer: BufWriter<File>,
+    position: u64,
+}
+
+impl LocalWriter {
+    pub fn new(filename: &str) -> Result<Self, LavaError> {
+        let file = File::create(filename)?;
+        Ok(Self {
+            writer: BufWriter::with_capacity(WRITER_BUFFER_SIZE, file),
+            position: 0,
+        })
+    }
+}
+
+#[async_trait]
+impl super::Writer for LocalWriter {
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), LavaError> {
+        self.writer.write_all(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    async fn finish(&mut self) -> Result<(), LavaError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use async_trait::async_trait;
+
+use crate::lava::error::LavaError;
+
+/// Collects the output in memory, e.g. to build an index and hand the bytes to the caller.
+#[derive(Default)]
+pub struct MemoryWriter {
+    buffer: Vec<u8>,
+}
+
+impl MemoryWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+#[async_trait]
+impl super::Writer for MemoryWriter {
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), LavaError> {
+        self.buffer.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn position(&self) -> u64 {
+        self.buffer.len() as u64
+    }
+
+    async fn finish(&mut self) -> Result<(), LavaError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use async_trait::async_trait;
+
+use crate::formats::readers::StorageConfig;
+use crate::lava::error::LavaError;
+
+mod aws_writer;
+mod local_writer;
+mod memory_writer;
+
+pub use aws_writer::AsyncAwsWriter;
+pub use local_writer::LocalWriter;
+pub use memory_writer::MemoryWriter;
+
+/// Sequential output of an index build or merge. Index files are only ever appended to, so the
+/// position is all the builders need to record offsets.
+#[async_trait]
+pub trait Writer: Send {
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), LavaError>;
+
+    /// The number of bytes written so far, i.e. the offset of the next write.
+    fn position(&self) -> u64;
+
+    /// Flushes everything and makes the output visible. Must be called exactly once, after the
+    /// last write, otherwise the output may be incomplete or missing.
+    async fn finish(&mut self) -> Result<(), LavaError>;
+}
+
+/// The local path of `file`, a plain path or a `file://` URL. Other schemes can't be written to.
+fn local_path(file: &str) -> Result<&str, LavaError> {
+    match file.split_once("://") {
+        None => Ok(file),
+        Some(("file", path)) => Ok(path),
+        Some((scheme, _)) => Err(LavaError::Unsupported(format!(
+            "cannot write {}, only s3:// and local paths can be written to, not {}://",
+            file, scheme
+        ))),
+    }
+}
+
+/// Opens a writer for `file`, uploading to S3 for `s3://` paths and writing to the local
+/// filesystem for plain paths and `file://` URLs.
+pub async fn get_writer(
+    file: &str,
+    storage_config: &StorageConfig,
+) -> Result<Box<dyn Writer>, LavaError> {
+    if file.starts_with("s3://") {
+        let client = storage_config.s3_client().await;
+        Ok(Box::new(AsyncAwsWriter::new(client, file)?))
+    } else {
+        Ok(Box::new(LocalWriter::new(local_path(file)?)?))
+    }
+}
+
+/// Copies `from` to `to`, both must be on the same storage.
+pub async fn copy_file(
+    from: &str,
+    to: &str,
+    storage_config: &StorageConfig,
+) -> Result<(), LavaError> {
+    match (from.starts_with("s3://"), to.starts_with("s3://")) {
+        (true, true) => aws_writer::copy_object(&storage_config.s3_client().await, from, to).await,
+        (false, false) => {
+            std::fs::copy(local_path(from)?, local_path(to)?)?;
+            Ok(())
+        }
+        _ => Err(LavaError::Parse(format!(
+            "cannot copy {} to {} across storages",
+            from, to
+        ))),
+    }
+}
+
+pub async fn delete_file(file: &str, storage_config: &StorageConfig) -> Result<(), LavaError> {
+    if file.starts_with("s3://") {
+        aws_writer::delete_object(&storage_config.s3_client().await, file).await
+    } else {
+        std::fs::remove_file(local_path(file)?)?;
+        Ok(())
+    }
+}
+
+/// Moves `from` to `to`. Object stores have no rename, so there this is a copy and a delete.
+pub async fn rename_file(
+    from: &str,
+    to: &str,
+    storage_config: &StorageConfig,
+) -> Result<(), LavaError> {
+    if !from.starts_with("s3://") && !to.starts_with("s3://") {
+        std::fs::rename(local_path(from)?, local_path(to)?)?;
+        return Ok(());
+    }
+    copy_file(from, to, storage_config).await?;
+    delete_file(from, storage_config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_writer, rename_file, MemoryWriter, Writer};
+    use crate::formats::readers::StorageConfig;
+    use crate::lava::error::LavaError;
+
+    #[tokio::test]
+    async fn test_local_and_memory_writers() {
+        let path = std::env::temp_dir().join(format!("rottnest_writer_{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let storage_config = StorageConfig::default();
+
+        let mut local = get_writer(&path, &storage_config).await.unwrap();
+        let mut memory = MemoryWriter::new();
+        for writer in [local.as_mut(), &mut memory as &mut dyn Writer] {
+            writer.write_all(b"hello ").await.unwrap();
+            assert_eq!(writer.position(), 6);
+            writer.write_all(b"world").await.unwrap();
+            writer.finish().await.unwrap();
+        }
+        assert_eq!(memory.into_inner(), b"hello world".to_vec());
+
+        let renamed = format!("{}.renamed", path);
+        rename_file(&path, &renamed, &storage_config).await.unwrap();
+        assert_eq!(std::fs::read(&renamed).unwrap(), b"hello world".to_vec());
+        assert!(!std::path::Path::new(&path).exists());
+        std::fs::remove_file(renamed).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_writer_schemes() {
+        let path = std::env::temp_dir().join(format!("rottnest_writer_url_{}", std::process::id()));
+        let storage_config = StorageConfig::default();
+
+        let mut writer = get_writer(&format!("file://{}", path.display()), &storage_config)
+            .await
+            .unwrap();
+        writer.write_all(b"hello").await.unwrap();
+        writer.finish().await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello".to_vec());
+        std::fs::remove_file(path).unwrap();
+
+        for file in [
+            "gs://bucket/a.lava",
+            "http://host/a.lava",
+            "azblob://c/a.lava",
+        ] {
+            let result = get_writer(file, &storage_config).await;
+            assert!(matches!(result, Err(LavaError::Unsupported(_))), "{}", file);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::formats::readers::{
-    get_file_size_and_reader, get_file_sizes_and_readers, AsyncReader, ClonableAsyncReader,
-    ReaderType,
+    decompress, get_file_size_and_reader, get_file_sizes_and_readers, AsyncReader, StorageConfig,
 };
+use crate::formats::writers::get_writer;
 use crate::lava::error::LavaError;
 use crate::lava::plist::PListChunk;
 use arrow::array::{make_array, Array, ArrayData, LargeStringArray, UInt64Array};
@@ -10,9 +10,8 @@ use tokio::task::JoinSet;
 
 use std::collections::{BTreeMap, HashMap};
 
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::sync::Arc;
+use std::io::{BufReader, Read};
+use std::time::Instant;
 use tokenizers::parallelism::MaybeParallelIterator;
 use zstd::stream::encode_all;
 use zstd::stream::Decoder;
@@ -22,11 +21,128 @@ use super::super::get_tokenizer;
 /*
 Structure of the lava file
 It is important to put the posting lists first. Just trust me bro.
-compressed_serialized_tokenizer | compressed posting lists line by line | compressed term dictionary | compressed posting list offsets|
-8 bytes = offsets of compressed term dict | 8 bytes = offset of compressed posting list offsets
+compressed_serialized_tokenizer | compressed posting lists line by line | (compressed position lists line by line) |
+compressed term dictionary | compressed posting list offsets | (compressed position list offsets) |
+8 bytes = offsets of compressed term dict | 8 bytes = offset of compressed posting list offsets | 8 bytes = number of documents |
+(8 bytes = offset of compressed position list offsets | 8 bytes = POSITIONS_MAGIC)
+The parts in parentheses are only there if the index was built with positions. The position lists are chunked
+like the posting lists, the position list of a token holds the positions of each uid in its posting list.
 */
 
+/// Ends the files that have a positions section, no file without one can end with it.
+const POSITIONS_MAGIC: u64 = u64::from_le_bytes(*b"LAVAPOS1");
+
+/// A position is the index of the token in its row, with the row's ordinal among the rows of its uid
+/// in the high bits, so phrases never span rows.
+const ROW_SHIFT: u32 = 32;
+
+/// The (token, offset in the chunk) of the query tokens whose posting lists are in a chunk.
+type ChunkTokens = Vec<(u32, u64)>;
+
+struct Bm25Footer {
+    compressed_term_dict_offset: u64,
+    compressed_plist_offsets_offset: u64,
+    num_documents: u64,
+    compressed_positions_offsets_offset: Option<u64>,
+    // where the compressed offsets end
+    footer_offset: u64,
+}
+
+impl Bm25Footer {
+    async fn read(reader: &mut AsyncReader, file_size: u64) -> Result<Self, LavaError> {
+        let results = reader.read_usize_from_end(5).await?;
+        if results[4] == POSITIONS_MAGIC {
+            Ok(Self {
+                compressed_term_dict_offset: results[0],
+                compressed_plist_offsets_offset: results[1],
+                num_documents: results[2],
+                compressed_positions_offsets_offset: Some(results[3]),
+                footer_offset: file_size - 40,
+            })
+        } else {
+            Ok(Self {
+                compressed_term_dict_offset: results[2],
+                compressed_plist_offsets_offset: results[3],
+                num_documents: results[4],
+                compressed_positions_offsets_offset: None,
+                footer_offset: file_size - 24,
+            })
+        }
+    }
+
+    fn plist_offsets_range(&self) -> (u64, u64) {
+        (
+            self.compressed_plist_offsets_offset,
+            self.compressed_positions_offsets_offset
+                .unwrap_or(self.footer_offset),
+        )
+    }
+}
+
+/// The position list of a token: for each uid in its posting list, the number of positions followed
+/// by the positions, delta encoded.
+fn encode_positions<'a>(uid_positions: impl Iterator<Item = &'a Vec<u64>>) -> Vec<u64> {
+    let mut result = vec![];
+    for positions in uid_positions {
+        result.push(positions.len() as u64);
+        let mut last = 0;
+        for position in positions {
+            result.push(position - last);
+            last = *position;
+        }
+    }
+    result
+}
+
+fn decode_positions(list: &[u64]) -> Result<Vec<Vec<u64>>, LavaError> {
+    let mut result = vec![];
+    let mut i = 0;
+    while i < list.len() {
+        let count = list[i] as usize;
+        let Some(deltas) = list.get(i + 1..i + 1 + count) else {
+            return Err(LavaError::Parse("corrupt position list".to_string()));
+        };
+        let mut last = 0;
+        result.push(
+            deltas
+                .iter()
+                .map(|delta| {
+                    last += delta;
+                    last
+                })
+                .collect(),
+        );
+        i += 1 + count;
+    }
+    Ok(result)
+}
+
+/// Whether the lists have a position each, in order and in the same row, with at most `slop` other
+/// tokens between the first and the last.
+fn phrase_matches(positions: &[&Vec<u64>], slop: usize) -> bool {
+    let Some((first, rest)) = positions.split_first() else {
+        return false;
+    };
+    for start in first.iter() {
+        // the earliest possible end of a phrase starting here
+        let mut end = *start;
+        for list in rest {
+            match list.get(list.partition_point(|position| *position <= end)) {
+                Some(position) => end = *position,
+                None => return false,
+            }
+        }
+        if end >> ROW_SHIFT == start >> ROW_SHIFT && end - start - rest.len() as u64 <= slop as u64
+        {
+            return true;
+        }
+    }
+    false
+}
+
 /// Function that tokenizes the input text and returns a list of tokens.
+/// With `positions`, the positions of every token in each uid are stored too, for phrase queries.
+#[allow(clippy::too_many_arguments)]
 #[tokio::main]
 pub async fn build_lava_bm25(
     output_file_name: String,
@@ -35,6 +151,8 @@ pub async fn build_lava_bm25(
     tokenizer_file: Option<String>,
     k1: Option<f32>,
     b: Option<f32>,
+    positions: bool,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     // if k1 and b are not provided, set them to default value
     let k1: f32 = k1.unwrap_or(1.2);
@@ -82,6 +200,12 @@ pub async fn build_lava_bm25(
 
     let mut inverted_index: Vec<BTreeMap<usize, f32>> = vec![BTreeMap::new(); vocab_size];
     let mut token_counts: Vec<usize> = vec![0; vocab_size];
+    let mut token_positions: Vec<BTreeMap<usize, Vec<u64>>> = if positions {
+        vec![BTreeMap::new(); vocab_size]
+    } else {
+        vec![]
+    };
+    let mut uid_rows: HashMap<usize, u64> = HashMap::new();
 
     let mut avg_len: f32 = 0.0;
     for encoding in encodings.iter() {
@@ -107,11 +231,21 @@ pub async fn build_lava_bm25(
 
             token_counts[*key as usize] += 1;
         }
+        if positions {
+            let row = uid_rows.entry(this_uid).or_insert(0);
+            for (position, key) in encoding.iter().enumerate() {
+                token_positions[*key as usize]
+                    .entry(this_uid)
+                    .or_default()
+                    .push((*row << ROW_SHIFT) | position as u64);
+            }
+            *row += 1;
+        }
     }
 
-    let mut file = File::create(output_file_name)?;
-    file.write_all(&(compressed_tokenizer.len() as u64).to_le_bytes())?;
-    file.write_all(&compressed_tokenizer)?;
+    let mut file = get_writer(&output_file_name, &storage_config).await?;
+    file.write_all(&(compressed_tokenizer.len() as u64).to_le_bytes()).await?;
+    file.write_all(&compressed_tokenizer).await?;
 
     let bytes = bincode::serialize(&token_counts)?;
     let compressed_token_counts: Vec<u8> = encode_all(&bytes[..], 0).expect("Compression failed");
@@ -123,7 +257,7 @@ pub async fn build_lava_bm25(
         inverted_index.len()
     );
 
-    let mut plist_offsets: Vec<u64> = vec![file.seek(SeekFrom::Current(0))?];
+    let mut plist_offsets: Vec<u64> = vec![file.position()];
     let mut plist_elems: Vec<u64> = vec![0];
     let mut plist_chunk = PListChunk::new()?;
     let mut counter: u64 = 0;
@@ -146,29 +280,59 @@ pub async fn build_lava_bm25(
         let written = plist_chunk.add_plist(&plist)?;
         if written > 1024 * 1024 || counter == inverted_index.len() as u64 {
             let bytes = plist_chunk.finalize_compression()?;
-            file.write_all(&bytes)?;
+            file.write_all(&bytes).await?;
             plist_offsets.push(plist_offsets[plist_offsets.len() - 1] + bytes.len() as u64);
             plist_elems.push(counter);
             plist_chunk = PListChunk::new()?;
         }
     }
 
+    // the position lists go in chunks of the same tokens as the posting lists
+    let positions_offsets: Option<Vec<u64>> = if positions {
+        let mut positions_offsets: Vec<u64> = vec![file.position()];
+        for chunk in plist_elems.windows(2) {
+            let mut positions_chunk = PListChunk::new()?;
+            for tok in chunk[0]..chunk[1] {
+                positions_chunk
+                    .add_plist(&encode_positions(token_positions[tok as usize].values()))?;
+            }
+            let bytes = positions_chunk.finalize_compression()?;
+            file.write_all(&bytes).await?;
+            positions_offsets
+                .push(positions_offsets[positions_offsets.len() - 1] + bytes.len() as u64);
+        }
+        Some(positions_offsets)
+    } else {
+        None
+    };
+
     plist_offsets.append(&mut plist_elems);
 
-    let compressed_term_dict_offset = file.seek(SeekFrom::Current(0))?;
-    file.write_all(&compressed_token_counts)?;
+    let compressed_term_dict_offset = file.position();
+    file.write_all(&compressed_token_counts).await?;
 
-    let compressed_plist_offsets_offset = file.seek(SeekFrom::Current(0))?;
+    let compressed_plist_offsets_offset = file.position();
     let serialized = bincode::serialize(&plist_offsets).unwrap();
     let compressed_plist_offsets =
         encode_all(&serialized[..], 0).expect("Compression of plist offsets failed");
-    file.write_all(&compressed_plist_offsets)?;
+    file.write_all(&compressed_plist_offsets).await?;
+
+    let compressed_positions_offsets_offset = file.position();
+    if let Some(positions_offsets) = &positions_offsets {
+        let serialized = bincode::serialize(positions_offsets)?;
+        file.write_all(&encode_all(&serialized[..], 0)?).await?;
+    }
 
-    file.write_all(&(compressed_term_dict_offset as u64).to_le_bytes())?;
-    file.write_all(&(compressed_plist_offsets_offset as u64).to_le_bytes())?;
-    file.write_all(&(encodings.len() as u64).to_le_bytes())?;
+    file.write_all(&(compressed_term_dict_offset as u64).to_le_bytes()).await?;
+    file.write_all(&(compressed_plist_offsets_offset as u64).to_le_bytes()).await?;
+    file.write_all(&(encodings.len() as u64).to_le_bytes()).await?;
+    if positions_offsets.is_some() {
+        file.write_all(&compressed_positions_offsets_offset.to_le_bytes()).await?;
+        file.write_all(&POSITIONS_MAGIC.to_le_bytes()).await?;
+    }
 
-    let cache_end = file.seek(SeekFrom::Current(0))? as usize;
+    let cache_end = file.position() as usize;
+    file.finish().await?;
 
     Ok(vec![(compressed_term_dict_offset as usize, cache_end)])
 }
@@ -212,6 +376,12 @@ impl PListChunkIterator {
         self.current_chunk[self.current_offset_in_chunk as usize].clone()
     }
 
+    // whether there is another posting list after the current one
+    pub fn has_next(&self) -> bool {
+        self.current_offset_in_chunk + 1 < self.current_chunk.len()
+            || self.current_chunk_offset + 2 < self.plist_offsets.len()
+    }
+
     pub async fn advance(&mut self) -> Result<(), LavaError> {
         self.current_offset_in_chunk += 1;
         if self.current_offset_in_chunk == self.current_chunk.len() {
@@ -247,7 +417,7 @@ pub(crate) async fn merge_lava_bm25(
     condensed_lava_file: &str,
     lava_files: Vec<String>,
     uid_offsets: Vec<u64>,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     // let mut builder = Fs::default();
     // let current_path = env::current_dir()?;
@@ -260,17 +430,18 @@ pub(crate) async fn merge_lava_bm25(
     let mut combined_token_counts: Vec<usize> = Vec::new();
     let mut total_num_documents: u64 = 0;
     let mut compressed_tokenizer: Option<Vec<u8>> = None;
+    let mut positions_iterators: Vec<Option<PListChunkIterator>> =
+        Vec::with_capacity(lava_files.len());
 
     for file in lava_files {
-        let reader_type = reader_type.clone();
-        let (file_size, mut reader) = get_file_size_and_reader(file, reader_type).await?;
+        let storage_config = storage_config.clone();
+        let (file_size, mut reader) = get_file_size_and_reader(file, storage_config).await?;
         let file_size = file_size as u64;
 
-        let results = reader.read_usize_from_end(3).await?;
-        let compressed_term_dict_offset = results[0];
-        let compressed_plist_offsets_offset = results[1];
-        let num_documents = results[2];
-        total_num_documents += num_documents;
+        let footer = Bm25Footer::read(&mut reader, file_size).await?;
+        let compressed_term_dict_offset = footer.compressed_term_dict_offset;
+        let compressed_plist_offsets_offset = footer.compressed_plist_offsets_offset;
+        total_num_documents += footer.num_documents;
 
         let compressed_token_counts = reader
             .read_range(compressed_term_dict_offset, compressed_plist_offsets_offset)
@@ -291,8 +462,9 @@ pub(crate) async fn merge_lava_bm25(
             }
         }
 
+        let (plist_offsets_start, plist_offsets_end) = footer.plist_offsets_range();
         let buffer2 = reader
-            .read_range(compressed_plist_offsets_offset, file_size - 24)
+            .read_range(plist_offsets_start, plist_offsets_end)
             .await?;
 
         decompressor = Decoder::new(&buffer2[..])?;
@@ -320,6 +492,22 @@ pub(crate) async fn merge_lava_bm25(
             None => compressed_tokenizer = Some(this_compressed_tokenizer.to_vec()),
         }
 
+        positions_iterators.push(match footer.compressed_positions_offsets_offset {
+            Some(offset) => {
+                let buffer = reader.read_range(offset, footer.footer_offset).await?;
+                let positions_offsets: Vec<u64> = decompress(&buffer)?;
+                Some(
+                    PListChunkIterator::new(
+                        reader.clone(),
+                        positions_offsets,
+                        this_plist_offsets[num_elements..].to_vec(),
+                    )
+                    .await?,
+                )
+            }
+            None => None,
+        });
+
         file_sizes.push(file_size);
         plist_chunk_iterators.push(
             PListChunkIterator::new(
@@ -331,14 +519,14 @@ pub(crate) async fn merge_lava_bm25(
         );
     }
 
-    let mut output_file = File::create(condensed_lava_file)?;
+    let mut output_file = get_writer(condensed_lava_file, &storage_config).await?;
 
     let compressed_tokenizer = compressed_tokenizer.unwrap();
     // let compressed_tokenizer_len = compressed_tokenizer.len();
-    output_file.write_all(&(compressed_tokenizer.len() as u64).to_le_bytes())?;
-    output_file.write_all(&compressed_tokenizer)?;
+    output_file.write_all(&(compressed_tokenizer.len() as u64).to_le_bytes()).await?;
+    output_file.write_all(&compressed_tokenizer).await?;
 
-    let mut new_plist_offsets: Vec<u64> = vec![output_file.seek(SeekFrom::Current(0))?];
+    let mut new_plist_offsets: Vec<u64> = vec![output_file.position()];
     let mut new_plist_elems: Vec<u64> = vec![0];
     let mut plist_chunk = PListChunk::new()?;
     let mut counter: u64 = 0;
@@ -362,8 +550,9 @@ pub(crate) async fn merge_lava_bm25(
                 }
             }
 
-            // this will return error for the last one, but it's ok
-            let _ = plist_chunk_iterators[i].advance().await;
+            if plist_chunk_iterators[i].has_next() {
+                plist_chunk_iterators[i].advance().await?;
+            }
         }
 
         counter += 1;
@@ -374,20 +563,50 @@ pub(crate) async fn merge_lava_bm25(
             let bytes = plist_chunk.finalize_compression()?;
             let this_len: u64 = bytes.len() as u64;
 
-            output_file.write(&bytes)?;
+            output_file.write_all(&bytes).await?;
             new_plist_offsets.push(new_plist_offsets[new_plist_offsets.len() - 1] + this_len);
             new_plist_elems.push(counter);
             plist_chunk = PListChunk::new()?;
         }
     }
 
+    // positions are only kept if every file has them
+    let keep_positions = positions_iterators.iter().all(Option::is_some);
+    let positions_offsets: Option<Vec<u64>> = if keep_positions {
+        let mut positions_iterators: Vec<PListChunkIterator> =
+            positions_iterators.into_iter().flatten().collect();
+
+        // the uids keep their order, so the position lists are concatenated like the posting lists
+        let mut positions_offsets: Vec<u64> = vec![output_file.position()];
+        for chunk in new_plist_elems.windows(2) {
+            let mut positions_chunk = PListChunk::new()?;
+            for _ in chunk[0]..chunk[1] {
+                let mut positions: Vec<u64> = vec![];
+                for iterator in positions_iterators.iter_mut() {
+                    positions.extend(iterator.get());
+                    if iterator.has_next() {
+                        iterator.advance().await?;
+                    }
+                }
+                positions_chunk.add_plist(&positions)?;
+            }
+            let bytes = positions_chunk.finalize_compression()?;
+            output_file.write_all(&bytes).await?;
+            positions_offsets
+                .push(positions_offsets[positions_offsets.len() - 1] + bytes.len() as u64);
+        }
+        Some(positions_offsets)
+    } else {
+        None
+    };
+
     new_plist_offsets.append(&mut new_plist_elems);
 
     let bytes = bincode::serialize(&combined_token_counts)?;
     let compressed_token_counts = encode_all(&bytes[..], 0).expect("Compression failed");
 
-    let compressed_term_dict_offset = output_file.seek(SeekFrom::Current(0))?;
-    output_file.write(&compressed_token_counts)?;
+    let compressed_term_dict_offset = output_file.position();
+    output_file.write_all(&compressed_token_counts).await?;
 
     let serialized = bincode::serialize(&new_plist_offsets).unwrap();
     let compressed_plist_offsets =
@@ -395,24 +614,38 @@ pub(crate) async fn merge_lava_bm25(
 
     let compressed_plist_offsets_offset =
         compressed_term_dict_offset + compressed_token_counts.len() as u64;
-    output_file.write(&compressed_plist_offsets)?;
+    output_file.write_all(&compressed_plist_offsets).await?;
+
+    let compressed_positions_offsets_offset = output_file.position();
+    if let Some(positions_offsets) = &positions_offsets {
+        let serialized = bincode::serialize(positions_offsets)?;
+        let compressed_positions_offsets = encode_all(&serialized[..], 0)?;
+        output_file.write_all(&compressed_positions_offsets).await?;
+    }
 
-    output_file.write(&(compressed_term_dict_offset as u64).to_le_bytes())?;
-    output_file.write(&(compressed_plist_offsets_offset as u64).to_le_bytes())?;
-    output_file.write(&(total_num_documents as u64).to_le_bytes())?;
+    output_file.write_all(&(compressed_term_dict_offset as u64).to_le_bytes()).await?;
+    output_file.write_all(&(compressed_plist_offsets_offset as u64).to_le_bytes()).await?;
+    output_file.write_all(&total_num_documents.to_le_bytes()).await?;
+    if positions_offsets.is_some() {
+        output_file.write_all(&compressed_positions_offsets_offset.to_le_bytes()).await?;
+        output_file.write_all(&POSITIONS_MAGIC.to_le_bytes()).await?;
+    }
+
+    let cache_end = output_file.position() as usize;
+    output_file.finish().await?;
 
-    Ok(vec![(
-        compressed_term_dict_offset as usize,
-        output_file.seek(SeekFrom::Current(0))? as usize,
-    )])
+    Ok(vec![(compressed_term_dict_offset as usize, cache_end)])
 }
 
+/// With `phrase_slop`, `query_tokens` are a phrase: only the uids that have the tokens in order, with at
+/// most `phrase_slop` other tokens in between, are scored. A slop of 0 asks for the exact phrase.
 pub(crate) async fn search_bm25_async(
     file_sizes: Vec<usize>,
     mut readers: Vec<AsyncReader>,
     query_tokens: Vec<u32>,
     query_weights: Vec<f32>,
     k: usize,
+    phrase_slop: Option<usize>,
 ) -> Result<Vec<(u64, u64)>, LavaError> {
     let mut idf: HashMap<u32, f32> = HashMap::new();
     let mut total_token_counts: HashMap<u32, usize> = HashMap::new();
@@ -421,21 +654,35 @@ pub(crate) async fn search_bm25_async(
     }
     let mut total_documents: usize = 0;
     let mut all_plist_offsets: Vec<Vec<u64>> = Vec::new();
+    let mut all_positions_offsets: Vec<Vec<u64>> = Vec::new();
     let mut chunks_to_search: HashMap<(usize, usize), Vec<(u32, u64)>> = HashMap::new();
 
     for i in 0..readers.len() {
-        let results = readers[i].read_usize_from_end(3).await?;
-        let compressed_term_dictionary_offset = results[0];
-        let compressed_plist_offsets_offset = results[1];
-        let num_documents = results[2];
-
-        // now read the term dictionary
-        let token_counts = readers[i]
-            .read_range_and_decompress(
-                compressed_term_dictionary_offset,
-                compressed_plist_offsets_offset,
-            )
-            .await?;
+        let footer = Bm25Footer::read(&mut readers[i], file_sizes[i] as u64).await?;
+        let num_documents = footer.num_documents;
+
+        // the term dictionary and the offsets are adjacent, fetch them together
+        let mut ranges = vec![
+            (
+                footer.compressed_term_dict_offset,
+                footer.compressed_plist_offsets_offset,
+            ),
+            footer.plist_offsets_range(),
+        ];
+        if phrase_slop.is_some() {
+            let Some(offset) = footer.compressed_positions_offsets_offset else {
+                return Err(LavaError::Unsupported(format!(
+                    "phrase queries need positions, bm25 index {} was built without them",
+                    readers[i].filename
+                )));
+            };
+            ranges.push((offset, footer.footer_offset));
+        }
+        let buffers = readers[i].read_ranges(&ranges).await?;
+        let token_counts: Vec<u64> = decompress(&buffers[0])?;
+        if phrase_slop.is_some() {
+            all_positions_offsets.push(decompress(&buffers[2])?);
+        }
 
         for query_token in query_tokens.iter() {
             total_token_counts.insert(
@@ -445,9 +692,7 @@ pub(crate) async fn search_bm25_async(
         }
         total_documents += num_documents as usize;
 
-        let plist_offsets = readers[i]
-            .read_range_and_decompress(compressed_plist_offsets_offset, file_sizes[i] as u64 - 24)
-            .await?;
+        let plist_offsets: Vec<u64> = decompress(&buffers[1])?;
 
         if plist_offsets.len() % 2 != 0 {
             let err = LavaError::Parse("data corruption".to_string());
@@ -491,55 +736,92 @@ pub(crate) async fn search_bm25_async(
     let mut plist_result: Vec<(u64, u64)> = Vec::new();
     let mut page_scores: HashMap<(u64, u64), f32> = HashMap::new();
 
+    // group the chunks by file so every file is fetched with one coalesced read_ranges call
+    let mut file_chunks: BTreeMap<usize, Vec<(usize, ChunkTokens)>> = BTreeMap::new();
+    for ((file_id, chunk_id), token_offsets) in chunks_to_search.into_iter() {
+        file_chunks
+            .entry(file_id)
+            .or_default()
+            .push((chunk_id, token_offsets));
+    }
+
     let mut join_set: JoinSet<Result<Vec<(usize, u64, u32, u64)>, LavaError>> = JoinSet::new();
-    // need to parallelize this @Rain.
-    for (file_id, chunk_id, tokens, offsets) in
-        chunks_to_search
-            .into_iter()
-            .map(|((file_id, chunk_id), token_offsets)| {
-                let (tokens, offsets): (Vec<u32>, Vec<u64>) = token_offsets.into_iter().unzip();
-                (file_id, chunk_id, Arc::new(tokens), Arc::new(offsets))
+    for (file_id, chunks) in file_chunks.into_iter() {
+        let mut reader = readers[file_id].clone();
+        let mut ranges: Vec<(u64, u64)> = chunks
+            .iter()
+            .map(|(chunk_id, _)| {
+                (
+                    all_plist_offsets[file_id][*chunk_id],
+                    all_plist_offsets[file_id][*chunk_id + 1],
+                )
             })
-    {
-        let reader_type = match readers[file_id].reader {
-            ClonableAsyncReader::AwsSdk(_) => ReaderType::AwsSdk,
-            ClonableAsyncReader::Http(_) => ReaderType::Http,
-            ClonableAsyncReader::Local(_) => ReaderType::Local,
-        };
-
-        let mut reader = match reader_type {
-            ReaderType::AwsSdk | ReaderType::Http => readers[file_id].clone(),
-            ReaderType::Local => {
-                get_file_size_and_reader(readers[file_id].filename.clone(), reader_type)
-                    .await
-                    .unwrap()
-                    .1
+            .collect();
+        // the position lists of the same tokens, after the posting lists
+        if phrase_slop.is_some() {
+            for (chunk_id, _) in chunks.iter() {
+                ranges.push((
+                    all_positions_offsets[file_id][*chunk_id],
+                    all_positions_offsets[file_id][*chunk_id + 1],
+                ));
             }
-        };
-        let start = all_plist_offsets[file_id][chunk_id];
-        let end = all_plist_offsets[file_id][chunk_id + 1];
-        let tokens = tokens.clone();
-        let offsets = offsets.clone();
+        }
+        let phrase = phrase_slop.map(|slop| (slop, query_tokens.clone()));
 
         join_set.spawn(async move {
-            // println!("file_id: {}, chunk_id: {}", file_id, chunk_id);
-            let buffer3 = reader.read_range(start, end).await?;
-
-            // get all the second item in the offsets into its own vector
-
-            let results: Vec<Vec<u64>> =
-                PListChunk::search_compressed(buffer3.to_vec(), offsets.as_ref())?;
+            let mut buffers = reader.read_ranges(&ranges).await?;
+            let positions_buffers = buffers.split_off(chunks.len());
 
             let mut res = vec![];
-            for (i, result) in results.iter().enumerate() {
-                let token = &tokens[i];
-                assert_eq!(result.len() % 2, 0);
-                for i in (0..result.len()).step_by(2) {
-                    let uid = result[i];
-                    let page_score = result[i + 1];
-                    res.push((file_id, uid, *token, page_score));
+            let mut positions: HashMap<(u64, u32), Vec<u64>> = HashMap::new();
+            for (chunk_idx, ((_, token_offsets), buffer3)) in
+                chunks.into_iter().zip(buffers).enumerate()
+            {
+                let (tokens, offsets): (Vec<u32>, Vec<u64>) = token_offsets.into_iter().unzip();
+                let results: Vec<Vec<u64>> =
+                    PListChunk::search_compressed(buffer3.to_vec(), &offsets)?;
+                let positions_results: Vec<Vec<u64>> = match positions_buffers.get(chunk_idx) {
+                    Some(buffer) => PListChunk::search_compressed(buffer.to_vec(), &offsets)?,
+                    None => vec![],
+                };
+
+                for (i, result) in results.iter().enumerate() {
+                    let token = &tokens[i];
+                    assert_eq!(result.len() % 2, 0);
+                    for i in (0..result.len()).step_by(2) {
+                        let uid = result[i];
+                        let page_score = result[i + 1];
+                        res.push((file_id, uid, *token, page_score));
+                    }
+
+                    if let Some(positions_result) = positions_results.get(i) {
+                        let uid_positions = decode_positions(positions_result)?;
+                        if uid_positions.len() * 2 != result.len() {
+                            return Err(LavaError::Parse(
+                                "position lists do not match the posting lists".to_string(),
+                            ));
+                        }
+                        for (j, this_positions) in uid_positions.into_iter().enumerate() {
+                            let entry = positions.entry((result[2 * j], *token)).or_default();
+                            entry.extend(this_positions);
+                            entry.sort_unstable();
+                        }
+                    }
                 }
             }
+
+            if let Some((slop, phrase)) = phrase {
+                let mut matches: HashMap<u64, bool> = HashMap::new();
+                res.retain(|(_, uid, _, _)| {
+                    *matches.entry(*uid).or_insert_with(|| {
+                        let lists: Option<Vec<&Vec<u64>>> = phrase
+                            .iter()
+                            .map(|token| positions.get(&(*uid, *token)))
+                            .collect();
+                        lists.is_some_and(|lists| phrase_matches(&lists, slop))
+                    })
+                });
+            }
             Ok(res)
         });
     }
@@ -573,17 +855,198 @@ pub async fn search_lava_bm25(
     query_tokens: Vec<u32>,
     query_weights: Vec<f32>,
     k: usize,
-    reader_type: ReaderType,
+    phrase_slop: Option<usize>,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(u64, u64)>, LavaError> {
-    let (file_sizes, readers) = get_file_sizes_and_readers(&files, reader_type).await?;
-    search_bm25_async(file_sizes, readers, query_tokens, query_weights, k).await
+    let start_time = Instant::now();
+    let (file_sizes, readers) = get_file_sizes_and_readers(&files, storage_config.clone()).await?;
+    storage_config.record_stage("open", start_time);
+
+    let start_time = Instant::now();
+    let result = search_bm25_async(
+        file_sizes,
+        readers,
+        query_tokens,
+        query_weights,
+        k,
+        phrase_slop,
+    )
+    .await;
+    storage_config.record_stage("search", start_time);
+    result
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::formats::readers::ReaderType;
+    use crate::formats::readers::StorageConfig;
+    use crate::lava::error::LavaError;
+    use arrow::array::{Array, LargeStringArray, UInt64Array};
+    use std::collections::{BTreeSet, HashMap};
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::pre_tokenizers::whitespace::Whitespace;
+    use tokenizers::Tokenizer;
+
+    use super::{build_lava_bm25, merge_lava_bm25, phrase_matches, search_lava_bm25};
+
+    const WORDS: [&str; 10] = [
+        "[UNK]", "new", "york", "is", "big", "has", "things", "the", "city", "of",
+    ];
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("rottnest_bm25_{}_{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
 
-    use super::search_lava_bm25;
+    fn write_tokenizer() -> String {
+        let vocab: HashMap<String, u32> = WORDS
+            .iter()
+            .enumerate()
+            .map(|(i, word)| (word.to_string(), i as u32))
+            .collect();
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Whitespace {});
+        let path = temp_path("tokenizer.json");
+        tokenizer.save(&path, false).unwrap();
+        path
+    }
+
+    fn token(word: &str) -> u32 {
+        WORDS.iter().position(|w| *w == word).unwrap() as u32
+    }
+
+    fn build(name: &str, texts: &[(&str, u64)], positions: bool) -> String {
+        let path = temp_path(name);
+        let array = LargeStringArray::from(texts.iter().map(|(text, _)| *text).collect::<Vec<_>>());
+        let uid = UInt64Array::from(texts.iter().map(|(_, uid)| *uid).collect::<Vec<_>>());
+        build_lava_bm25(
+            path.clone(),
+            array.to_data(),
+            uid.to_data(),
+            Some(write_tokenizer()),
+            None,
+            None,
+            positions,
+            StorageConfig::default(),
+        )
+        .unwrap();
+        path
+    }
+
+    fn search(file: &str, words: &[&str], phrase_slop: Option<usize>) -> BTreeSet<(u64, u64)> {
+        search_lava_bm25(
+            vec![file.to_string()],
+            words.iter().map(|word| token(word)).collect(),
+            vec![1.0; words.len()],
+            10,
+            phrase_slop,
+            StorageConfig::default(),
+        )
+        .unwrap()
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_phrase_matches() {
+        let a = vec![1, 5, (1 << 32) + 7];
+        let b = vec![3, 6, (1 << 32) + 9];
+        assert!(phrase_matches(&[&a, &b], 0));
+        assert!(phrase_matches(&[&b, &a], 1));
+        assert!(!phrase_matches(&[&b, &a], 0));
+        // the same token twice needs two positions
+        assert!(!phrase_matches(&[&vec![4], &vec![4]], 3));
+        // a phrase does not continue into the next row
+        assert!(!phrase_matches(&[&vec![5], &vec![1 << 32]], usize::MAX));
+    }
+
+    #[test]
+    fn test_phrase_search() {
+        let texts = [
+            ("new york is big", 0),
+            ("york has new things", 1),
+            ("new big york", 2),
+            // the rows of a uid are searched apart
+            ("the city of new", 3),
+            ("york is big", 3),
+        ];
+        let positional = build("positional.lava", &texts, true);
+        let plain = build("plain.lava", &texts, false);
+
+        // a file with positions is searched like any other
+        let all = BTreeSet::from([(0, 0), (0, 1), (0, 2), (0, 3)]);
+        assert_eq!(search(&positional, &["new", "york"], None), all);
+        assert_eq!(search(&plain, &["new", "york"], None), all);
+
+        assert_eq!(
+            search(&positional, &["new", "york"], Some(0)),
+            BTreeSet::from([(0, 0)])
+        );
+        assert_eq!(
+            search(&positional, &["new", "york"], Some(1)),
+            BTreeSet::from([(0, 0), (0, 2)])
+        );
+        assert_eq!(
+            search(&positional, &["york", "is", "big"], Some(0)),
+            BTreeSet::from([(0, 0), (0, 3)])
+        );
+        assert!(search(&positional, &["big", "new"], Some(5)).is_empty());
+
+        let err = search_lava_bm25(
+            vec![plain.clone()],
+            vec![token("new"), token("york")],
+            vec![1.0, 1.0],
+            10,
+            Some(0),
+            StorageConfig::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, LavaError::Unsupported(_)));
+
+        // merges keep the positions of every file
+        let other = build(
+            "other.lava",
+            &[("things of new york", 0), ("york new", 1)],
+            true,
+        );
+        let merged = temp_path("merged.lava");
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(merge_lava_bm25(
+                &merged,
+                vec![positional.clone(), other.clone()],
+                vec![0, 100],
+                StorageConfig::default(),
+            ))
+            .unwrap();
+        assert_eq!(
+            search(&merged, &["new", "york"], Some(0)),
+            BTreeSet::from([(0, 0), (0, 100)])
+        );
+
+        // but a merge with a file without them has none
+        let merged_plain = temp_path("merged_plain.lava");
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(merge_lava_bm25(
+                &merged_plain,
+                vec![positional.clone(), plain.clone()],
+                vec![0, 100],
+                StorageConfig::default(),
+            ))
+            .unwrap();
+        assert_eq!(search(&merged_plain, &["big"], None).len(), 6);
+
+        for file in [positional, plain, other, merged, merged_plain] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
 
     #[test]
     pub fn test_search_lava_one() {
@@ -594,7 +1057,8 @@ mod tests {
             vec![6300, 15050],
             vec![0.1, 0.2],
             10,
-            ReaderType::default(),
+            None,
+            StorageConfig::default(),
         )
         .unwrap();
 
@@ -608,7 +1072,8 @@ mod tests {
             vec![6300, 15050],
             vec![0.1, 0.2],
             10,
-            ReaderType::default(),
+            None,
+            StorageConfig::default(),
         )
         .unwrap();
 
@@ -14,6 +14,10 @@ pub enum LavaError {
     Tokenizers(#[from] tokenizers::Error),
     Unsupported(String),
     Redis(#[from] redis::RedisError),
+    NotFound(String),
+    PermissionDenied(String),
+    Timeout(String),
+    Unavailable(String),
     Unknown,
     #[cfg(feature = "py")]
     Pyo3(#[from] pyo3::PyErr),
@@ -35,15 +39,73 @@ impl Display for LavaError {
             LavaError::Thrift(err) => write!(f, "Thrift error: {}", err),
             LavaError::Tokenizers(err) => write!(f, "Tokenizers error: {}", err),
             LavaError::Redis(err) => write!(f, "Redis error: {}", err),
+            LavaError::NotFound(err) => write!(f, "Not found: {}", err),
+            LavaError::PermissionDenied(err) => write!(f, "Permission denied: {}", err),
+            LavaError::Timeout(err) => write!(f, "Timeout: {}", err),
+            LavaError::Unavailable(err) => write!(f, "Service unavailable: {}", err),
             #[cfg(feature = "py")]
             LavaError::Pyo3(err) => write!(f, "Pyo3 error: {}", err),
         }
     }
 }
 
+impl LavaError {
+    /// The error with `context`, e.g. the file it is about, in front of its message. Errors
+    /// without a message of their own become parse errors.
+    pub fn context(self, context: impl Display) -> LavaError {
+        match self {
+            LavaError::Io(err) => {
+                LavaError::Io(std::io::Error::new(err.kind(), format!("{}: {}", context, err)))
+            }
+            LavaError::Compression(err) => LavaError::Compression(format!("{}: {}", context, err)),
+            LavaError::Parse(err) => LavaError::Parse(format!("{}: {}", context, err)),
+            LavaError::Unsupported(err) => LavaError::Unsupported(format!("{}: {}", context, err)),
+            LavaError::NotFound(err) => LavaError::NotFound(format!("{}: {}", context, err)),
+            LavaError::PermissionDenied(err) => {
+                LavaError::PermissionDenied(format!("{}: {}", context, err))
+            }
+            LavaError::Timeout(err) => LavaError::Timeout(format!("{}: {}", context, err)),
+            LavaError::Unavailable(err) => LavaError::Unavailable(format!("{}: {}", context, err)),
+            err => LavaError::Parse(format!("{}: {}", context, err)),
+        }
+    }
+
+    /// Whether a request that failed with this error may succeed when it is retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LavaError::Timeout(_) | LavaError::Unavailable(_) => true,
+            LavaError::Io(err) => matches!(
+                err.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            LavaError::Reqwest(err) => {
+                err.is_timeout()
+                    || err.is_connect()
+                    || err.is_request()
+                    || err.is_body()
+                    || err
+                        .status()
+                        .is_some_and(|status| status.is_server_error() || status == 429)
+            }
+            _ => false,
+        }
+    }
+}
+
 #[cfg(feature = "py")]
 impl From<LavaError> for pyo3::PyErr {
     fn from(e: LavaError) -> pyo3::PyErr {
-        pyo3::exceptions::PyOSError::new_err(e.to_string())
+        match e {
+            LavaError::NotFound(_) => pyo3::exceptions::PyFileNotFoundError::new_err(e.to_string()),
+            LavaError::PermissionDenied(_) => {
+                pyo3::exceptions::PyPermissionError::new_err(e.to_string())
+            }
+            LavaError::Timeout(_) => pyo3::exceptions::PyTimeoutError::new_err(e.to_string()),
+            _ => pyo3::exceptions::PyOSError::new_err(e.to_string()),
+        }
     }
 }
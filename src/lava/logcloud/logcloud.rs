@@ -8,7 +8,7 @@ use tokio::{task::JoinSet, time::sleep};
 use crate::{
     formats::readers::{
         get_file_size_and_reader, get_file_sizes_and_readers, get_reader, AsyncReader,
-        ClonableAsyncReader, ReaderType, READ_RANGE_COUNTER,
+        ClonableAsyncReader, StorageConfig,
     },
     lava::{
         error::LavaError,
@@ -21,10 +21,7 @@ use serde::de::DeserializeOwned;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
-use std::{
-    collections::{HashMap, HashSet},
-    sync::atomic::Ordering,
-};
+use std::collections::{HashMap, HashSet};
 
 use std::{
     fs::{self, read_dir},
@@ -753,7 +750,7 @@ pub async fn search_hawaii_oahu(
             vec![hawaii_filename],
             query.clone(),
             limit,
-            ReaderType::default(),
+            StorageConfig::default(),
             None,
             None,
             wavelet_tree,
@@ -874,18 +871,18 @@ pub async fn index_logcloud(index_name: &str, num_groups: usize, use_wavelet: Op
     let _ = write_kauai(index_name, num_groups).unwrap();
     let texts: Vec<(u64, String)> = write_oahu(index_name);
     if use_wavelet {
-        let _ = _build_lava_substring_char_wavelet(format!("{}.hawaii", index_name), texts, 1)
+        let _ = _build_lava_substring_char_wavelet(format!("{}.hawaii", index_name), texts, 1, StorageConfig::default())
             .await
             .unwrap();
     } else {
-        let _ = _build_lava_substring_char(format!("{}.hawaii", index_name), texts, 1)
+        let _ = _build_lava_substring_char(format!("{}.hawaii", index_name), texts, 1, StorageConfig::default())
             .await
             .unwrap();
     }
 }
 
 #[tokio::main]
-pub async fn index_analysis(split_index_prefixes: Vec<String>, reader_type: ReaderType) -> () {
+pub async fn index_analysis(split_index_prefixes: Vec<String>, storage_config: StorageConfig) -> () {
     let mut oahu_filenames = split_index_prefixes
         .iter()
         .map(|split_index_prefix| format!("{}.oahu", split_index_prefix))
@@ -897,11 +894,11 @@ pub async fn index_analysis(split_index_prefixes: Vec<String>, reader_type: Read
         .collect::<Vec<_>>();
 
     let (oahu_sizes, mut reader_oahus) =
-        get_file_sizes_and_readers(&oahu_filenames, reader_type.clone())
+        get_file_sizes_and_readers(&oahu_filenames, storage_config.clone())
             .await
             .unwrap();
     let (hawaii_sizes, mut reader_hawaiis) =
-        get_file_sizes_and_readers(&hawaii_filenames, reader_type.clone())
+        get_file_sizes_and_readers(&hawaii_filenames, storage_config.clone())
             .await
             .unwrap();
 
@@ -1025,7 +1022,7 @@ pub async fn search_logcloud(
     split_index_prefixes: Vec<String>,
     query: String,
     limit: usize,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
     wavelet_tree: bool,
     exact: bool,
 ) -> Result<(u32, Vec<(usize, PlistSize)>), LavaError> {
@@ -1039,7 +1036,7 @@ pub async fn search_logcloud(
         .collect::<Vec<_>>();
 
     let (kauai_sizes, reader_kauais) =
-        get_file_sizes_and_readers(&kauai_filenames, reader_type.clone()).await?;
+        get_file_sizes_and_readers(&kauai_filenames, storage_config.clone()).await?;
 
     let mut set = JoinSet::new();
     for (file_id, (kauai_size, reader_kauai)) in kauai_sizes
@@ -1084,7 +1081,7 @@ pub async fn search_logcloud(
         }
     }
 
-    println!("kauai time {:?}", start_time.elapsed());
+    storage_config.record_stage("kauai", start_time);
 
     let start_time = std::time::Instant::now();
 
@@ -1102,7 +1099,7 @@ pub async fn search_logcloud(
         .collect::<Vec<_>>();
 
     let (oahu_sizes, mut reader_oahus) =
-        get_file_sizes_and_readers(&oahu_filenames, reader_type.clone()).await?;
+        get_file_sizes_and_readers(&oahu_filenames, storage_config.clone()).await?;
 
     let mut set = JoinSet::new();
     let new_limit = limit - all_uids.len();
@@ -1138,10 +1135,7 @@ pub async fn search_logcloud(
         }
     }
 
-    println!("oahu time {:?}", start_time.elapsed());
-
-    let count = READ_RANGE_COUNTER.load(Ordering::SeqCst);
-    println!("read_range has been called {} times", count);
+    storage_config.record_stage("oahu", start_time);
 
     // println!("all_uids {:?}", all_uids);
 
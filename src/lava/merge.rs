@@ -4,7 +4,8 @@ use itertools::Itertools;
 use std::collections::BTreeSet;
 use std::sync::{Arc, Mutex};
 
-use crate::formats::readers::ReaderType;
+use crate::formats::readers::StorageConfig;
+use crate::formats::writers::{copy_file, delete_file, rename_file};
 
 use crate::lava::bm25::merge_lava_bm25;
 use crate::lava::error::LavaError;
@@ -21,7 +22,7 @@ async fn async_parallel_merge_files(
     uid_offsets: Vec<u64>,
     k: usize,
     mode: usize, // 0 for bm25 1 for substring 2 for uuid
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
     cache_ranges: Option<Vec<Vec<(usize, usize)>>>,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     assert!(mode == 1 || mode == 0 || mode == 2);
@@ -32,8 +33,13 @@ async fn async_parallel_merge_files(
     match files.len() {
         0 => Err(LavaError::Parse("out of chunks".to_string())), // Assuming LavaError can be constructed like this
         1 => {
-            // the recursion will end here in this case. rename the files[0] to the supposed output name
-            std::fs::rename(files[0].clone(), condensed_lava_file).unwrap();
+            // the recursion will end here in this case. rename the files[0] to the supposed output name,
+            // input files are copied instead since they must be left alone
+            if do_not_delete.contains(&files[0]) {
+                copy_file(&files[0], &condensed_lava_file, &storage_config).await?;
+            } else {
+                rename_file(&files[0], &condensed_lava_file, &storage_config).await?;
+            }
             let mut cache_ranges = cache_ranges.unwrap();
             assert!(cache_ranges.len() == 1);
             Ok(cache_ranges.remove(0))
@@ -78,11 +84,13 @@ async fn async_parallel_merge_files(
                 let merged_files_clone = Arc::clone(&merged_files_shared);
                 let new_uid_offsets_clone = Arc::clone(&new_uid_offsets_shared);
                 let do_not_delete_clone = do_not_delete.clone();
-                let reader_type = reader_type.clone();
+                let storage_config = storage_config.clone();
+                let condensed_lava_file = condensed_lava_file.clone();
 
                 let task: tokio::task::JoinHandle<Vec<(usize, usize)>> = tokio::spawn(async move {
                     let my_uuid = uuid::Uuid::new_v4();
-                    let merged_filename = my_uuid.to_string(); // Define this function based on your requirements
+                    // intermediates go next to the output, so merges into a bucket never touch local disk
+                    let merged_filename = format!("{}.{}.tmp", condensed_lava_file, my_uuid);
 
                     println!("mergin {:?}", file_chunk);
 
@@ -92,7 +100,7 @@ async fn async_parallel_merge_files(
                                 &merged_filename,
                                 file_chunk.to_vec(),
                                 uid_chunk.to_vec(),
-                                reader_type.clone(),
+                                storage_config.clone(),
                             )
                             .await
                         }
@@ -101,7 +109,7 @@ async fn async_parallel_merge_files(
                                 &merged_filename,
                                 file_chunk.to_vec(),
                                 uid_chunk.to_vec(),
-                                reader_type.clone(),
+                                storage_config.clone(),
                             )
                             .await
                         }
@@ -110,7 +118,7 @@ async fn async_parallel_merge_files(
                                 &merged_filename,
                                 file_chunk.to_vec(),
                                 uid_chunk.to_vec(),
-                                reader_type.clone(),
+                                storage_config.clone(),
                             )
                             .await
                         }
@@ -123,7 +131,7 @@ async fn async_parallel_merge_files(
                     for file in file_chunk {
                         if !do_not_delete_clone.contains(&file) {
                             println!("deleting {}", file);
-                            std::fs::remove_file(file).unwrap();
+                            delete_file(&file, &storage_config).await.unwrap();
                         }
                     }
 
@@ -162,7 +170,7 @@ async fn async_parallel_merge_files(
                 new_uid_offsets,
                 k,
                 mode,
-                reader_type.clone(),
+                storage_config.clone(),
                 Some(cache_ranges),
             )
             .await
@@ -177,7 +185,7 @@ pub async fn parallel_merge_files(
     uid_offsets: Vec<u64>,
     k: usize,
     mode: usize, // 0 for bm25 1 for substring 2 for uuid
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let do_not_delete = BTreeSet::from_iter(files.clone().into_iter());
     let result = async_parallel_merge_files(
@@ -187,7 +195,7 @@ pub async fn parallel_merge_files(
         uid_offsets,
         k,
         mode,
-        reader_type,
+        storage_config,
         None,
     )
     .await?;
@@ -196,7 +204,7 @@ pub async fn parallel_merge_files(
 
 #[cfg(test)]
 mod tests {
-    use crate::{formats::readers::ReaderType, lava::merge::parallel_merge_files};
+    use crate::{formats::readers::StorageConfig, lava::merge::parallel_merge_files};
 
     #[test]
     pub fn test_merge_lava_bm25() {
@@ -206,7 +214,7 @@ mod tests {
             vec![0, 1000000],
             2,
             0,
-            ReaderType::default(),
+            StorageConfig::default(),
         );
 
         println!("{:?}", res);
@@ -223,7 +231,7 @@ mod tests {
             vec![0, 1000000],
             2,
             1,
-            ReaderType::default(),
+            StorageConfig::default(),
         );
 
         println!("{:?}", res);
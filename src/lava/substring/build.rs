@@ -1,4 +1,6 @@
 use super::constants::*;
+use crate::formats::readers::StorageConfig;
+use crate::formats::writers::get_writer;
 use crate::lava::error::LavaError;
 
 use crate::lava::substring::wavelet_tree::{construct_wavelet_tree, write_wavelet_tree_to_disk};
@@ -11,8 +13,6 @@ use serde_json;
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::fs::File;
-use std::io::{BufWriter, Seek, SeekFrom, Write};
 use tokenizers::parallelism::MaybeParallelIterator;
 use tokenizers::tokenizer::Tokenizer; // You'll need the `byteorder` crate
 use zstd::stream::encode_all;
@@ -21,6 +21,7 @@ pub async fn _build_lava_substring_char_wavelet(
     output_file_name: String,
     texts: Vec<(u64, String)>,
     char_skip_factor: u32,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let named_encodings = texts
         .into_iter()
@@ -87,21 +88,21 @@ pub async fn _build_lava_substring_char_wavelet(
 
     let wavelet_tree = construct_wavelet_tree(&bwt);
 
-    let mut file = File::create(output_file_name)?;
+    let mut file = get_writer(&output_file_name, &storage_config).await?;
 
-    let (offsets, level_offsets) = write_wavelet_tree_to_disk(&wavelet_tree, &mut file).unwrap();
+    let (offsets, level_offsets) = write_wavelet_tree_to_disk(&wavelet_tree, file.as_mut()).await?;
 
     // print out total file size so far
-    println!("total file size: {}", file.seek(SeekFrom::Current(0))?);
+    println!("total file size: {}", file.position());
 
-    let mut posting_list_offsets: Vec<usize> = vec![file.seek(SeekFrom::Current(0))? as usize];
+    let mut posting_list_offsets: Vec<usize> = vec![file.position() as usize];
 
     for i in (0..idx.len()).step_by(FM_CHUNK_TOKS) {
         let slice = &idx[i..std::cmp::min(idx.len(), i + FM_CHUNK_TOKS)];
         let serialized_slice = bincode::serialize(slice)?;
         let compressed_slice = encode_all(&serialized_slice[..], 0).expect("Compression failed");
-        file.write_all(&compressed_slice)?;
-        posting_list_offsets.push(file.seek(SeekFrom::Current(0))? as usize);
+        file.write_all(&compressed_slice).await?;
+        posting_list_offsets.push(file.position() as usize);
     }
 
     let metadata: (Vec<usize>, Vec<usize>, Vec<usize>, Vec<usize>, usize) = (
@@ -112,14 +113,15 @@ pub async fn _build_lava_substring_char_wavelet(
         bwt.len(),
     );
 
-    let cache_start = file.seek(SeekFrom::Current(0))? as usize;
+    let cache_start = file.position() as usize;
 
     let serialized_metadata = bincode::serialize(&metadata)?;
     let compressed_metadata = encode_all(&serialized_metadata[..], 0).expect("Compression failed");
-    file.write_all(&compressed_metadata)?;
-    file.write_all(&cache_start.to_le_bytes())?;
+    file.write_all(&compressed_metadata).await?;
+    file.write_all(&cache_start.to_le_bytes()).await?;
 
-    let cache_end = file.seek(SeekFrom::Current(0))? as usize;
+    let cache_end = file.position() as usize;
+    file.finish().await?;
 
     Ok(vec![(cache_start, cache_end)])
 }
@@ -128,6 +130,7 @@ pub async fn _build_lava_substring_char(
     output_file_name: String,
     texts: Vec<(u64, String)>,
     char_skip_factor: u32,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let named_encodings = texts
         .into_iter()
@@ -180,9 +183,9 @@ pub async fn _build_lava_substring_char(
         }
     }
 
-    let mut file = File::create(output_file_name)?;
+    let mut file = get_writer(&output_file_name, &storage_config).await?;
 
-    let mut fm_chunk_offsets: Vec<usize> = vec![file.seek(SeekFrom::Current(0))? as usize];
+    let mut fm_chunk_offsets: Vec<usize> = vec![file.position() as usize];
 
     let mut current_chunk: Vec<u8> = vec![];
     let mut current_chunk_counts: HashMap<u8, u64> = HashMap::new();
@@ -201,19 +204,19 @@ pub async fn _build_lava_substring_char(
             let compressed_counts =
                 encode_all(&serialized_counts[..], 10).expect("Compression failed");
             println!("chunk size: {}", compressed_counts.len());
-            file.write_all(&(compressed_counts.len() as u64).to_le_bytes())?;
-            file.write_all(&compressed_counts)?;
+            file.write_all(&(compressed_counts.len() as u64).to_le_bytes()).await?;
+            file.write_all(&compressed_counts).await?;
             let serialized_chunk = bincode::serialize(&current_chunk)?;
             let compressed_chunk =
                 encode_all(&serialized_chunk[..], 10).expect("Compression failed");
-            file.write_all(&compressed_chunk)?;
-            fm_chunk_offsets.push(file.seek(SeekFrom::Current(0))? as usize);
+            file.write_all(&compressed_chunk).await?;
+            fm_chunk_offsets.push(file.position() as usize);
             current_chunk_counts = next_chunk_counts.clone();
             current_chunk = vec![];
         }
     }
     // print out total file size so far
-    println!("total file size: {}", file.seek(SeekFrom::Current(0))?);
+    println!("total file size: {}", file.position());
 
     let mut cumulative_counts: Vec<u64> = vec![0];
     for i in 0..256 {
@@ -221,42 +224,43 @@ pub async fn _build_lava_substring_char(
             .push(cumulative_counts[i] + *current_chunk_counts.get(&(i as u8)).unwrap_or(&0));
     }
 
-    let mut posting_list_offsets: Vec<usize> = vec![file.seek(SeekFrom::Current(0))? as usize];
+    let mut posting_list_offsets: Vec<usize> = vec![file.position() as usize];
 
     for i in (0..idx.len()).step_by(FM_CHUNK_TOKS) {
         let slice = &idx[i..std::cmp::min(idx.len(), i + FM_CHUNK_TOKS)];
         let serialized_slice = bincode::serialize(slice)?;
         let compressed_slice = encode_all(&serialized_slice[..], 0).expect("Compression failed");
-        file.write_all(&compressed_slice)?;
-        posting_list_offsets.push(file.seek(SeekFrom::Current(0))? as usize);
+        file.write_all(&compressed_slice).await?;
+        posting_list_offsets.push(file.position() as usize);
     }
 
-    let cache_start = file.seek(SeekFrom::Current(0))? as usize;
+    let cache_start = file.position() as usize;
 
-    let fm_chunk_offsets_offset = file.seek(SeekFrom::Current(0))? as usize;
+    let fm_chunk_offsets_offset = file.position() as usize;
     let serialized_fm_chunk_offsets = bincode::serialize(&fm_chunk_offsets)?;
     let compressed_fm_chunk_offsets =
         encode_all(&serialized_fm_chunk_offsets[..], 0).expect("Compression failed");
-    file.write_all(&compressed_fm_chunk_offsets)?;
+    file.write_all(&compressed_fm_chunk_offsets).await?;
 
-    let posting_list_offsets_offset = file.seek(SeekFrom::Current(0))? as usize;
+    let posting_list_offsets_offset = file.position() as usize;
     let serialized_posting_list_offsets = bincode::serialize(&posting_list_offsets)?;
     let compressed_posting_list_offsets =
         encode_all(&serialized_posting_list_offsets[..], 0).expect("Compression failed");
-    file.write_all(&compressed_posting_list_offsets)?;
+    file.write_all(&compressed_posting_list_offsets).await?;
 
-    let total_counts_offset = file.seek(SeekFrom::Current(0))? as usize;
+    let total_counts_offset = file.position() as usize;
     let serialized_total_counts = bincode::serialize(&cumulative_counts)?;
     let compressed_total_counts: Vec<u8> =
         encode_all(&serialized_total_counts[..], 0).expect("Compression failed");
-    file.write_all(&compressed_total_counts)?;
+    file.write_all(&compressed_total_counts).await?;
 
-    file.write_all(&(fm_chunk_offsets_offset as u64).to_le_bytes())?;
-    file.write_all(&(posting_list_offsets_offset as u64).to_le_bytes())?;
-    file.write_all(&(total_counts_offset as u64).to_le_bytes())?;
-    file.write_all(&(bwt.len() as u64).to_le_bytes())?;
+    file.write_all(&(fm_chunk_offsets_offset as u64).to_le_bytes()).await?;
+    file.write_all(&(posting_list_offsets_offset as u64).to_le_bytes()).await?;
+    file.write_all(&(total_counts_offset as u64).to_le_bytes()).await?;
+    file.write_all(&(bwt.len() as u64).to_le_bytes()).await?;
 
-    let cache_end = file.seek(SeekFrom::Current(0))? as usize;
+    let cache_end = file.position() as usize;
+    file.finish().await?;
 
     Ok(vec![(cache_start, cache_end)])
 }
@@ -267,6 +271,7 @@ pub async fn build_lava_substring_char(
     array: ArrayData,
     uid: ArrayData,
     char_skip_factor: Option<u32>,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let array = make_array(array);
     // let uid = make_array(ArrayData::from_pyarrow(uid)?);
@@ -301,8 +306,8 @@ pub async fn build_lava_substring_char(
     }
 
     println!("made it to this point");
-    // _build_lava_substring_char(output_file_name, texts, char_skip_factor).await
-    _build_lava_substring_char_wavelet(output_file_name, texts, char_skip_factor).await
+    // _build_lava_substring_char(output_file_name, texts, char_skip_factor, storage_config).await
+    _build_lava_substring_char_wavelet(output_file_name, texts, char_skip_factor, storage_config).await
 }
 
 #[tokio::main]
@@ -312,6 +317,7 @@ pub async fn build_lava_substring(
     uid: ArrayData,
     tokenizer_file: Option<String>,
     token_skip_factor: Option<u32>,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let array = make_array(array);
     // let uid = make_array(ArrayData::from_pyarrow(uid)?);
@@ -463,11 +469,11 @@ pub async fn build_lava_substring(
         }
     }
 
-    let mut file = File::create(output_file_name)?;
-    file.write_all(&(compressed_tokenizer.len() as u64).to_le_bytes())?;
-    file.write_all(&compressed_tokenizer)?;
+    let mut file = get_writer(&output_file_name, &storage_config).await?;
+    file.write_all(&(compressed_tokenizer.len() as u64).to_le_bytes()).await?;
+    file.write_all(&compressed_tokenizer).await?;
 
-    let mut fm_chunk_offsets: Vec<usize> = vec![file.seek(SeekFrom::Current(0))? as usize];
+    let mut fm_chunk_offsets: Vec<usize> = vec![file.position() as usize];
 
     let mut current_chunk: Vec<u32> = vec![];
     let mut current_chunk_counts: HashMap<u32, u64> = HashMap::new();
@@ -486,20 +492,20 @@ pub async fn build_lava_substring(
             let compressed_counts =
                 encode_all(&serialized_counts[..], 10).expect("Compression failed");
 
-            file.write_all(&(compressed_counts.len() as u64).to_le_bytes())?;
-            file.write_all(&compressed_counts)?;
+            file.write_all(&(compressed_counts.len() as u64).to_le_bytes()).await?;
+            file.write_all(&compressed_counts).await?;
             let serialized_chunk = bincode::serialize(&current_chunk)?;
             let compressed_chunk =
                 encode_all(&serialized_chunk[..], 10).expect("Compression failed");
-            file.write_all(&compressed_chunk)?;
+            file.write_all(&compressed_chunk).await?;
 
-            fm_chunk_offsets.push(file.seek(SeekFrom::Current(0))? as usize);
+            fm_chunk_offsets.push(file.position() as usize);
             current_chunk_counts = next_chunk_counts.clone();
             current_chunk = vec![];
         }
     }
     // print out total file size so far
-    println!("total file size: {}", file.seek(SeekFrom::Current(0))?);
+    println!("total file size: {}", file.position());
 
     let mut cumulative_counts: Vec<u64> = vec![0];
     for i in 0..tokenizer.get_vocab_size(false) {
@@ -507,42 +513,43 @@ pub async fn build_lava_substring(
             .push(cumulative_counts[i] + *current_chunk_counts.get(&(i as u32)).unwrap_or(&0));
     }
 
-    let mut posting_list_offsets: Vec<usize> = vec![file.seek(SeekFrom::Current(0))? as usize];
+    let mut posting_list_offsets: Vec<usize> = vec![file.position() as usize];
 
     for i in (0..idx.len()).step_by(FM_CHUNK_TOKS) {
         let slice = &idx[i..std::cmp::min(idx.len(), i + FM_CHUNK_TOKS)];
         let serialized_slice = bincode::serialize(slice)?;
         let compressed_slice = encode_all(&serialized_slice[..], 0).expect("Compression failed");
-        file.write_all(&compressed_slice)?;
-        posting_list_offsets.push(file.seek(SeekFrom::Current(0))? as usize);
+        file.write_all(&compressed_slice).await?;
+        posting_list_offsets.push(file.position() as usize);
     }
 
-    let cache_start = file.seek(SeekFrom::Current(0))? as usize;
+    let cache_start = file.position() as usize;
 
-    let fm_chunk_offsets_offset = file.seek(SeekFrom::Current(0))? as usize;
+    let fm_chunk_offsets_offset = file.position() as usize;
     let serialized_fm_chunk_offsets = bincode::serialize(&fm_chunk_offsets)?;
     let compressed_fm_chunk_offsets =
         encode_all(&serialized_fm_chunk_offsets[..], 0).expect("Compression failed");
-    file.write_all(&compressed_fm_chunk_offsets)?;
+    file.write_all(&compressed_fm_chunk_offsets).await?;
 
-    let posting_list_offsets_offset = file.seek(SeekFrom::Current(0))? as usize;
+    let posting_list_offsets_offset = file.position() as usize;
     let serialized_posting_list_offsets = bincode::serialize(&posting_list_offsets)?;
     let compressed_posting_list_offsets =
         encode_all(&serialized_posting_list_offsets[..], 0).expect("Compression failed");
-    file.write_all(&compressed_posting_list_offsets)?;
+    file.write_all(&compressed_posting_list_offsets).await?;
 
-    let total_counts_offset = file.seek(SeekFrom::Current(0))? as usize;
+    let total_counts_offset = file.position() as usize;
     let serialized_total_counts = bincode::serialize(&cumulative_counts)?;
     let compressed_total_counts: Vec<u8> =
         encode_all(&serialized_total_counts[..], 0).expect("Compression failed");
-    file.write_all(&compressed_total_counts)?;
+    file.write_all(&compressed_total_counts).await?;
 
-    file.write_all(&(fm_chunk_offsets_offset as u64).to_le_bytes())?;
-    file.write_all(&(posting_list_offsets_offset as u64).to_le_bytes())?;
-    file.write_all(&(total_counts_offset as u64).to_le_bytes())?;
-    file.write_all(&(bwt.len() as u64).to_le_bytes())?;
+    file.write_all(&(fm_chunk_offsets_offset as u64).to_le_bytes()).await?;
+    file.write_all(&(posting_list_offsets_offset as u64).to_le_bytes()).await?;
+    file.write_all(&(total_counts_offset as u64).to_le_bytes()).await?;
+    file.write_all(&(bwt.len() as u64).to_le_bytes()).await?;
 
-    let cache_end = file.seek(SeekFrom::Current(0))? as usize;
+    let cache_end = file.position() as usize;
+    file.finish().await?;
 
     Ok(vec![(cache_start, cache_end)])
 }
@@ -1,13 +1,13 @@
-use std::{collections::HashMap, io::Seek, io::SeekFrom, io::Write};
+use std::collections::HashMap;
 
 use super::constants::*;
 use super::fm_chunk::FMChunk;
 use crate::{
-    formats::readers::{get_file_size_and_reader, AsyncReader, ReaderType},
+    formats::readers::{get_file_size_and_reader, AsyncReader, StorageConfig},
+    formats::writers::get_writer,
     lava::error::LavaError,
 };
 use bit_vec::BitVec;
-use std::fs::File;
 use zstd::stream::encode_all;
 
 struct PListIterator {
@@ -173,7 +173,7 @@ pub(crate) async fn merge_lava_substring(
     condensed_lava_file: &str,
     lava_files: Vec<String>,
     uid_offsets: Vec<u64>,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     // first merge the tokenizer, then merge the fm indices then merge the posting lists.
     // let mut builder = Fs::default();
@@ -196,9 +196,9 @@ pub(crate) async fn merge_lava_substring(
         // @Rain just make two different readers for now because this is hopefully low overhead
         // instead of bothering with wrapping this thing in Arc<Mutex<>>. Lots of tech debt to clean up
         // needed for the FMChunkIterator and PListIterator
-        let (_, mut reader) = get_file_size_and_reader(file.clone(), reader_type.clone()).await?;
+        let (_, mut reader) = get_file_size_and_reader(file.clone(), storage_config.clone()).await?;
         let (file_size, reader1) =
-            get_file_size_and_reader(file.clone(), reader_type.clone()).await?;
+            get_file_size_and_reader(file.clone(), storage_config.clone()).await?;
         let file_size = file_size as u64;
 
         let results = reader.read_usize_from_end(4).await?;
@@ -266,10 +266,10 @@ pub(crate) async fn merge_lava_substring(
     // let duration = start.elapsed();
     // println!("interleave time: {:?}", duration);
 
-    let mut output_file = File::create(condensed_lava_file)?;
+    let mut output_file = get_writer(condensed_lava_file, &storage_config).await?;
     let compressed_tokenizer = compressed_tokenizer.unwrap();
-    output_file.write_all(&(compressed_tokenizer.len() as u64).to_le_bytes())?;
-    output_file.write_all(&compressed_tokenizer)?;
+    output_file.write_all(&(compressed_tokenizer.len() as u64).to_le_bytes()).await?;
+    output_file.write_all(&compressed_tokenizer).await?;
 
     let mut bwt_output: Vec<u32> = Vec::with_capacity(interleave.len());
     let mut index_output: Vec<u64> = Vec::with_capacity(interleave.len());
@@ -325,7 +325,7 @@ pub(crate) async fn merge_lava_substring(
     let mut current_chunk: Vec<u32> = vec![];
     let mut current_chunk_counts: HashMap<u32, u64> = HashMap::new();
     let mut next_chunk_counts: HashMap<u32, u64> = HashMap::new();
-    let mut fm_chunk_offsets: Vec<usize> = vec![output_file.seek(SeekFrom::Current(0))? as usize];
+    let mut fm_chunk_offsets: Vec<usize> = vec![output_file.position() as usize];
 
     for i in 0..bwt_output.len() {
         let current_tok = bwt_output[i];
@@ -339,56 +339,56 @@ pub(crate) async fn merge_lava_substring(
             let serialized_counts = bincode::serialize(&current_chunk_counts)?;
             let compressed_counts =
                 encode_all(&serialized_counts[..], 0).expect("Compression failed");
-            output_file.write_all(&(compressed_counts.len() as u64).to_le_bytes())?;
-            output_file.write_all(&compressed_counts)?;
+            output_file.write_all(&(compressed_counts.len() as u64).to_le_bytes()).await?;
+            output_file.write_all(&compressed_counts).await?;
             let serialized_chunk = bincode::serialize(&current_chunk)?;
             let compressed_chunk =
                 encode_all(&serialized_chunk[..], 0).expect("Compression failed");
-            output_file.write_all(&compressed_chunk)?;
-            fm_chunk_offsets.push(output_file.seek(SeekFrom::Current(0))? as usize);
+            output_file.write_all(&compressed_chunk).await?;
+            fm_chunk_offsets.push(output_file.position() as usize);
             current_chunk_counts = next_chunk_counts.clone();
             current_chunk = vec![];
         }
     }
 
     let mut posting_list_offsets: Vec<usize> =
-        vec![output_file.seek(SeekFrom::Current(0))? as usize];
+        vec![output_file.position() as usize];
 
     for i in (0..index_output.len()).step_by(FM_CHUNK_TOKS) {
         let slice = &index_output[i..std::cmp::min(index_output.len(), i + FM_CHUNK_TOKS)];
         let serialized_slice = bincode::serialize(slice)?;
         let compressed_slice = encode_all(&serialized_slice[..], 0).expect("Compression failed");
-        output_file.write_all(&compressed_slice)?;
-        posting_list_offsets.push(output_file.seek(SeekFrom::Current(0))? as usize);
+        output_file.write_all(&compressed_slice).await?;
+        posting_list_offsets.push(output_file.position() as usize);
     }
 
-    let cache_start = output_file.seek(SeekFrom::Current(0))? as usize;
+    let cache_start = output_file.position() as usize;
 
-    let fm_chunk_offsets_offset = output_file.seek(SeekFrom::Current(0))? as usize;
+    let fm_chunk_offsets_offset = output_file.position() as usize;
     let serialized_fm_chunk_offsets = bincode::serialize(&fm_chunk_offsets)?;
     let compressed_fm_chunk_offsets =
         encode_all(&serialized_fm_chunk_offsets[..], 0).expect("Compression failed");
-    output_file.write_all(&compressed_fm_chunk_offsets)?;
+    output_file.write_all(&compressed_fm_chunk_offsets).await?;
 
-    let posting_list_offsets_offset = output_file.seek(SeekFrom::Current(0))? as usize;
+    let posting_list_offsets_offset = output_file.position() as usize;
     let serialized_posting_list_offsets = bincode::serialize(&posting_list_offsets)?;
     let compressed_posting_list_offsets =
         encode_all(&serialized_posting_list_offsets[..], 0).expect("Compression failed");
-    output_file.write_all(&compressed_posting_list_offsets)?;
+    output_file.write_all(&compressed_posting_list_offsets).await?;
 
-    let total_counts_offset = output_file.seek(SeekFrom::Current(0))? as usize;
+    let total_counts_offset = output_file.position() as usize;
     let serialized_total_counts = bincode::serialize(&combined_cumulative_counts)?;
     let compressed_total_counts: Vec<u8> =
         encode_all(&serialized_total_counts[..], 0).expect("Compression failed");
-    output_file.write_all(&compressed_total_counts)?;
+    output_file.write_all(&compressed_total_counts).await?;
 
-    output_file.write_all(&(fm_chunk_offsets_offset as u64).to_le_bytes())?;
-    output_file.write_all(&(posting_list_offsets_offset as u64).to_le_bytes())?;
-    output_file.write_all(&(total_counts_offset as u64).to_le_bytes())?;
-    output_file.write_all(&(bwt_output.len() as u64).to_le_bytes())?;
+    output_file.write_all(&(fm_chunk_offsets_offset as u64).to_le_bytes()).await?;
+    output_file.write_all(&(posting_list_offsets_offset as u64).to_le_bytes()).await?;
+    output_file.write_all(&(total_counts_offset as u64).to_le_bytes()).await?;
+    output_file.write_all(&(bwt_output.len() as u64).to_le_bytes()).await?;
 
-    Ok(vec![(
-        cache_start,
-        output_file.seek(SeekFrom::Current(0))? as usize,
-    )])
+    let cache_end = output_file.position() as usize;
+    output_file.finish().await?;
+
+    Ok(vec![(cache_start, cache_end)])
 }
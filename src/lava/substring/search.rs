@@ -1,6 +1,6 @@
 use super::constants::*;
 use super::fm_chunk::FMChunk;
-use crate::formats::readers::{get_file_sizes_and_readers, AsyncReader, ReaderType};
+use crate::formats::readers::{get_file_sizes_and_readers, AsyncReader, StorageConfig};
 use crate::lava::error::LavaError;
 use crate::lava::get_tokenizer_async;
 
@@ -37,7 +37,6 @@ async fn search_generic_async(
 ) -> Result<Vec<(u64, u64)>, LavaError> {
     let mut join_set = JoinSet::new();
 
-    let mut start_time = Instant::now();
     for file_id in 0..readers.len() {
         let reader = readers.remove(0);
         let file_size = file_sizes.remove(0);
@@ -86,8 +85,6 @@ async fn search_generic_async(
 
     join_set.shutdown().await;
 
-    println!("Time stage 1 read: {:?}", start_time.elapsed());
-
     let result: Vec<(u64, u64)> = result.into_iter().collect_vec();
     Ok(result)
 }
@@ -120,13 +117,22 @@ where
     for i in (0..query.len()).rev() {
         let current_token = query[i];
 
-        let start_byte = fm_chunk_offsets[start / FM_CHUNK_TOKS];
-        let end_byte = fm_chunk_offsets[start / FM_CHUNK_TOKS + 1];
-        let start_chunk = reader.read_range(start_byte, end_byte).await.unwrap();
-
-        let start_byte = fm_chunk_offsets[end / FM_CHUNK_TOKS];
-        let end_byte = fm_chunk_offsets[end / FM_CHUNK_TOKS + 1];
-        let end_chunk = reader.read_range(start_byte, end_byte).await.unwrap();
+        // both FM chunks are fetched together, this is a single request when they coincide
+        let mut chunks = reader
+            .read_ranges(&[
+                (
+                    fm_chunk_offsets[start / FM_CHUNK_TOKS],
+                    fm_chunk_offsets[start / FM_CHUNK_TOKS + 1],
+                ),
+                (
+                    fm_chunk_offsets[end / FM_CHUNK_TOKS],
+                    fm_chunk_offsets[end / FM_CHUNK_TOKS + 1],
+                ),
+            ])
+            .await
+            .unwrap();
+        let end_chunk = chunks.pop().unwrap();
+        let start_chunk = chunks.pop().unwrap();
 
         start = cumulative_counts[current_token.as_()] as usize
             + FMChunk::<T>::new(start_chunk)
@@ -204,7 +210,7 @@ where
 }
 
 use super::wavelet_tree::search_wavelet_tree_from_reader;
-use crate::formats::readers::read_and_decompress;
+use crate::formats::readers::{decompress, read_and_decompress};
 
 async fn search_substring_wavelet_one_file(
     file_id: u64,
@@ -339,15 +345,16 @@ where
     let total_counts_offset = results[2];
     let n = results[3];
 
-    let fm_chunk_offsets: Vec<u64> = reader
-        .read_range_and_decompress(fm_chunk_offsets_offset, posting_list_offsets_offset)
-        .await?;
-    let posting_list_offsets: Vec<u64> = reader
-        .read_range_and_decompress(posting_list_offsets_offset, total_counts_offset)
-        .await?;
-    let cumulative_counts: Vec<u64> = reader
-        .read_range_and_decompress(total_counts_offset, (file_size - 32) as u64)
+    let buffers = reader
+        .read_ranges(&[
+            (fm_chunk_offsets_offset, posting_list_offsets_offset),
+            (posting_list_offsets_offset, total_counts_offset),
+            (total_counts_offset, (file_size - 32) as u64),
+        ])
         .await?;
+    let fm_chunk_offsets: Vec<u64> = decompress(&buffers[0])?;
+    let posting_list_offsets: Vec<u64> = decompress(&buffers[1])?;
+    let cumulative_counts: Vec<u64> = decompress(&buffers[2])?;
 
     let mut query_set = JoinSet::new();
 
@@ -382,7 +389,7 @@ pub async fn _search_lava_substring_char(
     files: Vec<String>,
     query: String,
     k: usize,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
     token_viable_limit: Option<usize>,
     sample_factor: Option<usize>,
     wavelet_tree: bool,
@@ -429,8 +436,12 @@ pub async fn _search_lava_substring_char(
 
     // println!("query {:?}", query);
 
-    let (file_sizes, readers) = get_file_sizes_and_readers(&files, reader_type).await?;
-    search_generic_async(
+    let start_time = Instant::now();
+    let (file_sizes, readers) = get_file_sizes_and_readers(&files, storage_config.clone()).await?;
+    storage_config.record_stage("open", start_time);
+
+    let start_time = Instant::now();
+    let result = search_generic_async(
         file_sizes,
         readers,
         if wavelet_tree {
@@ -440,7 +451,9 @@ pub async fn _search_lava_substring_char(
         },
         k,
     )
-    .await
+    .await;
+    storage_config.record_stage("search", start_time);
+    result
 }
 
 #[tokio::main]
@@ -448,12 +461,14 @@ pub async fn search_lava_substring(
     files: Vec<String>,
     query: String,
     k: usize,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
     token_viable_limit: Option<usize>,
     sample_factor: Option<usize>,
 ) -> Result<Vec<(u64, u64)>, LavaError> {
-    let (_file_sizes, readers) = get_file_sizes_and_readers(&files, reader_type.clone()).await?;
+    let start_time = Instant::now();
+    let (_file_sizes, readers) = get_file_sizes_and_readers(&files, storage_config.clone()).await?;
     let tokenizer = get_tokenizer_async(readers).await?.0;
+    storage_config.record_stage("tokenizer", start_time);
 
     let mut skip_tokens: HashSet<u32> = HashSet::new();
     for char in SKIP.chars() {
@@ -525,8 +540,14 @@ pub async fn search_lava_substring(
 
     // println!("query {:?}", query);
 
-    let (file_sizes, readers) = get_file_sizes_and_readers(&files, reader_type).await?;
-    search_generic_async(file_sizes, readers, QueryParam::Substring(query), k).await
+    let start_time = Instant::now();
+    let (file_sizes, readers) = get_file_sizes_and_readers(&files, storage_config.clone()).await?;
+    storage_config.record_stage("open", start_time);
+
+    let start_time = Instant::now();
+    let result = search_generic_async(file_sizes, readers, QueryParam::Substring(query), k).await;
+    storage_config.record_stage("search", start_time);
+    result
 }
 
 #[tokio::main]
@@ -534,7 +555,7 @@ pub async fn search_lava_substring_char(
     files: Vec<String>,
     query: String,
     k: usize,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
     token_viable_limit: Option<usize>,
     sample_factor: Option<usize>,
 ) -> Result<Vec<(u64, u64)>, LavaError> {
@@ -542,7 +563,7 @@ pub async fn search_lava_substring_char(
         files,
         query,
         k,
-        reader_type,
+        storage_config,
         token_viable_limit,
         sample_factor,
         false,
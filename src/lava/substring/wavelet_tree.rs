@@ -1,15 +1,14 @@
 use crate::{
     formats::readers::{
         get_file_size_and_reader, get_file_sizes_and_readers, get_reader, get_readers, AsyncReader,
-        ClonableAsyncReader, ReaderType,
+        ClonableAsyncReader, StorageConfig,
     },
+    formats::writers::Writer,
     lava::error::LavaError,
 };
 use log::info;
 use rand::distributions::Alphanumeric;
 use rand::{Rng, SeedableRng};
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
 use std::vec::Vec;
 use zstd::stream::{decode_all, encode_all};
 const ALPHABET: usize = 256;
@@ -43,10 +42,10 @@ fn pack_bits(bits: &[bool]) -> Vec<u8> {
         .collect()
 }
 
-pub(crate) fn write_wavelet_tree_to_disk(
+pub(crate) async fn write_wavelet_tree_to_disk(
     tree: &WaveletTree,
-    file: &mut File,
-) -> std::io::Result<(Vec<usize>, Vec<usize>)> {
+    file: &mut dyn Writer,
+) -> Result<(Vec<usize>, Vec<usize>), LavaError> {
     let mut total_length = 0;
     let mut offsets = vec![0];
     let mut level_offsets = vec![0];
@@ -72,7 +71,7 @@ pub(crate) fn write_wavelet_tree_to_disk(
             rank_1 += bitvector_rank(&chunk.to_vec(), true, chunk.len());
 
             let compressed_chunk = encode_all(&packed_chunks[..], 0)?;
-            file.write_all(&compressed_chunk)?;
+            file.write_all(&compressed_chunk).await?;
 
             offsets.push(offsets.last().unwrap() + compressed_chunk.len());
             total_length += compressed_chunk.len();
@@ -1,7 +1,7 @@
 use crate::{
     formats::readers::{
         get_file_size_and_reader, get_file_sizes_and_readers, get_reader, get_readers, AsyncReader,
-        ReaderType,
+        StorageConfig,
     },
     lava::error::LavaError,
 };
@@ -73,8 +73,8 @@ pub(crate) async fn get_tokenizer_async(
 #[tokio::main]
 pub async fn get_tokenizer_vocab(
     files: Vec<String>,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<Vec<String>, LavaError> {
-    let (_file_sizes, readers) = get_file_sizes_and_readers(&files, reader_type).await?;
+    let (_file_sizes, readers) = get_file_sizes_and_readers(&files, storage_config).await?;
     Ok(get_tokenizer_async(readers).await?.1)
 }
@@ -1,16 +1,15 @@
 use std::{
     cmp::{max, min},
     collections::{BTreeMap, BTreeSet},
-    fs::File,
-    io::{Seek, SeekFrom},
     num::ParseIntError,
     ops::AddAssign,
 };
 
 use crate::{
     formats::readers::{
-        get_file_size_and_reader, get_file_sizes_and_readers, AsyncReader, ClonableAsyncReader, ReaderType,
+        get_file_size_and_reader, get_file_sizes_and_readers, AsyncReader, ClonableAsyncReader,
     },
+    formats::writers::Writer,
     lava::error::LavaError,
 };
 use bitvec::prelude::*;
@@ -19,8 +18,6 @@ use std::collections::VecDeque;
 use std::io::Read;
 use zstd::stream::{encode_all, read::Decoder};
 
-use std::io::Write;
-
 #[derive(Serialize, Deserialize, Clone)]
 pub struct BinaryTrieNode<T: Clone + AddAssign> {
     pub left: Option<Box<BinaryTrieNode<T>>>,
@@ -215,7 +212,7 @@ impl FastTrie {
         reader1: &mut AsyncReader,
         file_size2: usize,
         reader2: &mut AsyncReader,
-        output_file: &str,
+        output_file: &mut dyn Writer,
         uid_offset_0: usize,
         uid_offset_1: usize,
     ) -> Result<(usize, usize), LavaError> {
@@ -238,8 +235,6 @@ impl FastTrie {
         let mut root_lut: BTreeMap<BitVec, (Vec<usize>, Option<usize>)> = BTreeMap::new();
         let mut offsets: Vec<usize> = vec![0];
 
-        let mut output_file = File::create(output_file).unwrap();
-
         for key in keys1.difference(&keys2) {
             let (values, offset) = lut1.remove(key).unwrap();
             // read the thing from lut1
@@ -247,8 +242,8 @@ impl FastTrie {
                 Some(x) => {
                     let node = Self::read_and_adjust_node(reader1, offsets1[x], offsets1[x + 1], uid_offset_0).await?;
                     let serialized_node = bincode::serialize(&node).unwrap();
-                    let _ = output_file.write(&encode_all(&serialized_node[..], 10).unwrap());
-                    offsets.push(output_file.seek(SeekFrom::Current(0))? as usize);
+                    output_file.write_all(&encode_all(&serialized_node[..], 10).unwrap()).await?;
+                    offsets.push(output_file.position() as usize);
 
                     root_lut.insert(key.clone(), (values, Some(offsets.len() - 2)));
                 }
@@ -267,8 +262,8 @@ impl FastTrie {
                 Some(x) => {
                     let node = Self::read_and_adjust_node(reader2, offsets2[x], offsets2[x + 1], uid_offset_1).await?;
                     let serialized_node = bincode::serialize(&node).unwrap();
-                    let _ = output_file.write(&encode_all(&serialized_node[..], 10).unwrap());
-                    offsets.push(output_file.seek(SeekFrom::Current(0))? as usize);
+                    output_file.write_all(&encode_all(&serialized_node[..], 10).unwrap()).await?;
+                    offsets.push(output_file.position() as usize);
 
                     root_lut.insert(key.clone(), (values, Some(offsets.len() - 2)));
                 }
@@ -293,8 +288,8 @@ impl FastTrie {
                     let mut node = node1;
                     node.extend(*node2);
                     let serialized_node = bincode::serialize(&node).unwrap();
-                    let _ = output_file.write(&encode_all(&serialized_node[..], 10).unwrap());
-                    offsets.push(output_file.seek(SeekFrom::Current(0))? as usize);
+                    output_file.write_all(&encode_all(&serialized_node[..], 10).unwrap()).await?;
+                    offsets.push(output_file.position() as usize);
 
                     let mut values = values1.clone();
                     values.extend(values2.clone());
@@ -305,8 +300,8 @@ impl FastTrie {
                     let node =
                         Self::read_and_adjust_node(reader1, offsets1[x1], offsets1[x1 + 1], uid_offset_0).await?;
                     let serialized_node = bincode::serialize(&node).unwrap();
-                    let _ = output_file.write(&encode_all(&serialized_node[..], 10).unwrap());
-                    offsets.push(output_file.seek(SeekFrom::Current(0))? as usize);
+                    output_file.write_all(&encode_all(&serialized_node[..], 10).unwrap()).await?;
+                    offsets.push(output_file.position() as usize);
 
                     let mut values = values1.clone();
                     values.extend(values2.clone());
@@ -317,8 +312,8 @@ impl FastTrie {
                     let node =
                         Self::read_and_adjust_node(reader2, offsets2[x2], offsets2[x2 + 1], uid_offset_1).await?;
                     let serialized_node = bincode::serialize(&node).unwrap();
-                    let _ = output_file.write(&encode_all(&serialized_node[..], 10).unwrap());
-                    offsets.push(output_file.seek(SeekFrom::Current(0))? as usize);
+                    output_file.write_all(&encode_all(&serialized_node[..], 10).unwrap()).await?;
+                    offsets.push(output_file.position() as usize);
 
                     let mut values = values1.clone();
                     values.extend(values2.clone());
@@ -333,7 +328,7 @@ impl FastTrie {
             }
         }
 
-        let metadata_page_offset = output_file.seek(SeekFrom::Current(0))? as usize;
+        let metadata_page_offset = output_file.position() as usize;
 
         println!("{:?}", root_lut);
 
@@ -343,12 +338,12 @@ impl FastTrie {
         let serialized_metadata = bincode::serialize(&metadata).unwrap();
         let compressed = encode_all(&serialized_metadata[..], 10).unwrap();
 
-        let cache_start = output_file.seek(SeekFrom::Current(0))? as usize;
+        let cache_start = output_file.position() as usize;
 
-        output_file.write(&compressed)?;
-        output_file.write(&(metadata_page_offset as u64).to_le_bytes())?;
+        output_file.write_all(&compressed).await?;
+        output_file.write_all(&(metadata_page_offset as u64).to_le_bytes()).await?;
 
-        let cache_end = output_file.seek(SeekFrom::Current(0))? as usize;
+        let cache_end = output_file.position() as usize;
 
         Ok((cache_start, cache_end))
     }
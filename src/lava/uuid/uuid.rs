@@ -2,8 +2,9 @@ use std::collections::BTreeSet;
 
 use crate::{
     formats::readers::{
-        get_file_size_and_reader, get_file_sizes_and_readers, AsyncReader, ReaderType,
+        get_file_size_and_reader, get_file_sizes_and_readers, AsyncReader, StorageConfig,
     },
+    formats::writers::get_writer,
     lava::error::LavaError,
 };
 
@@ -18,6 +19,7 @@ pub async fn build_lava_uuid(
     output_file_name: String,
     array: ArrayData,
     uid: ArrayData,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let array = make_array(array);
     // let uid = make_array(ArrayData::from_pyarrow(uid)?);
@@ -55,7 +57,9 @@ pub async fn build_lava_uuid(
     let root = BinaryTrieNode::build(&texts, &inds);
     let fast_trie = FastTrie::new(root, Some(16));
     let (serialized_fast_trie, (cache_start, cache_end)) = fast_trie.serialize();
-    std::fs::write(output_file_name, serialized_fast_trie).unwrap();
+    let mut output_file = get_writer(&output_file_name, &storage_config).await?;
+    output_file.write_all(&serialized_fast_trie).await?;
+    output_file.finish().await?;
 
     Ok(vec![(cache_start, cache_end)])
 }
@@ -64,16 +68,16 @@ pub(crate) async fn merge_lava_uuid(
     condensed_lava_file: &str,
     lava_files: Vec<String>,
     uid_offsets: Vec<u64>,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     // currently only support merging two files, but can support more in the future.
     assert_eq!(lava_files.len(), 2);
     assert_eq!(uid_offsets.len(), 2);
 
     let (file_size1, mut reader1) =
-        get_file_size_and_reader(lava_files[0].clone(), reader_type.clone()).await?;
+        get_file_size_and_reader(lava_files[0].clone(), storage_config.clone()).await?;
     let (file_size2, mut reader2) =
-        get_file_size_and_reader(lava_files[1].clone(), reader_type.clone()).await?;
+        get_file_size_and_reader(lava_files[1].clone(), storage_config.clone()).await?;
 
     // let buffer: bytes::Bytes = reader1.read_range(0, file_size1 as u64).await?;
     // let mut fast_trie1 = FastTrie::deserialize(buffer.to_vec());
@@ -89,16 +93,18 @@ pub(crate) async fn merge_lava_uuid(
     // let mut output_file = File::create(condensed_lava_file)?;
     // output_file.write(&serialized)?;
 
+    let mut output_file = get_writer(condensed_lava_file, &storage_config).await?;
     let (cache_start, cache_end) = FastTrie::extend_with_readers_into_file(
         file_size1,
         &mut reader1,
         file_size2,
         &mut reader2,
-        condensed_lava_file,
+        output_file.as_mut(),
         uid_offsets[0] as usize,
         uid_offsets[1] as usize,
     )
     .await?;
+    output_file.finish().await?;
 
     Ok(vec![(cache_start, cache_end)])
 }
@@ -124,12 +130,15 @@ pub async fn search_lava_uuid(
     files: Vec<String>,
     query: String,
     k: usize,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<Vec<(u64, u64)>, LavaError> {
-    let (mut file_sizes, mut readers) = get_file_sizes_and_readers(&files, reader_type).await?;
+    let start_time = Instant::now();
+    let (mut file_sizes, mut readers) =
+        get_file_sizes_and_readers(&files, storage_config.clone()).await?;
+    storage_config.record_stage("open", start_time);
     let mut join_set = JoinSet::new();
 
-    let mut start_time = Instant::now();
+    let start_time = Instant::now();
     for file_id in 0..readers.len() {
         let reader = readers.remove(0);
         let file_size = file_sizes.remove(0);
@@ -155,7 +164,7 @@ pub async fn search_lava_uuid(
 
     join_set.shutdown().await;
 
-    println!("Time stage 1 read: {:?}", start_time.elapsed());
+    storage_config.record_stage("search", start_time);
 
     let result: Vec<(u64, u64)> = result.into_iter().collect_vec();
     Ok(result)
@@ -1,11 +1,12 @@
 use crate::{
-    formats::readers::{get_file_sizes_and_readers, get_reader, ReaderType},
+    formats::readers::{get_file_sizes_and_readers, get_reader, AsyncReader, StorageConfig},
     lava::error::LavaError,
 };
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use futures::stream::{FuturesUnordered, StreamExt};
 use ndarray::{concatenate, stack, Array1, Array2, Axis};
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::io::{self, Cursor, Read};
 use std::time::Instant;
 use zstd::stream::Decoder;
@@ -23,11 +24,11 @@ async fn search_lava_vector_async(
     files: Vec<String>,
     query: Vec<f32>,
     nprobes: usize,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<(Vec<usize>, Vec<Array1<u8>>, Vec<(usize, Array1<u8>)>), LavaError> {
     let start = Instant::now();
 
-    let (_, mut readers) = get_file_sizes_and_readers(&files, reader_type.clone()).await?;
+    let (_, mut readers) = get_file_sizes_and_readers(&files, storage_config.clone()).await?;
 
     let mut futures = Vec::new();
 
@@ -59,8 +60,7 @@ async fn search_lava_vector_async(
     let result: Vec<Result<(usize, Array2<f32>), tokio::task::JoinError>> =
         futures::future::join_all(futures).await;
 
-    let end = Instant::now();
-    println!("Time stage 1 read: {:?}", end - start);
+    storage_config.record_stage("stage 1 read", start);
 
     let start = Instant::now();
 
@@ -124,12 +124,11 @@ async fn search_lava_vector_async(
         file_indices[file_idx].push(remainder);
     }
 
-    let end = Instant::now();
-    println!("Time math: {:?}", end - start);
+    storage_config.record_stage("math", start);
 
     let start = Instant::now();
 
-    let (_, mut readers) = get_file_sizes_and_readers(&files, reader_type.clone()).await?;
+    let (_, mut readers) = get_file_sizes_and_readers(&files, storage_config.clone()).await?;
 
     let mut file_ids = vec![];
     let mut futures = Vec::new();
@@ -182,20 +181,30 @@ async fn search_lava_vector_async(
 
     let pq_bytes: Vec<Array1<u8>> = result.iter().map(|x| x.1.clone()).collect::<Vec<_>>();
 
-    let end = Instant::now();
-    println!("Time stage 2 read: {:?}", end - start);
+    storage_config.record_stage("stage 2 read", start);
 
     let start = Instant::now();
-    let reader = get_reader(files[file_ids[0]].clone(), reader_type.clone())
+    let reader = get_reader(files[file_ids[0]].clone(), storage_config.clone())
         .await
         .unwrap();
 
+    // one reader per file, so every file is only looked up once
+    let mut file_readers: HashMap<usize, AsyncReader> = HashMap::new();
+    for (to_read, _) in result.iter() {
+        for (file_id, _, _) in to_read.iter() {
+            if !file_readers.contains_key(file_id) {
+                let mut reader_c = reader.clone();
+                reader_c.update_filename(files[*file_id].clone()).await.unwrap();
+                file_readers.insert(*file_id, reader_c);
+            }
+        }
+    }
+
     let mut futures = FuturesUnordered::new();
     for i in 0..result.len() {
         let to_read = result[i].0.clone();
         for (file_id, start, end) in to_read.into_iter() {
-            let mut reader_c = reader.clone();
-            reader_c.update_filename(files[file_id].clone()).unwrap();
+            let mut reader_c = file_readers[&file_id].clone();
 
             futures.push(tokio::spawn(async move {
                 let start_time = Instant::now();
@@ -216,8 +225,7 @@ async fn search_lava_vector_async(
         ranges.push(x.unwrap());
     }
 
-    let end = Instant::now();
-    println!("Time stage 3 read: {:?}", end - start);
+    storage_config.record_stage("stage 3 read", start);
 
     Ok((file_ids, pq_bytes, ranges))
 }
@@ -226,14 +234,14 @@ pub fn search_lava_vector(
     files: Vec<String>,
     query: Vec<f32>,
     nprobes: usize,
-    reader_type: ReaderType,
+    storage_config: StorageConfig,
 ) -> Result<(Vec<usize>, Vec<Array1<u8>>, Vec<(usize, Array1<u8>)>), LavaError> {
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap();
 
-    let res = rt.block_on(search_lava_vector_async(files, query, nprobes, reader_type));
+    let res = rt.block_on(search_lava_vector_async(files, query, nprobes, storage_config));
     rt.shutdown_background();
     res
 }
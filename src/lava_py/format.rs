@@ -1,4 +1,8 @@
-use crate::formats::{cache, parquet, MatchResult, ParquetLayout};
+use crate::formats::readers::{self, AccessRecord, AccessRecorder, ReadLimits};
+use crate::formats::{
+    cache, ipc, parquet, text, BlockLayout, Literal, MatchResult, ParquetLayout,
+    ParquetLayoutStream, Predicate, RowGroupLayout,
+};
 use crate::lava::error::LavaError;
 use arrow::array::ArrayData;
 use arrow::pyarrow::{PyArrowType, ToPyArrow};
@@ -9,6 +13,8 @@ use pyo3::{pyfunction, types::PyString};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, HashMap};
 use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::sync::Arc;
 
 #[pyclass]
 pub struct ParquetLayoutWrapper {
@@ -42,6 +48,87 @@ impl ParquetLayoutWrapper {
     }
 }
 
+#[pyclass]
+pub struct RowGroupLayoutWrapper {
+    #[pyo3(get, set)]
+    pub row_group: usize,
+    #[pyo3(get, set)]
+    pub dictionary_page_sizes: Vec<usize>, // 0 means no dict page
+    #[pyo3(get, set)]
+    pub data_page_sizes: Vec<usize>,
+    #[pyo3(get, set)]
+    pub data_page_offsets: Vec<usize>,
+    #[pyo3(get, set)]
+    pub data_page_num_rows: Vec<usize>,
+}
+
+impl From<RowGroupLayout> for RowGroupLayoutWrapper {
+    fn from(row_group_layout: RowGroupLayout) -> Self {
+        RowGroupLayoutWrapper {
+            row_group: row_group_layout.row_group,
+            dictionary_page_sizes: row_group_layout.dictionary_page_sizes,
+            data_page_sizes: row_group_layout.data_page_sizes,
+            data_page_offsets: row_group_layout.data_page_offsets,
+            data_page_num_rows: row_group_layout.data_page_num_rows,
+        }
+    }
+}
+
+/// Iterates over the (arrays, RowGroupLayoutWrapper) of the row groups of a column, in order.
+#[pyclass]
+pub struct ParquetLayoutStreamWrapper {
+    #[pyo3(get)]
+    pub num_row_groups: usize,
+    #[pyo3(get)]
+    pub metadata_bytes: PyObject,
+    stream: ParquetLayoutStream,
+}
+
+#[pymethods]
+impl ParquetLayoutStreamWrapper {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(
+        &mut self,
+        py: Python,
+    ) -> Result<Option<(Vec<PyArrowType<ArrayData>>, RowGroupLayoutWrapper)>, LavaError> {
+        let stream = &mut self.stream;
+        let Some(row_group) = py.allow_threads(|| stream.next()) else {
+            return Ok(None);
+        };
+        let (arrs, layout) = row_group?;
+        Ok(Some((
+            arrs.into_iter().map(PyArrowType).collect(),
+            layout.into(),
+        )))
+    }
+}
+
+#[pyclass]
+pub struct BlockLayoutWrapper {
+    #[pyo3(get, set)]
+    pub metadata_bytes: PyObject,
+    #[pyo3(get, set)]
+    pub block_offsets: Vec<usize>,
+    #[pyo3(get, set)]
+    pub block_sizes: Vec<usize>,
+    #[pyo3(get, set)]
+    pub block_num_rows: Vec<usize>,
+}
+
+impl BlockLayoutWrapper {
+    fn from_block_layout(py: Python, block_layout: BlockLayout) -> Self {
+        BlockLayoutWrapper {
+            metadata_bytes: PyBytes::new(py, &block_layout.metadata_bytes).into_py(py),
+            block_offsets: block_layout.block_offsets,
+            block_sizes: block_layout.block_sizes,
+            block_num_rows: block_layout.block_num_rows,
+        }
+    }
+}
+
 #[pyclass]
 pub struct MatchResultWrapper {
     #[pyo3(get, set)]
@@ -101,15 +188,103 @@ pub fn populate_cache(
     filenames: Vec<&PyString>,
     ranges: Vec<Vec<(usize, usize)>>,
     reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
 ) -> Result<(), LavaError> {
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
+    let storage_config = super::storage_config(reader_type, storage_options)?;
 
     let mut range_dict: BTreeMap<String, Vec<(usize, usize)>> = BTreeMap::new();
     for (i, filename) in filenames.iter().enumerate() {
         range_dict.insert(filename.to_string(), ranges[i].clone());
     }
 
-    py.allow_threads(|| cache::populate_cache(range_dict, reader_type.into()))
+    py.allow_threads(|| cache::populate_cache(range_dict, storage_config))
+}
+
+/// Sets the process wide range cache used by `populate_cache` and the readers, see
+/// `CacheConfig::from_map` for the backends and their options. None disables the cache.
+#[pyfunction]
+pub fn set_range_cache(
+    backend: Option<&PyString>,
+    options: Option<HashMap<String, String>>,
+) -> Result<(), LavaError> {
+    let range_cache = match backend {
+        Some(backend) => Some(
+            cache::CacheConfig::from_map(backend.to_str()?, &options.unwrap_or_default())?
+                .build()?,
+        ),
+        None => None,
+    };
+    cache::set_range_cache(range_cache);
+    Ok(())
+}
+
+/// Sets the size of the process wide cache of decoded parquet footers and dictionary pages.
+/// None disables the cache.
+#[pyfunction]
+pub fn set_parquet_cache(capacity_mb: Option<u64>) {
+    cache::set_parquet_cache(
+        capacity_mb
+            .map(|capacity_mb| Arc::new(cache::ParquetCache::new(capacity_mb * 1024 * 1024))),
+    );
+}
+
+/// The backend of the process wide range cache, None if there is none.
+#[pyfunction]
+pub fn range_cache_backend() -> Option<&'static str> {
+    cache::get_range_cache().map(|range_cache| range_cache.name())
+}
+
+/// Starts recording the ranges read by all readers created afterwards, see
+/// `stop_access_recording`.
+#[pyfunction]
+pub fn start_access_recording() {
+    readers::set_access_recorder(Some(Arc::new(AccessRecorder::new())));
+}
+
+/// Stops recording and returns the ranges read since `start_access_recording` as
+/// (file, offset, length, count), most frequently read first.
+#[pyfunction]
+pub fn stop_access_recording() -> Vec<(String, u64, u64, u64)> {
+    let recorder = readers::get_access_recorder();
+    readers::set_access_recorder(None);
+    recorder
+        .map(|recorder| recorder.trace())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|r| (r.filename, r.offset, r.length, r.count))
+        .collect()
+}
+
+/// Caches the most frequently read ranges of a trace returned by `stop_access_recording`, up to
+/// `byte_budget` bytes. Returns the ranges cached per file.
+#[pyfunction]
+pub fn warm_cache(
+    py: Python,
+    trace: Vec<(String, u64, u64, u64)>,
+    byte_budget: u64,
+    reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+) -> Result<BTreeMap<String, Vec<(usize, usize)>>, LavaError> {
+    let storage_config = super::storage_config(reader_type, storage_options)?;
+    let trace: Vec<AccessRecord> = trace
+        .into_iter()
+        .map(|(filename, offset, length, count)| AccessRecord {
+            filename,
+            offset,
+            length,
+            count,
+        })
+        .collect();
+    py.allow_threads(|| cache::warm_cache(&trace, byte_budget, storage_config))
+}
+
+/// Sets the process wide limits of the remote readers, None means unlimited.
+#[pyfunction]
+pub fn set_read_limits(max_concurrent_requests: Option<usize>, max_bytes_per_second: Option<u64>) {
+    readers::set_read_limits(ReadLimits {
+        max_concurrent_requests,
+        max_bytes_per_second,
+    });
 }
 
 #[pyfunction]
@@ -118,18 +293,65 @@ pub fn get_parquet_layout(
     column_name: &PyString,
     file: &PyString,
     reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
 ) -> Result<(Vec<PyArrowType<ArrayData>>, ParquetLayoutWrapper), LavaError> {
     let column_name = column_name.to_string();
     let file = file.to_string();
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
+    let storage_config = super::storage_config(reader_type, storage_options)?;
     let (arrs, parquet_layout) =
-        py.allow_threads(|| parquet::get_parquet_layout(&column_name, &file, reader_type.into()))?;
+        py.allow_threads(|| parquet::get_parquet_layout(&column_name, &file, storage_config))?;
     Ok((
         arrs.into_iter().map(|x| PyArrowType(x)).collect(),
         ParquetLayoutWrapper::from_parquet_layout(py, parquet_layout),
     ))
 }
 
+/// Like `get_parquet_layout`, but row group by row group as they are read, holding at most about
+/// `memory_budget_mb` of them at once (1GB by default).
+#[pyfunction]
+pub fn stream_parquet_layout(
+    py: Python,
+    column_name: &PyString,
+    file: &PyString,
+    memory_budget_mb: Option<usize>,
+    reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+) -> Result<ParquetLayoutStreamWrapper, LavaError> {
+    let column_name = column_name.to_string();
+    let file = file.to_string();
+    let storage_config = super::storage_config(reader_type, storage_options)?;
+    let memory_budget = memory_budget_mb
+        .map_or(parquet::DEFAULT_LAYOUT_MEMORY_BUDGET, |memory_budget_mb| {
+            memory_budget_mb * 1024 * 1024
+        });
+    let stream = py.allow_threads(|| {
+        parquet::stream_parquet_layout(&column_name, &file, storage_config, memory_budget)
+    })?;
+    Ok(ParquetLayoutStreamWrapper {
+        num_row_groups: stream.num_row_groups,
+        metadata_bytes: PyBytes::new(py, &stream.metadata_bytes).into_py(py),
+        stream,
+    })
+}
+
+/// The layout of a column without its values, from the page index of the file if it has one.
+#[pyfunction]
+pub fn get_parquet_page_layout(
+    py: Python,
+    column_name: &PyString,
+    file: &PyString,
+    reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+) -> Result<ParquetLayoutWrapper, LavaError> {
+    let column_name = column_name.to_string();
+    let file = file.to_string();
+    let storage_config = super::storage_config(reader_type, storage_options)?;
+    let parquet_layout = py.allow_threads(|| {
+        parquet::get_parquet_page_layout(&column_name, &file, storage_config)
+    })?;
+    Ok(ParquetLayoutWrapper::from_parquet_layout(py, parquet_layout))
+}
+
 #[pyfunction]
 pub fn read_indexed_pages(
     py: Python,
@@ -142,7 +364,9 @@ pub fn read_indexed_pages(
     reader_type: Option<&PyString>,
     metadata_bytes: Option<&PyDict>,
     in_order: Option<bool>,
-) -> Result<Vec<PyArrowType<ArrayData>>, LavaError> {
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
     let column_name = column_name.to_string();
     let file_metadata: Option<HashMap<String, Bytes>> = match metadata_bytes {
         Some(dict) => {
@@ -162,7 +386,8 @@ pub fn read_indexed_pages(
 
     let file_paths: Vec<String> = file_paths.iter().map(|x| x.to_string()).collect();
     let page_offsets: Vec<u64> = page_offsets.iter().map(|x| *x as u64).collect();
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
+    let config = storage_config.clone();
     let match_result = py.allow_threads(|| {
         parquet::read_indexed_pages(
             column_name,
@@ -171,10 +396,248 @@ pub fn read_indexed_pages(
             page_offsets,
             page_sizes,
             dict_page_sizes, // 0 means no dict page
-            reader_type.into(),
+            config,
             file_metadata,
             in_order,
         )
     })?;
-    Ok(match_result.into_iter().map(|x| PyArrowType(x)).collect())
+    let arrays: Vec<PyArrowType<ArrayData>> = match_result.into_iter().map(|x| PyArrowType(x)).collect();
+    super::with_query_stats(py, arrays, &storage_config)
+}
+
+/// The layout of an Arrow IPC file, a block per record batch, and the values of a column.
+#[pyfunction]
+pub fn get_ipc_layout(
+    py: Python,
+    column_name: String,
+    file: String,
+    reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+) -> Result<(Vec<PyArrowType<ArrayData>>, BlockLayoutWrapper), LavaError> {
+    let storage_config = super::storage_config(reader_type, storage_options)?;
+    let (arrs, block_layout) =
+        py.allow_threads(|| ipc::get_ipc_layout(&column_name, &file, storage_config))?;
+    Ok((
+        arrs.into_iter().map(PyArrowType).collect(),
+        BlockLayoutWrapper::from_block_layout(py, block_layout),
+    ))
+}
+
+/// Reads record batches of Arrow IPC files by their offsets and sizes in the layouts of the
+/// files, and returns the values of a column of each.
+#[pyfunction]
+pub fn read_indexed_batches(
+    py: Python,
+    column_name: String,
+    file_paths: Vec<String>,
+    block_offsets: Vec<u64>,
+    block_sizes: Vec<usize>,
+    reader_type: Option<&PyString>,
+    metadata_bytes: Option<HashMap<String, Vec<u8>>>,
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
+    let file_footers = metadata_bytes.map(|metadata_bytes| {
+        metadata_bytes
+            .into_iter()
+            .map(|(file_path, bytes)| (file_path, Bytes::from(bytes)))
+            .collect()
+    });
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
+    let config = storage_config.clone();
+    let arrays = py.allow_threads(|| {
+        ipc::read_indexed_batches(
+            column_name,
+            file_paths,
+            block_offsets,
+            block_sizes,
+            config,
+            file_footers,
+        )
+    })?;
+    let arrays: Vec<PyArrowType<ArrayData>> = arrays.into_iter().map(PyArrowType).collect();
+    super::with_query_stats(py, arrays, &storage_config)
+}
+
+/// The layout of a text file, in blocks of lines, and the values of the lines: the values of a
+/// column of newline-delimited JSON objects if one is given, otherwise the lines themselves.
+#[pyfunction]
+pub fn get_text_layout(
+    py: Python,
+    file: String,
+    column_name: Option<String>,
+    lines_per_block: Option<usize>,
+    reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+) -> Result<(Vec<PyArrowType<ArrayData>>, BlockLayoutWrapper), LavaError> {
+    let lines_per_block = lines_per_block.unwrap_or(text::DEFAULT_LINES_PER_BLOCK);
+    let storage_config = super::storage_config(reader_type, storage_options)?;
+    let (arrs, block_layout) = py.allow_threads(|| {
+        text::get_text_layout(
+            &file,
+            column_name.as_deref(),
+            lines_per_block,
+            storage_config,
+        )
+    })?;
+    Ok((
+        arrs.into_iter().map(PyArrowType).collect(),
+        BlockLayoutWrapper::from_block_layout(py, block_layout),
+    ))
+}
+
+/// Reads blocks of lines of text files by their offsets and sizes in the layouts of the files.
+#[pyfunction]
+pub fn read_indexed_lines(
+    py: Python,
+    file_paths: Vec<String>,
+    block_offsets: Vec<u64>,
+    block_sizes: Vec<usize>,
+    column_name: Option<String>,
+    reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
+    let config = storage_config.clone();
+    let arrays = py.allow_threads(|| {
+        text::read_indexed_lines(file_paths, block_offsets, block_sizes, column_name, config)
+    })?;
+    let arrays: Vec<PyArrowType<ArrayData>> = arrays.into_iter().map(PyArrowType).collect();
+    super::with_query_stats(py, arrays, &storage_config)
+}
+
+/// Reads the rows of the hits, row ranges of row groups of files, and returns the given columns
+/// of those rows as one pyarrow RecordBatch, in the order of the hits.
+#[pyfunction]
+pub fn read_rows(
+    py: Python,
+    file_paths: Vec<String>,
+    row_groups: Vec<usize>,
+    row_ranges: Vec<(usize, usize)>,
+    columns: Vec<String>,
+    reader_type: Option<&PyString>,
+    metadata_bytes: Option<HashMap<String, Vec<u8>>>,
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
+    let file_metadata = metadata_bytes.map(|metadata_bytes| {
+        metadata_bytes
+            .into_iter()
+            .map(|(file_path, bytes)| (file_path, Bytes::from(bytes)))
+            .collect()
+    });
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
+    let config = storage_config.clone();
+    let batch = py.allow_threads(|| {
+        parquet::read_rows(
+            file_paths,
+            row_groups,
+            row_ranges,
+            columns,
+            config,
+            file_metadata,
+        )
+    })?;
+    super::with_query_stats(py, PyArrowType(batch), &storage_config)
+}
+
+fn parse_literal(value: Option<&PyAny>) -> Result<Literal, LavaError> {
+    let value = value.ok_or_else(|| LavaError::Parse("filter value missing".to_string()))?;
+    // bools are ints in python, so they go first
+    if let Ok(value) = value.extract::<bool>() {
+        Ok(Literal::Bool(value))
+    } else if let Ok(value) = value.extract::<i64>() {
+        Ok(Literal::Int(value))
+    } else if let Ok(value) = value.extract::<f64>() {
+        Ok(Literal::Float(value))
+    } else {
+        Ok(Literal::Str(value.extract::<String>()?))
+    }
+}
+
+/// The filter of (column, op, value) tuples that all have to hold, like the filters of pyarrow.
+/// Ops are `==`, `<`, `<=`, `>`, `>=`, `in` with a list of values and `is_null` with None.
+fn parse_filters(filters: Vec<(String, String, Option<&PyAny>)>) -> Result<Predicate, LavaError> {
+    let mut predicates = Vec::with_capacity(filters.len());
+    for (column, op, value) in filters {
+        let predicate = match op.as_str() {
+            "==" | "=" => Predicate::Eq(column, parse_literal(value)?),
+            "<" => Predicate::Range(
+                column,
+                Bound::Unbounded,
+                Bound::Excluded(parse_literal(value)?),
+            ),
+            "<=" => Predicate::Range(
+                column,
+                Bound::Unbounded,
+                Bound::Included(parse_literal(value)?),
+            ),
+            ">" => Predicate::Range(
+                column,
+                Bound::Excluded(parse_literal(value)?),
+                Bound::Unbounded,
+            ),
+            ">=" => Predicate::Range(
+                column,
+                Bound::Included(parse_literal(value)?),
+                Bound::Unbounded,
+            ),
+            "in" => {
+                let values = value
+                    .ok_or_else(|| LavaError::Parse("filter value missing".to_string()))?
+                    .iter()?
+                    .map(|value| parse_literal(Some(value?)))
+                    .collect::<Result<Vec<_>, LavaError>>()?;
+                Predicate::In(column, values)
+            }
+            "is_null" => Predicate::IsNull(column),
+            _ => {
+                return Err(LavaError::Parse(format!(
+                    "unknown filter op {}, expected one of ==, <, <=, >, >=, in, is_null",
+                    op
+                )))
+            }
+        };
+        predicates.push(predicate);
+    }
+    Ok(Predicate::And(predicates))
+}
+
+/// Like `read_rows`, but returns only the rows matching `filters`, see `parse_filters`. Row
+/// groups and pages the statistics of the files rule out are not read.
+#[pyfunction]
+pub fn read_filtered_rows(
+    py: Python,
+    file_paths: Vec<String>,
+    row_groups: Vec<usize>,
+    row_ranges: Vec<(usize, usize)>,
+    columns: Vec<String>,
+    filters: Vec<(String, String, Option<&PyAny>)>,
+    reader_type: Option<&PyString>,
+    metadata_bytes: Option<HashMap<String, Vec<u8>>>,
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
+    let predicate = parse_filters(filters)?;
+    let file_metadata = metadata_bytes.map(|metadata_bytes| {
+        metadata_bytes
+            .into_iter()
+            .map(|(file_path, bytes)| (file_path, Bytes::from(bytes)))
+            .collect()
+    });
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
+    let config = storage_config.clone();
+    let batch = py.allow_threads(|| {
+        parquet::read_filtered_rows(
+            file_paths,
+            row_groups,
+            row_ranges,
+            columns,
+            predicate,
+            config,
+            file_metadata,
+        )
+    })?;
+    super::with_query_stats(py, PyArrowType(batch), &storage_config)
 }
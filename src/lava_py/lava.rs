@@ -7,7 +7,8 @@ use crate::lava;
 use crate::lava::error::LavaError;
 use ndarray::{Array1, Array2, Ix2};
 use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArrayDyn};
-use pyo3::Py;
+use pyo3::{Py, PyObject};
+use std::collections::HashMap;
 use std::time::Instant;
 
 #[pyfunction]
@@ -18,10 +19,17 @@ pub fn search_lava_bm25(
     query_weights: Vec<f32>,
     k: usize,
     reader_type: Option<&PyString>,
-) -> Result<Vec<(u64, u64)>, LavaError> {
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
-
-    py.allow_threads(|| lava::search_lava_bm25(files, query_tokens, query_weights, k, reader_type.into()))
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+    phrase_slop: Option<usize>,
+) -> Result<PyObject, LavaError> {
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
+
+    let config = storage_config.clone();
+    let result = py.allow_threads(|| {
+        lava::search_lava_bm25(files, query_tokens, query_weights, k, phrase_slop, config)
+    })?;
+    super::with_query_stats(py, result, &storage_config)
 }
 
 #[pyfunction]
@@ -34,19 +42,19 @@ pub fn search_lava_substring(
     token_viable_limit: Option<usize>,
     sample_factor: Option<usize>,
     char_index: Option<bool>,
-) -> Result<Vec<(u64, u64)>, LavaError> {
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
     let char_index = char_index.unwrap_or(false);
 
-    if char_index {
-        py.allow_threads(|| {
-            lava::search_lava_substring_char(files, query, k, reader_type.into(), token_viable_limit, sample_factor)
-        })
+    let config = storage_config.clone();
+    let result = if char_index {
+        py.allow_threads(|| lava::search_lava_substring_char(files, query, k, config, token_viable_limit, sample_factor))
     } else {
-        py.allow_threads(|| {
-            lava::search_lava_substring(files, query, k, reader_type.into(), token_viable_limit, sample_factor)
-        })
-    }
+        py.allow_threads(|| lava::search_lava_substring(files, query, k, config, token_viable_limit, sample_factor))
+    }?;
+    super::with_query_stats(py, result, &storage_config)
 }
 
 #[pyfunction]
@@ -56,10 +64,14 @@ pub fn search_lava_uuid(
     query: String,
     k: usize,
     reader_type: Option<&PyString>,
-) -> Result<Vec<(u64, u64)>, LavaError> {
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
-
-    py.allow_threads(|| lava::search_lava_uuid(files, query, k, reader_type.into()))
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
+
+    let config = storage_config.clone();
+    let result = py.allow_threads(|| lava::search_lava_uuid(files, query, k, config))?;
+    super::with_query_stats(py, result, &storage_config)
 }
 
 #[pyfunction]
@@ -69,27 +81,31 @@ pub fn search_lava_vector(
     query: Vec<f32>,
     nprobes: usize,
     reader_type: Option<&PyString>,
-) -> Result<(Vec<usize>, Vec<Py<PyArray1<u8>>>, Vec<(usize, Py<PyArray1<u8>>)>), LavaError> {
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
 
     let start = Instant::now();
 
+    let config = storage_config.clone();
     let result: (Vec<usize>, Vec<Array1<u8>>, Vec<(usize, Array1<u8>)>) =
-        py.allow_threads(move || lava::search_lava_vector(files, query, nprobes, reader_type.into()))?;
+        py.allow_threads(move || lava::search_lava_vector(files, query, nprobes, config))?;
 
     let end = Instant::now();
     println!("rust func call: {:?}", end - start);
 
     let start = Instant::now();
 
-    let x = result.1.into_iter().map(|x| x.into_pyarray_bound(py).unbind()).collect();
+    let x: Vec<Py<PyArray1<u8>>> = result.1.into_iter().map(|x| x.into_pyarray_bound(py).unbind()).collect();
 
-    let y = result.2.into_iter().map(|(x, y)| (x, y.into_pyarray_bound(py).unbind())).collect();
+    let y: Vec<(usize, Py<PyArray1<u8>>)> =
+        result.2.into_iter().map(|(x, y)| (x, y.into_pyarray_bound(py).unbind())).collect();
 
     let end = Instant::now();
     println!("conversion: {:?}", end - start);
 
-    Ok((result.0, x, y))
+    super::with_query_stats(py, (result.0, x, y), &storage_config)
 }
 
 #[pyfunction]
@@ -97,10 +113,11 @@ pub fn get_tokenizer_vocab(
     py: Python,
     files: Vec<String>,
     reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
 ) -> Result<Vec<String>, LavaError> {
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
+    let storage_config = super::storage_config(reader_type, storage_options)?;
 
-    py.allow_threads(|| lava::get_tokenizer_vocab(files, reader_type.into()))
+    py.allow_threads(|| lava::get_tokenizer_vocab(files, storage_config))
 }
 
 #[pyfunction]
@@ -111,11 +128,12 @@ pub fn merge_lava_generic(
     uid_offsets: Vec<u64>,
     merge_type: usize,
     reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
+    let storage_config = super::storage_config(reader_type, storage_options)?;
 
     py.allow_threads(|| {
-        lava::parallel_merge_files(condensed_lava_file, lava_files, uid_offsets, 2, merge_type, reader_type.into())
+        lava::parallel_merge_files(condensed_lava_file, lava_files, uid_offsets, 2, merge_type, storage_config)
     })
 }
 
@@ -126,13 +144,28 @@ pub fn build_lava_bm25(
     array: &PyAny,
     uid: &PyAny,
     tokenizer_file: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+    positions: Option<bool>,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let output_file_name = output_file_name.to_string();
     let array = ArrayData::from_pyarrow_bound(&array.as_borrowed())?;
     let uid = ArrayData::from_pyarrow_bound(&uid.as_borrowed())?;
     let tokenizer_file = tokenizer_file.map(|x| x.to_string());
+    let positions = positions.unwrap_or(false);
+    let storage_config = super::storage_config(None, storage_options)?;
 
-    py.allow_threads(|| lava::build_lava_bm25(output_file_name, array, uid, tokenizer_file, Some(1.2), Some(0.75)))
+    py.allow_threads(|| {
+        lava::build_lava_bm25(
+            output_file_name,
+            array,
+            uid,
+            tokenizer_file,
+            Some(1.2),
+            Some(0.75),
+            positions,
+            storage_config,
+        )
+    })
 }
 
 #[pyfunction]
@@ -141,11 +174,13 @@ pub fn build_lava_uuid(
     output_file_name: &PyString,
     array: &PyAny,
     uid: &PyAny,
+    storage_options: Option<HashMap<String, String>>,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let output_file_name = output_file_name.to_string();
     let array = ArrayData::from_pyarrow_bound(&array.as_borrowed())?;
     let uid = ArrayData::from_pyarrow_bound(&uid.as_borrowed())?;
-    py.allow_threads(|| lava::build_lava_uuid(output_file_name, array, uid))
+    let storage_config = super::storage_config(None, storage_options)?;
+    py.allow_threads(|| lava::build_lava_uuid(output_file_name, array, uid, storage_config))
 }
 
 #[pyfunction]
@@ -157,17 +192,21 @@ pub fn build_lava_substring(
     tokenizer_file: Option<&PyString>,
     token_skip_factor: Option<u32>,
     char_index: Option<bool>,
+    storage_options: Option<HashMap<String, String>>,
 ) -> Result<Vec<(usize, usize)>, LavaError> {
     let output_file_name = output_file_name.to_string();
     let array = ArrayData::from_pyarrow_bound(&array.as_borrowed())?;
     let uid = ArrayData::from_pyarrow_bound(&uid.as_borrowed())?;
     let tokenizer_file = tokenizer_file.map(|x| x.to_string());
+    let storage_config = super::storage_config(None, storage_options)?;
 
     let char_index = char_index.unwrap_or(false);
 
     if char_index {
-        py.allow_threads(|| lava::build_lava_substring_char(output_file_name, array, uid, token_skip_factor))
+        py.allow_threads(|| lava::build_lava_substring_char(output_file_name, array, uid, token_skip_factor, storage_config))
     } else {
-        py.allow_threads(|| lava::build_lava_substring(output_file_name, array, uid, tokenizer_file, token_skip_factor))
+        py.allow_threads(|| {
+            lava::build_lava_substring(output_file_name, array, uid, tokenizer_file, token_skip_factor, storage_config)
+        })
     }
 }
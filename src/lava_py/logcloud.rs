@@ -2,8 +2,9 @@ use arrow::array::ArrayData;
 use arrow::pyarrow::FromPyArrow;
 use parquet::file::page_index::index;
 use pyo3::{pyfunction, types::PyString, PyAny};
-use pyo3::{Py, PyResult};
+use pyo3::{Py, PyObject, PyResult};
 use pyo3::{PyNativeType, Python};
+use std::collections::HashMap;
 
 use crate::lava;
 use crate::lava::error::LavaError;
@@ -28,18 +29,22 @@ pub fn search_logcloud(
     reader_type: Option<&PyString>,
     wavelet_tree: Option<bool>,
     exact: Option<bool>,
-) -> Result<(u32, Vec<(usize, u32)>), LavaError> {
-    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
-    py.allow_threads(|| {
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<PyObject, LavaError> {
+    let storage_config = super::query_storage_config(reader_type, storage_options, return_stats)?;
+    let config = storage_config.clone();
+    let result = py.allow_threads(|| {
         lava::search_logcloud(
             split_index_prefixes,
             query,
             limit,
-            reader_type.into(),
+            config,
             wavelet_tree.unwrap_or(false),
             exact.unwrap_or(false),
         )
-    })
+    })?;
+    super::with_query_stats(py, result, &storage_config)
 }
 
 #[pyfunction]
@@ -1,4 +1,9 @@
 use pyo3::prelude::*;
+use pyo3::types::PyString;
+use std::collections::HashMap;
+
+use crate::formats::readers::{QueryStats, StorageConfig};
+use crate::lava::error::LavaError;
 
 mod format;
 mod lava;
@@ -19,8 +24,23 @@ fn rottnest(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(lava::get_tokenizer_vocab, m)?)?;
     m.add_function(wrap_pyfunction!(lava::merge_lava_generic, m)?)?;
     m.add_function(wrap_pyfunction!(format::get_parquet_layout, m)?)?;
+    m.add_function(wrap_pyfunction!(format::stream_parquet_layout, m)?)?;
+    m.add_function(wrap_pyfunction!(format::get_parquet_page_layout, m)?)?;
     m.add_function(wrap_pyfunction!(format::read_indexed_pages, m)?)?;
+    m.add_function(wrap_pyfunction!(format::read_rows, m)?)?;
+    m.add_function(wrap_pyfunction!(format::read_filtered_rows, m)?)?;
+    m.add_function(wrap_pyfunction!(format::get_ipc_layout, m)?)?;
+    m.add_function(wrap_pyfunction!(format::read_indexed_batches, m)?)?;
+    m.add_function(wrap_pyfunction!(format::get_text_layout, m)?)?;
+    m.add_function(wrap_pyfunction!(format::read_indexed_lines, m)?)?;
     m.add_function(wrap_pyfunction!(format::populate_cache, m)?)?;
+    m.add_function(wrap_pyfunction!(format::set_range_cache, m)?)?;
+    m.add_function(wrap_pyfunction!(format::range_cache_backend, m)?)?;
+    m.add_function(wrap_pyfunction!(format::set_parquet_cache, m)?)?;
+    m.add_function(wrap_pyfunction!(format::start_access_recording, m)?)?;
+    m.add_function(wrap_pyfunction!(format::stop_access_recording, m)?)?;
+    m.add_function(wrap_pyfunction!(format::warm_cache, m)?)?;
+    m.add_function(wrap_pyfunction!(format::set_read_limits, m)?)?;
     #[cfg(feature = "logcloud")]
     {
         m.add_function(wrap_pyfunction!(logcloud::index_logcloud, m)?)?;
@@ -31,3 +51,80 @@ fn rottnest(_py: Python, m: &PyModule) -> PyResult<()> {
 
     Ok(())
 }
+
+/// Builds the storage config of a query from the `reader_type` and `storage_options` arguments.
+fn storage_config(
+    reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+) -> Result<StorageConfig, LavaError> {
+    let reader_type = reader_type.map(|x| x.to_string()).unwrap_or_default();
+    StorageConfig::from_map(reader_type.into(), &storage_options.unwrap_or_default())
+}
+
+/// Like `storage_config`, but also collects the stats of the query if `return_stats` is set.
+fn query_storage_config(
+    reader_type: Option<&PyString>,
+    storage_options: Option<HashMap<String, String>>,
+    return_stats: Option<bool>,
+) -> Result<StorageConfig, LavaError> {
+    let storage_config = storage_config(reader_type, storage_options)?;
+    Ok(match return_stats.unwrap_or(false) {
+        true => storage_config.with_query_stats(),
+        false => storage_config,
+    })
+}
+
+/// Returns `result`, or `(result, stats)` if the query collected stats.
+fn with_query_stats<T: IntoPy<PyObject>>(
+    py: Python,
+    result: T,
+    storage_config: &StorageConfig,
+) -> Result<PyObject, LavaError> {
+    match storage_config.query_stats() {
+        Some(stats) => Ok((result, Py::new(py, QueryStatsWrapper::from(stats))?).into_py(py)),
+        None => Ok(result.into_py(py)),
+    }
+}
+
+#[pyclass]
+pub struct QueryStatsWrapper {
+    #[pyo3(get)]
+    pub requests: u64,
+    #[pyo3(get)]
+    pub bytes_read: u64,
+    #[pyo3(get)]
+    pub cache_hits: u64,
+    #[pyo3(get)]
+    pub cache_misses: u64,
+    #[pyo3(get)]
+    pub largest_read: u64,
+    #[pyo3(get)]
+    pub stages: Vec<(String, f64)>, // seconds
+}
+
+#[pymethods]
+impl QueryStatsWrapper {
+    fn __repr__(&self) -> String {
+        format!(
+            "QueryStats(requests={}, bytes_read={}, cache_hits={}, cache_misses={}, largest_read={}, stages={:?})",
+            self.requests, self.bytes_read, self.cache_hits, self.cache_misses, self.largest_read, self.stages
+        )
+    }
+}
+
+impl From<QueryStats> for QueryStatsWrapper {
+    fn from(stats: QueryStats) -> Self {
+        QueryStatsWrapper {
+            requests: stats.requests,
+            bytes_read: stats.bytes_read,
+            cache_hits: stats.cache_hits,
+            cache_misses: stats.cache_misses,
+            largest_read: stats.largest_read,
+            stages: stats
+                .stages
+                .into_iter()
+                .map(|(stage, duration)| (stage, duration.as_secs_f64()))
+                .collect(),
+        }
+    }
+}